@@ -12,7 +12,7 @@ use winutils_rs::windefs::HResult;
 
 /// Common result codes and error codes that are specific to virtualization,
 /// that can be returned by the HCS APIs.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ResultCode {
     Success,
     OutOfMemory,
@@ -429,6 +429,21 @@ pub enum ResultCode {
     /// The virtual machine or container reported a critical  and was stopped or restarted.
     HcsGuestCritical,
 
+    /// The requested information is not available for this process.
+    HcsProcessInfoNotAvailable,
+
+    /// The connection with the compute service was lost, such as when the service restarted.
+    HcsServiceDisconnect,
+
+    /// The process has already exited.
+    HcsProcessAlreadyStopped,
+
+    /// The virtual machine or container is not configured to perform the operation.
+    HcsSystemNotConfiguredForOperation,
+
+    /// The operation has already been cancelled.
+    HcsOperationAlreadyCancelled,
+
     /// A virtual switch with the given name was not found.
     ErrorVnetVirtualSwitchNameNotFound,
 
@@ -742,6 +757,11 @@ pub(crate) fn hresult_to_result_code(hresult: &HResult) -> ResultCode {
         0x8037011A => ResultCode::HcsOperationResultAllocationFailed,
         0x8037011B => ResultCode::HcsAccessDenied,
         0x8037011C => ResultCode::HcsGuestCritical,
+        0x8037011D => ResultCode::HcsProcessInfoNotAvailable,
+        0x8037011E => ResultCode::HcsServiceDisconnect,
+        0x8037011F => ResultCode::HcsProcessAlreadyStopped,
+        0x80370120 => ResultCode::HcsSystemNotConfiguredForOperation,
+        0x80370121 => ResultCode::HcsOperationAlreadyCancelled,
         0xC0370200 => ResultCode::ErrorVnetVirtualSwitchNameNotFound,
         0x80370001 => ResultCode::ErrorVidRemoteNodeParentGpaPagesUsed,
         0x80370300 => ResultCode::WhvUnknownCapability,
@@ -802,7 +822,6 @@ pub(crate) fn hresult_to_result_code(hresult: &HResult) -> ResultCode {
 }
 
 #[allow(overflowing_literals)]
-#[allow(dead_code)]
 pub(crate) fn result_code_to_hresult(result_code: ResultCode) -> HResult {
     match result_code {
         ResultCode::Success => 0,
@@ -947,6 +966,11 @@ pub(crate) fn result_code_to_hresult(result_code: ResultCode) -> HResult {
         ResultCode::HcsOperationResultAllocationFailed => 0x8037011A,
         ResultCode::HcsAccessDenied => 0x8037011B,
         ResultCode::HcsGuestCritical => 0x8037011C,
+        ResultCode::HcsProcessInfoNotAvailable => 0x8037011D,
+        ResultCode::HcsServiceDisconnect => 0x8037011E,
+        ResultCode::HcsProcessAlreadyStopped => 0x8037011F,
+        ResultCode::HcsSystemNotConfiguredForOperation => 0x80370120,
+        ResultCode::HcsOperationAlreadyCancelled => 0x80370121,
         ResultCode::ErrorVnetVirtualSwitchNameNotFound => 0xC0370200,
         ResultCode::ErrorVidRemoteNodeParentGpaPagesUsed => 0x80370001,
         ResultCode::WhvUnknownCapability => 0x80370300,
@@ -1005,3 +1029,534 @@ pub(crate) fn result_code_to_hresult(result_code: ResultCode) -> HResult {
         ResultCode::UnknownHResult(other) => other,
     }
 }
+
+/// Component that reported a result code, decoded from the facility of its HRESULT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Facility {
+    /// Generic COM result codes, such as `E_FAIL`.
+    Generic,
+    Win32,
+    Hypervisor,
+
+    /// Virtualization infrastructure driver.
+    Vid,
+
+    /// Compute service, reported as an NTSTATUS based HRESULT.
+    VmCompute,
+
+    /// Host compute service.
+    Hcs,
+
+    /// Windows hypervisor platform.
+    Whv,
+
+    /// Other virtualization components, such as virtual networking or virtual SMB.
+    Virtualization,
+
+    /// Host compute network service.
+    Hcn,
+
+    /// Guest compute network service.
+    Gcn,
+
+    /// HRESULT facility not known by this crate.
+    Other(u16),
+}
+
+/// Broad classification of result codes, used to decide how to handle a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    Success,
+
+    /// Failure that might go away if the operation is retried, such as a timeout
+    /// or a lost connection with the compute service.
+    Transient,
+
+    NotFound,
+    AccessDenied,
+
+    /// The operation is not allowed in the current state of the target.
+    InvalidState,
+
+    Other,
+}
+
+const FACILITY_NULL: u16 = 0x0;
+const FACILITY_ITF: u16 = 0x4;
+const FACILITY_WIN32: u16 = 0x7;
+const FACILITY_HYPERVISOR: u16 = 0x35;
+const FACILITY_VIRTUALIZATION: u16 = 0x37;
+const FACILITY_HNS: u16 = 0x3B;
+
+#[allow(overflowing_literals)]
+const E_ACCESSDENIED: HResult = 0x80070005;
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_PATH_NOT_FOUND: HResult = 0x80070003;
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_NOT_FOUND: HResult = 0x80070490;
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_TIMEOUT: HResult = 0x800705B4;
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_SERVICE_NOT_ACTIVE: HResult = 0x80070426;
+
+impl ResultCode {
+    pub fn from_hresult(hresult: HResult) -> ResultCode {
+        hresult_to_result_code(&hresult)
+    }
+
+    pub fn to_hresult(self) -> HResult {
+        result_code_to_hresult(self)
+    }
+
+    /// Returns the raw facility field of the HRESULT.
+    pub fn facility_code(self) -> u16 {
+        ((self.to_hresult() >> 16) & 0x1FFF) as u16
+    }
+
+    /// Returns the component that reported the result code.
+    pub fn facility(self) -> Facility {
+        let hresult = self.to_hresult() as u32;
+        let code = hresult & 0xFFFF;
+        let is_ntstatus = hresult & 0xC000_0000 == 0xC000_0000;
+
+        match self.facility_code() {
+            FACILITY_NULL | FACILITY_ITF => Facility::Generic,
+            FACILITY_WIN32 => Facility::Win32,
+            FACILITY_HYPERVISOR => Facility::Hypervisor,
+            FACILITY_VIRTUALIZATION => match code >> 8 {
+                0x0 if is_ntstatus => Facility::Vid,
+                0x1 if is_ntstatus => Facility::VmCompute,
+                0x1 => Facility::Hcs,
+                0x3 => Facility::Whv,
+                _ => Facility::Virtualization,
+            },
+            FACILITY_HNS if code >= 0x21 => Facility::Gcn,
+            FACILITY_HNS => Facility::Hcn,
+            other => Facility::Other(other),
+        }
+    }
+
+    /// Returns the symbolic name of the result code, as defined in winerror.h.
+    /// Unknown HRESULTs return `"UNKNOWN"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ResultCode::Success => "S_OK",
+            ResultCode::OutOfMemory => "E_OUTOFMEMORY",
+            ResultCode::FileNotFound => "ERROR_FILE_NOT_FOUND",
+            ResultCode::Fail => "E_FAIL",
+            ResultCode::InvalidArgument => "E_INVALIDARG",
+            ResultCode::Unexpected => "E_UNEXPECTED",
+            ResultCode::HvInvalidHypercallCode => "ERROR_HV_INVALID_HYPERCALL_CODE",
+            ResultCode::HvInvalidHypercallInput => "ERROR_HV_INVALID_HYPERCALL_INPUT",
+            ResultCode::HvInvalidAlignment => "ERROR_HV_INVALID_ALIGNMENT",
+            ResultCode::HvInvalidParameter => "ERROR_HV_INVALID_PARAMETER",
+            ResultCode::HvAccessDenied => "ERROR_HV_ACCESS_DENIED",
+            ResultCode::HvInvalidPartitionState => "ERROR_HV_INVALID_PARTITION_STATE",
+            ResultCode::HvOperationDenied => "ERROR_HV_OPERATION_DENIED",
+            ResultCode::HvUnknownProperty => "ERROR_HV_UNKNOWN_PROPERTY",
+            ResultCode::HvPropertyValueOutOfRange => "ERROR_HV_PROPERTY_VALUE_OUT_OF_RANGE",
+            ResultCode::HvInsufficientMemory => "ERROR_HV_INSUFFICIENT_MEMORY",
+            ResultCode::HvPartitionTooDeep => "ERROR_HV_PARTITION_TOO_DEEP",
+            ResultCode::HvInvalidPartitionId => "ERROR_HV_INVALID_PARTITION_ID",
+            ResultCode::HvInvalidVpIndex => "ERROR_HV_INVALID_VP_INDEX",
+            ResultCode::HvInvalidPortId => "ERROR_HV_INVALID_PORT_ID",
+            ResultCode::HvInvalidConnectionId => "ERROR_HV_INVALID_CONNECTION_ID",
+            ResultCode::HvInsufficientBuffers => "ERROR_HV_INSUFFICIENT_BUFFERS",
+            ResultCode::HvNotAcknowledged => "ERROR_HV_NOT_ACKNOWLEDGED",
+            ResultCode::HvInvalidVpState => "ERROR_HV_INVALID_VP_STATE",
+            ResultCode::HvAcknowledged => "ERROR_HV_ACKNOWLEDGED",
+            ResultCode::HvInvalidSaveRestoreState => "ERROR_HV_INVALID_SAVE_RESTORE_STATE",
+            ResultCode::HvInvalidSynicState => "ERROR_HV_INVALID_SYNIC_STATE",
+            ResultCode::HvObjectInUse => "ERROR_HV_OBJECT_IN_USE",
+            ResultCode::HvInvalidProximityDomainInfo => "ERROR_HV_INVALID_PROXIMITY_DOMAIN_INFO",
+            ResultCode::HvNoData => "ERROR_HV_NO_DATA",
+            ResultCode::HvInactive => "ERROR_HV_INACTIVE",
+            ResultCode::HvNoResources => "ERROR_HV_NO_RESOURCES",
+            ResultCode::HvFeatureUnavailable => "ERROR_HV_FEATURE_UNAVAILABLE",
+            ResultCode::HvInsufficientBuffer => "ERROR_HV_INSUFFICIENT_BUFFER",
+            ResultCode::HvInsufficientDeviceDomains => "ERROR_HV_INSUFFICIENT_DEVICE_DOMAINS",
+            ResultCode::HvCpuidFeatureValidation => "ERROR_HV_CPUID_FEATURE_VALIDATION",
+            ResultCode::HvCpuidXsaveFeatureValidation => "ERROR_HV_CPUID_XSAVE_FEATURE_VALIDATION",
+            ResultCode::HvProcessorStartupTimeout => "ERROR_HV_PROCESSOR_STARTUP_TIMEOUT",
+            ResultCode::HvSmxEnabled => "ERROR_HV_SMX_ENABLED",
+            ResultCode::HvInvalidLpIndex => "ERROR_HV_INVALID_LP_INDEX",
+            ResultCode::HvInvalidRegisterValue => "ERROR_HV_INVALID_REGISTER_VALUE",
+            ResultCode::HvInvalidVtlState => "ERROR_HV_INVALID_VTL_STATE",
+            ResultCode::HvNxNotDetected => "ERROR_HV_NX_NOT_DETECTED",
+            ResultCode::HvInvalidDeviceId => "ERROR_HV_INVALID_DEVICE_ID",
+            ResultCode::HvInvalidDeviceState => "ERROR_HV_INVALID_DEVICE_STATE",
+            ResultCode::HvPendingPageRequests => "ERROR_HV_PENDING_PAGE_REQUESTS",
+            ResultCode::HvPageRequestInvalid => "ERROR_HV_PAGE_REQUEST_INVALID",
+            ResultCode::HvInvalidCpuGroupId => "ERROR_HV_INVALID_CPU_GROUP_ID",
+            ResultCode::HvInvalidCpuGroupState => "ERROR_HV_INVALID_CPU_GROUP_STATE",
+            ResultCode::HvOperationFailed => "ERROR_HV_OPERATION_FAILED",
+            ResultCode::HvNotAllowedWithNestedVirtActive => {
+                "ERROR_HV_NOT_ALLOWED_WITH_NESTED_VIRT_ACTIVE"
+            }
+            ResultCode::HvInsufficientRootMemory => "ERROR_HV_INSUFFICIENT_ROOT_MEMORY",
+            ResultCode::HvNotPresent => "ERROR_HV_NOT_PRESENT",
+            ResultCode::VidDuplicateHandler => "ERROR_VID_DUPLICATE_HANDLER",
+            ResultCode::VidTooManyHandlers => "ERROR_VID_TOO_MANY_HANDLERS",
+            ResultCode::VidQueueFull => "ERROR_VID_QUEUE_FULL",
+            ResultCode::VidHandlerNotPresent => "ERROR_VID_HANDLER_NOT_PRESENT",
+            ResultCode::VidInvalidObjectName => "ERROR_VID_INVALID_OBJECT_NAME",
+            ResultCode::VidPartitionNameTooLong => "ERROR_VID_PARTITION_NAME_TOO_LONG",
+            ResultCode::VidMessageQueueNameTooLong => "ERROR_VID_MESSAGE_QUEUE_NAME_TOO_LONG",
+            ResultCode::VidPartitionAlreadyExists => "ERROR_VID_PARTITION_ALREADY_EXISTS",
+            ResultCode::VidPartitionDoesNotExist => "ERROR_VID_PARTITION_DOES_NOT_EXIST",
+            ResultCode::VidPartitionNameNotFound => "ERROR_VID_PARTITION_NAME_NOT_FOUND",
+            ResultCode::VidMessageQueueAlreadyExists => "ERROR_VID_MESSAGE_QUEUE_ALREADY_EXISTS",
+            ResultCode::VidExceededMbpEntryMapLimit => "ERROR_VID_EXCEEDED_MBP_ENTRY_MAP_LIMIT",
+            ResultCode::VidMbStillReferenced => "ERROR_VID_MB_STILL_REFERENCED",
+            ResultCode::VidChildGpaPageSetCorrupted => "ERROR_VID_CHILD_GPA_PAGE_SET_CORRUPTED",
+            ResultCode::VidInvalidNumaSettings => "ERROR_VID_INVALID_NUMA_SETTINGS",
+            ResultCode::VidInvalidNumaNodeIndex => "ERROR_VID_INVALID_NUMA_NODE_INDEX",
+            ResultCode::VidNotificationQueueAlreadyAssociated => {
+                "ERROR_VID_NOTIFICATION_QUEUE_ALREADY_ASSOCIATED"
+            }
+            ResultCode::VidInvalidMemoryBlockHandle => "ERROR_VID_INVALID_MEMORY_BLOCK_HANDLE",
+            ResultCode::VidPageRangeOverflow => "ERROR_VID_PAGE_RANGE_OVERFLOW",
+            ResultCode::VidInvalidMessageQueueHandle => "ERROR_VID_INVALID_MESSAGE_QUEUE_HANDLE",
+            ResultCode::VidInvalidGpaRangeHandle => "ERROR_VID_INVALID_GPA_RANGE_HANDLE",
+            ResultCode::VidNoMemoryBlockNotificationQueue => {
+                "ERROR_VID_NO_MEMORY_BLOCK_NOTIFICATION_QUEUE"
+            }
+            ResultCode::VidMemoryBlockLockCountExceeded => {
+                "ERROR_VID_MEMORY_BLOCK_LOCK_COUNT_EXCEEDED"
+            }
+            ResultCode::VidInvalidPpmHandle => "ERROR_VID_INVALID_PPM_HANDLE",
+            ResultCode::VidMbpsAreLocked => "ERROR_VID_MBPS_ARE_LOCKED",
+            ResultCode::VidMessageQueueClosed => "ERROR_VID_MESSAGE_QUEUE_CLOSED",
+            ResultCode::VidVirtualProcessorLimitExceeded => {
+                "ERROR_VID_VIRTUAL_PROCESSOR_LIMIT_EXCEEDED"
+            }
+            ResultCode::VidStopPending => "ERROR_VID_STOP_PENDING",
+            ResultCode::VidInvalidProcessorState => "ERROR_VID_INVALID_PROCESSOR_STATE",
+            ResultCode::VidExceededKmContextCountLimit => {
+                "ERROR_VID_EXCEEDED_KM_CONTEXT_COUNT_LIMIT"
+            }
+            ResultCode::VidKmInterfaceAlreadyInitialized => {
+                "ERROR_VID_KM_INTERFACE_ALREADY_INITIALIZED"
+            }
+            ResultCode::VidMbPropertyAlreadySetReset => "ERROR_VID_MB_PROPERTY_ALREADY_SET_RESET",
+            ResultCode::VidMmioRangeDestroyed => "ERROR_VID_MMIO_RANGE_DESTROYED",
+            ResultCode::VidInvalidChildGpaPageSet => "ERROR_VID_INVALID_CHILD_GPA_PAGE_SET",
+            ResultCode::VidReservePageSetIsBeingUsed => "ERROR_VID_RESERVE_PAGE_SET_IS_BEING_USED",
+            ResultCode::VidReservePageSetTooSmall => "ERROR_VID_RESERVE_PAGE_SET_TOO_SMALL",
+            ResultCode::VidMbpAlreadyLockedUsingReservedPage => {
+                "ERROR_VID_MBP_ALREADY_LOCKED_USING_RESERVED_PAGE"
+            }
+            ResultCode::VidMbpCountExceededLimit => "ERROR_VID_MBP_COUNT_EXCEEDED_LIMIT",
+            ResultCode::VidSavedStateCorrupt => "ERROR_VID_SAVED_STATE_CORRUPT",
+            ResultCode::VidSavedStateUnrecognizedItem => "ERROR_VID_SAVED_STATE_UNRECOGNIZED_ITEM",
+            ResultCode::VidSavedStateIncompatible => "ERROR_VID_SAVED_STATE_INCOMPATIBLE",
+            ResultCode::VidVtlAccessDenied => "ERROR_VID_VTL_ACCESS_DENIED",
+            ResultCode::VmComputeTerminatedDuringStart => "ERROR_VMCOMPUTE_TERMINATED_DURING_START",
+            ResultCode::VmComputeImageMismatch => "ERROR_VMCOMPUTE_IMAGE_MISMATCH",
+            ResultCode::VmComputeHypervNotInstalled => "ERROR_VMCOMPUTE_HYPERV_NOT_INSTALLED",
+            ResultCode::VmComputeOperationPending => "ERROR_VMCOMPUTE_OPERATION_PENDING",
+            ResultCode::VmComputeTooManyNotifications => "ERROR_VMCOMPUTE_TOO_MANY_NOTIFICATIONS",
+            ResultCode::VmComputeInvalidState => "ERROR_VMCOMPUTE_INVALID_STATE",
+            ResultCode::VmComputeUnexpectedExit => "ERROR_VMCOMPUTE_UNEXPECTED_EXIT",
+            ResultCode::VmComputeTerminated => "ERROR_VMCOMPUTE_TERMINATED",
+            ResultCode::VmComputeConnectFailed => "ERROR_VMCOMPUTE_CONNECT_FAILED",
+            ResultCode::VmComputeTimeout => "ERROR_VMCOMPUTE_TIMEOUT",
+            ResultCode::VmComputeConnectionClosed => "ERROR_VMCOMPUTE_CONNECTION_CLOSED",
+            ResultCode::VmComputeUnknownMessage => "ERROR_VMCOMPUTE_UNKNOWN_MESSAGE",
+            ResultCode::VmComputeUnsupportedProtocolVersion => {
+                "ERROR_VMCOMPUTE_UNSUPPORTED_PROTOCOL_VERSION"
+            }
+            ResultCode::VmComputeInvalidJson => "ERROR_VMCOMPUTE_INVALID_JSON",
+            ResultCode::VmComputeSystemNotFound => "ERROR_VMCOMPUTE_SYSTEM_NOT_FOUND",
+            ResultCode::VmComputeSystemAlreadyExists => "ERROR_VMCOMPUTE_SYSTEM_ALREADY_EXISTS",
+            ResultCode::VmComputeSystemAlreadyStopped => "ERROR_VMCOMPUTE_SYSTEM_ALREADY_STOPPED",
+            ResultCode::VmComputeProtocol => "ERROR_VMCOMPUTE_PROTOCOL",
+            ResultCode::VmComputeInvalidLayer => "ERROR_VMCOMPUTE_INVALID_LAYER",
+            ResultCode::VmComputeWindowsInsiderRequired => {
+                "ERROR_VMCOMPUTE_WINDOWS_INSIDER_REQUIRED"
+            }
+            ResultCode::HcsTerminatedDuringStart => "HCS_E_TERMINATED_DURING_START",
+            ResultCode::HcsImageMismatch => "HCS_E_IMAGE_MISMATCH",
+            ResultCode::HcsHypervNotInstalled => "HCS_E_HYPERV_NOT_INSTALLED",
+            ResultCode::HcsInvalidState => "HCS_E_INVALID_STATE",
+            ResultCode::HcsUnexpectedExit => "HCS_E_UNEXPECTED_EXIT",
+            ResultCode::HcsTerminated => "HCS_E_TERMINATED",
+            ResultCode::HcsConnectFailed => "HCS_E_CONNECT_FAILED",
+            ResultCode::HcsConnectionTimeout => "HCS_E_CONNECTION_TIMEOUT",
+            ResultCode::HcsConnectionClosed => "HCS_E_CONNECTION_CLOSED",
+            ResultCode::HcsUnknownMessage => "HCS_E_UNKNOWN_MESSAGE",
+            ResultCode::HcsUnsupportedProtocolVersion => "HCS_E_UNSUPPORTED_PROTOCOL_VERSION",
+            ResultCode::HcsInvalidJson => "HCS_E_INVALID_JSON",
+            ResultCode::HcsSystemNotFound => "HCS_E_SYSTEM_NOT_FOUND",
+            ResultCode::HcsSystemAlreadyExists => "HCS_E_SYSTEM_ALREADY_EXISTS",
+            ResultCode::HcsSystemAlreadyStopped => "HCS_E_SYSTEM_ALREADY_STOPPED",
+            ResultCode::HcsProtocol => "HCS_E_PROTOCOL_ERROR",
+            ResultCode::HcsInvalidLayer => "HCS_E_INVALID_LAYER",
+            ResultCode::HcsWindowsInsiderRequired => "HCS_E_WINDOWS_INSIDER_REQUIRED",
+            ResultCode::HcsServiceNotAvailable => "HCS_E_SERVICE_NOT_AVAILABLE",
+            ResultCode::HcsOperationNotStarted => "HCS_E_OPERATION_NOT_STARTED",
+            ResultCode::HcsOperationAlreadyStarted => "HCS_E_OPERATION_ALREADY_STARTED",
+            ResultCode::HcsOperationPending => "HCS_E_OPERATION_PENDING",
+            ResultCode::HcsOperationTimeout => "HCS_E_OPERATION_TIMEOUT",
+            ResultCode::HcsOperationSystemCallbackAlreadySet => {
+                "HCS_E_OPERATION_SYSTEM_CALLBACK_ALREADY_SET"
+            }
+            ResultCode::HcsOperationResultAllocationFailed => {
+                "HCS_E_OPERATION_RESULT_ALLOCATION_FAILED"
+            }
+            ResultCode::HcsAccessDenied => "HCS_E_ACCESS_DENIED",
+            ResultCode::HcsGuestCritical => "HCS_E_GUEST_CRITICAL_ERROR",
+            ResultCode::HcsProcessInfoNotAvailable => "HCS_E_PROCESS_INFO_NOT_AVAILABLE",
+            ResultCode::HcsServiceDisconnect => "HCS_E_SERVICE_DISCONNECT",
+            ResultCode::HcsProcessAlreadyStopped => "HCS_E_PROCESS_ALREADY_STOPPED",
+            ResultCode::HcsSystemNotConfiguredForOperation => {
+                "HCS_E_SYSTEM_NOT_CONFIGURED_FOR_OPERATION"
+            }
+            ResultCode::HcsOperationAlreadyCancelled => "HCS_E_OPERATION_ALREADY_CANCELLED",
+            ResultCode::ErrorVnetVirtualSwitchNameNotFound => {
+                "ERROR_VNET_VIRTUAL_SWITCH_NAME_NOT_FOUND"
+            }
+            ResultCode::ErrorVidRemoteNodeParentGpaPagesUsed => {
+                "ERROR_VID_REMOTE_NODE_PARENT_GPA_PAGES_USED"
+            }
+            ResultCode::WhvUnknownCapability => "WHV_E_UNKNOWN_CAPABILITY",
+            ResultCode::WhvInsufficientBuffer => "WHV_E_INSUFFICIENT_BUFFER",
+            ResultCode::WhvUnknownProperty => "WHV_E_UNKNOWN_PROPERTY",
+            ResultCode::WhvUnsupportedHypervisorConfig => "WHV_E_UNSUPPORTED_HYPERVISOR_CONFIG",
+            ResultCode::WhvInvalidPartitionConfig => "WHV_E_INVALID_PARTITION_CONFIG",
+            ResultCode::WhvGpaRangeNotFound => "WHV_E_GPA_RANGE_NOT_FOUND",
+            ResultCode::WhvVpAlreadyExists => "WHV_E_VP_ALREADY_EXISTS",
+            ResultCode::WhvVpDoesNotExist => "WHV_E_VP_DOES_NOT_EXIST",
+            ResultCode::WhvInvalidVpState => "WHV_E_INVALID_VP_STATE",
+            ResultCode::WhvInvalidVpRegisterName => "WHV_E_INVALID_VP_REGISTER_NAME",
+            ResultCode::ErrorVsmbSavedStateFileNotFound => "ERROR_VSMB_SAVED_STATE_FILE_NOT_FOUND",
+            ResultCode::ErrorVsmbSavedStateCorrupt => "ERROR_VSMB_SAVED_STATE_CORRUPT",
+            ResultCode::HcnNetworkNotFound => "HCN_E_NETWORK_NOT_FOUND",
+            ResultCode::HcnEndpointNotFound => "HCN_E_ENDPOINT_NOT_FOUND",
+            ResultCode::HcnLayerNotFound => "HCN_E_LAYER_NOT_FOUND",
+            ResultCode::HcnSwitchNotFound => "HCN_E_SWITCH_NOT_FOUND",
+            ResultCode::HcnSubnetNotFound => "HCN_E_SUBNET_NOT_FOUND",
+            ResultCode::HcnAdapterNotFound => "HCN_E_ADAPTER_NOT_FOUND",
+            ResultCode::HcnPortNotFound => "HCN_E_PORT_NOT_FOUND",
+            ResultCode::HcnPolicyNotFound => "HCN_E_POLICY_NOT_FOUND",
+            ResultCode::HcnVfpPortsettingNotFound => "HCN_E_VFP_PORTSETTING_NOT_FOUND",
+            ResultCode::HcnInvalidNetwork => "HCN_E_INVALID_NETWORK",
+            ResultCode::HcnInvalidNetworkType => "HCN_E_INVALID_NETWORK_TYPE",
+            ResultCode::HcnInvalidEndpoint => "HCN_E_INVALID_ENDPOINT",
+            ResultCode::HcnInvalidPolicy => "HCN_E_INVALID_POLICY",
+            ResultCode::HcnInvalidPolicyType => "HCN_E_INVALID_POLICY_TYPE",
+            ResultCode::HcnInvalidRemoteEndpointOperation => {
+                "HCN_E_INVALID_REMOTE_ENDPOINT_OPERATION"
+            }
+            ResultCode::HcnNetworkAlreadyExists => "HCN_E_NETWORK_ALREADY_EXISTS",
+            ResultCode::HcnLayerAlreadyExists => "HCN_E_LAYER_ALREADY_EXISTS",
+            ResultCode::HcnPolicyAlreadyExists => "HCN_E_POLICY_ALREADY_EXISTS",
+            ResultCode::HcnPortAlreadyExists => "HCN_E_PORT_ALREADY_EXISTS",
+            ResultCode::HcnEndpointAlreadyAttached => "HCN_E_ENDPOINT_ALREADY_ATTACHED",
+            ResultCode::HcnRequestUnsupported => "HCN_E_REQUEST_UNSUPPORTED",
+            ResultCode::HcnMappingNotSupported => "HCN_E_MAPPING_NOT_SUPPORTED",
+            ResultCode::HcnDegradedOperation => "HCN_E_DEGRADED_OPERATION",
+            ResultCode::HcnSharedSwitchModification => "HCN_E_SHARED_SWITCH_MODIFICATION",
+            ResultCode::HcnGuidConversionFailure => "HCN_E_GUID_CONVERSION_FAILURE",
+            ResultCode::HcnRegkeyFailure => "HCN_E_REGKEY_FAILURE",
+            ResultCode::HcnInvalidJson => "HCN_E_INVALID_JSON",
+            ResultCode::HcnInvalidJsonReference => "HCN_E_INVALID_JSON_REFERENCE",
+            ResultCode::HcnEndpointSharingDisabled => "HCN_E_ENDPOINT_SHARING_DISABLED",
+            ResultCode::HcnInvalidIp => "HCN_E_INVALID_IP",
+            ResultCode::HcnSwitchExtensionNotFound => "HCN_E_SWITCH_EXTENSION_NOT_FOUND",
+            ResultCode::HcnManagerStopped => "HCN_E_MANAGER_STOPPED",
+            ResultCode::GcnModuleNotFound => "GCN_E_MODULE_NOT_FOUND",
+            ResultCode::GcnNoRequestHandlers => "GCN_E_NO_REQUEST_HANDLERS",
+            ResultCode::GcnRequestUnsupported => "GCN_E_REQUEST_UNSUPPORTED",
+            ResultCode::GcnRuntimekeysFailed => "GCN_E_RUNTIMEKEYS_FAILED",
+            ResultCode::GcnNetadapterTimeout => "GCN_E_NETADAPTER_TIMEOUT",
+            ResultCode::GcnNetadapterNotFound => "GCN_E_NETADAPTER_NOT_FOUND",
+            ResultCode::GcnNetcompartmentNotFound => "GCN_E_NETCOMPARTMENT_NOT_FOUND",
+            ResultCode::GcnNetinterfaceNotFound => "GCN_E_NETINTERFACE_NOT_FOUND",
+            ResultCode::GcnDefaultnamespaceExists => "GCN_E_DEFAULTNAMESPACE_EXISTS",
+            ResultCode::UnknownHResult(_) => "UNKNOWN",
+        }
+    }
+
+    /// Returns the broad classification of the result code.
+    pub fn class(self) -> ErrorClass {
+        if self == ResultCode::Success {
+            ErrorClass::Success
+        } else if self.is_transient() {
+            ErrorClass::Transient
+        } else if self.is_not_found() {
+            ErrorClass::NotFound
+        } else if self.is_access_denied() {
+            ErrorClass::AccessDenied
+        } else if self.is_invalid_state() {
+            ErrorClass::InvalidState
+        } else {
+            ErrorClass::Other
+        }
+    }
+
+    /// Returns true if the failure might go away if the operation is retried,
+    /// such as timeouts or a lost connection with the compute service.
+    pub fn is_transient(self) -> bool {
+        match self {
+            ResultCode::HcsConnectFailed
+            | ResultCode::HcsConnectionTimeout
+            | ResultCode::HcsConnectionClosed
+            | ResultCode::HcsServiceNotAvailable
+            | ResultCode::HcsServiceDisconnect
+            | ResultCode::HcsOperationTimeout
+            | ResultCode::VmComputeConnectFailed
+            | ResultCode::VmComputeTimeout
+            | ResultCode::VmComputeConnectionClosed
+            | ResultCode::VmComputeTooManyNotifications
+            | ResultCode::HvInsufficientBuffers
+            | ResultCode::VidQueueFull
+            | ResultCode::VidStopPending
+            | ResultCode::GcnNetadapterTimeout => true,
+            ResultCode::UnknownHResult(hresult) => {
+                hresult == HRESULT_ERROR_TIMEOUT || hresult == HRESULT_ERROR_SERVICE_NOT_ACTIVE
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the connection with the compute service was lost, after which
+    /// handles to compute systems and processes must be opened again.
+    pub fn is_service_disconnect(self) -> bool {
+        matches!(
+            self,
+            ResultCode::HcsServiceDisconnect
+                | ResultCode::HcsConnectionClosed
+                | ResultCode::VmComputeConnectionClosed
+        )
+    }
+
+    /// Returns true if the target of the operation doesn't exist.
+    pub fn is_not_found(self) -> bool {
+        match self {
+            ResultCode::FileNotFound
+            | ResultCode::HcsSystemNotFound
+            | ResultCode::VmComputeSystemNotFound
+            | ResultCode::VidPartitionDoesNotExist
+            | ResultCode::VidPartitionNameNotFound
+            | ResultCode::WhvGpaRangeNotFound
+            | ResultCode::WhvVpDoesNotExist
+            | ResultCode::ErrorVnetVirtualSwitchNameNotFound
+            | ResultCode::ErrorVsmbSavedStateFileNotFound
+            | ResultCode::HcnNetworkNotFound
+            | ResultCode::HcnEndpointNotFound
+            | ResultCode::HcnLayerNotFound
+            | ResultCode::HcnSwitchNotFound
+            | ResultCode::HcnSubnetNotFound
+            | ResultCode::HcnAdapterNotFound
+            | ResultCode::HcnPortNotFound
+            | ResultCode::HcnPolicyNotFound
+            | ResultCode::HcnVfpPortsettingNotFound
+            | ResultCode::HcnSwitchExtensionNotFound
+            | ResultCode::GcnModuleNotFound
+            | ResultCode::GcnNetadapterNotFound
+            | ResultCode::GcnNetcompartmentNotFound
+            | ResultCode::GcnNetinterfaceNotFound => true,
+            ResultCode::UnknownHResult(hresult) => {
+                hresult == HRESULT_ERROR_PATH_NOT_FOUND || hresult == HRESULT_ERROR_NOT_FOUND
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_access_denied(self) -> bool {
+        match self {
+            ResultCode::HcsAccessDenied
+            | ResultCode::HvAccessDenied
+            | ResultCode::VidVtlAccessDenied => true,
+            ResultCode::UnknownHResult(hresult) => hresult == E_ACCESSDENIED,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the operation is not allowed in the current state of its target,
+    /// such as starting a compute system that already stopped.
+    pub fn is_invalid_state(self) -> bool {
+        matches!(
+            self,
+            ResultCode::HcsInvalidState
+                | ResultCode::HcsSystemAlreadyStopped
+                | ResultCode::HcsOperationAlreadyStarted
+                | ResultCode::HcsProcessAlreadyStopped
+                | ResultCode::VmComputeInvalidState
+                | ResultCode::VmComputeSystemAlreadyStopped
+                | ResultCode::HvInvalidPartitionState
+                | ResultCode::HvOperationDenied
+                | ResultCode::HvInvalidVpState
+                | ResultCode::HvInvalidSynicState
+                | ResultCode::HvInvalidVtlState
+                | ResultCode::HvInvalidDeviceState
+                | ResultCode::HvInvalidCpuGroupState
+                | ResultCode::VidInvalidProcessorState
+                | ResultCode::WhvInvalidVpState
+        )
+    }
+}
+
+impl std::fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (0x{:08X})", self.name(), self.to_hresult())
+    }
+}
+
+impl std::error::Error for ResultCode {}
+
+impl std::convert::From<HResult> for ResultCode {
+    fn from(hresult: HResult) -> Self {
+        ResultCode::from_hresult(hresult)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(overflowing_literals)]
+    fn result_code_classification() {
+        let code = ResultCode::from_hresult(0x8037010E);
+        assert_eq!(code, ResultCode::HcsSystemNotFound);
+        assert_eq!(code.to_hresult(), 0x8037010E);
+        assert_eq!(code.name(), "HCS_E_SYSTEM_NOT_FOUND");
+        assert_eq!(code.to_string(), "HCS_E_SYSTEM_NOT_FOUND (0x8037010E)");
+        assert_eq!(code.facility(), Facility::Hcs);
+        assert_eq!(code.class(), ErrorClass::NotFound);
+
+        assert_eq!(ResultCode::VmComputeTimeout.facility(), Facility::VmCompute);
+        assert_eq!(ResultCode::VidQueueFull.facility(), Facility::Vid);
+        assert_eq!(ResultCode::HvAccessDenied.facility(), Facility::Hypervisor);
+        assert_eq!(ResultCode::GcnModuleNotFound.facility(), Facility::Gcn);
+        assert_eq!(ResultCode::HcnPortNotFound.facility(), Facility::Hcn);
+        assert_eq!(ResultCode::FileNotFound.facility(), Facility::Win32);
+        assert_eq!(ResultCode::Fail.facility(), Facility::Generic);
+
+        assert!(ResultCode::HcsConnectionClosed.is_transient());
+        assert!(ResultCode::from_hresult(0x8037011E).is_service_disconnect());
+        assert!(ResultCode::from_hresult(0x8037011E).is_transient());
+        assert_eq!(
+            ResultCode::from_hresult(0x8037011D),
+            ResultCode::HcsProcessInfoNotAvailable
+        );
+        assert_eq!(
+            ResultCode::from_hresult(0x8037011F).class(),
+            ErrorClass::InvalidState
+        );
+        assert!(ResultCode::from_hresult(0x80070005).is_access_denied());
+        assert_eq!(
+            ResultCode::HcsInvalidState.class(),
+            ErrorClass::InvalidState
+        );
+        assert_eq!(ResultCode::Success.class(), ErrorClass::Success);
+        assert_eq!(ResultCode::HcsInvalidJson.class(), ErrorClass::Other);
+
+        let unknown = ResultCode::from(0x80AB0001);
+        assert_eq!(unknown, ResultCode::UnknownHResult(0x80AB0001));
+        assert_eq!(unknown.facility(), Facility::Other(0xAB));
+        assert_eq!(unknown.name(), "UNKNOWN");
+    }
+}
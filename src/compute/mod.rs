@@ -10,6 +10,7 @@
 
 pub mod defs;
 pub mod errorcodes;
+pub mod trace;
 
 use winutils_rs::windefs::Handle;
 
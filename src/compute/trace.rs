@@ -0,0 +1,529 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Structured tracing of the HCS, HCN and storage API calls, enabled by feature `tracing`.
+//!
+//! Each API call opens an `hcs_api` span that records the name of the API, the ID or handle
+//! it targets, its duration and its result code. JSON documents sent to and returned by
+//! the API are recorded as events of the span, with credentials and environment variables redacted.
+//!
+//! Spans of calls that start an operation are kept until the operation is closed: the operation
+//! ID, type and result are recorded on them once the operation completes, and operation completion
+//! callbacks run inside them. Likewise, compute system and process events are recorded on the span
+//! of the call that set their callback.
+//!
+//! Without the feature, the instrumentation of the API calls compiles down to nothing.
+//! `redact_document` and `redact_value` are also available with feature `schema`, to redact
+//! other records of the API documents.
+
+#[cfg(any(feature = "tracing", feature = "schema"))]
+pub use redaction::{redact_document, redact_value};
+
+#[cfg(feature = "tracing")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::*;
+
+#[cfg(any(feature = "tracing", feature = "schema"))]
+mod redaction {
+    const REDACTED: &str = "<redacted>";
+
+    fn is_sensitive(key: &str) -> bool {
+        let key = key.to_ascii_lowercase();
+        ["password", "secret", "token", "credential", "privatekey"]
+            .iter()
+            .any(|sensitive| key.contains(sensitive))
+    }
+
+    /// Replaces the values of credentials, secrets and environment variables of a JSON document.
+    pub fn redact_value(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if is_sensitive(key) {
+                        *value = serde_json::Value::from(REDACTED);
+                    } else if key == "Environment" && value.is_object() {
+                        for variable in value.as_object_mut().unwrap().values_mut() {
+                            *variable = serde_json::Value::from(REDACTED);
+                        }
+                    } else {
+                        redact_value(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+            _ => {}
+        }
+    }
+
+    /// Returns a JSON document with the values of credentials, secrets and environment variables
+    /// redacted. Documents that are not JSON are redacted entirely.
+    pub fn redact_document(document: &str) -> String {
+        match serde_json::from_str::<serde_json::Value>(document) {
+            Ok(mut value) => {
+                redact_value(&mut value);
+                value.to_string()
+            }
+            Err(_) => format!("<{} bytes of non JSON data>", document.len()),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn redact_credentials_and_environment() {
+            let document = r#"{
+                "ContainerCredentialGuard": {"Spec": "gmsa"},
+                "Process": {
+                    "CommandLine": "cmd.exe",
+                    "Environment": {"PATH": "C:\\", "API_KEY": "abc"}
+                },
+                "Users": [{"Name": "admin", "Password": "hunter2"}]
+            }"#;
+
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&redact_document(document)).unwrap(),
+                serde_json::json!({
+                    "ContainerCredentialGuard": REDACTED,
+                    "Process": {
+                        "CommandLine": "cmd.exe",
+                        "Environment": {"PATH": REDACTED, "API_KEY": REDACTED}
+                    },
+                    "Users": [{"Name": "admin", "Password": REDACTED}]
+                })
+            );
+            assert_eq!(redact_document("C:\\layers"), "<9 bytes of non JSON data>");
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use super::redact_document;
+    use crate::compute::defs::*;
+    use crate::compute::errorcodes::ResultCode;
+    use crate::computenetwork::ErrorResult;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use tracing::field::{debug, display, Empty};
+    use tracing::Span;
+    use winutils_rs::windefs::*;
+
+    /// Spans of the calls that started operations, by operation handle.
+    static OPERATIONS: Mutex<BTreeMap<usize, Span>> = Mutex::new(BTreeMap::new());
+
+    /// Handles and spans of the calls that set event callbacks, by callback context.
+    static CALLBACKS: Mutex<BTreeMap<usize, (usize, Span)>> = Mutex::new(BTreeMap::new());
+
+    #[allow(overflowing_literals)]
+    const HRESULT_ERROR_TIMEOUT: HResult = 0x800705B4;
+
+    fn record_document(span: &Span, message: &str, document: &str) {
+        if !document.is_empty() && tracing::enabled!(tracing::Level::DEBUG) {
+            tracing::debug!(parent: span, document = %redact_document(document), "{}", message);
+        }
+    }
+
+    fn record_handle<H: TracedValue>(span: &Span, handle: &H) {
+        if let Some(handle) = handle.handle() {
+            span.record("handle", display(format_args!("{:#x}", handle)));
+        }
+    }
+
+    fn record_result<R: TracedResult>(span: &Span, result: &R) {
+        match result.result_code() {
+            None => {
+                span.record("result", "S_OK");
+            }
+            Some(result_code) => {
+                span.record("result", display(result_code));
+                tracing::warn!(parent: span, error = %result_code, "failed");
+            }
+        }
+
+        if let Some(error_record) = result.error_record() {
+            record_document(span, "error record", error_record);
+        }
+
+        if let Some(response) = result.response() {
+            record_document(span, "response", response);
+        }
+    }
+
+    /// Values returned by the API calls.
+    pub(crate) trait TracedValue {
+        fn response(&self) -> Option<&str> {
+            None
+        }
+
+        fn handle(&self) -> Option<usize> {
+            None
+        }
+    }
+
+    impl TracedValue for () {}
+    impl TracedValue for u64 {}
+    impl TracedValue for HcsOperationType {}
+    impl TracedValue for HcsProcessInformation {}
+
+    impl TracedValue for String {
+        fn response(&self) -> Option<&str> {
+            Some(self)
+        }
+    }
+
+    impl<T> TracedValue for *mut T {
+        fn handle(&self) -> Option<usize> {
+            Some(*self as usize)
+        }
+    }
+
+    impl<T> TracedValue for *const T {
+        fn handle(&self) -> Option<usize> {
+            Some(*self as usize)
+        }
+    }
+
+    /// Results of the API calls.
+    pub(crate) trait TracedResult {
+        fn result_code(&self) -> Option<ResultCode>;
+
+        fn response(&self) -> Option<&str>;
+
+        fn handle(&self) -> Option<usize> {
+            None
+        }
+
+        fn error_record(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    impl<T: TracedValue> TracedResult for Result<T, ResultCode> {
+        fn result_code(&self) -> Option<ResultCode> {
+            self.as_ref().err().copied()
+        }
+
+        fn response(&self) -> Option<&str> {
+            self.as_ref().ok().and_then(TracedValue::response)
+        }
+
+        fn handle(&self) -> Option<usize> {
+            self.as_ref().ok().and_then(TracedValue::handle)
+        }
+    }
+
+    impl<T: TracedValue> TracedResult for Result<T, ErrorResult> {
+        fn result_code(&self) -> Option<ResultCode> {
+            self.as_ref().err().map(|error| error.result_code)
+        }
+
+        fn response(&self) -> Option<&str> {
+            self.as_ref().ok().and_then(TracedValue::response)
+        }
+
+        fn handle(&self) -> Option<usize> {
+            self.as_ref().ok().and_then(TracedValue::handle)
+        }
+
+        fn error_record(&self) -> Option<&str> {
+            self.as_ref().err().map(|error| error.error_record.as_str())
+        }
+    }
+
+    impl<T> TracedResult for (String, Result<T, ResultCode>) {
+        fn result_code(&self) -> Option<ResultCode> {
+            self.1.as_ref().err().copied()
+        }
+
+        fn response(&self) -> Option<&str> {
+            Some(&self.0)
+        }
+    }
+
+    /// Span of an API call, from before the call until its result is known.
+    pub(crate) struct ApiCall {
+        span: Span,
+        start: Instant,
+        operation: Option<usize>,
+        callback: Option<usize>,
+        closed_handle: Option<usize>,
+    }
+
+    impl ApiCall {
+        pub(crate) fn new(api: &'static str) -> ApiCall {
+            ApiCall {
+                span: tracing::debug_span!(
+                    "hcs_api",
+                    api,
+                    target = Empty,
+                    handle = Empty,
+                    operation_id = Empty,
+                    operation_type = Empty,
+                    duration_us = Empty,
+                    result = Empty,
+                ),
+                start: Instant::now(),
+                operation: None,
+                callback: None,
+                closed_handle: None,
+            }
+        }
+
+        /// Records the ID or path of the object targeted by the call.
+        pub(crate) fn target(self, target: &str) -> Self {
+            self.span.record("target", target);
+            self
+        }
+
+        /// Records the ID of the network object targeted by the call.
+        pub(crate) fn guid(self, id: &Guid) -> Self {
+            self.span.record(
+                "target",
+                display(format_args!(
+                    "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+                    id.Data1,
+                    id.Data2,
+                    id.Data3,
+                    id.Data4[0],
+                    id.Data4[1],
+                    id.Data4[2],
+                    id.Data4[3],
+                    id.Data4[4],
+                    id.Data4[5],
+                    id.Data4[6],
+                    id.Data4[7]
+                )),
+            );
+            self
+        }
+
+        /// Records the handle of the object targeted by the call.
+        pub(crate) fn handle<H: TracedValue>(self, handle: H) -> Self {
+            record_handle(&self.span, &handle);
+            self
+        }
+
+        /// Records a JSON document sent to the API.
+        pub(crate) fn request(self, document: &str) -> Self {
+            record_document(&self.span, "request", document);
+            self
+        }
+
+        /// Keeps the span to record the completion of the operation started by the call.
+        /// It's kept before making the call, since the operation can complete before it returns.
+        pub(crate) fn operation(mut self, operation: HcsOperationHandle) -> Self {
+            OPERATIONS
+                .lock()
+                .unwrap()
+                .insert(operation as usize, self.span.clone());
+            self.operation = Some(operation as usize);
+            self
+        }
+
+        /// Keeps the span to record the events received by the callback set by the call.
+        pub(crate) fn callback<H: TracedValue>(mut self, handle: H, context: *mut Void) -> Self {
+            let handle_value = handle.handle().unwrap_or_default();
+            CALLBACKS
+                .lock()
+                .unwrap()
+                .insert(context as usize, (handle_value, self.span.clone()));
+            self.callback = Some(context as usize);
+            self.handle(handle)
+        }
+
+        /// Drops the spans kept for an operation or for the callbacks of a handle,
+        /// closed by the call.
+        pub(crate) fn close<H: TracedValue>(mut self, handle: H) -> Self {
+            self.closed_handle = handle.handle();
+            self.handle(handle)
+        }
+
+        /// Records the result of the call, returning it back.
+        pub(crate) fn finish<R: TracedResult>(self, result: R) -> R {
+            self.span
+                .record("duration_us", self.start.elapsed().as_micros() as u64);
+
+            if let Some(handle) = result.handle() {
+                self.span
+                    .record("handle", display(format_args!("{:#x}", handle)));
+            }
+
+            record_result(&self.span, &result);
+
+            if result.result_code().is_some() {
+                if let Some(operation) = self.operation {
+                    OPERATIONS.lock().unwrap().remove(&operation);
+                }
+
+                if let Some(context) = self.callback {
+                    CALLBACKS.lock().unwrap().remove(&context);
+                }
+            }
+
+            if let Some(handle) = self.closed_handle {
+                OPERATIONS.lock().unwrap().remove(&handle);
+                CALLBACKS
+                    .lock()
+                    .unwrap()
+                    .retain(|_, (callback_handle, _)| *callback_handle != handle);
+            }
+
+            result
+        }
+    }
+
+    /// Records the completion of an operation on the span of the call that started it.
+    pub(crate) fn complete_operation<R, I, T>(
+        operation: HcsOperationHandle,
+        operation_id: I,
+        operation_type: T,
+        result: &R,
+    ) where
+        R: TracedResult,
+        I: FnOnce() -> u64,
+        T: FnOnce() -> HcsOperationType,
+    {
+        // Results of operations that didn't complete yet, or of waits that timed out
+        if let Some(result_code) = result.result_code() {
+            if matches!(
+                result_code,
+                ResultCode::HcsOperationPending | ResultCode::VmComputeOperationPending
+            ) || result_code.to_hresult() == HRESULT_ERROR_TIMEOUT
+            {
+                return;
+            }
+        }
+
+        let span = match OPERATIONS.lock().unwrap().get(&(operation as usize)) {
+            Some(span) => span.clone(),
+            None => return,
+        };
+
+        span.record("operation_id", operation_id());
+        span.record("operation_type", debug(operation_type()));
+        tracing::debug!(parent: &span, "operation completed");
+        record_result(&span, result);
+    }
+
+    pub(crate) type Entered = tracing::span::EnteredSpan;
+
+    /// Enters the span of the call that started an operation, if any.
+    pub(crate) fn enter_operation(operation: HcsOperationHandle) -> Entered {
+        OPERATIONS
+            .lock()
+            .unwrap()
+            .get(&(operation as usize))
+            .cloned()
+            .unwrap_or_else(Span::none)
+            .entered()
+    }
+
+    /// Records an event received by a callback on the span of the call that set it.
+    pub(crate) fn callback_event(context: *mut Void, event: &HcsEvent) {
+        let span = match CALLBACKS.lock().unwrap().get(&(context as usize)) {
+            Some((_, span)) => span.clone(),
+            None => return,
+        };
+
+        let event_data = match event.event_data.is_null() {
+            true => String::new(),
+            false => {
+                unsafe { widestring::U16CStr::from_ptr_str(event.event_data) }.to_string_lossy()
+            }
+        };
+
+        tracing::debug!(
+            parent: &span,
+            event_type = ?event.event_type,
+            event_data = %redact_document(&event_data),
+            "event"
+        );
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use crate::compute::defs::*;
+    use winutils_rs::windefs::*;
+
+    pub(crate) struct ApiCall;
+
+    impl ApiCall {
+        #[inline(always)]
+        pub(crate) fn new(_api: &'static str) -> ApiCall {
+            ApiCall
+        }
+
+        #[inline(always)]
+        pub(crate) fn target(self, _target: &str) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn guid(self, _id: &Guid) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn handle<H>(self, _handle: H) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn request(self, _document: &str) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn operation(self, _operation: HcsOperationHandle) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn callback<H>(self, _handle: H, _context: *mut Void) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn close<H>(self, _handle: H) -> Self {
+            self
+        }
+
+        #[inline(always)]
+        pub(crate) fn finish<R>(self, result: R) -> R {
+            result
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn complete_operation<R, I, T>(
+        _operation: HcsOperationHandle,
+        _operation_id: I,
+        _operation_type: T,
+        _result: &R,
+    ) where
+        I: FnOnce() -> u64,
+        T: FnOnce() -> HcsOperationType,
+    {
+    }
+
+    pub(crate) struct Entered;
+
+    #[inline(always)]
+    pub(crate) fn enter_operation(_operation: HcsOperationHandle) -> Entered {
+        Entered
+    }
+
+    #[inline(always)]
+    pub(crate) fn callback_event(_context: *mut Void, _event: &HcsEvent) {}
+}
@@ -0,0 +1,362 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Runs a process to completion in a compute system, capturing its exit code and output.
+//! The logic is written against an abstract process, so that it can be exercised
+//! without an actual compute system.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::schema::responses::system::ProcessStatus;
+use crate::HcsResult;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+/// Callback invoked with each chunk of output read from a process std pipe.
+pub type ExecOutputCallback = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Std pipes of a process, in stdin, stdout and stderr order.
+pub type ExecPipes<W, R> = (Option<W>, Option<R>, Option<R>);
+
+/// Process running in a compute system, as driven by `exec_process`.
+pub trait HcsExecProcess {
+    type Stdin: Write + Send + 'static;
+    type Output: Read + Send + 'static;
+
+    /// Takes ownership of the std pipes of the process.
+    fn take_pipes(&mut self) -> ExecPipes<Self::Stdin, Self::Output>;
+
+    /// Waits for the process to exit, for at most `timeout` if set.
+    /// Returns true if the process exited.
+    fn wait_for_exit(&mut self, timeout: Option<Duration>) -> HcsResult<bool>;
+
+    /// Queries the status of the process.
+    fn status(&self) -> HcsResult<ProcessStatus>;
+
+    /// Terminates the process.
+    fn terminate(&self) -> HcsResult<()>;
+}
+
+/// Options that control how `exec_process` runs a process.
+pub struct ExecOptions {
+    /// Maximum amount of time the process is allowed to run, if set.
+    pub timeout: Option<Duration>,
+
+    /// If set, the process is terminated when the timeout elapses.
+    /// Otherwise, the process is left running and `exec_process` fails.
+    pub kill_on_timeout: bool,
+
+    /// Data written to the stdin of the process, which is closed afterwards.
+    pub stdin: Option<Vec<u8>>,
+
+    /// Called with each chunk of stdout as it's read, on a separate thread.
+    pub on_stdout: Option<ExecOutputCallback>,
+
+    /// Called with each chunk of stderr as it's read, on a separate thread.
+    pub on_stderr: Option<ExecOutputCallback>,
+}
+
+impl std::default::Default for ExecOptions {
+    fn default() -> Self {
+        ExecOptions {
+            timeout: None,
+            kill_on_timeout: true,
+            stdin: None,
+            on_stdout: None,
+            on_stderr: None,
+        }
+    }
+}
+
+impl ExecOptions {
+    pub fn new() -> ExecOptions {
+        ExecOptions::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> ExecOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn kill_on_timeout(mut self, kill_on_timeout: bool) -> ExecOptions {
+        self.kill_on_timeout = kill_on_timeout;
+        self
+    }
+
+    pub fn stdin<D: Into<Vec<u8>>>(mut self, data: D) -> ExecOptions {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    pub fn on_stdout<F>(mut self, callback: F) -> ExecOptions
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.on_stdout = Some(Box::new(callback));
+        self
+    }
+
+    pub fn on_stderr<F>(mut self, callback: F) -> ExecOptions
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.on_stderr = Some(Box::new(callback));
+        self
+    }
+}
+
+/// Result of running a process to completion.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExecOutput {
+    pub exit_code: u32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+
+    /// True if the process was terminated because it ran past the timeout.
+    pub timed_out: bool,
+}
+
+impl ExecOutput {
+    /// Returns true if the process exited on its own with exit code 0.
+    pub fn success(&self) -> bool {
+        self.exit_code == 0 && !self.timed_out
+    }
+}
+
+fn spawn_output_reader<R>(
+    mut output: R,
+    mut callback: Option<ExecOutputCallback>,
+) -> std::thread::JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        loop {
+            match output.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    if let Some(callback) = callback.as_mut() {
+                        callback(&chunk[..read]);
+                    }
+                    captured.extend_from_slice(&chunk[..read]);
+                }
+                Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+
+        captured
+    })
+}
+
+/// Runs a process to completion, feeding its stdin and capturing its output and exit code.
+pub fn exec_process<P: HcsExecProcess>(
+    mut process: P,
+    options: ExecOptions,
+) -> HcsResult<ExecOutput> {
+    let ExecOptions {
+        timeout,
+        kill_on_timeout,
+        stdin: stdin_data,
+        on_stdout,
+        on_stderr,
+    } = options;
+
+    let (stdin, stdout, stderr) = process.take_pipes();
+
+    let stdin_writer = match (stdin, stdin_data) {
+        (Some(mut stdin), Some(data)) => Some(std::thread::spawn(move || {
+            // The process might exit without reading all of its input, so failures are ignored.
+            // The pipe is closed once dropped, signaling end of file to the process.
+            let _ = stdin.write_all(&data).and_then(|_| stdin.flush());
+        })),
+        // Without data to write, the pipe is dropped right away to close it
+        _ => None,
+    };
+
+    let stdout_reader = stdout.map(|stdout| spawn_output_reader(stdout, on_stdout));
+    let stderr_reader = stderr.map(|stderr| spawn_output_reader(stderr, on_stderr));
+
+    let mut timed_out = false;
+
+    if !process.wait_for_exit(timeout)? {
+        if !kill_on_timeout {
+            // The pipes are still in use by the running process, so the
+            // readers are left to finish on their own
+            return Err(ResultCode::HcsOperationTimeout);
+        }
+
+        timed_out = true;
+        process.terminate()?;
+        process.wait_for_exit(None)?;
+    }
+
+    let status = process.status()?;
+
+    if let Some(stdin_writer) = stdin_writer {
+        let _ = stdin_writer.join();
+    }
+
+    let join_reader = |reader: Option<std::thread::JoinHandle<Vec<u8>>>| match reader {
+        Some(reader) => reader.join().unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    Ok(ExecOutput {
+        exit_code: status.exit_code,
+        stdout: join_reader(stdout_reader),
+        stderr: join_reader(stderr_reader),
+        timed_out,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FakeProcess {
+        stdin: Option<SharedBuffer>,
+        stdout: Option<Cursor<Vec<u8>>>,
+        stderr: Option<Cursor<Vec<u8>>>,
+        hangs: bool,
+        terminated: Arc<AtomicBool>,
+        exit_code: u32,
+    }
+
+    impl FakeProcess {
+        fn new(stdout: &[u8], stderr: &[u8], exit_code: u32) -> FakeProcess {
+            FakeProcess {
+                stdin: Some(SharedBuffer::default()),
+                stdout: Some(Cursor::new(stdout.to_vec())),
+                stderr: Some(Cursor::new(stderr.to_vec())),
+                hangs: false,
+                terminated: Arc::new(AtomicBool::new(false)),
+                exit_code,
+            }
+        }
+
+        fn exited(&self) -> bool {
+            !self.hangs || self.terminated.load(Ordering::SeqCst)
+        }
+    }
+
+    impl HcsExecProcess for FakeProcess {
+        type Stdin = SharedBuffer;
+        type Output = Cursor<Vec<u8>>;
+
+        fn take_pipes(&mut self) -> ExecPipes<SharedBuffer, Cursor<Vec<u8>>> {
+            (self.stdin.take(), self.stdout.take(), self.stderr.take())
+        }
+
+        fn wait_for_exit(&mut self, timeout: Option<Duration>) -> HcsResult<bool> {
+            if !self.exited() {
+                std::thread::sleep(timeout.expect("Fake process would hang forever"));
+            }
+            Ok(self.exited())
+        }
+
+        fn status(&self) -> HcsResult<ProcessStatus> {
+            Ok(ProcessStatus {
+                process_id: 4,
+                exited: self.exited(),
+                exit_code: if self.terminated.load(Ordering::SeqCst) {
+                    1
+                } else {
+                    self.exit_code
+                },
+                last_wait_result: 0,
+            })
+        }
+
+        fn terminate(&self) -> HcsResult<()> {
+            self.terminated.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn exec_captures_output() {
+        let process = FakeProcess::new(b"hello", b"world", 3);
+        let stdin = process.stdin.clone().unwrap();
+        let streamed = Arc::new(Mutex::new(Vec::new()));
+        let streamed_clone = streamed.clone();
+
+        let output = exec_process(
+            process,
+            ExecOptions::new()
+                .stdin("input")
+                .on_stdout(move |chunk| streamed_clone.lock().unwrap().extend_from_slice(chunk)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            ExecOutput {
+                exit_code: 3,
+                stdout: b"hello".to_vec(),
+                stderr: b"world".to_vec(),
+                timed_out: false,
+            }
+        );
+        assert!(!output.success());
+        assert_eq!(&*streamed.lock().unwrap(), b"hello");
+        assert_eq!(&*stdin.0.lock().unwrap(), b"input");
+    }
+
+    #[test]
+    fn exec_kills_on_timeout() {
+        let mut process = FakeProcess::new(b"partial", b"", 0);
+        process.hangs = true;
+        let terminated = process.terminated.clone();
+
+        let output = exec_process(
+            process,
+            ExecOptions::new().timeout(Duration::from_millis(10)),
+        )
+        .unwrap();
+
+        assert!(terminated.load(Ordering::SeqCst));
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, 1);
+        assert_eq!(output.stdout, b"partial");
+
+        let mut process = FakeProcess::new(b"", b"", 0);
+        process.hangs = true;
+        let terminated = process.terminated.clone();
+
+        assert_eq!(
+            exec_process(
+                process,
+                ExecOptions::new()
+                    .timeout(Duration::from_millis(10))
+                    .kill_on_timeout(false),
+            ),
+            Err(ResultCode::HcsOperationTimeout)
+        );
+        assert!(!terminated.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,561 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Client side lifecycle tracking of compute systems.
+//!
+//! `ManagedSystem` keeps track of the state of a compute system out of the results of the
+//! operations done through it and the events notified by HCS, so that operations that are
+//! not valid in the current state are rejected before reaching HCS.
+
+use crate::compute::defs::{HcsEventType, HcsNotifications};
+use crate::compute::errorcodes::ResultCode;
+use crate::computecore::reconnect::{EventHandler, HcsReconnectBackend};
+use crate::schema::options::{PauseOptions, SaveOptions, SaveType};
+use crate::schema::responses::system::State;
+use crate::schema::ComputeSystem;
+use crate::HcsResult;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Compute system operations needed to manage the lifecycle of compute systems,
+/// on top of the ones needed to reconnect to them.
+pub trait HcsLifecycleBackend: HcsReconnectBackend {
+    /// Requests the guest to shut down, waiting up to `timeout` for it to stop.
+    fn shutdown_system(&self, system: &Self::System, timeout: Duration) -> HcsResult<()>;
+}
+
+/// Lifecycle operations on a compute system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LifecycleOperation {
+    Start,
+    Pause,
+    Resume,
+    Save,
+    Shutdown,
+    Terminate,
+}
+
+impl LifecycleOperation {
+    /// Returns true if the operation is valid in the given state.
+    pub fn is_valid_in(self, state: &State) -> bool {
+        match self {
+            LifecycleOperation::Start => *state == State::Created,
+            LifecycleOperation::Pause => *state == State::Running,
+            LifecycleOperation::Resume => *state == State::Paused,
+            LifecycleOperation::Save => *state == State::Paused,
+            LifecycleOperation::Shutdown => *state == State::Running,
+            LifecycleOperation::Terminate => *state != State::Stopped,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LifecycleError {
+    /// The operation is not valid in the current state of the compute system.
+    InvalidTransition {
+        operation: LifecycleOperation,
+        state: State,
+    },
+
+    /// The guest initiated a crash, and only terminating the compute system is allowed.
+    CrashInProgress(LifecycleOperation),
+
+    /// HCS failed the operation.
+    Hcs(ResultCode),
+}
+
+impl std::fmt::Display for LifecycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LifecycleError::InvalidTransition { operation, state } => write!(
+                f,
+                "cannot {:?} a compute system in state {:?}",
+                operation, state
+            ),
+            LifecycleError::CrashInProgress(operation) => write!(
+                f,
+                "cannot {:?} a compute system with a crash in progress",
+                operation
+            ),
+            LifecycleError::Hcs(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for LifecycleError {}
+
+impl std::convert::From<ResultCode> for LifecycleError {
+    fn from(error: ResultCode) -> Self {
+        LifecycleError::Hcs(error)
+    }
+}
+
+pub type LifecycleResult<T> = Result<T, LifecycleError>;
+
+/// What caused a change of state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChangeCause {
+    Operation(LifecycleOperation),
+    Event(HcsEventType),
+    Notification(HcsNotifications),
+
+    /// The state was queried from HCS.
+    Refresh,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateChange {
+    pub previous: State,
+    pub current: State,
+    pub cause: StateChangeCause,
+}
+
+/// How a compute system was stopped by `ManagedSystem::stop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOutcome {
+    AlreadyStopped,
+    ShutDown,
+    Terminated,
+}
+
+#[derive(Default)]
+struct Lifecycle {
+    state: State,
+    crash_initiated: bool,
+    watchers: Vec<Sender<StateChange>>,
+}
+
+impl Lifecycle {
+    fn transition(&mut self, current: State, cause: StateChangeCause) {
+        if self.state == current {
+            return;
+        }
+
+        let change = StateChange {
+            previous: std::mem::replace(&mut self.state, current.clone()),
+            current,
+            cause,
+        };
+
+        if change.current == State::Stopped {
+            self.crash_initiated = false;
+        }
+
+        self.watchers
+            .retain(|watcher| watcher.send(change.clone()).is_ok());
+    }
+}
+
+/// Compute system that tracks its lifecycle state on the client side.
+pub struct ManagedSystem<B: HcsLifecycleBackend> {
+    backend: B,
+    id: String,
+    system: B::System,
+    lifecycle: Arc<Mutex<Lifecycle>>,
+}
+
+impl<B: HcsLifecycleBackend> ManagedSystem<B> {
+    fn new(backend: B, id: &str, system: B::System, state: State) -> LifecycleResult<Self> {
+        let mut managed = ManagedSystem {
+            backend,
+            id: String::from(id),
+            system,
+            lifecycle: Arc::new(Mutex::new(Lifecycle {
+                state,
+                ..Default::default()
+            })),
+        };
+
+        let handler = managed.event_handler();
+        managed
+            .backend
+            .set_system_callback(&mut managed.system, handler)?;
+        Ok(managed)
+    }
+
+    /// Creates a compute system, in the `Created` state.
+    pub fn create(backend: B, id: &str, document: &ComputeSystem) -> LifecycleResult<Self> {
+        let system = backend.create_system(id, document)?;
+        ManagedSystem::new(backend, id, system, State::Created)
+    }
+
+    /// Opens an existing compute system, querying its current state.
+    pub fn open(backend: B, id: &str) -> LifecycleResult<Self> {
+        let system = backend.open_system(id)?;
+        let state = backend.system_state(&system)?;
+        ManagedSystem::new(backend, id, system, state)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn system(&self) -> &B::System {
+        &self.system
+    }
+
+    /// Returns the tracked state of the compute system.
+    pub fn state(&self) -> State {
+        self.lifecycle.lock().unwrap().state.clone()
+    }
+
+    /// Returns true if the guest initiated a crash and the compute system hasn't stopped yet.
+    pub fn is_crash_initiated(&self) -> bool {
+        self.lifecycle.lock().unwrap().crash_initiated
+    }
+
+    /// Returns a receiver of the state changes of the compute system from now on.
+    pub fn watch(&self) -> Receiver<StateChange> {
+        let (sender, receiver) = channel();
+        self.lifecycle.lock().unwrap().watchers.push(sender);
+        receiver
+    }
+
+    /// Returns an event handler that updates the tracked state out of compute system events.
+    /// It's registered when the managed system is created or opened, and can be registered
+    /// again on a handle to the same compute system, such as after a reconnect.
+    pub fn event_handler(&self) -> EventHandler {
+        let lifecycle = self.lifecycle.clone();
+        Arc::new(move |event_type, _event_data| {
+            let mut lifecycle = lifecycle.lock().unwrap();
+            match event_type {
+                HcsEventType::SystemExited => {
+                    lifecycle.transition(State::Stopped, StateChangeCause::Event(event_type))
+                }
+                HcsEventType::SystemCrashInitiated => lifecycle.crash_initiated = true,
+                _ => {}
+            }
+        })
+    }
+
+    /// Updates the tracked state out of a notification, for callbacks registered
+    /// through the notification APIs used before Windows 1809.
+    pub fn handle_notification(&self, notification: HcsNotifications) {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        let cause = StateChangeCause::Notification(notification);
+        match notification {
+            HcsNotifications::SystemExited => lifecycle.transition(State::Stopped, cause),
+            HcsNotifications::SystemStartCompleted | HcsNotifications::SystemResumeCompleted => {
+                lifecycle.transition(State::Running, cause)
+            }
+            HcsNotifications::SystemPauseCompleted => lifecycle.transition(State::Paused, cause),
+            HcsNotifications::SystemCrashInitiated => lifecycle.crash_initiated = true,
+            _ => {}
+        }
+    }
+
+    /// Queries the state of the compute system from HCS, updating the tracked one.
+    pub fn refresh(&self) -> LifecycleResult<State> {
+        let state = self.backend.system_state(&self.system)?;
+        self.lifecycle
+            .lock()
+            .unwrap()
+            .transition(state.clone(), StateChangeCause::Refresh);
+        Ok(state)
+    }
+
+    fn check(&self, operation: LifecycleOperation) -> LifecycleResult<()> {
+        let lifecycle = self.lifecycle.lock().unwrap();
+
+        if lifecycle.crash_initiated && operation != LifecycleOperation::Terminate {
+            return Err(LifecycleError::CrashInProgress(operation));
+        }
+
+        if !operation.is_valid_in(&lifecycle.state) {
+            return Err(LifecycleError::InvalidTransition {
+                operation,
+                state: lifecycle.state.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Runs an operation after validating it, and applies its resulting state.
+    fn run<F>(&self, operation: LifecycleOperation, next: State, call: F) -> LifecycleResult<()>
+    where
+        F: FnOnce(&B, &B::System) -> HcsResult<()>,
+    {
+        self.check(operation)?;
+        let cause = StateChangeCause::Operation(operation);
+
+        match call(&self.backend, &self.system) {
+            Ok(()) => {
+                self.lifecycle.lock().unwrap().transition(next, cause);
+                Ok(())
+            }
+            Err(error) => {
+                // Only errors about the compute system itself mean it stopped, files or
+                // paths that weren't found leave it as it was
+                if matches!(
+                    error,
+                    ResultCode::HcsSystemNotFound
+                        | ResultCode::VmComputeSystemNotFound
+                        | ResultCode::HcsSystemAlreadyStopped
+                        | ResultCode::VmComputeSystemAlreadyStopped
+                ) {
+                    self.lifecycle
+                        .lock()
+                        .unwrap()
+                        .transition(State::Stopped, cause);
+                }
+                Err(LifecycleError::Hcs(error))
+            }
+        }
+    }
+
+    pub fn start(&self) -> LifecycleResult<()> {
+        self.run(
+            LifecycleOperation::Start,
+            State::Running,
+            |backend, system| backend.start_system(system),
+        )
+    }
+
+    pub fn pause(&self, options: &PauseOptions) -> LifecycleResult<()> {
+        self.run(
+            LifecycleOperation::Pause,
+            State::Paused,
+            |backend, system| backend.pause_system(system, options),
+        )
+    }
+
+    pub fn resume(&self) -> LifecycleResult<()> {
+        self.run(
+            LifecycleOperation::Resume,
+            State::Running,
+            |backend, system| backend.resume_system(system),
+        )
+    }
+
+    /// Saves the paused compute system. Saving it as a template leaves it
+    /// in the `SavedAsTemplate` state.
+    pub fn save(&self, options: &SaveOptions) -> LifecycleResult<()> {
+        let next = if options.save_type == Some(SaveType::AsTemplate) {
+            State::SavedAsTemplate
+        } else {
+            State::Paused
+        };
+
+        self.run(LifecycleOperation::Save, next, |backend, system| {
+            backend.save_system(system, options)
+        })
+    }
+
+    /// Requests the guest to shut down, waiting up to `timeout` for it to stop.
+    pub fn shutdown(&self, timeout: Duration) -> LifecycleResult<()> {
+        self.run(
+            LifecycleOperation::Shutdown,
+            State::Stopped,
+            |backend, system| backend.shutdown_system(system, timeout),
+        )
+    }
+
+    pub fn terminate(&self) -> LifecycleResult<()> {
+        self.run(
+            LifecycleOperation::Terminate,
+            State::Stopped,
+            |backend, system| backend.terminate_system(system),
+        )
+    }
+
+    /// Stops the compute system, giving the guest up to `grace` to shut down when running.
+    /// Falls back to terminating it if the shutdown fails or times out.
+    pub fn stop(&self, grace: Duration) -> LifecycleResult<StopOutcome> {
+        if self.state() == State::Stopped {
+            return Ok(StopOutcome::AlreadyStopped);
+        }
+
+        if self.check(LifecycleOperation::Shutdown).is_ok() && self.shutdown(grace).is_ok() {
+            return Ok(StopOutcome::ShutDown);
+        }
+
+        // The shutdown might have found the compute system already stopped
+        if self.state() == State::Stopped {
+            return Ok(StopOutcome::AlreadyStopped);
+        }
+
+        match self.terminate() {
+            Ok(()) => Ok(StopOutcome::Terminated),
+            Err(_) if self.state() == State::Stopped => Ok(StopOutcome::AlreadyStopped),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computecore::testing::FakeBackend;
+
+    /// Returns a fake backend with a paused compute system "uvm".
+    fn paused_backend() -> FakeBackend {
+        let backend = FakeBackend::default();
+        backend.insert("uvm", "", State::Paused);
+        backend
+    }
+
+    #[test]
+    fn lifecycle_transitions() {
+        let managed =
+            ManagedSystem::create(FakeBackend::default(), "uvm", &ComputeSystem::default())
+                .unwrap();
+        let changes = managed.watch();
+
+        assert_eq!(
+            managed.resume(),
+            Err(LifecycleError::InvalidTransition {
+                operation: LifecycleOperation::Resume,
+                state: State::Created,
+            })
+        );
+        managed.start().unwrap();
+        managed.pause(&PauseOptions::default()).unwrap();
+        assert_eq!(
+            managed
+                .pause(&PauseOptions::default())
+                .unwrap_err()
+                .to_string(),
+            "cannot Pause a compute system in state Paused"
+        );
+        managed
+            .save(&SaveOptions {
+                save_type: Some(SaveType::AsTemplate),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(managed.state(), State::SavedAsTemplate);
+        managed.terminate().unwrap();
+
+        assert_eq!(
+            managed.backend().take_operations(),
+            vec![
+                "create uvm",
+                "start uvm",
+                "pause uvm",
+                "save uvm",
+                "terminate uvm"
+            ]
+        );
+        assert_eq!(
+            changes.try_iter().collect::<Vec<_>>(),
+            vec![
+                StateChange {
+                    previous: State::Created,
+                    current: State::Running,
+                    cause: StateChangeCause::Operation(LifecycleOperation::Start),
+                },
+                StateChange {
+                    previous: State::Running,
+                    current: State::Paused,
+                    cause: StateChangeCause::Operation(LifecycleOperation::Pause),
+                },
+                StateChange {
+                    previous: State::Paused,
+                    current: State::SavedAsTemplate,
+                    cause: StateChangeCause::Operation(LifecycleOperation::Save),
+                },
+                StateChange {
+                    previous: State::SavedAsTemplate,
+                    current: State::Stopped,
+                    cause: StateChangeCause::Operation(LifecycleOperation::Terminate),
+                },
+            ]
+        );
+        assert_eq!(
+            managed.stop(Duration::from_secs(1)),
+            Ok(StopOutcome::AlreadyStopped)
+        );
+    }
+
+    #[test]
+    fn lifecycle_events_and_stop() {
+        let managed = ManagedSystem::open(paused_backend(), "uvm").unwrap();
+        assert_eq!(managed.state(), State::Paused);
+
+        managed.resume().unwrap();
+        managed
+            .backend()
+            .notify("uvm", HcsEventType::SystemCrashInitiated, "");
+        assert_eq!(
+            managed.pause(&PauseOptions::default()),
+            Err(LifecycleError::CrashInProgress(LifecycleOperation::Pause))
+        );
+
+        // A crashing system can't shut down gracefully
+        assert_eq!(
+            managed.stop(Duration::from_secs(1)),
+            Ok(StopOutcome::Terminated)
+        );
+        assert!(!managed.is_crash_initiated());
+
+        let managed = ManagedSystem::open(paused_backend(), "uvm").unwrap();
+        managed.resume().unwrap();
+        managed
+            .backend()
+            .script("shutdown", Err(ResultCode::HcsOperationTimeout));
+        assert_eq!(
+            managed.stop(Duration::from_secs(1)),
+            Ok(StopOutcome::Terminated)
+        );
+        assert_eq!(
+            managed.backend().take_operations(),
+            vec![
+                "open uvm",
+                "state uvm",
+                "resume uvm",
+                "shutdown uvm",
+                "terminate uvm"
+            ]
+        );
+
+        let managed = ManagedSystem::open(paused_backend(), "uvm").unwrap();
+        managed.resume().unwrap();
+        assert_eq!(
+            managed.stop(Duration::from_secs(1)),
+            Ok(StopOutcome::ShutDown)
+        );
+
+        let managed = ManagedSystem::open(paused_backend(), "uvm").unwrap();
+        managed
+            .backend()
+            .script("resume", Err(ResultCode::HcsSystemAlreadyStopped));
+        assert_eq!(
+            managed.resume(),
+            Err(LifecycleError::Hcs(ResultCode::HcsSystemAlreadyStopped))
+        );
+        assert_eq!(managed.state(), State::Stopped);
+
+        let managed = ManagedSystem::open(paused_backend(), "uvm").unwrap();
+        managed
+            .backend()
+            .script("save", Err(ResultCode::FileNotFound));
+        assert_eq!(
+            managed.save(&SaveOptions::default()),
+            Err(LifecycleError::Hcs(ResultCode::FileNotFound))
+        );
+        assert_eq!(managed.state(), State::Paused);
+
+        let managed = ManagedSystem::open(paused_backend(), "uvm").unwrap();
+        let changes = managed.watch();
+        managed
+            .backend()
+            .notify("uvm", HcsEventType::SystemExited, "");
+        assert_eq!(
+            changes.try_recv().unwrap().cause,
+            StateChangeCause::Event(HcsEventType::SystemExited)
+        );
+        managed.handle_notification(HcsNotifications::SystemStartCompleted);
+        assert_eq!(managed.state(), State::Running);
+    }
+}
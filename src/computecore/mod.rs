@@ -14,17 +14,59 @@ pub(crate) mod bindings;
 #[cfg(feature = "bindings")]
 pub mod bindings;
 
+#[cfg(feature = "utilities")]
+pub mod exec;
+
+#[cfg(feature = "utilities")]
+pub mod lifecycle;
+
+#[cfg(feature = "utilities")]
+pub mod processio;
+
+#[cfg(feature = "utilities")]
+pub mod reconciler;
+
+#[cfg(feature = "utilities")]
+pub mod reconnect;
+
+#[cfg(feature = "utilities")]
+pub mod replay;
+
+#[cfg(feature = "utilities")]
+pub mod snapshot;
+
+#[cfg(all(test, feature = "utilities"))]
+mod testing;
+
 #[cfg(feature = "utilities")]
 pub mod utilities;
 
+#[cfg(feature = "utilities")]
+pub mod warmpool;
+
 use crate::compute::defs::*;
 use crate::compute::errorcodes::{hresult_to_result_code, ResultCode};
+use crate::compute::trace::{self, ApiCall};
 use crate::computecore::bindings::*;
 use crate::HcsResult;
 use widestring::WideCString;
 use winutils_rs::utilities::LocalWString;
 use winutils_rs::windefs::*;
 
+/// Records the result of an operation on the span of the API call that started it.
+fn complete_operation<T>(
+    operation: HcsOperationHandle,
+    result: (String, HcsResult<T>),
+) -> (String, HcsResult<T>) {
+    trace::complete_operation(
+        operation,
+        || unsafe { HcsGetOperationId(operation) },
+        || unsafe { HcsGetOperationType(operation) },
+        &result,
+    );
+    result
+}
+
 /// Enumerates all compute systems visible to the caller.
 pub fn enumerate_compute_systems(
     operation: HcsOperationHandle,
@@ -35,7 +77,11 @@ pub fn enumerate_compute_systems(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsEnumerateComputeSystems")
+        .request(query_str)
+        .operation(operation);
+
+    call.finish(unsafe {
         match HcsEnumerateComputeSystems(
             WideCString::from_str(query_str).unwrap().as_ptr(),
             operation,
@@ -43,7 +89,7 @@ pub fn enumerate_compute_systems(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Creates an operation, used to track an HCS API call.
@@ -51,75 +97,91 @@ pub fn create_operation(
     context: *mut Void,
     callback: HcsOperationCompletion,
 ) -> HcsResult<HcsOperationHandle> {
-    unsafe {
+    let call = ApiCall::new("HcsCreateOperation");
+
+    call.finish(unsafe {
         match HcsCreateOperation(context, callback) {
             handle if handle != std::ptr::null_mut() => Ok(handle),
             _ => Err(ResultCode::Unexpected),
         }
-    }
+    })
 }
 
 /// Closes an operation, freeing resources used to track an HCS API call.
 pub fn close_operation(operation: HcsOperationHandle) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsCloseOperation").close(operation);
+
+    call.finish(unsafe {
         HcsCloseOperation(operation);
         Ok(())
-    }
+    })
 }
 
 /// Returns the operation context as a raw pointer.
 pub fn get_operation_context(operation: HcsOperationHandle) -> HcsResult<*mut Void> {
-    unsafe { Ok(HcsGetOperationContext(operation)) }
+    let call = ApiCall::new("HcsGetOperationContext").handle(operation);
+
+    call.finish(unsafe { Ok(HcsGetOperationContext(operation)) })
 }
 
 /// Sets the operation context, supplied as a raw pointer.
 pub fn set_operation_context(operation: HcsOperationHandle, context: *mut Void) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsSetOperationContext").handle(operation);
+
+    call.finish(unsafe {
         match HcsSetOperationContext(operation, context) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Returns the compute system handle associated to a given operation.
 pub fn get_compute_system_from_operation(
     operation: HcsOperationHandle,
 ) -> HcsResult<HcsSystemHandle> {
-    unsafe {
+    let call = ApiCall::new("HcsGetComputeSystemFromOperation").handle(operation);
+
+    call.finish(unsafe {
         match HcsGetComputeSystemFromOperation(operation) {
             handle if handle != std::ptr::null_mut() => Ok(handle),
             _ => Err(ResultCode::HcsSystemNotFound),
         }
-    }
+    })
 }
 
 /// Returns the process handle associated to a given operation.
 pub fn get_process_from_operation(operation: HcsOperationHandle) -> HcsResult<HcsProcessHandle> {
-    unsafe {
+    let call = ApiCall::new("HcsGetProcessFromOperation").handle(operation);
+
+    call.finish(unsafe {
         match HcsGetProcessFromOperation(operation) {
             handle if handle != std::ptr::null_mut() => Ok(handle),
             _ => Err(ResultCode::Unexpected),
         }
-    }
+    })
 }
 
 /// Returns the operation type.
 pub fn get_operation_type(operation: HcsOperationHandle) -> HcsResult<HcsOperationType> {
-    unsafe { Ok(HcsGetOperationType(operation)) }
+    let call = ApiCall::new("HcsGetOperationType").handle(operation);
+
+    call.finish(unsafe { Ok(HcsGetOperationType(operation)) })
 }
 
 /// Returns the operation ID. Assigned and valid only after an operation has been passed in
 /// to an HCS API.
 pub fn get_operation_id(operation: HcsOperationHandle) -> HcsResult<u64> {
-    unsafe { Ok(HcsGetOperationId(operation)) }
+    let call = ApiCall::new("HcsGetOperationId").handle(operation);
+
+    call.finish(unsafe { Ok(HcsGetOperationId(operation)) })
 }
 
 /// Returns the operation result as a JSON document.
 pub fn get_operation_result(operation: HcsOperationHandle) -> (String, HcsResult<()>) {
     let mut result_document = LocalWString::new();
 
-    unsafe {
+    let result = unsafe {
         match HcsGetOperationResult(operation, result_document.ptr_mut()) {
             0 => (result_document.to_string(), Ok(())),
             hresult => (
@@ -127,7 +189,9 @@ pub fn get_operation_result(operation: HcsOperationHandle) -> (String, HcsResult
                 Err(hresult_to_result_code(&hresult)),
             ),
         }
-    }
+    };
+
+    complete_operation(operation, result)
 }
 
 /// Returns the operation result as a JSON document and the process info.
@@ -137,7 +201,7 @@ pub fn get_operation_result_and_process_info(
 ) -> (String, HcsResult<HcsProcessInformation>) {
     let mut result_document = LocalWString::new();
 
-    unsafe {
+    let result = unsafe {
         let mut process_info = std::mem::zeroed::<HcsProcessInformation>();
 
         match HcsGetOperationResultAndProcessInfo(
@@ -151,7 +215,9 @@ pub fn get_operation_result_and_process_info(
                 Err(hresult_to_result_code(&hresult)),
             ),
         }
-    }
+    };
+
+    complete_operation(operation, result)
 }
 
 /// Waits synchronously for an operation to complete and returns the result as a JSON document.
@@ -161,7 +227,7 @@ pub fn wait_for_operation_result(
 ) -> (String, HcsResult<()>) {
     let mut result_document = LocalWString::new();
 
-    unsafe {
+    let result = unsafe {
         match HcsWaitForOperationResult(operation, timeout_ms, result_document.ptr_mut()) {
             0 => (result_document.to_string(), Ok(())),
             hresult => (
@@ -169,7 +235,9 @@ pub fn wait_for_operation_result(
                 Err(hresult_to_result_code(&hresult)),
             ),
         }
-    }
+    };
+
+    complete_operation(operation, result)
 }
 
 /// Waits syncrhonously for an operation to complete and returns the result as a JSON document,
@@ -180,7 +248,7 @@ pub fn wait_for_operation_result_and_process_info(
 ) -> (String, HcsResult<HcsProcessInformation>) {
     let mut result_document = LocalWString::new();
 
-    unsafe {
+    let result = unsafe {
         let mut process_info = std::mem::zeroed::<HcsProcessInformation>();
 
         match HcsWaitForOperationResultAndProcessInfo(
@@ -195,7 +263,9 @@ pub fn wait_for_operation_result_and_process_info(
                 Err(hresult_to_result_code(&hresult)),
             ),
         }
-    }
+    };
+
+    complete_operation(operation, result)
 }
 
 /// Sets an operation callback for when it completes.
@@ -204,22 +274,26 @@ pub fn set_operation_callback(
     context: *mut Void,
     callback: HcsOperationCompletion,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsSetOperationCallback").handle(operation);
+
+    call.finish(unsafe {
         match HcsSetOperationCallback(operation, context, callback) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Cancels an operation.
 pub fn cancel_operation(operation: HcsOperationHandle) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsCancelOperation").handle(operation);
+
+    call.finish(unsafe {
         match HcsCancelOperation(operation) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Creates a compute system with the given JSON document describing its configuration.
@@ -237,7 +311,12 @@ pub fn create_compute_system(
         None => std::ptr::null_mut(),
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsCreateComputeSystem")
+        .target(id)
+        .request(configuration)
+        .operation(operation);
+
+    call.finish(unsafe {
         match HcsCreateComputeSystem(
             WideCString::from_str(id).unwrap().as_ptr(),
             WideCString::from_str(configuration).unwrap().as_ptr(),
@@ -248,7 +327,7 @@ pub fn create_compute_system(
             0 => Ok(compute_system_handle),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Opens a compute system and returns a handle to it, with the specified access rights.
@@ -260,7 +339,9 @@ pub fn create_compute_system(
 pub fn open_compute_system(id: &str, requested_access: DWord) -> HcsResult<HcsSystemHandle> {
     let mut compute_system_handle: HcsSystemHandle = std::ptr::null_mut();
 
-    unsafe {
+    let call = ApiCall::new("HcsOpenComputeSystem").target(id);
+
+    call.finish(unsafe {
         match HcsOpenComputeSystem(
             WideCString::from_str(id).unwrap().as_ptr(),
             requested_access,
@@ -269,7 +350,7 @@ pub fn open_compute_system(id: &str, requested_access: DWord) -> HcsResult<HcsSy
             0 => Ok(compute_system_handle),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Closes a handle to a compute system.
@@ -278,10 +359,12 @@ pub fn open_compute_system(id: &str, requested_access: DWord) -> HcsResult<HcsSy
 /// In some cases, a compute system could have been created with the flag `TerminateOnLastHandleClosed`,
 /// which would cause the system to be tore down forcefully.
 pub fn close_compute_system(compute_system: HcsSystemHandle) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsCloseComputeSystem").close(compute_system);
+
+    call.finish(unsafe {
         HcsCloseComputeSystem(compute_system);
         Ok(())
-    }
+    })
 }
 
 /// Starts a compute system.
@@ -296,7 +379,12 @@ pub fn start_compute_system(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsStartComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsStartComputeSystem(
             compute_system,
             operation,
@@ -305,7 +393,7 @@ pub fn start_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Shutdowns a compute system.
@@ -325,7 +413,12 @@ pub fn shutdown_compute_system(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsShutDownComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsShutDownComputeSystem(
             compute_system,
             operation,
@@ -334,7 +427,7 @@ pub fn shutdown_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Terminates a compute system.
@@ -355,7 +448,12 @@ pub fn terminate_compute_system(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsTerminateComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsTerminateComputeSystem(
             compute_system,
             operation,
@@ -364,7 +462,7 @@ pub fn terminate_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Pauses a compute system.
@@ -382,7 +480,12 @@ pub fn pause_compute_system(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsPauseComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsPauseComputeSystem(
             compute_system,
             operation,
@@ -391,7 +494,7 @@ pub fn pause_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Resumes a compute system.
@@ -409,7 +512,12 @@ pub fn resume_compute_system(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsResumeComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsResumeComputeSystem(
             compute_system,
             operation,
@@ -418,7 +526,7 @@ pub fn resume_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Saves a compute system.
@@ -436,7 +544,12 @@ pub fn save_compute_system(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsSaveComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsSaveComputeSystem(
             compute_system,
             operation,
@@ -445,7 +558,7 @@ pub fn save_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Requests for a compute system's properties.
@@ -460,7 +573,12 @@ pub fn get_compute_system_properties(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsGetComputeSystemProperties")
+        .handle(compute_system)
+        .operation(operation)
+        .request(property_query_str);
+
+    call.finish(unsafe {
         match HcsGetComputeSystemProperties(
             compute_system,
             operation,
@@ -469,7 +587,7 @@ pub fn get_compute_system_properties(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Modifies a compute system, described by the supplied JSON document configuration.
@@ -486,7 +604,12 @@ pub fn modify_compute_system(
     configuration: &str,
     identity: Handle,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsModifyComputeSystem")
+        .handle(compute_system)
+        .operation(operation)
+        .request(configuration);
+
+    call.finish(unsafe {
         match HcsModifyComputeSystem(
             compute_system,
             operation,
@@ -496,7 +619,7 @@ pub fn modify_compute_system(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Sets a per compute system callback, that is used to receive 'notifications' of different
@@ -508,12 +631,14 @@ pub fn set_compute_system_callback(
     context: *mut Void,
     callback: HcsEventCallback,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsSetComputeSystemCallback").callback(compute_system, context);
+
+    call.finish(unsafe {
         match HcsSetComputeSystemCallback(compute_system, callback_options, context, callback) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Creates a process on a compute system, described by the supplied JSON document.
@@ -531,7 +656,12 @@ pub fn create_process(
         None => std::ptr::null_mut(),
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsCreateProcess")
+        .handle(compute_system)
+        .operation(operation)
+        .request(process_parameters);
+
+    call.finish(unsafe {
         match HcsCreateProcess(
             compute_system,
             WideCString::from_str(process_parameters).unwrap().as_ptr(),
@@ -542,7 +672,7 @@ pub fn create_process(
             0 => Ok(process_handle),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Opens a handle to a process, from a given compute system, with the specified access rights.
@@ -558,7 +688,9 @@ pub fn open_process(
 ) -> HcsResult<HcsProcessHandle> {
     let mut process_handle: HcsProcessHandle = std::ptr::null_mut();
 
-    unsafe {
+    let call = ApiCall::new("HcsOpenProcess").handle(compute_system);
+
+    call.finish(unsafe {
         match HcsOpenProcess(
             compute_system,
             process_id,
@@ -568,7 +700,7 @@ pub fn open_process(
             0 => Ok(process_handle),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Closes a handle to a process running in a compute system.
@@ -578,10 +710,12 @@ pub fn open_process(
 /// make sure to save the process ID since that's required to correctly find
 /// such process. Otherwise, there's no way to open the process again.
 pub fn close_process(process: HcsProcessHandle) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsCloseProcess").close(process);
+
+    call.finish(unsafe {
         HcsCloseProcess(process);
         Ok(())
-    }
+    })
 }
 
 /// Terminates a process running in a compute system.
@@ -596,7 +730,12 @@ pub fn terminate_process(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsTerminateProcess")
+        .handle(process)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsTerminateProcess(
             process,
             operation,
@@ -605,7 +744,7 @@ pub fn terminate_process(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Signals a process running in a compute system.
@@ -620,7 +759,12 @@ pub fn signal_process(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsSignalProcess")
+        .handle(process)
+        .operation(operation)
+        .request(options_str);
+
+    call.finish(unsafe {
         match HcsSignalProcess(
             process,
             operation,
@@ -629,17 +773,21 @@ pub fn signal_process(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Returns basic information about a process running on a compute system.
 pub fn get_process_info(process: HcsProcessHandle, operation: HcsOperationHandle) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsGetProcessInfo")
+        .handle(process)
+        .operation(operation);
+
+    call.finish(unsafe {
         match HcsGetProcessInfo(process, operation) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Returns properties of a process running on a compute system.
@@ -654,7 +802,12 @@ pub fn get_process_properties(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsGetProcessProperties")
+        .handle(process)
+        .operation(operation)
+        .request(property_query_str);
+
+    call.finish(unsafe {
         match HcsGetProcessProperties(
             process,
             operation,
@@ -663,7 +816,7 @@ pub fn get_process_properties(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Requests to modify a process running on a compute system.
@@ -678,7 +831,12 @@ pub fn modify_process(
         None => "{}",
     };
 
-    unsafe {
+    let call = ApiCall::new("HcsModifyProcess")
+        .handle(process)
+        .operation(operation)
+        .request(settings_str);
+
+    call.finish(unsafe {
         match HcsModifyProcess(
             process,
             operation,
@@ -687,7 +845,7 @@ pub fn modify_process(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Sets a per process callback, that is used to receive 'notifications' of different
@@ -699,18 +857,22 @@ pub fn set_process_callback(
     context: *mut Void,
     callback: HcsEventCallback,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsSetProcessCallback").callback(process, context);
+
+    call.finish(unsafe {
         match HcsSetProcessCallback(process, callback_options, context, callback) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Returns Host Compute Service properties as a JSON document.
 /// Determine what to query through a JSON document.
 pub fn get_service_properties(property_query: &str) -> HcsResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcsGetServiceProperties").request(property_query);
+
+    call.finish(unsafe {
         let mut result = LocalWString::new();
 
         match HcsGetServiceProperties(
@@ -720,12 +882,14 @@ pub fn get_service_properties(property_query: &str) -> HcsResult<String> {
             0 => Ok(result.to_string()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Modifies Host Compute Service - wide settings, described by the supplied JSON document.
 pub fn modify_service_settings(settings: &str) -> HcsResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcsModifyServiceSettings").request(settings);
+
+    call.finish(unsafe {
         let mut result = LocalWString::new();
 
         match HcsModifyServiceSettings(
@@ -735,7 +899,7 @@ pub fn modify_service_settings(settings: &str) -> HcsResult<String> {
             0 => Ok(result.to_string()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Submits a **Windows Error Reporting** report, described by the supplied JSON document.
@@ -744,18 +908,22 @@ pub fn modify_service_settings(settings: &str) -> HcsResult<String> {
 /// Refer to https://docs.microsoft.com/en-us/windows/desktop/wer/windows-error-reporting
 /// for detailed information about WER.
 pub fn submit_wer_report(settings: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsSubmitWerReport").request(settings);
+
+    call.finish(unsafe {
         match HcsSubmitWerReport(WideCString::from_str(settings).unwrap().as_ptr()) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Creates an empty VMGS (Virtual Machine Guest State) file, that can be used later
 /// when creating a Virtual Machine compute system to store guest specific information.
 pub fn create_empty_guest_state_file(guest_state_file_path: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsCreateEmptyGuestStateFile").target(guest_state_file_path);
+
+    call.finish(unsafe {
         match HcsCreateEmptyGuestStateFile(
             WideCString::from_str(guest_state_file_path)
                 .unwrap()
@@ -764,7 +932,7 @@ pub fn create_empty_guest_state_file(guest_state_file_path: &str) -> HcsResult<(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Creates an empty VMRS (Virtual Machine Runtime State) file, that can be used later
@@ -772,7 +940,9 @@ pub fn create_empty_guest_state_file(guest_state_file_path: &str) -> HcsResult<(
 /// This can be then used to restore runtime state of a Virtual Machine when creating
 /// a new compute system based on this.
 pub fn create_empty_runtime_state_file(runtime_state_file_path: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsCreateEmptyRuntimeStateFile").target(runtime_state_file_path);
+
+    call.finish(unsafe {
         match HcsCreateEmptyRuntimeStateFile(
             WideCString::from_str(runtime_state_file_path)
                 .unwrap()
@@ -781,13 +951,15 @@ pub fn create_empty_runtime_state_file(runtime_state_file_path: &str) -> HcsResu
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Grants VM access to a file. This allows Virtual Machine compute systems to have
 /// access during runtime to a file (like virtual hard disks).
 pub fn grant_vm_access(vm_id: &str, file_path: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsGrantVmAccess").target(vm_id);
+
+    call.finish(unsafe {
         match HcsGrantVmAccess(
             WideCString::from_str(vm_id).unwrap().as_ptr(),
             WideCString::from_str(file_path).unwrap().as_ptr(),
@@ -795,13 +967,15 @@ pub fn grant_vm_access(vm_id: &str, file_path: &str) -> HcsResult<()> {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Revokes VM access to a file. This prevents Virtual Machine compute systems to have
 /// access during runtime to a file (like virtual hard disks).
 pub fn revoke_vm_access(vm_id: &str, file_path: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsRevokeVmAccess").target(vm_id);
+
+    call.finish(unsafe {
         match HcsRevokeVmAccess(
             WideCString::from_str(vm_id).unwrap().as_ptr(),
             WideCString::from_str(file_path).unwrap().as_ptr(),
@@ -809,5 +983,5 @@ pub fn revoke_vm_access(vm_id: &str, file_path: &str) -> HcsResult<()> {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
@@ -0,0 +1,703 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Standard IO plumbing of compute system processes.
+//! The std pipes are exposed as `Read`/`Write` streams, and the requests that affect them
+//! (closing handles, resizing the console) are issued through an abstract process control,
+//! so that the plumbing can be exercised without an actual compute system.
+
+use crate::schema::process::{
+    CloseHandle, ConsoleSize, ModifyOperation, ProcessModifyRequest, StdHandle,
+};
+use crate::HcsResult;
+use std::io::{Read, Write};
+
+/// Control plane of a compute system process, used to issue the requests
+/// that complement its std pipes.
+pub trait HcsProcessControl {
+    /// Sends a modify request to the compute system process and waits for it to complete.
+    fn modify_process(&self, request: &ProcessModifyRequest) -> HcsResult<()>;
+}
+
+/// Standard IO of a compute system process.
+///
+/// `C` is the process control, `W` the stdin pipe and `R` the stdout and stderr pipes.
+/// Pipes that were not created for the process are `None`.
+pub struct HcsProcessIo<C, W, R> {
+    control: C,
+    stdin: Option<W>,
+    close_stdin: fn(W),
+    stdout: Option<R>,
+    stderr: Option<R>,
+}
+
+impl<C, W, R> HcsProcessIo<C, W, R>
+where
+    C: HcsProcessControl,
+{
+    pub fn new(
+        control: C,
+        stdin: Option<W>,
+        stdout: Option<R>,
+        stderr: Option<R>,
+    ) -> HcsProcessIo<C, W, R> {
+        HcsProcessIo {
+            control,
+            stdin,
+            close_stdin: drop,
+            stdout,
+            stderr,
+        }
+    }
+
+    /// Returns the process control.
+    pub fn control(&self) -> &C {
+        &self.control
+    }
+
+    pub fn stdin(&mut self) -> Option<&mut W> {
+        self.stdin.as_mut()
+    }
+
+    pub fn stdout(&mut self) -> Option<&mut R> {
+        self.stdout.as_mut()
+    }
+
+    pub fn stderr(&mut self) -> Option<&mut R> {
+        self.stderr.as_mut()
+    }
+
+    /// Takes ownership of the stdin pipe, leaving `None` in its place.
+    pub fn take_stdin(&mut self) -> Option<W> {
+        self.stdin.take()
+    }
+
+    /// Takes ownership of the stdout pipe, leaving `None` in its place.
+    pub fn take_stdout(&mut self) -> Option<R> {
+        self.stdout.take()
+    }
+
+    /// Takes ownership of the stderr pipe, leaving `None` in its place.
+    pub fn take_stderr(&mut self) -> Option<R> {
+        self.stderr.take()
+    }
+
+    /// Closes the local end of the stdin pipe and requests the process side
+    /// to be closed too, so that the process observes end of file.
+    pub fn close_stdin(&mut self) -> HcsResult<()> {
+        self.close_handle(StdHandle::StdIn)
+    }
+
+    /// Closes the local end of the given std handle, or all of them,
+    /// and requests the process side to be closed too.
+    /// Data written to stdin reaches the process before its side is closed.
+    pub fn close_handle(&mut self, handle: StdHandle) -> HcsResult<()> {
+        if matches!(handle, StdHandle::StdIn | StdHandle::All) {
+            if let Some(stdin) = self.stdin.take() {
+                (self.close_stdin)(stdin);
+            }
+        }
+        if matches!(handle, StdHandle::StdOut | StdHandle::All) {
+            drop(self.stdout.take());
+        }
+        if matches!(handle, StdHandle::StdErr | StdHandle::All) {
+            drop(self.stderr.take());
+        }
+
+        self.control.modify_process(&ProcessModifyRequest {
+            operation: ModifyOperation::CloseHandle,
+            console_size: None,
+            close_handle: Some(CloseHandle { handle }),
+        })
+    }
+
+    /// Resizes the console of a process created with an emulated console.
+    pub fn resize_console(&self, console_size: ConsoleSize) -> HcsResult<()> {
+        self.control.modify_process(&ProcessModifyRequest {
+            operation: ModifyOperation::ConsoleSize,
+            console_size: Some(console_size),
+            close_handle: None,
+        })
+    }
+
+    /// Consumes the process IO and returns the process control and the pipes.
+    pub fn into_parts(self) -> (C, Option<W>, Option<R>, Option<R>) {
+        (self.control, self.stdin, self.stdout, self.stderr)
+    }
+}
+
+fn missing_pipe(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotConnected,
+        format!("the process has no {} pipe", name),
+    )
+}
+
+/// Writing to the process IO writes to the stdin pipe.
+impl<C, W, R> Write for HcsProcessIo<C, W, R>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.write(buf),
+            None => Err(missing_pipe("stdin")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Err(missing_pipe("stdin")),
+        }
+    }
+}
+
+/// Reading from the process IO reads from the stdout pipe.
+impl<C, W, R> Read for HcsProcessIo<C, W, R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.stdout.as_mut() {
+            Some(stdout) => stdout.read(buf),
+            None => Err(missing_pipe("stdout")),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::asyncio::{AsyncPipeReader, AsyncPipeWriter};
+
+#[cfg(feature = "async")]
+impl<C, W, R> HcsProcessIo<C, W, R>
+where
+    C: HcsProcessControl,
+    W: Write + Send + 'static,
+    R: Read + Send + 'static,
+{
+    /// Converts the blocking pipes into their async equivalents.
+    pub fn into_async(self) -> HcsProcessIo<C, AsyncPipeWriter, AsyncPipeReader> {
+        HcsProcessIo {
+            control: self.control,
+            stdin: self.stdin.map(AsyncPipeWriter::new),
+            close_stdin: AsyncPipeWriter::close_drained,
+            stdout: self.stdout.map(AsyncPipeReader::new),
+            stderr: self.stderr.map(AsyncPipeReader::new),
+        }
+    }
+}
+
+/// Asynchronously writing to the process IO writes to the stdin pipe.
+#[cfg(feature = "async")]
+impl<C, W, R> futures_io::AsyncWrite for HcsProcessIo<C, W, R>
+where
+    C: Unpin,
+    W: futures_io::AsyncWrite + Unpin,
+    R: Unpin,
+{
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut().stdin.as_mut() {
+            Some(stdin) => std::pin::Pin::new(stdin).poll_write(cx, buf),
+            None => std::task::Poll::Ready(Err(missing_pipe("stdin"))),
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut().stdin.as_mut() {
+            Some(stdin) => std::pin::Pin::new(stdin).poll_flush(cx),
+            None => std::task::Poll::Ready(Err(missing_pipe("stdin"))),
+        }
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut().stdin.as_mut() {
+            Some(stdin) => std::pin::Pin::new(stdin).poll_close(cx),
+            None => std::task::Poll::Ready(Err(missing_pipe("stdin"))),
+        }
+    }
+}
+
+/// Asynchronously reading from the process IO reads from the stdout pipe.
+#[cfg(feature = "async")]
+impl<C, W, R> futures_io::AsyncRead for HcsProcessIo<C, W, R>
+where
+    C: Unpin,
+    W: Unpin,
+    R: futures_io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+        buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut().stdout.as_mut() {
+            Some(stdout) => std::pin::Pin::new(stdout).poll_read(cx, buf),
+            None => std::task::Poll::Ready(Err(missing_pipe("stdout"))),
+        }
+    }
+}
+
+/// Async adapters of blocking pipes.
+/// Each adapter owns a thread that performs the blocking calls on the pipe,
+/// and wakes up the task polling the adapter once progress can be made.
+#[cfg(feature = "async")]
+mod asyncio {
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+    use std::pin::Pin;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::task::{Context, Poll, Waker};
+
+    /// Maximum amount of bytes buffered by the adapters before applying backpressure.
+    const BUFFER_CAPACITY: usize = 64 * 1024;
+
+    #[derive(Default)]
+    struct PipeState {
+        buffer: VecDeque<u8>,
+        in_flight: bool,
+        closed: bool,
+        error: Option<std::io::Error>,
+        waker: Option<Waker>,
+    }
+
+    #[derive(Default)]
+    struct SharedPipeState {
+        state: Mutex<PipeState>,
+        condvar: Condvar,
+    }
+
+    impl SharedPipeState {
+        fn wake(state: &mut PipeState) {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Async reader on top of a blocking pipe.
+    pub struct AsyncPipeReader {
+        shared: Arc<SharedPipeState>,
+    }
+
+    impl AsyncPipeReader {
+        pub fn new<R: Read + Send + 'static>(mut reader: R) -> AsyncPipeReader {
+            let shared = Arc::new(SharedPipeState::default());
+            let thread_shared = shared.clone();
+
+            std::thread::spawn(move || {
+                let mut chunk = vec![0u8; 4096];
+
+                loop {
+                    let result = reader.read(&mut chunk);
+                    let mut state = thread_shared.state.lock().unwrap();
+
+                    match result {
+                        Ok(0) => state.closed = true,
+                        Ok(read) => state.buffer.extend(&chunk[..read]),
+                        Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => {
+                            continue
+                        }
+                        Err(error) => {
+                            state.error = Some(error);
+                            state.closed = true;
+                        }
+                    }
+
+                    SharedPipeState::wake(&mut state);
+
+                    while state.buffer.len() >= BUFFER_CAPACITY && !state.closed {
+                        state = thread_shared.condvar.wait(state).unwrap();
+                    }
+
+                    // Either the pipe reached its end or the adapter was dropped
+                    if state.closed {
+                        break;
+                    }
+                }
+            });
+
+            AsyncPipeReader { shared }
+        }
+    }
+
+    impl futures_io::AsyncRead for AsyncPipeReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut state = self.shared.state.lock().unwrap();
+
+            if !state.buffer.is_empty() {
+                let read = std::cmp::min(buf.len(), state.buffer.len());
+                for (destination, source) in buf.iter_mut().zip(state.buffer.drain(..read)) {
+                    *destination = source;
+                }
+                self.shared.condvar.notify_one();
+                return Poll::Ready(Ok(read));
+            }
+
+            if let Some(error) = state.error.take() {
+                return Poll::Ready(Err(error));
+            }
+
+            if state.closed {
+                return Poll::Ready(Ok(0));
+            }
+
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    impl std::ops::Drop for AsyncPipeReader {
+        fn drop(&mut self) {
+            self.shared.state.lock().unwrap().closed = true;
+            self.shared.condvar.notify_one();
+        }
+    }
+
+    /// Async writer on top of a blocking pipe.
+    /// The pipe is closed once the writer is closed or dropped and all buffered data is written.
+    pub struct AsyncPipeWriter {
+        shared: Arc<SharedPipeState>,
+    }
+
+    impl AsyncPipeWriter {
+        pub fn new<W: Write + Send + 'static>(mut writer: W) -> AsyncPipeWriter {
+            let shared = Arc::new(SharedPipeState::default());
+            let thread_shared = shared.clone();
+
+            std::thread::spawn(move || loop {
+                let mut state = thread_shared.state.lock().unwrap();
+
+                while state.buffer.is_empty() && !state.closed {
+                    state = thread_shared.condvar.wait(state).unwrap();
+                }
+
+                if state.buffer.is_empty() {
+                    SharedPipeState::wake(&mut state);
+                    thread_shared.condvar.notify_all();
+                    break;
+                }
+
+                let data: Vec<u8> = state.buffer.drain(..).collect();
+                state.in_flight = true;
+                drop(state);
+
+                let result = writer.write_all(&data).and_then(|_| writer.flush());
+
+                let mut state = thread_shared.state.lock().unwrap();
+                state.in_flight = false;
+
+                if let Err(error) = result {
+                    state.error = Some(error);
+                    state.buffer.clear();
+                    state.closed = true;
+                }
+
+                SharedPipeState::wake(&mut state);
+                thread_shared.condvar.notify_all();
+            });
+
+            AsyncPipeWriter { shared }
+        }
+
+        /// Closes the writer, blocking until all buffered data is written to the pipe.
+        pub fn close_drained(self) {
+            let mut state = self.shared.state.lock().unwrap();
+            state.closed = true;
+            self.shared.condvar.notify_all();
+
+            while !state.buffer.is_empty() || state.in_flight {
+                state = self.shared.condvar.wait(state).unwrap();
+            }
+        }
+
+        fn poll_drained(&self, cx: &mut Context) -> Poll<std::io::Result<()>> {
+            let mut state = self.shared.state.lock().unwrap();
+
+            if let Some(error) = state.error.take() {
+                return Poll::Ready(Err(error));
+            }
+
+            if state.buffer.is_empty() && !state.in_flight {
+                return Poll::Ready(Ok(()));
+            }
+
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    impl futures_io::AsyncWrite for AsyncPipeWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut state = self.shared.state.lock().unwrap();
+
+            if let Some(error) = state.error.take() {
+                return Poll::Ready(Err(error));
+            }
+
+            if state.closed {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "the pipe is closed",
+                )));
+            }
+
+            let available = BUFFER_CAPACITY.saturating_sub(state.buffer.len());
+
+            if available == 0 {
+                state.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let written = std::cmp::min(available, buf.len());
+            state.buffer.extend(&buf[..written]);
+            self.shared.condvar.notify_one();
+            Poll::Ready(Ok(written))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+            self.poll_drained(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+            self.shared.state.lock().unwrap().closed = true;
+            self.shared.condvar.notify_one();
+            self.poll_drained(cx)
+        }
+    }
+
+    impl std::ops::Drop for AsyncPipeWriter {
+        fn drop(&mut self) {
+            self.shared.state.lock().unwrap().closed = true;
+            self.shared.condvar.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    #[derive(Default)]
+    struct FakeProcessControl {
+        requests: RefCell<Vec<ProcessModifyRequest>>,
+    }
+
+    impl HcsProcessControl for FakeProcessControl {
+        fn modify_process(&self, request: &ProcessModifyRequest) -> HcsResult<()> {
+            self.requests.borrow_mut().push(request.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn process_io_streams() {
+        let mut io = HcsProcessIo::new(
+            FakeProcessControl::default(),
+            Some(Vec::new()),
+            Some(Cursor::new(b"hello".to_vec())),
+            Some(Cursor::new(b"error".to_vec())),
+        );
+
+        io.write_all(b"input").unwrap();
+        assert_eq!(io.stdin().unwrap().as_slice(), b"input");
+
+        let mut stdout = String::new();
+        io.read_to_string(&mut stdout).unwrap();
+        assert_eq!(stdout, "hello");
+
+        let mut stderr = String::new();
+        io.stderr().unwrap().read_to_string(&mut stderr).unwrap();
+        assert_eq!(stderr, "error");
+    }
+
+    #[test]
+    fn process_io_modify_requests() {
+        let mut io: HcsProcessIo<_, Vec<u8>, Cursor<Vec<u8>>> =
+            HcsProcessIo::new(FakeProcessControl::default(), Some(Vec::new()), None, None);
+
+        io.close_stdin().unwrap();
+        assert!(io.stdin().is_none());
+        assert_eq!(
+            io.write(b"input").unwrap_err().kind(),
+            std::io::ErrorKind::NotConnected
+        );
+
+        io.resize_console(ConsoleSize {
+            height: 25,
+            width: 80,
+        })
+        .unwrap();
+
+        let requests: Vec<String> = io
+            .control()
+            .requests
+            .borrow()
+            .iter()
+            .map(|request| serde_json::to_string(request).unwrap())
+            .collect();
+
+        assert_eq!(
+            requests,
+            vec![
+                r#"{"Operation":"CloseHandle","CloseHandle":{"Handle":"StdIn"}}"#,
+                r#"{"Operation":"ConsoleSize","ConsoleSize":{"Height":25,"Width":80}}"#,
+            ]
+        );
+    }
+
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        struct ThreadWaker(std::thread::Thread);
+
+        impl std::task::Wake for ThreadWaker {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker =
+            std::task::Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = std::task::Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+
+        loop {
+            match future.as_mut().poll(&mut context) {
+                std::task::Poll::Ready(output) => return output,
+                std::task::Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn process_io_async_streams() {
+        use futures_io::{AsyncRead, AsyncWrite};
+        use std::pin::Pin;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let stdin = SharedBuffer::default();
+        let mut io = HcsProcessIo::new(
+            FakeProcessControl::default(),
+            Some(stdin.clone()),
+            Some(Cursor::new(vec![7u8; 100_000])),
+            None,
+        )
+        .into_async();
+
+        block_on(std::future::poll_fn(|cx| {
+            Pin::new(&mut io).poll_write(cx, b"input")
+        }))
+        .unwrap();
+        block_on(std::future::poll_fn(|cx| Pin::new(&mut io).poll_flush(cx))).unwrap();
+        assert_eq!(&*stdin.0.lock().unwrap(), b"input");
+
+        let mut stdout = Vec::new();
+        let mut chunk = [0u8; 1000];
+        loop {
+            let read = block_on(std::future::poll_fn(|cx| {
+                Pin::new(&mut io).poll_read(cx, &mut chunk)
+            }))
+            .unwrap();
+            if read == 0 {
+                break;
+            }
+            stdout.extend_from_slice(&chunk[..read]);
+        }
+        assert_eq!(stdout, vec![7u8; 100_000]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn process_io_async_close_stdin_drains() {
+        use futures_io::AsyncWrite;
+        use std::pin::Pin;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct SlowPipe(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SlowPipe {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                self.0.lock().unwrap().push(buf[0]);
+                Ok(1)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        /// Records the stdin received by the process when its side is closed.
+        struct StdinControl {
+            stdin: SlowPipe,
+            received: RefCell<Vec<Vec<u8>>>,
+        }
+
+        impl HcsProcessControl for StdinControl {
+            fn modify_process(&self, _request: &ProcessModifyRequest) -> HcsResult<()> {
+                let received = self.stdin.0.lock().unwrap().clone();
+                self.received.borrow_mut().push(received);
+                Ok(())
+            }
+        }
+
+        let stdin = SlowPipe::default();
+        let control = StdinControl {
+            stdin: stdin.clone(),
+            received: RefCell::new(Vec::new()),
+        };
+        let mut io = HcsProcessIo::<_, _, Cursor<Vec<u8>>>::new(control, Some(stdin), None, None)
+            .into_async();
+
+        block_on(std::future::poll_fn(|cx| {
+            Pin::new(&mut io).poll_write(cx, b"input")
+        }))
+        .unwrap();
+        io.close_stdin().unwrap();
+        assert_eq!(
+            io.control().received.borrow().clone(),
+            vec![b"input".to_vec()]
+        );
+    }
+}
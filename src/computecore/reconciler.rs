@@ -0,0 +1,553 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Desired state reconciliation of the compute systems owned by a set of owners.
+//!
+//! On each pass, the `Reconciler` enumerates the compute systems of every owner and compares
+//! them against the desired ones: missing systems are created and started, systems whose
+//! desired document changed are modified, and systems that are no longer desired are terminated.
+//!
+//! HCS doesn't return the document a compute system was created with, so drift is detected
+//! against the last document the reconciler applied to it. Existing systems it didn't apply
+//! a document to are adopted as they are.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::computecore::snapshot::HcsComputeBackend;
+use crate::schema::requests::modifications::plan_modifications;
+use crate::schema::requests::system::{ModifySettingRequest, SystemQuery};
+use crate::schema::responses::system::Properties;
+use crate::schema::ComputeSystem;
+use crate::HcsResult;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Mutex;
+
+/// Compute system operations needed to reconcile a fleet of compute systems,
+/// on top of the ones the snapshot workflows are built on.
+pub trait HcsFleetBackend: HcsComputeBackend {
+    /// Enumerates the compute systems matching a query.
+    fn enumerate_systems(&self, query: &SystemQuery) -> HcsResult<Vec<Properties>>;
+
+    fn modify_system(&self, system: &Self::System, request: &ModifySettingRequest)
+        -> HcsResult<()>;
+}
+
+/// Compute system that should exist. Its owner is the owner of the document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesiredSystem {
+    pub id: String,
+    pub document: ComputeSystem,
+}
+
+/// Source of the compute systems that should exist for an owner.
+pub trait DesiredStateSource {
+    fn desired_systems(&self, owner: &str) -> HcsResult<Vec<DesiredSystem>>;
+}
+
+impl<F> DesiredStateSource for F
+where
+    F: Fn(&str) -> HcsResult<Vec<DesiredSystem>>,
+{
+    fn desired_systems(&self, owner: &str) -> HcsResult<Vec<DesiredSystem>> {
+        self(owner)
+    }
+}
+
+impl DesiredStateSource for Vec<DesiredSystem> {
+    fn desired_systems(&self, owner: &str) -> HcsResult<Vec<DesiredSystem>> {
+        Ok(self
+            .iter()
+            .filter(|desired| desired.document.owner == owner)
+            .cloned()
+            .collect())
+    }
+}
+
+/// Identifies a compute system within the fleet.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemKey {
+    pub owner: String,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReconcileAction {
+    /// Create and start a missing compute system.
+    Create(SystemKey),
+
+    /// Apply changes of the desired document to a running compute system.
+    Modify {
+        key: SystemKey,
+        requests: Vec<ModifySettingRequest>,
+    },
+
+    /// Terminate and create again a compute system whose desired document changed
+    /// in ways that can't be applied while it runs.
+    Recreate {
+        key: SystemKey,
+        changes: Vec<String>,
+    },
+
+    /// Terminate a compute system that is no longer desired.
+    Terminate(SystemKey),
+
+    /// Start tracking an existing compute system, assuming it matches its desired document.
+    Adopt(SystemKey),
+}
+
+impl ReconcileAction {
+    pub fn key(&self) -> &SystemKey {
+        match self {
+            ReconcileAction::Create(key)
+            | ReconcileAction::Terminate(key)
+            | ReconcileAction::Adopt(key) => key,
+            ReconcileAction::Modify { key, .. } | ReconcileAction::Recreate { key, .. } => key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionOutcome {
+    /// The action was only planned, because of a dry run.
+    Planned,
+    Succeeded,
+
+    /// The action was not allowed by the reconciler options.
+    Skipped,
+    Failed(ResultCode),
+}
+
+/// Action taken by the reconciler, reported to the event handler and in the pass report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconcileEvent {
+    pub action: ReconcileAction,
+    pub outcome: ActionOutcome,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReconcileReport {
+    pub events: Vec<ReconcileEvent>,
+}
+
+impl ReconcileReport {
+    /// Returns the actions that failed, along with their errors.
+    pub fn failures(&self) -> Vec<(&ReconcileAction, ResultCode)> {
+        self.events
+            .iter()
+            .filter_map(|event| match event.outcome {
+                ActionOutcome::Failed(error) => Some((&event.action, error)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns true if the pass didn't need to change anything.
+    pub fn is_converged(&self) -> bool {
+        self.events
+            .iter()
+            .all(|event| matches!(event.action, ReconcileAction::Adopt(_)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconcilerOptions {
+    /// Plan and report the actions without running them.
+    pub dry_run: bool,
+
+    /// Maximum number of actions running at the same time.
+    pub max_concurrency: usize,
+
+    /// Allow terminating and creating again compute systems whose changes need a restart.
+    /// Otherwise those actions are skipped.
+    pub recreate_drifted: bool,
+}
+
+impl std::default::Default for ReconcilerOptions {
+    fn default() -> Self {
+        ReconcilerOptions {
+            dry_run: false,
+            max_concurrency: 4,
+            recreate_drifted: false,
+        }
+    }
+}
+
+pub type ReconcileEventHandler = Box<dyn Fn(&ReconcileEvent) + Send + Sync>;
+
+/// Reconciles the compute systems of a set of owners against a desired state source.
+pub struct Reconciler<B: HcsFleetBackend, S: DesiredStateSource> {
+    backend: B,
+    source: S,
+    owners: Vec<String>,
+    options: ReconcilerOptions,
+    applied: BTreeMap<SystemKey, ComputeSystem>,
+    event_handler: Option<ReconcileEventHandler>,
+}
+
+fn create_and_start<B: HcsFleetBackend>(
+    backend: &B,
+    id: &str,
+    document: &ComputeSystem,
+) -> HcsResult<()> {
+    let system = backend.create_system(id, document)?;
+    backend.start_system(&system)
+}
+
+fn execute<B: HcsFleetBackend>(
+    backend: &B,
+    options: ReconcilerOptions,
+    action: &ReconcileAction,
+    document: Option<&ComputeSystem>,
+) -> ActionOutcome {
+    if options.dry_run {
+        return ActionOutcome::Planned;
+    }
+
+    let result = match (action, document) {
+        (ReconcileAction::Create(key), Some(document)) => {
+            create_and_start(backend, &key.id, document)
+        }
+        (ReconcileAction::Modify { key, requests }, _) => {
+            backend.open_system(&key.id).and_then(|system| {
+                requests
+                    .iter()
+                    .try_for_each(|request| backend.modify_system(&system, request))
+            })
+        }
+        (ReconcileAction::Recreate { .. }, _) if !options.recreate_drifted => {
+            return ActionOutcome::Skipped;
+        }
+        (ReconcileAction::Recreate { key, .. }, Some(document)) => backend
+            .open_system(&key.id)
+            .and_then(|system| backend.terminate_system(&system))
+            .and_then(|_| create_and_start(backend, &key.id, document)),
+        (ReconcileAction::Terminate(key), _) => backend
+            .open_system(&key.id)
+            .and_then(|system| backend.terminate_system(&system)),
+        (ReconcileAction::Adopt(_), _) => Ok(()),
+        _ => Err(ResultCode::Unexpected),
+    };
+
+    match result {
+        Ok(()) => ActionOutcome::Succeeded,
+        Err(error) => ActionOutcome::Failed(error),
+    }
+}
+
+impl<B, S> Reconciler<B, S>
+where
+    B: HcsFleetBackend + Sync,
+    S: DesiredStateSource,
+{
+    pub fn new(backend: B, source: S, owners: &[&str]) -> Reconciler<B, S> {
+        Reconciler {
+            backend,
+            source,
+            owners: owners.iter().map(|owner| String::from(*owner)).collect(),
+            options: ReconcilerOptions::default(),
+            applied: BTreeMap::new(),
+            event_handler: None,
+        }
+    }
+
+    pub fn with_options(mut self, options: ReconcilerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets a handler called for each action as soon as it completes,
+    /// which can happen from any of the threads running actions.
+    pub fn with_event_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&ReconcileEvent) + Send + Sync + 'static,
+    {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn options(&self) -> ReconcilerOptions {
+        self.options
+    }
+
+    /// Returns the last document applied to a compute system by the reconciler.
+    pub fn applied_document(&self, key: &SystemKey) -> Option<&ComputeSystem> {
+        self.applied.get(key)
+    }
+
+    fn plan_owner(
+        &self,
+        owner: &str,
+        plan: &mut Vec<(ReconcileAction, Option<ComputeSystem>)>,
+    ) -> HcsResult<()> {
+        let query = SystemQuery {
+            owners: vec![String::from(owner)],
+            ..Default::default()
+        };
+        let existing: BTreeSet<String> = self
+            .backend
+            .enumerate_systems(&query)?
+            .into_iter()
+            .map(|properties| properties.id)
+            .collect();
+
+        let mut desired_ids = BTreeSet::new();
+        for desired in self.source.desired_systems(owner)? {
+            let key = SystemKey {
+                owner: String::from(owner),
+                id: desired.id.clone(),
+            };
+            desired_ids.insert(desired.id.clone());
+
+            if !existing.contains(&desired.id) {
+                plan.push((ReconcileAction::Create(key), Some(desired.document)));
+                continue;
+            }
+
+            let applied = match self.applied.get(&key) {
+                Some(applied) => applied,
+                None => {
+                    plan.push((ReconcileAction::Adopt(key), Some(desired.document)));
+                    continue;
+                }
+            };
+
+            let modifications = plan_modifications(applied, &desired.document);
+            if modifications.requires_restart() {
+                let changes = modifications.restart_required;
+                plan.push((
+                    ReconcileAction::Recreate { key, changes },
+                    Some(desired.document),
+                ));
+            } else if !modifications.is_empty() {
+                let requests = modifications.requests;
+                plan.push((
+                    ReconcileAction::Modify { key, requests },
+                    Some(desired.document),
+                ));
+            }
+        }
+
+        for id in existing.difference(&desired_ids) {
+            let key = SystemKey {
+                owner: String::from(owner),
+                id: id.clone(),
+            };
+            plan.push((ReconcileAction::Terminate(key), None));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the actions that a reconciliation pass would take now.
+    pub fn plan(&self) -> HcsResult<Vec<ReconcileAction>> {
+        let mut plan = Vec::new();
+        for owner in &self.owners {
+            self.plan_owner(owner, &mut plan)?;
+        }
+        Ok(plan.into_iter().map(|(action, _)| action).collect())
+    }
+
+    /// Runs a reconciliation pass. Failing to enumerate the compute systems or
+    /// to get the desired ones fails the pass, while failed actions are reported.
+    pub fn reconcile(&mut self) -> HcsResult<ReconcileReport> {
+        let mut plan = Vec::new();
+        for owner in &self.owners {
+            self.plan_owner(owner, &mut plan)?;
+        }
+
+        let workers = self.options.max_concurrency.max(1).min(plan.len());
+        let queue = Mutex::new(plan.into_iter().enumerate().collect::<VecDeque<_>>());
+        let completed = Mutex::new(Vec::new());
+
+        let backend = &self.backend;
+        let options = self.options;
+        let event_handler = &self.event_handler;
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap().pop_front();
+                    let (index, (action, document)) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+
+                    let outcome = execute(backend, options, &action, document.as_ref());
+                    let event = ReconcileEvent { action, outcome };
+                    if let Some(handler) = event_handler {
+                        handler(&event);
+                    }
+                    completed.lock().unwrap().push((index, event, document));
+                });
+            }
+        });
+
+        let mut completed = completed.into_inner().unwrap();
+        completed.sort_by_key(|(index, _, _)| *index);
+
+        let mut report = ReconcileReport::default();
+        for (_, event, document) in completed {
+            if event.outcome == ActionOutcome::Succeeded {
+                match (&event.action, document) {
+                    (ReconcileAction::Terminate(key), _) => {
+                        self.applied.remove(key);
+                    }
+                    (action, Some(document)) => {
+                        self.applied.insert(action.key().clone(), document);
+                    }
+                    _ => {}
+                }
+            }
+            report.events.push(event);
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computecore::testing::FakeBackend;
+    use crate::schema::responses::system::State;
+    use crate::schema::VirtualMachine;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Returns the operations that changed the fleet, sorted since they can run concurrently.
+    fn take_changes(fleet: &FakeBackend) -> Vec<String> {
+        let mut changes: Vec<String> = fleet
+            .take_operations()
+            .into_iter()
+            .filter(|operation| !operation.starts_with("open ") && !operation.starts_with("state "))
+            .collect();
+        changes.sort();
+        changes
+    }
+
+    fn desired(id: &str, memory_mb: u64, processors: u32) -> DesiredSystem {
+        let mut virtual_machine = VirtualMachine::default();
+        virtual_machine.compute_topology.memory.size_in_mb = memory_mb;
+        virtual_machine.compute_topology.processor.count = processors;
+
+        DesiredSystem {
+            id: String::from(id),
+            document: ComputeSystem {
+                owner: String::from("agent"),
+                virtual_machine: Some(virtual_machine),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn key(id: &str) -> SystemKey {
+        SystemKey {
+            owner: String::from("agent"),
+            id: String::from(id),
+        }
+    }
+
+    #[test]
+    fn reconcile_fleet() {
+        let fleet = FakeBackend::default();
+        fleet.insert("adopted", "agent", State::Running);
+        fleet.insert("orphan", "agent", State::Running);
+        fleet.insert("foreign", "someone-else", State::Running);
+
+        let desired_state = Arc::new(Mutex::new(vec![
+            desired("adopted", 1024, 2),
+            desired("missing", 1024, 2),
+        ]));
+        let source = desired_state.clone();
+        let events = Arc::new(AtomicUsize::new(0));
+        let counted = events.clone();
+
+        let mut reconciler = Reconciler::new(
+            fleet,
+            move |owner: &str| source.lock().unwrap().desired_systems(owner),
+            &["agent"],
+        )
+        .with_options(ReconcilerOptions {
+            dry_run: true,
+            max_concurrency: 2,
+            ..Default::default()
+        })
+        .with_event_handler(move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let expected = vec![
+            ReconcileAction::Adopt(key("adopted")),
+            ReconcileAction::Create(key("missing")),
+            ReconcileAction::Terminate(key("orphan")),
+        ];
+        assert_eq!(reconciler.plan().unwrap(), expected);
+
+        // Dry run: everything is planned, nothing runs
+        let report = reconciler.reconcile().unwrap();
+        assert!(report
+            .events
+            .iter()
+            .all(|event| event.outcome == ActionOutcome::Planned));
+        assert!(take_changes(reconciler.backend()).is_empty());
+        assert_eq!(reconciler.applied_document(&key("adopted")), None);
+
+        reconciler = reconciler.with_options(ReconcilerOptions::default());
+        let report = reconciler.reconcile().unwrap();
+        assert_eq!(
+            report
+                .events
+                .iter()
+                .map(|event| event.action.clone())
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert!(report.failures().is_empty());
+        assert_eq!(
+            take_changes(reconciler.backend()),
+            vec!["create missing", "start missing", "terminate orphan"]
+        );
+        assert!(reconciler.reconcile().unwrap().is_converged());
+
+        // Memory changes are applied in place, processor changes need a restart
+        *desired_state.lock().unwrap() =
+            vec![desired("adopted", 2048, 2), desired("missing", 1024, 4)];
+        let report = reconciler.reconcile().unwrap();
+        assert_eq!(
+            report.events[1],
+            ReconcileEvent {
+                action: ReconcileAction::Recreate {
+                    key: key("missing"),
+                    changes: vec![String::from(
+                        "VirtualMachine/ComputeTopology/Processor/Count"
+                    )],
+                },
+                outcome: ActionOutcome::Skipped,
+            }
+        );
+        assert_eq!(
+            take_changes(reconciler.backend()),
+            vec!["modify adopted VirtualMachine/ComputeTopology/Memory/SizeInMB"]
+        );
+
+        reconciler = reconciler.with_options(ReconcilerOptions {
+            recreate_drifted: true,
+            ..Default::default()
+        });
+        assert_eq!(reconciler.reconcile().unwrap().events.len(), 1);
+        assert_eq!(
+            take_changes(reconciler.backend()),
+            vec!["create missing", "start missing", "terminate missing"]
+        );
+        assert!(reconciler.reconcile().unwrap().is_converged());
+        assert_eq!(events.load(Ordering::SeqCst), 9);
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Retry and reconnection policy for operations on compute systems and processes.
+//!
+//! When the compute service restarts, pending and new calls fail with `HCS_E_SERVICE_DISCONNECT`,
+//! callbacks receive `HcsEventType::ServiceDisconnect`, and every open handle stops working.
+//! `ReconnectingSystem` detects the disconnect, opens the compute system and its tracked
+//! processes again by ID, registers their callbacks again and retries idempotent operations.
+
+use crate::compute::defs::HcsEventType;
+use crate::compute::errorcodes::ResultCode;
+use crate::computecore::snapshot::HcsComputeBackend;
+use crate::HcsResult;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Handler of compute system and process events, called with the event type
+/// and the event data. It can be called from any thread.
+pub type EventHandler = Arc<dyn Fn(HcsEventType, &str) + Send + Sync>;
+
+/// Compute system operations needed to reconnect after a service disconnect,
+/// on top of the ones the snapshot workflows are built on.
+pub trait HcsReconnectBackend: HcsComputeBackend {
+    type Process;
+
+    /// Opens a process of a compute system, created through the HCS APIs.
+    fn open_process(&self, system: &Self::System, process_id: u32) -> HcsResult<Self::Process>;
+
+    /// Registers the event callback of a compute system, replacing the previous one.
+    fn set_system_callback(
+        &self,
+        system: &mut Self::System,
+        handler: EventHandler,
+    ) -> HcsResult<()>;
+
+    /// Registers the event callback of a process, replacing the previous one.
+    fn set_process_callback(
+        &self,
+        process: &mut Self::Process,
+        handler: EventHandler,
+    ) -> HcsResult<()>;
+}
+
+/// Retry policy with exponential backoff, applied to failures classified as transient.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts of an operation, including the first one.
+    pub max_attempts: u32,
+
+    /// Wait before the first retry, doubled on each following one.
+    pub initial_backoff: Duration,
+
+    pub max_backoff: Duration,
+
+    /// Function used to wait between attempts.
+    pub sleep: fn(Duration),
+}
+
+impl std::default::Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            sleep: std::thread::sleep,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the wait before the given retry, starting at 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    /// Runs an idempotent operation, retrying it while it fails with a transient result code.
+    pub fn retry<T, F>(&self, mut operation: F) -> HcsResult<T>
+    where
+        F: FnMut() -> HcsResult<T>,
+    {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Err(error) if error.is_transient() && retry + 1 < self.max_attempts => {
+                    (self.sleep)(self.backoff(retry));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Wraps an event handler, flagging the disconnect when a service disconnect is received.
+fn watch_disconnect(disconnected: &Arc<AtomicBool>, handler: Option<EventHandler>) -> EventHandler {
+    let disconnected = disconnected.clone();
+    Arc::new(move |event_type, event_data| {
+        if event_type == HcsEventType::ServiceDisconnect {
+            disconnected.store(true, Ordering::SeqCst);
+        }
+
+        if let Some(handler) = &handler {
+            handler(event_type, event_data);
+        }
+    })
+}
+
+struct TrackedProcess<P> {
+    process: P,
+    handler: Option<EventHandler>,
+}
+
+/// Compute system handle that survives compute service restarts.
+pub struct ReconnectingSystem<B: HcsReconnectBackend> {
+    backend: B,
+    policy: RetryPolicy,
+    id: String,
+    system: B::System,
+    handler: Option<EventHandler>,
+    processes: BTreeMap<u32, TrackedProcess<B::Process>>,
+    disconnected: Arc<AtomicBool>,
+    reconnects: u32,
+}
+
+impl<B: HcsReconnectBackend> ReconnectingSystem<B> {
+    /// Opens an existing compute system by ID, watching it for service disconnects.
+    pub fn open(backend: B, id: &str, policy: RetryPolicy) -> HcsResult<ReconnectingSystem<B>> {
+        let system = policy.retry(|| backend.open_system(id))?;
+        let disconnected = Arc::new(AtomicBool::new(false));
+
+        let mut reconnecting = ReconnectingSystem {
+            backend,
+            policy,
+            id: String::from(id),
+            system,
+            handler: None,
+            processes: BTreeMap::new(),
+            disconnected,
+            reconnects: 0,
+        };
+
+        let handler = watch_disconnect(&reconnecting.disconnected, None);
+        reconnecting
+            .backend
+            .set_system_callback(&mut reconnecting.system, handler)?;
+        Ok(reconnecting)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns the current handle of the compute system, which changes on reconnects.
+    pub fn system(&self) -> &B::System {
+        &self.system
+    }
+
+    /// Returns true if a service disconnect was detected and the handles
+    /// have not been opened again yet.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of times the handles have been opened again.
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// Sets the event handler of the compute system, which is registered again on reconnects.
+    pub fn set_event_handler(&mut self, handler: EventHandler) -> HcsResult<()> {
+        self.handler = Some(handler);
+        let handler = watch_disconnect(&self.disconnected, self.handler.clone());
+        self.backend.set_system_callback(&mut self.system, handler)
+    }
+
+    /// Opens a process of the compute system and tracks it, so that it is opened
+    /// again on reconnects. Its event handler, if any, is registered again as well.
+    pub fn open_process(
+        &mut self,
+        process_id: u32,
+        handler: Option<EventHandler>,
+    ) -> HcsResult<()> {
+        self.run(true, |this| {
+            this.backend.open_process(&this.system, process_id)
+        })
+        .and_then(|mut process| {
+            let watched = watch_disconnect(&self.disconnected, handler.clone());
+            self.backend.set_process_callback(&mut process, watched)?;
+            self.processes
+                .insert(process_id, TrackedProcess { process, handler });
+            Ok(())
+        })
+    }
+
+    /// Stops tracking a process, closing its handle.
+    pub fn close_process(&mut self, process_id: u32) {
+        self.processes.remove(&process_id);
+    }
+
+    /// Returns the IDs of the tracked processes.
+    pub fn process_ids(&self) -> Vec<u32> {
+        self.processes.keys().cloned().collect()
+    }
+
+    /// Opens the compute system and its tracked processes again, registering their callbacks.
+    /// Processes that no longer exist stop being tracked.
+    pub fn reconnect(&mut self) -> HcsResult<()> {
+        let backend = &self.backend;
+        let id = &self.id;
+        let mut system = self.policy.retry(|| backend.open_system(id))?;
+
+        let handler = watch_disconnect(&self.disconnected, self.handler.clone());
+        self.backend.set_system_callback(&mut system, handler)?;
+        self.system = system;
+        self.disconnected.store(false, Ordering::SeqCst);
+        self.reconnects += 1;
+
+        let process_ids = self.process_ids();
+        for process_id in process_ids {
+            let backend = &self.backend;
+            let system = &self.system;
+            match self
+                .policy
+                .retry(|| backend.open_process(system, process_id))
+            {
+                Ok(mut process) => {
+                    let tracked = self.processes.get_mut(&process_id).unwrap();
+                    let handler = watch_disconnect(&self.disconnected, tracked.handler.clone());
+                    self.backend.set_process_callback(&mut process, handler)?;
+                    tracked.process = process;
+                }
+                Err(error) if error.is_not_found() => {
+                    self.processes.remove(&process_id);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run<T, F>(&mut self, idempotent: bool, mut operation: F) -> HcsResult<T>
+    where
+        F: FnMut(&Self) -> HcsResult<T>,
+    {
+        let mut retry = 0;
+        loop {
+            if self.is_disconnected() {
+                self.reconnect()?;
+            }
+
+            let error = match operation(self) {
+                Ok(result) => return Ok(result),
+                Err(error) => error,
+            };
+
+            if error.is_service_disconnect() {
+                self.disconnected.store(true, Ordering::SeqCst);
+            }
+
+            if !idempotent || !error.is_transient() || retry + 1 >= self.policy.max_attempts {
+                return Err(error);
+            }
+
+            (self.policy.sleep)(self.policy.backoff(retry));
+            retry += 1;
+        }
+    }
+
+    /// Runs an idempotent operation on the compute system, reconnecting after
+    /// service disconnects and retrying it on transient failures.
+    pub fn call<T, F>(&mut self, mut operation: F) -> HcsResult<T>
+    where
+        F: FnMut(&B, &B::System) -> HcsResult<T>,
+    {
+        self.run(true, |this| operation(&this.backend, &this.system))
+    }
+
+    /// Runs an operation on the compute system that must not be repeated.
+    /// Pending reconnects are done before running it, but it is never retried.
+    pub fn call_once<T, F>(&mut self, operation: F) -> HcsResult<T>
+    where
+        F: FnOnce(&B, &B::System) -> HcsResult<T>,
+    {
+        let mut operation = Some(operation);
+        self.run(false, |this| match operation.take() {
+            Some(operation) => operation(&this.backend, &this.system),
+            None => Err(ResultCode::Unexpected),
+        })
+    }
+
+    /// Runs an idempotent operation on a tracked process, reconnecting after
+    /// service disconnects and retrying it on transient failures.
+    pub fn call_process<T, F>(&mut self, process_id: u32, mut operation: F) -> HcsResult<T>
+    where
+        F: FnMut(&B, &B::Process) -> HcsResult<T>,
+    {
+        self.run(true, |this| match this.processes.get(&process_id) {
+            Some(tracked) => operation(&this.backend, &tracked.process),
+            None => Err(ResultCode::InvalidArgument),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computecore::testing::{FakeBackend, FakeHandle};
+    use crate::schema::options::PauseOptions;
+    use crate::schema::responses::system::State;
+
+    fn no_sleep_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            sleep: |_| {},
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retry_policy() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10), Duration::from_secs(5));
+        assert_eq!(policy.backoff(40), Duration::from_secs(5));
+
+        let backend = FakeBackend::default();
+        backend.insert("uvm", "", State::Running);
+        for result in [
+            Err(ResultCode::HcsOperationTimeout),
+            Err(ResultCode::HcsConnectionTimeout),
+            Ok(()),
+            Err(ResultCode::HcsOperationTimeout),
+            Err(ResultCode::HcsOperationTimeout),
+            Err(ResultCode::HcsOperationTimeout),
+            Err(ResultCode::HcsInvalidState),
+        ] {
+            backend.script("pause", result);
+        }
+
+        let mut system = ReconnectingSystem::open(backend, "uvm", no_sleep_policy()).unwrap();
+        let pause = |backend: &FakeBackend, system: &FakeHandle| {
+            backend.pause_system(system, &PauseOptions::default())
+        };
+
+        assert_eq!(system.call(pause), Ok(()));
+        assert_eq!(system.call(pause), Err(ResultCode::HcsOperationTimeout));
+        assert_eq!(system.call(pause), Err(ResultCode::HcsInvalidState));
+        assert_eq!(system.reconnect_count(), 0);
+    }
+
+    #[test]
+    fn reconnect_after_service_disconnect() {
+        let backend = FakeBackend::default();
+        backend.insert("uvm", "", State::Running);
+        backend.add_process(10);
+        backend.add_process(20);
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let handler: EventHandler = Arc::new(move |event_type, _| {
+            recorded.lock().unwrap().push(event_type);
+        });
+
+        let mut system = ReconnectingSystem::open(backend, "uvm", no_sleep_policy()).unwrap();
+        system.set_event_handler(handler.clone()).unwrap();
+        system.open_process(10, Some(handler)).unwrap();
+        system.open_process(20, None).unwrap();
+        assert_eq!(system.open_process(30, None), Err(ResultCode::FileNotFound));
+
+        // Disconnect notified through callbacks: handles are opened again before the next call
+        system.backend().remove_process(20);
+        system.backend().restart_service();
+        assert!(system.is_disconnected());
+        assert_eq!(
+            events.lock().unwrap().clone(),
+            vec![HcsEventType::ServiceDisconnect; 2]
+        );
+
+        assert_eq!(
+            system.call(|backend, system| backend.system_state(system)),
+            Ok(State::Running)
+        );
+        assert_eq!(system.reconnect_count(), 1);
+        assert_eq!(system.process_ids(), vec![10]);
+        assert_eq!(system.system().id, "uvm");
+        assert_eq!(
+            system.call_process(10, |backend, process| backend.check(process)),
+            Ok(())
+        );
+
+        // Disconnect only noticed through a failed call: idempotent calls are retried
+        system.backend().disconnect();
+        assert_eq!(
+            system.call(|backend, system| backend.resume_system(system)),
+            Ok(())
+        );
+        assert_eq!(system.reconnect_count(), 2);
+
+        // Operations that must not be repeated fail, but reconnect on the next call
+        system.backend().disconnect();
+        assert_eq!(
+            system.call_once(|backend, system| backend.start_system(system)),
+            Err(ResultCode::HcsServiceDisconnect)
+        );
+        assert!(system.is_disconnected());
+        assert_eq!(
+            system.call_once(|backend, system| backend.start_system(system)),
+            Ok(())
+        );
+        assert_eq!(system.reconnect_count(), 3);
+        let opens: Vec<String> = system
+            .backend()
+            .take_operations()
+            .into_iter()
+            .filter(|operation| operation.starts_with("open"))
+            .collect();
+        assert_eq!(
+            opens,
+            vec![
+                "open uvm",
+                "open_process uvm/10",
+                "open_process uvm/20",
+                "open_process uvm/30",
+                "open uvm",
+                "open_process uvm/10",
+                "open_process uvm/20",
+                "open uvm",
+                "open_process uvm/10",
+                "open uvm",
+                "open_process uvm/10",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1392 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Recording of the calls made through the compute system and network backends,
+//! and their deterministic replay.
+//!
+//! `RecordingBackend` wraps the backends used on a Windows host, usually `HcsServiceBackend`
+//! and `HcnServiceBackend`, and writes every call with its input document, result document
+//! and HRESULT, as well as the events received by callbacks, to a trace with one JSON entry per line.
+//!
+//! `ReplayBackend` reads such a trace and stands in for both backends on any host. Each call
+//! must match the next recorded one, and gets back the recorded result. Recorded events are
+//! delivered to the registered callbacks in the order they were received: events received
+//! while a call was in flight are delivered once that call is replayed, before it returns,
+//! and the others before the call that followed them in the recording.
+//!
+//! Documents are recorded with credentials, secrets and environment variables redacted,
+//! and calls are matched on their redacted documents.
+
+use crate::compute::defs::HcsEventType;
+use crate::compute::errorcodes::ResultCode;
+use crate::compute::trace::{redact_document, redact_value};
+use crate::computecore::lifecycle::HcsLifecycleBackend;
+use crate::computecore::reconciler::HcsFleetBackend;
+use crate::computecore::reconnect::{EventHandler, HcsReconnectBackend};
+use crate::computecore::snapshot::HcsComputeBackend;
+use crate::computenetwork::backend::HcnBackend;
+use crate::computenetwork::{ErrorResult, HcnResult};
+use crate::schema::options::{PauseOptions, SaveOptions};
+use crate::schema::requests::system::{ModifySettingRequest, SystemQuery};
+use crate::schema::responses::system::{Properties, State};
+use crate::schema::utils::{is_default, GuidSerde};
+use crate::schema::ComputeSystem;
+use crate::HcsResult;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use winutils_rs::windefs::HResult;
+
+/// Call made through a backend.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RecordedCall {
+    /// Number of the call in the trace, starting at 1.
+    #[serde(default, rename = "Id", skip_serializing_if = "is_default")]
+    pub id: u64,
+
+    /// Name of the backend function.
+    #[serde(rename = "Api")]
+    pub api: String,
+
+    /// ID of the compute system or network object the call is made on.
+    #[serde(default, rename = "Target", skip_serializing_if = "String::is_empty")]
+    pub target: String,
+
+    #[serde(default, rename = "Input", skip_serializing_if = "Value::is_null")]
+    pub input: Value,
+
+    /// Result document of the call, or error record of failed HCN calls.
+    #[serde(default, rename = "Output", skip_serializing_if = "Value::is_null")]
+    pub output: Value,
+
+    #[serde(default, rename = "HResult", skip_serializing_if = "is_default")]
+    pub hresult: HResult,
+}
+
+/// Event received by a compute system or process callback.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    /// ID of the compute system the event is about.
+    #[serde(rename = "Target")]
+    pub target: String,
+
+    #[serde(default, rename = "ProcessId", skip_serializing_if = "Option::is_none")]
+    pub process_id: Option<u32>,
+
+    /// ID of the call in flight when the event was received, if any.
+    #[serde(default, rename = "Call", skip_serializing_if = "Option::is_none")]
+    pub call: Option<u64>,
+
+    /// Value of the `HcsEventType`.
+    #[serde(rename = "EventType")]
+    pub event_type: u32,
+
+    #[serde(
+        default,
+        rename = "EventData",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub event_data: String,
+}
+
+impl RecordedEvent {
+    pub fn event_type(&self) -> HcsEventType {
+        match self.event_type {
+            0x00000001 => HcsEventType::SystemExited,
+            0x00000002 => HcsEventType::SystemCrashInitiated,
+            0x00000003 => HcsEventType::SystemCrashReport,
+            0x00000004 => HcsEventType::SystemRdpEnhancedModeStateChanged,
+            0x00000005 => HcsEventType::SystemSiloJobCreated,
+            0x00000006 => HcsEventType::SystemGuestConnectionClosed,
+            0x00010000 => HcsEventType::ProcessExited,
+            0x01000000 => HcsEventType::OperationCallback,
+            0x02000000 => HcsEventType::ServiceDisconnect,
+            _ => HcsEventType::Invalid,
+        }
+    }
+}
+
+/// Entry of a trace, one per line.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum TraceEntry {
+    Call(RecordedCall),
+    Event(RecordedEvent),
+}
+
+/// Returns the JSON value of a document, or the document as a string if it isn't JSON.
+fn document_value(document: &str) -> Value {
+    serde_json::from_str(document).unwrap_or_else(|_| Value::from(document))
+}
+
+fn document_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(document) => document.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+/// Calls of a trace that started and haven't been recorded yet.
+#[derive(Default)]
+struct CallsInFlight {
+    last_id: u64,
+    ids: Vec<u64>,
+}
+
+/// Writes trace entries, one JSON object per line. Clones write to the same trace.
+#[derive(Clone)]
+pub struct TraceRecorder {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    error: Arc<Mutex<Option<std::io::Error>>>,
+    calls: Arc<Mutex<CallsInFlight>>,
+}
+
+impl TraceRecorder {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> TraceRecorder {
+        TraceRecorder {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            error: Arc::new(Mutex::new(None)),
+            calls: Arc::new(Mutex::new(CallsInFlight::default())),
+        }
+    }
+
+    /// Creates a trace file, replacing any existing one.
+    pub fn create<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<TraceRecorder> {
+        Ok(TraceRecorder::new(std::fs::File::create(path)?))
+    }
+
+    /// Writes an entry to the trace. Entries are flushed as they are written,
+    /// so traces of processes that crash are complete.
+    pub fn record(&self, entry: &TraceEntry) {
+        let line = serde_json::to_string(entry).expect("Failed to serialize trace entry");
+        let mut writer = self.writer.lock().unwrap();
+
+        if let Err(error) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            self.error.lock().unwrap().get_or_insert(error);
+        }
+    }
+
+    /// Returns the first error writing the trace, if any.
+    pub fn take_error(&self) -> Option<std::io::Error> {
+        self.error.lock().unwrap().take()
+    }
+
+    /// Returns the ID of a call that is starting.
+    fn start_call(&self) -> u64 {
+        let mut calls = self.calls.lock().unwrap();
+        calls.last_id += 1;
+        let id = calls.last_id;
+        calls.ids.push(id);
+        id
+    }
+
+    /// Records a call once it returned.
+    fn finish_call(&self, call: RecordedCall) {
+        self.record(&TraceEntry::Call(call.clone()));
+        self.calls.lock().unwrap().ids.retain(|id| *id != call.id);
+    }
+
+    /// Returns the ID of the latest call that started and hasn't returned yet.
+    fn call_in_flight(&self) -> Option<u64> {
+        self.calls.lock().unwrap().ids.last().cloned()
+    }
+}
+
+/// Compute system opened through a `RecordingBackend`.
+pub struct RecordedSystem<S> {
+    id: String,
+    system: S,
+}
+
+impl<S> RecordedSystem<S> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.system
+    }
+}
+
+/// Process opened through a `RecordingBackend`.
+pub struct RecordedProcess<P> {
+    system_id: String,
+    process_id: u32,
+    process: P,
+}
+
+impl<P> RecordedProcess<P> {
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.process
+    }
+}
+
+/// Backend that records the calls made through another backend, and the events it delivers.
+pub struct RecordingBackend<B> {
+    backend: B,
+    recorder: TraceRecorder,
+}
+
+impl<B> RecordingBackend<B> {
+    pub fn new(backend: B, recorder: TraceRecorder) -> RecordingBackend<B> {
+        RecordingBackend { backend, recorder }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn recorder(&self) -> &TraceRecorder {
+        &self.recorder
+    }
+
+    fn record_call(
+        &self,
+        id: u64,
+        api: &str,
+        target: &str,
+        mut input: Value,
+        mut output: Value,
+        hresult: HResult,
+    ) {
+        redact_value(&mut input);
+        redact_value(&mut output);
+        self.recorder.finish_call(RecordedCall {
+            id,
+            api: String::from(api),
+            target: String::from(target),
+            input,
+            output,
+            hresult,
+        });
+    }
+
+    fn record<T, C, F>(
+        &self,
+        api: &str,
+        target: &str,
+        input: Value,
+        call: C,
+        output: F,
+    ) -> HcsResult<T>
+    where
+        C: FnOnce() -> HcsResult<T>,
+        F: FnOnce(&T) -> Value,
+    {
+        let id = self.recorder.start_call();
+        let result = call();
+        match &result {
+            Ok(value) => self.record_call(id, api, target, input, output(value), 0),
+            Err(error) => self.record_call(id, api, target, input, Value::Null, error.to_hresult()),
+        }
+        result
+    }
+
+    fn record_hcn<T, C, F>(
+        &self,
+        api: &str,
+        target: &str,
+        input: Value,
+        call: C,
+        output: F,
+    ) -> HcnResult<T>
+    where
+        C: FnOnce() -> HcnResult<T>,
+        F: FnOnce(&T) -> Value,
+    {
+        let id = self.recorder.start_call();
+        let result = call();
+        match &result {
+            Ok(value) => self.record_call(id, api, target, input, output(value), 0),
+            Err(error) => self.record_call(
+                id,
+                api,
+                target,
+                input,
+                document_value(&error.error_record),
+                error.result_code.to_hresult(),
+            ),
+        }
+        result
+    }
+
+    fn recording_handler(
+        &self,
+        target: &str,
+        process_id: Option<u32>,
+        handler: EventHandler,
+    ) -> EventHandler {
+        let recorder = self.recorder.clone();
+        let target = String::from(target);
+
+        Arc::new(move |event_type, event_data| {
+            recorder.record(&TraceEntry::Event(RecordedEvent {
+                target: target.clone(),
+                process_id,
+                call: recorder.call_in_flight(),
+                event_type: event_type as u32,
+                event_data: match event_data.is_empty() {
+                    true => String::new(),
+                    false => redact_document(event_data),
+                },
+            }));
+            handler(event_type, event_data)
+        })
+    }
+}
+
+impl<B: HcsComputeBackend> HcsComputeBackend for RecordingBackend<B> {
+    type System = RecordedSystem<B::System>;
+
+    fn create_system(&self, id: &str, document: &ComputeSystem) -> HcsResult<Self::System> {
+        self.record(
+            "create_system",
+            id,
+            to_value(document),
+            || self.backend.create_system(id, document),
+            |_| Value::Null,
+        )
+        .map(|system| RecordedSystem {
+            id: String::from(id),
+            system,
+        })
+    }
+
+    fn open_system(&self, id: &str) -> HcsResult<Self::System> {
+        self.record(
+            "open_system",
+            id,
+            Value::Null,
+            || self.backend.open_system(id),
+            |_| Value::Null,
+        )
+        .map(|system| RecordedSystem {
+            id: String::from(id),
+            system,
+        })
+    }
+
+    fn start_system(&self, system: &Self::System) -> HcsResult<()> {
+        self.record(
+            "start_system",
+            &system.id,
+            Value::Null,
+            || self.backend.start_system(&system.system),
+            |_| Value::Null,
+        )
+    }
+
+    fn pause_system(&self, system: &Self::System, options: &PauseOptions) -> HcsResult<()> {
+        self.record(
+            "pause_system",
+            &system.id,
+            to_value(options),
+            || self.backend.pause_system(&system.system, options),
+            |_| Value::Null,
+        )
+    }
+
+    fn resume_system(&self, system: &Self::System) -> HcsResult<()> {
+        self.record(
+            "resume_system",
+            &system.id,
+            Value::Null,
+            || self.backend.resume_system(&system.system),
+            |_| Value::Null,
+        )
+    }
+
+    fn save_system(&self, system: &Self::System, options: &SaveOptions) -> HcsResult<()> {
+        self.record(
+            "save_system",
+            &system.id,
+            to_value(options),
+            || self.backend.save_system(&system.system, options),
+            |_| Value::Null,
+        )
+    }
+
+    fn terminate_system(&self, system: &Self::System) -> HcsResult<()> {
+        self.record(
+            "terminate_system",
+            &system.id,
+            Value::Null,
+            || self.backend.terminate_system(&system.system),
+            |_| Value::Null,
+        )
+    }
+
+    fn system_state(&self, system: &Self::System) -> HcsResult<State> {
+        self.record(
+            "system_state",
+            &system.id,
+            Value::Null,
+            || self.backend.system_state(&system.system),
+            to_value,
+        )
+    }
+
+    fn create_runtime_state_file(&self, system_id: &str, path: &str) -> HcsResult<()> {
+        self.record(
+            "create_runtime_state_file",
+            system_id,
+            Value::from(path),
+            || self.backend.create_runtime_state_file(system_id, path),
+            |_| Value::Null,
+        )
+    }
+}
+
+impl<B: HcsReconnectBackend> HcsReconnectBackend for RecordingBackend<B> {
+    type Process = RecordedProcess<B::Process>;
+
+    fn open_process(&self, system: &Self::System, process_id: u32) -> HcsResult<Self::Process> {
+        self.record(
+            "open_process",
+            &system.id,
+            Value::from(process_id),
+            || self.backend.open_process(&system.system, process_id),
+            |_| Value::Null,
+        )
+        .map(|process| RecordedProcess {
+            system_id: system.id.clone(),
+            process_id,
+            process,
+        })
+    }
+
+    fn set_system_callback(
+        &self,
+        system: &mut Self::System,
+        handler: EventHandler,
+    ) -> HcsResult<()> {
+        let handler = self.recording_handler(&system.id, None, handler);
+        let inner = &mut system.system;
+        self.record(
+            "set_system_callback",
+            &system.id,
+            Value::Null,
+            || self.backend.set_system_callback(inner, handler),
+            |_| Value::Null,
+        )
+    }
+
+    fn set_process_callback(
+        &self,
+        process: &mut Self::Process,
+        handler: EventHandler,
+    ) -> HcsResult<()> {
+        let handler = self.recording_handler(&process.system_id, Some(process.process_id), handler);
+        let inner = &mut process.process;
+        self.record(
+            "set_process_callback",
+            &process.system_id,
+            Value::from(process.process_id),
+            || self.backend.set_process_callback(inner, handler),
+            |_| Value::Null,
+        )
+    }
+}
+
+impl<B: HcsLifecycleBackend> HcsLifecycleBackend for RecordingBackend<B> {
+    fn shutdown_system(&self, system: &Self::System, timeout: Duration) -> HcsResult<()> {
+        self.record(
+            "shutdown_system",
+            &system.id,
+            Value::from(timeout.as_millis() as u64),
+            || self.backend.shutdown_system(&system.system, timeout),
+            |_| Value::Null,
+        )
+    }
+}
+
+impl<B: HcsFleetBackend> HcsFleetBackend for RecordingBackend<B> {
+    fn enumerate_systems(&self, query: &SystemQuery) -> HcsResult<Vec<Properties>> {
+        self.record(
+            "enumerate_systems",
+            "",
+            to_value(query),
+            || self.backend.enumerate_systems(query),
+            to_value,
+        )
+    }
+
+    fn modify_system(
+        &self,
+        system: &Self::System,
+        request: &ModifySettingRequest,
+    ) -> HcsResult<()> {
+        self.record(
+            "modify_system",
+            &system.id,
+            to_value(request),
+            || self.backend.modify_system(&system.system, request),
+            |_| Value::Null,
+        )
+    }
+}
+
+/// Implements the calls of `HcnBackend` out of the functions that record or replay them.
+macro_rules! hcn_backend_calls {
+    ($call:ident, $call_with_id:ident, $call_with_settings:ident) => {
+        fn enumerate_networks(&self, query: &str) -> HcnResult<String> {
+            $call!(self, enumerate_networks, "", document_value(query), query)
+        }
+
+        fn create_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, create_network, id, settings)
+        }
+
+        fn modify_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, modify_network, id, settings)
+        }
+
+        fn query_network_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+            $call_with_settings!(self, query_network_properties, id, query)
+        }
+
+        fn delete_network(&self, id: &GuidSerde) -> HcnResult<()> {
+            $call_with_id!(self, delete_network, id)
+        }
+
+        fn enumerate_namespaces(&self, query: &str) -> HcnResult<String> {
+            $call!(self, enumerate_namespaces, "", document_value(query), query)
+        }
+
+        fn create_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, create_namespace, id, settings)
+        }
+
+        fn modify_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, modify_namespace, id, settings)
+        }
+
+        fn query_namespace_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+            $call_with_settings!(self, query_namespace_properties, id, query)
+        }
+
+        fn delete_namespace(&self, id: &GuidSerde) -> HcnResult<()> {
+            $call_with_id!(self, delete_namespace, id)
+        }
+
+        fn enumerate_endpoints(&self, query: &str) -> HcnResult<String> {
+            $call!(self, enumerate_endpoints, "", document_value(query), query)
+        }
+
+        fn create_endpoint(
+            &self,
+            network: &GuidSerde,
+            id: &GuidSerde,
+            settings: &str,
+        ) -> HcnResult<()> {
+            let input = serde_json::json!({
+                "Network": network.to_string(),
+                "Settings": document_value(settings),
+            });
+            $call!(
+                self,
+                create_endpoint,
+                &id.to_string(),
+                input,
+                network,
+                id,
+                settings
+            )
+        }
+
+        fn modify_endpoint(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, modify_endpoint, id, settings)
+        }
+
+        fn query_endpoint_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+            $call_with_settings!(self, query_endpoint_properties, id, query)
+        }
+
+        fn delete_endpoint(&self, id: &GuidSerde) -> HcnResult<()> {
+            $call_with_id!(self, delete_endpoint, id)
+        }
+
+        fn enumerate_load_balancers(&self, query: &str) -> HcnResult<String> {
+            $call!(self, enumerate_load_balancers, "", document_value(query), query)
+        }
+
+        fn create_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, create_load_balancer, id, settings)
+        }
+
+        fn modify_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+            $call_with_settings!(self, modify_load_balancer, id, settings)
+        }
+
+        fn query_load_balancer_properties(
+            &self,
+            id: &GuidSerde,
+            query: &str,
+        ) -> HcnResult<String> {
+            $call_with_settings!(self, query_load_balancer_properties, id, query)
+        }
+
+        fn delete_load_balancer(&self, id: &GuidSerde) -> HcnResult<()> {
+            $call_with_id!(self, delete_load_balancer, id)
+        }
+    };
+}
+
+macro_rules! record_hcn_call {
+    ($self:ident, $api:ident, $target:expr, $input:expr, $($arg:expr),*) => {
+        $self.record_hcn(
+            stringify!($api),
+            $target,
+            $input,
+            || $self.backend.$api($($arg),*),
+            HcnOutput::to_output,
+        )
+    };
+}
+
+macro_rules! record_hcn_call_with_id {
+    ($self:ident, $api:ident, $id:expr) => {
+        record_hcn_call!($self, $api, &$id.to_string(), Value::Null, $id)
+    };
+}
+
+macro_rules! record_hcn_call_with_settings {
+    ($self:ident, $api:ident, $id:expr, $settings:expr) => {
+        record_hcn_call!(
+            $self,
+            $api,
+            &$id.to_string(),
+            document_value($settings),
+            $id,
+            $settings
+        )
+    };
+}
+
+impl<B: HcnBackend> HcnBackend for RecordingBackend<B> {
+    hcn_backend_calls!(
+        record_hcn_call,
+        record_hcn_call_with_id,
+        record_hcn_call_with_settings
+    );
+}
+
+/// Call that didn't match the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayMismatch {
+    /// Position of the expected entry in the trace.
+    pub position: usize,
+
+    /// Entry expected at that position, if the trace didn't end.
+    pub expected: Option<TraceEntry>,
+
+    pub actual: RecordedCall,
+}
+
+impl std::fmt::Display for ReplayMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let actual = serde_json::to_string(&self.actual).unwrap_or_default();
+
+        match &self.expected {
+            Some(expected) => write!(
+                f,
+                "Entry {} of the trace is {}, but the call was {}",
+                self.position,
+                serde_json::to_string(expected).unwrap_or_default(),
+                actual
+            ),
+            None => write!(f, "The trace ended, but the call was {}", actual),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+
+    /// A line of the trace is not a valid entry.
+    Parse {
+        line: usize,
+        message: String,
+    },
+
+    /// A call didn't match the recording. Only the first mismatch is reported.
+    Mismatch(Box<ReplayMismatch>),
+
+    /// Entries of the trace were not replayed.
+    Incomplete {
+        remaining: usize,
+    },
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "Failed to read the trace: {}", error),
+            ReplayError::Parse { line, message } => {
+                write!(f, "Invalid trace entry at line {}: {}", line, message)
+            }
+            ReplayError::Mismatch(mismatch) => mismatch.fmt(f),
+            ReplayError::Incomplete { remaining } => {
+                write!(f, "{} entries of the trace were not replayed", remaining)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(error: std::io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+/// Compute system opened through a `ReplayBackend`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaySystem {
+    id: String,
+}
+
+impl ReplaySystem {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Process opened through a `ReplayBackend`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayProcess {
+    system_id: String,
+    process_id: u32,
+}
+
+impl ReplayProcess {
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+}
+
+struct ReplayState {
+    entries: Vec<TraceEntry>,
+    position: usize,
+    mismatches: Vec<ReplayMismatch>,
+
+    /// Event handlers by compute system ID and process ID.
+    handlers: BTreeMap<(String, Option<u32>), EventHandler>,
+}
+
+/// Backend that replays a trace. Clones replay the same trace, so a single trace
+/// can back both the compute system and the network code under test.
+#[derive(Clone)]
+pub struct ReplayBackend {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayBackend {
+    pub fn new(entries: Vec<TraceEntry>) -> ReplayBackend {
+        ReplayBackend {
+            state: Arc::new(Mutex::new(ReplayState {
+                entries: events_after_calls(entries),
+                position: 0,
+                mismatches: Vec::new(),
+                handlers: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Reads a trace written by a `TraceRecorder`. Empty lines are ignored.
+    pub fn parse<R: BufRead>(reader: R) -> Result<ReplayBackend, ReplayError> {
+        let mut entries = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            entries.push(
+                serde_json::from_str(&line).map_err(|error| ReplayError::Parse {
+                    line: index + 1,
+                    message: error.to_string(),
+                })?,
+            );
+        }
+
+        Ok(ReplayBackend::new(entries))
+    }
+
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<ReplayBackend, ReplayError> {
+        ReplayBackend::parse(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Delivers the recorded events that come next in the trace to their handlers.
+    /// Events without a handler are dropped, like they would be by HCS.
+    pub fn dispatch_events(&self) {
+        self.dispatch(|_| true);
+    }
+
+    /// Delivers the events that come next in the trace, as long as `filter` accepts them.
+    fn dispatch<F: Fn(&RecordedEvent) -> bool>(&self, filter: F) {
+        loop {
+            let (handler, event) = {
+                let mut state = self.state.lock().unwrap();
+                let event = match state.entries.get(state.position) {
+                    Some(TraceEntry::Event(event)) if filter(event) => event.clone(),
+                    _ => return,
+                };
+                state.position += 1;
+
+                let key = (event.target.clone(), event.process_id);
+                (state.handlers.get(&key).cloned(), event)
+            };
+
+            // Handlers are called without holding the lock, since they can call back into the backend
+            if let Some(handler) = handler {
+                handler(event.event_type(), &event.event_data);
+            }
+        }
+    }
+
+    /// Returns the calls that didn't match the recording so far.
+    pub fn mismatches(&self) -> Vec<ReplayMismatch> {
+        self.state.lock().unwrap().mismatches.clone()
+    }
+
+    /// Returns the number of entries of the trace not replayed yet.
+    pub fn remaining(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.entries.len() - state.position
+    }
+
+    /// Verifies that all calls matched the recording and the whole trace was replayed.
+    pub fn finish(&self) -> Result<(), ReplayError> {
+        self.dispatch_events();
+
+        if let Some(mismatch) = self.mismatches().into_iter().next() {
+            return Err(ReplayError::Mismatch(Box::new(mismatch)));
+        }
+
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(ReplayError::Incomplete { remaining }),
+        }
+    }
+
+    /// Matches a call against the next entry of the trace, returning the recorded call
+    /// once the events received during it are delivered.
+    /// Calls that don't match are reported as mismatches, and don't advance the replay.
+    fn replay(&self, api: &str, target: &str, mut input: Value) -> Result<RecordedCall, ()> {
+        self.dispatch_events();
+
+        redact_value(&mut input);
+        let actual = RecordedCall {
+            id: 0,
+            api: String::from(api),
+            target: String::from(target),
+            input,
+            output: Value::Null,
+            hresult: 0,
+        };
+
+        let recorded = {
+            let mut state = self.state.lock().unwrap();
+            let position = state.position;
+
+            match state.entries.get(position) {
+                Some(TraceEntry::Call(recorded))
+                    if recorded.api == actual.api
+                        && recorded.target == actual.target
+                        && recorded.input == actual.input =>
+                {
+                    let recorded = recorded.clone();
+                    state.position += 1;
+                    recorded
+                }
+                expected => {
+                    let mismatch = ReplayMismatch {
+                        position,
+                        expected: expected.cloned(),
+                        actual,
+                    };
+                    state.mismatches.push(mismatch);
+                    return Err(());
+                }
+            }
+        };
+
+        self.dispatch(|event| event.call == Some(recorded.id));
+        Ok(recorded)
+    }
+
+    fn replay_hcs<T, F>(&self, api: &str, target: &str, input: Value, output: F) -> HcsResult<T>
+    where
+        F: FnOnce(&Value) -> Option<T>,
+    {
+        let recorded = self
+            .replay(api, target, input)
+            .map_err(|_| ResultCode::Unexpected)?;
+
+        match recorded.hresult {
+            0 => output(&recorded.output).ok_or(ResultCode::Unexpected),
+            hresult => Err(ResultCode::from_hresult(hresult)),
+        }
+    }
+
+    fn replay_hcn<T, F>(&self, api: &str, target: &str, input: Value, output: F) -> HcnResult<T>
+    where
+        F: FnOnce(&Value) -> Option<T>,
+    {
+        let recorded = self.replay(api, target, input).map_err(|_| ErrorResult {
+            error_record: format!("{} doesn't match the recording", api),
+            result_code: ResultCode::Unexpected,
+        })?;
+
+        match recorded.hresult {
+            0 => output(&recorded.output).ok_or(ErrorResult {
+                error_record: format!("Invalid recorded output of {}", api),
+                result_code: ResultCode::Unexpected,
+            }),
+            hresult => Err(ErrorResult {
+                error_record: document_string(&recorded.output),
+                result_code: ResultCode::from_hresult(hresult),
+            }),
+        }
+    }
+
+    fn set_handler(&self, system_id: &str, process_id: Option<u32>, handler: EventHandler) {
+        self.state
+            .lock()
+            .unwrap()
+            .handlers
+            .insert((String::from(system_id), process_id), handler);
+    }
+}
+
+/// Moves the events received during a call right after it, since calls are recorded
+/// once they return. Events of calls that never returned are left at the end.
+fn events_after_calls(entries: Vec<TraceEntry>) -> Vec<TraceEntry> {
+    let mut ordered = Vec::with_capacity(entries.len());
+    let mut returned = BTreeSet::new();
+    let mut pending: BTreeMap<u64, Vec<TraceEntry>> = BTreeMap::new();
+
+    for entry in entries {
+        match &entry {
+            TraceEntry::Call(call) if call.id != 0 => {
+                returned.insert(call.id);
+                ordered.push(entry.clone());
+                ordered.extend(pending.remove(&call.id).unwrap_or_default());
+                continue;
+            }
+            TraceEntry::Event(RecordedEvent {
+                call: Some(call), ..
+            }) if !returned.contains(call) => {
+                pending.entry(*call).or_default().push(entry);
+                continue;
+            }
+            _ => {}
+        }
+        ordered.push(entry);
+    }
+
+    ordered.extend(pending.into_values().flatten());
+    ordered
+}
+
+fn no_output(_: &Value) -> Option<()> {
+    Some(())
+}
+
+fn from_output<T: serde::de::DeserializeOwned>(output: &Value) -> Option<T> {
+    serde_json::from_value(output.clone()).ok()
+}
+
+impl HcsComputeBackend for ReplayBackend {
+    type System = ReplaySystem;
+
+    fn create_system(&self, id: &str, document: &ComputeSystem) -> HcsResult<ReplaySystem> {
+        self.replay_hcs("create_system", id, to_value(document), no_output)?;
+        Ok(ReplaySystem {
+            id: String::from(id),
+        })
+    }
+
+    fn open_system(&self, id: &str) -> HcsResult<ReplaySystem> {
+        self.replay_hcs("open_system", id, Value::Null, no_output)?;
+        Ok(ReplaySystem {
+            id: String::from(id),
+        })
+    }
+
+    fn start_system(&self, system: &ReplaySystem) -> HcsResult<()> {
+        self.replay_hcs("start_system", &system.id, Value::Null, no_output)
+    }
+
+    fn pause_system(&self, system: &ReplaySystem, options: &PauseOptions) -> HcsResult<()> {
+        self.replay_hcs("pause_system", &system.id, to_value(options), no_output)
+    }
+
+    fn resume_system(&self, system: &ReplaySystem) -> HcsResult<()> {
+        self.replay_hcs("resume_system", &system.id, Value::Null, no_output)
+    }
+
+    fn save_system(&self, system: &ReplaySystem, options: &SaveOptions) -> HcsResult<()> {
+        self.replay_hcs("save_system", &system.id, to_value(options), no_output)
+    }
+
+    fn terminate_system(&self, system: &ReplaySystem) -> HcsResult<()> {
+        self.replay_hcs("terminate_system", &system.id, Value::Null, no_output)
+    }
+
+    fn system_state(&self, system: &ReplaySystem) -> HcsResult<State> {
+        self.replay_hcs("system_state", &system.id, Value::Null, from_output)
+    }
+
+    fn create_runtime_state_file(&self, system_id: &str, path: &str) -> HcsResult<()> {
+        self.replay_hcs(
+            "create_runtime_state_file",
+            system_id,
+            Value::from(path),
+            no_output,
+        )
+    }
+}
+
+impl HcsReconnectBackend for ReplayBackend {
+    type Process = ReplayProcess;
+
+    fn open_process(&self, system: &ReplaySystem, process_id: u32) -> HcsResult<ReplayProcess> {
+        self.replay_hcs(
+            "open_process",
+            &system.id,
+            Value::from(process_id),
+            no_output,
+        )?;
+        Ok(ReplayProcess {
+            system_id: system.id.clone(),
+            process_id,
+        })
+    }
+
+    fn set_system_callback(
+        &self,
+        system: &mut ReplaySystem,
+        handler: EventHandler,
+    ) -> HcsResult<()> {
+        self.replay_hcs("set_system_callback", &system.id, Value::Null, no_output)?;
+        self.set_handler(&system.id, None, handler);
+        Ok(())
+    }
+
+    fn set_process_callback(
+        &self,
+        process: &mut ReplayProcess,
+        handler: EventHandler,
+    ) -> HcsResult<()> {
+        self.replay_hcs(
+            "set_process_callback",
+            &process.system_id,
+            Value::from(process.process_id),
+            no_output,
+        )?;
+        self.set_handler(&process.system_id, Some(process.process_id), handler);
+        Ok(())
+    }
+}
+
+impl HcsLifecycleBackend for ReplayBackend {
+    fn shutdown_system(&self, system: &ReplaySystem, timeout: Duration) -> HcsResult<()> {
+        self.replay_hcs(
+            "shutdown_system",
+            &system.id,
+            Value::from(timeout.as_millis() as u64),
+            no_output,
+        )
+    }
+}
+
+impl HcsFleetBackend for ReplayBackend {
+    fn enumerate_systems(&self, query: &SystemQuery) -> HcsResult<Vec<Properties>> {
+        self.replay_hcs("enumerate_systems", "", to_value(query), from_output)
+    }
+
+    fn modify_system(
+        &self,
+        system: &ReplaySystem,
+        request: &ModifySettingRequest,
+    ) -> HcsResult<()> {
+        self.replay_hcs("modify_system", &system.id, to_value(request), no_output)
+    }
+}
+
+/// Output of an HCN call, the document or nothing the call returns. Documents are recorded
+/// parsed, so they can be redacted.
+trait HcnOutput: Sized {
+    fn to_output(&self) -> Value;
+    fn from_output(output: &Value) -> Option<Self>;
+}
+
+impl HcnOutput for () {
+    fn to_output(&self) -> Value {
+        Value::Null
+    }
+
+    fn from_output(_: &Value) -> Option<()> {
+        Some(())
+    }
+}
+
+impl HcnOutput for String {
+    fn to_output(&self) -> Value {
+        document_value(self)
+    }
+
+    fn from_output(output: &Value) -> Option<String> {
+        Some(document_string(output))
+    }
+}
+
+macro_rules! replay_hcn_call {
+    ($self:ident, $api:ident, $target:expr, $input:expr, $($arg:expr),*) => {
+        $self.replay_hcn(stringify!($api), $target, $input, HcnOutput::from_output)
+    };
+}
+
+macro_rules! replay_hcn_call_with_id {
+    ($self:ident, $api:ident, $id:expr) => {
+        replay_hcn_call!($self, $api, &$id.to_string(), Value::Null, $id)
+    };
+}
+
+macro_rules! replay_hcn_call_with_settings {
+    ($self:ident, $api:ident, $id:expr, $settings:expr) => {
+        replay_hcn_call!(
+            $self,
+            $api,
+            &$id.to_string(),
+            document_value($settings),
+            $id,
+            $settings
+        )
+    };
+}
+
+impl HcnBackend for ReplayBackend {
+    hcn_backend_calls!(
+        replay_hcn_call,
+        replay_hcn_call_with_id,
+        replay_hcn_call_with_settings
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computecore::lifecycle::ManagedSystem;
+    use crate::computecore::testing::FakeBackend;
+    use crate::computenetwork::simulator::HcnSimulator;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buffer)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns a fake backend with a running compute system "vm".
+    fn host_backend() -> FakeBackend {
+        let backend = FakeBackend::default();
+        backend.insert("vm", "", State::Running);
+        backend
+    }
+
+    /// Runs the same orchestration against a backend, returning the events it received.
+    fn orchestrate<B, F>(backend: &B, fire_event: F) -> Vec<(HcsEventType, String)>
+    where
+        B: HcsReconnectBackend,
+        F: FnOnce(),
+    {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let received = events.clone();
+
+        assert_eq!(
+            backend.create_system("vm", &ComputeSystem::default()).err(),
+            Some(ResultCode::HcsSystemAlreadyExists)
+        );
+        let mut system = backend.open_system("vm").unwrap();
+        backend
+            .set_system_callback(
+                &mut system,
+                Arc::new(move |event_type, event_data| {
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((event_type, String::from(event_data)));
+                }),
+            )
+            .unwrap();
+        backend.start_system(&system).unwrap();
+        fire_event();
+        assert_eq!(backend.system_state(&system), Ok(State::Running));
+
+        let events = events.lock().unwrap().clone();
+        events
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let buffer = SharedBuffer::default();
+        let recording = RecordingBackend::new(host_backend(), TraceRecorder::new(buffer.clone()));
+        let exited = (
+            HcsEventType::SystemExited,
+            String::from(r#"{"ExitType":"None"}"#),
+        );
+
+        let recorded_events = orchestrate(&recording, || {
+            recording.backend().notify("vm", exited.0, &exited.1)
+        });
+        assert_eq!(recorded_events, vec![exited.clone()]);
+        assert!(recording.recorder().take_error().is_none());
+
+        let trace = buffer.0.lock().unwrap().clone();
+        assert_eq!(trace.iter().filter(|byte| **byte == b'\n').count(), 6);
+
+        // Events received outside of calls are delivered before the call that followed them
+        let replay = ReplayBackend::parse(trace.as_slice()).unwrap();
+        assert_eq!(orchestrate(&replay, || {}), vec![exited]);
+        replay.finish().unwrap();
+
+        let replay = ReplayBackend::parse(trace.as_slice()).unwrap();
+        assert_eq!(
+            replay.open_system("other").err(),
+            Some(ResultCode::Unexpected)
+        );
+        match replay.finish() {
+            Err(ReplayError::Mismatch(mismatch)) => {
+                assert_eq!(mismatch.position, 0);
+                assert_eq!(mismatch.actual.target, "other");
+            }
+            other => panic!("Unexpected replay result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replay_hcn_calls() {
+        let network = GuidSerde::new();
+        let replay = ReplayBackend::new(vec![
+            TraceEntry::Call(RecordedCall {
+                id: 1,
+                api: String::from("enumerate_networks"),
+                target: String::new(),
+                input: serde_json::json!({}),
+                output: serde_json::json!([network.to_string()]),
+                hresult: 0,
+            }),
+            TraceEntry::Call(RecordedCall {
+                id: 2,
+                api: String::from("delete_network"),
+                target: network.to_string(),
+                input: Value::Null,
+                output: serde_json::json!({"Success": false, "Error": "Not found"}),
+                hresult: ResultCode::HcnNetworkNotFound.to_hresult(),
+            }),
+        ]);
+
+        assert_eq!(
+            replay.enumerate_networks("{ }").unwrap(),
+            format!(r#"["{}"]"#, network)
+        );
+        let error = replay.delete_network(&network).unwrap_err();
+        assert_eq!(error.result_code, ResultCode::HcnNetworkNotFound);
+        assert_eq!(
+            serde_json::from_str::<Value>(&error.error_record).unwrap()["Error"],
+            "Not found"
+        );
+        replay.finish().unwrap();
+    }
+
+    #[test]
+    fn record_redacted_hcn_documents() {
+        let buffer = SharedBuffer::default();
+        let recording =
+            RecordingBackend::new(HcnSimulator::new(), TraceRecorder::new(buffer.clone()));
+        let network = GuidSerde {
+            data1: 1,
+            ..GuidSerde::new()
+        };
+
+        recording
+            .create_network(&network, r#"{"Type": "NAT", "Secret": "hunter2"}"#)
+            .unwrap();
+        let properties = recording.query_network_properties(&network, "").unwrap();
+        assert!(properties.contains("hunter2"));
+
+        let trace = buffer.0.lock().unwrap().clone();
+        assert!(!String::from_utf8_lossy(&trace).contains("hunter2"));
+
+        let replay = ReplayBackend::parse(trace.as_slice()).unwrap();
+        replay
+            .create_network(&network, r#"{"Type": "NAT", "Secret": "hunter2"}"#)
+            .unwrap();
+        let properties: Value =
+            serde_json::from_str(&replay.query_network_properties(&network, "").unwrap()).unwrap();
+        assert_eq!(properties["Secret"], "<redacted>");
+        replay.finish().unwrap();
+    }
+
+    #[test]
+    fn replay_events_received_during_calls() {
+        let call = |id, api: &str, output| {
+            TraceEntry::Call(RecordedCall {
+                id,
+                api: String::from(api),
+                target: String::from("vm"),
+                input: Value::Null,
+                output,
+                hresult: 0,
+            })
+        };
+
+        // The event is recorded before the call it was received during, which returns later
+        let replay = ReplayBackend::new(vec![
+            call(1, "open_system", Value::Null),
+            call(2, "system_state", Value::from("Running")),
+            call(3, "set_system_callback", Value::Null),
+            TraceEntry::Event(RecordedEvent {
+                target: String::from("vm"),
+                process_id: None,
+                call: Some(4),
+                event_type: HcsEventType::SystemExited as u32,
+                event_data: String::new(),
+            }),
+            call(4, "terminate_system", Value::Null),
+        ]);
+
+        let managed = ManagedSystem::open(replay.clone(), "vm").unwrap();
+        managed.terminate().unwrap();
+        assert_eq!(managed.state(), State::Stopped);
+        replay.finish().unwrap();
+    }
+}
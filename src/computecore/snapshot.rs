@@ -0,0 +1,362 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Save, restore and template cloning workflows for virtual machines.
+//! The workflows are written against an abstract compute backend, so that they
+//! can be exercised without actual compute systems.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::schema::options::{PauseOptions, SaveOptions, SaveType};
+use crate::schema::responses::system::State;
+use crate::schema::virtual_machines::RestoreState;
+use crate::schema::ComputeSystem;
+use crate::HcsResult;
+
+/// Compute system operations the snapshot workflows are built on.
+pub trait HcsComputeBackend {
+    type System;
+
+    /// Creates a compute system out of a document, without starting it.
+    fn create_system(&self, id: &str, document: &ComputeSystem) -> HcsResult<Self::System>;
+
+    /// Opens an existing compute system.
+    fn open_system(&self, id: &str) -> HcsResult<Self::System>;
+
+    fn start_system(&self, system: &Self::System) -> HcsResult<()>;
+    fn pause_system(&self, system: &Self::System, options: &PauseOptions) -> HcsResult<()>;
+    fn resume_system(&self, system: &Self::System) -> HcsResult<()>;
+    fn save_system(&self, system: &Self::System, options: &SaveOptions) -> HcsResult<()>;
+    fn terminate_system(&self, system: &Self::System) -> HcsResult<()>;
+
+    /// Queries the state of a compute system.
+    fn system_state(&self, system: &Self::System) -> HcsResult<State>;
+
+    /// Creates an empty runtime state file that a compute system can be saved to.
+    fn create_runtime_state_file(&self, system_id: &str, path: &str) -> HcsResult<()>;
+}
+
+/// What to do with a virtual machine once its state has been saved to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AfterSave {
+    /// Resume the virtual machine.
+    Resume,
+    /// Leave the virtual machine paused.
+    StayPaused,
+    /// Terminate the virtual machine.
+    Terminate,
+}
+
+/// Saved state of a virtual machine, which new virtual machines can be restored
+/// or cloned from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmSnapshot {
+    /// ID of the compute system the snapshot was taken from.
+    pub system_id: String,
+
+    /// Document the compute system was created from.
+    pub document: ComputeSystem,
+
+    /// Path to the runtime state file the compute system was saved to.
+    pub save_state_file_path: String,
+
+    /// Type of save that produced the snapshot.
+    pub save_type: SaveType,
+}
+
+fn check_state(state: State, expected: &[State]) -> HcsResult<()> {
+    if expected.contains(&state) {
+        Ok(())
+    } else {
+        Err(ResultCode::HcsInvalidState)
+    }
+}
+
+/// Pauses a running virtual machine, saves it with the options and then runs `after_save` on it.
+/// If saving fails, a virtual machine paused by this function is resumed.
+fn save<B, F>(backend: &B, system_id: &str, options: &SaveOptions, after_save: F) -> HcsResult<()>
+where
+    B: HcsComputeBackend,
+    F: FnOnce(&B::System) -> HcsResult<()>,
+{
+    let system = backend.open_system(system_id)?;
+    let state = backend.system_state(&system)?;
+    check_state(state.clone(), &[State::Running, State::Paused])?;
+
+    let paused_here = state == State::Running;
+    if paused_here {
+        backend.pause_system(&system, &PauseOptions::default())?;
+    }
+
+    let saved = backend
+        .create_runtime_state_file(system_id, &options.saved_state_filepath)
+        .and_then(|_| backend.save_system(&system, options));
+
+    if let Err(error) = saved {
+        if paused_here {
+            let _ = backend.resume_system(&system);
+        }
+        return Err(error);
+    }
+
+    after_save(&system)
+}
+
+impl VmSnapshot {
+    /// Pauses a running virtual machine, saves its state to a file and then resumes,
+    /// leaves paused or terminates it.
+    ///
+    /// If saving fails, a virtual machine paused by this function is resumed.
+    pub fn take<B: HcsComputeBackend>(
+        backend: &B,
+        system_id: &str,
+        document: &ComputeSystem,
+        save_state_file_path: &str,
+        after_save: AfterSave,
+    ) -> HcsResult<VmSnapshot> {
+        let options = SaveOptions {
+            save_type: Some(SaveType::ToFile),
+            saved_state_filepath: String::from(save_state_file_path),
+        };
+
+        save(backend, system_id, &options, |system| match after_save {
+            AfterSave::Resume => backend.resume_system(system),
+            AfterSave::StayPaused => Ok(()),
+            AfterSave::Terminate => backend.terminate_system(system),
+        })?;
+
+        Ok(VmSnapshot {
+            system_id: String::from(system_id),
+            document: document.clone(),
+            save_state_file_path: String::from(save_state_file_path),
+            save_type: SaveType::ToFile,
+        })
+    }
+
+    /// Saves a virtual machine as a template, which other virtual machines can be cloned from.
+    /// The virtual machine is left in the `SavedAsTemplate` state.
+    ///
+    /// If saving fails, a virtual machine paused by this function is resumed.
+    pub fn take_template<B: HcsComputeBackend>(
+        backend: &B,
+        system_id: &str,
+        document: &ComputeSystem,
+        save_state_file_path: &str,
+    ) -> HcsResult<VmSnapshot> {
+        let options = SaveOptions {
+            save_type: Some(SaveType::AsTemplate),
+            saved_state_filepath: String::from(save_state_file_path),
+        };
+
+        save(backend, system_id, &options, |system| {
+            check_state(backend.system_state(system)?, &[State::SavedAsTemplate])
+        })?;
+
+        Ok(VmSnapshot {
+            system_id: String::from(system_id),
+            document: document.clone(),
+            save_state_file_path: String::from(save_state_file_path),
+            save_type: SaveType::AsTemplate,
+        })
+    }
+
+    fn document_with_restore_state(&self, restore_state: RestoreState) -> HcsResult<ComputeSystem> {
+        let mut document = self.document.clone();
+        match document.virtual_machine.as_mut() {
+            Some(virtual_machine) => virtual_machine.restore_state = Some(restore_state),
+            None => return Err(ResultCode::InvalidArgument),
+        }
+        Ok(document)
+    }
+
+    /// Returns the document of a new virtual machine restored from the saved state file.
+    pub fn restore_document(&self) -> HcsResult<ComputeSystem> {
+        if self.save_type != SaveType::ToFile {
+            return Err(ResultCode::HcsInvalidState);
+        }
+
+        self.document_with_restore_state(RestoreState {
+            save_state_file_path: self.save_state_file_path.clone(),
+            template_system_id: String::new(),
+        })
+    }
+
+    /// Returns the document of a new virtual machine cloned from the template.
+    pub fn clone_document(&self) -> HcsResult<ComputeSystem> {
+        if self.save_type != SaveType::AsTemplate {
+            return Err(ResultCode::HcsInvalidState);
+        }
+
+        self.document_with_restore_state(RestoreState {
+            save_state_file_path: String::new(),
+            template_system_id: self.system_id.clone(),
+        })
+    }
+
+    /// Creates and starts a new virtual machine restored from the saved state file.
+    pub fn restore<B: HcsComputeBackend>(&self, backend: &B, id: &str) -> HcsResult<B::System> {
+        let system = backend.create_system(id, &self.restore_document()?)?;
+        backend.start_system(&system)?;
+        Ok(system)
+    }
+
+    /// Creates and starts new virtual machines cloned from the template.
+    ///
+    /// The template is checked to still be in the `SavedAsTemplate` state. If a clone fails,
+    /// the clones created so far are terminated and the error is returned.
+    pub fn clone_systems<B: HcsComputeBackend>(
+        &self,
+        backend: &B,
+        ids: &[&str],
+    ) -> HcsResult<Vec<B::System>> {
+        let document = self.clone_document()?;
+        let template = backend.open_system(&self.system_id)?;
+        check_state(backend.system_state(&template)?, &[State::SavedAsTemplate])?;
+
+        let mut clones = Vec::with_capacity(ids.len());
+        for id in ids {
+            let clone = backend.create_system(id, &document).and_then(|system| {
+                match backend.start_system(&system) {
+                    Ok(()) => Ok(system),
+                    Err(error) => {
+                        let _ = backend.terminate_system(&system);
+                        Err(error)
+                    }
+                }
+            });
+
+            match clone {
+                Ok(system) => clones.push(system),
+                Err(error) => {
+                    for system in &clones {
+                        let _ = backend.terminate_system(system);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+
+        Ok(clones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computecore::testing::FakeBackend;
+    use crate::schema::VirtualMachine;
+
+    fn document() -> ComputeSystem {
+        ComputeSystem {
+            owner: String::from("owner"),
+            virtual_machine: Some(VirtualMachine::default()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let backend = FakeBackend::default();
+        backend.insert("vm", "owner", State::Running);
+
+        let snapshot =
+            VmSnapshot::take(&backend, "vm", &document(), "vm.vmrs", AfterSave::Terminate).unwrap();
+        assert_eq!(
+            backend.take_operations(),
+            vec![
+                "open vm",
+                "state vm",
+                "pause vm",
+                "file vm.vmrs",
+                "save vm",
+                "terminate vm"
+            ]
+        );
+        assert!(snapshot.clone_document().is_err());
+
+        snapshot.restore(&backend, "restored").unwrap();
+        assert_eq!(backend.state("restored"), Some(State::Running));
+        assert_eq!(
+            backend
+                .document("restored")
+                .unwrap()
+                .virtual_machine
+                .unwrap()
+                .restore_state,
+            Some(RestoreState {
+                save_state_file_path: String::from("vm.vmrs"),
+                template_system_id: String::new(),
+            })
+        );
+
+        backend.insert("vm", "owner", State::Running);
+        backend.script("save", Err(ResultCode::Unexpected));
+        assert_eq!(
+            VmSnapshot::take(&backend, "vm", &document(), "vm.vmrs", AfterSave::Terminate),
+            Err(ResultCode::Unexpected)
+        );
+        assert_eq!(backend.state("vm"), Some(State::Running));
+
+        backend.insert("stopped", "owner", State::Stopped);
+        assert_eq!(
+            VmSnapshot::take(
+                &backend,
+                "stopped",
+                &document(),
+                "s.vmrs",
+                AfterSave::Resume
+            ),
+            Err(ResultCode::HcsInvalidState)
+        );
+    }
+
+    #[test]
+    fn clone_from_template() {
+        let backend = FakeBackend::default();
+        backend.insert("template", "owner", State::Running);
+
+        let snapshot =
+            VmSnapshot::take_template(&backend, "template", &document(), "template.vmrs").unwrap();
+        assert_eq!(backend.state("template"), Some(State::SavedAsTemplate));
+
+        let clones = snapshot
+            .clone_systems(&backend, &["clone1", "clone2"])
+            .unwrap();
+        assert_eq!(
+            clones
+                .iter()
+                .map(|clone| clone.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["clone1", "clone2"]
+        );
+        assert_eq!(
+            backend
+                .document("clone2")
+                .unwrap()
+                .virtual_machine
+                .unwrap()
+                .restore_state
+                .unwrap()
+                .template_system_id,
+            "template"
+        );
+
+        backend.insert("template", "owner", State::Stopped);
+        assert_eq!(
+            snapshot.clone_systems(&backend, &["clone3"]),
+            Err(ResultCode::HcsInvalidState)
+        );
+
+        backend.insert("template", "owner", State::Running);
+        backend.script("save", Err(ResultCode::Unexpected));
+        assert_eq!(
+            VmSnapshot::take_template(&backend, "template", &document(), "template.vmrs"),
+            Err(ResultCode::Unexpected)
+        );
+        assert_eq!(backend.state("template"), Some(State::Running));
+    }
+}
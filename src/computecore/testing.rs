@@ -0,0 +1,346 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Fake compute backend shared by the tests of the compute system workflows.
+
+use crate::compute::defs::HcsEventType;
+use crate::compute::errorcodes::ResultCode;
+use crate::computecore::lifecycle::HcsLifecycleBackend;
+use crate::computecore::reconciler::HcsFleetBackend;
+use crate::computecore::reconnect::{EventHandler, HcsReconnectBackend};
+use crate::computecore::snapshot::HcsComputeBackend;
+use crate::schema::options::{PauseOptions, SaveOptions, SaveType};
+use crate::schema::requests::system::{ModifySettingRequest, SystemQuery};
+use crate::schema::responses::system::{Properties, State};
+use crate::schema::ComputeSystem;
+use crate::HcsResult;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Handle of a compute system or process, tagged with the connection it was opened on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeHandle {
+    /// ID of the compute system, or "<system ID>/<process ID>" for processes.
+    pub id: String,
+    pub connection: u32,
+}
+
+struct FakeSystem {
+    owner: String,
+    state: State,
+}
+
+#[derive(Default)]
+struct FakeHost {
+    systems: BTreeMap<String, FakeSystem>,
+    documents: BTreeMap<String, ComputeSystem>,
+    processes: BTreeSet<u32>,
+    connection: u32,
+    script: VecDeque<(&'static str, HcsResult<()>)>,
+    operations: Vec<String>,
+    held: BTreeSet<&'static str>,
+    handlers: Vec<(String, EventHandler)>,
+}
+
+/// Fake compute service tracking the state of its systems by ID.
+///
+/// Every operation is recorded as "<operation> <target>", and succeeds unless a result
+/// was scripted for it. Clones share the same service.
+#[derive(Default, Clone)]
+pub struct FakeBackend {
+    host: Arc<Mutex<FakeHost>>,
+    released: Arc<Condvar>,
+}
+
+impl FakeBackend {
+    /// Adds a compute system in a state.
+    pub fn insert(&self, id: &str, owner: &str, state: State) {
+        self.host.lock().unwrap().systems.insert(
+            String::from(id),
+            FakeSystem {
+                owner: String::from(owner),
+                state,
+            },
+        );
+    }
+
+    pub fn state(&self, id: &str) -> Option<State> {
+        let host = self.host.lock().unwrap();
+        host.systems.get(id).map(|system| system.state.clone())
+    }
+
+    /// Returns the document a compute system was created with.
+    pub fn document(&self, id: &str) -> Option<ComputeSystem> {
+        self.host.lock().unwrap().documents.get(id).cloned()
+    }
+
+    /// Returns the number of compute systems whose ID contains `pattern` in a state.
+    pub fn count(&self, pattern: &str, state: State) -> usize {
+        let host = self.host.lock().unwrap();
+        host.systems
+            .iter()
+            .filter(|(id, system)| id.contains(pattern) && system.state == state)
+            .count()
+    }
+
+    pub fn add_process(&self, process_id: u32) {
+        self.host.lock().unwrap().processes.insert(process_id);
+    }
+
+    pub fn remove_process(&self, process_id: u32) {
+        self.host.lock().unwrap().processes.remove(&process_id);
+    }
+
+    /// Queues the result of the next call to an operation, which then succeeds again.
+    pub fn script(&self, operation: &'static str, result: HcsResult<()>) {
+        self.host
+            .lock()
+            .unwrap()
+            .script
+            .push_back((operation, result));
+    }
+
+    /// Makes the calls to an operation wait, once recorded, until it's released.
+    pub fn hold(&self, operation: &'static str) {
+        self.host.lock().unwrap().held.insert(operation);
+    }
+
+    pub fn release(&self, operation: &'static str) {
+        self.host.lock().unwrap().held.remove(operation);
+        self.released.notify_all();
+    }
+
+    /// Returns the operations recorded so far, in order, and forgets them.
+    pub fn take_operations(&self) -> Vec<String> {
+        std::mem::take(&mut self.host.lock().unwrap().operations)
+    }
+
+    /// Calls the event handler registered for a compute system or process.
+    pub fn notify(&self, target: &str, event_type: HcsEventType, event_data: &str) {
+        let handler = self
+            .host
+            .lock()
+            .unwrap()
+            .handlers
+            .iter()
+            .find(|(id, _)| id == target)
+            .map(|(_, handler)| handler.clone())
+            .expect("No event handler registered");
+        handler(event_type, event_data);
+    }
+
+    /// Invalidates the open handles without notifying the registered callbacks.
+    pub fn disconnect(&self) {
+        self.host.lock().unwrap().connection += 1;
+    }
+
+    /// Restarts the service, notifying a disconnect to the registered callbacks.
+    pub fn restart_service(&self) {
+        let handlers = {
+            let mut host = self.host.lock().unwrap();
+            host.connection += 1;
+            std::mem::take(&mut host.handlers)
+        };
+
+        for (_, handler) in handlers {
+            handler(HcsEventType::ServiceDisconnect, "");
+        }
+    }
+
+    /// Fails with `HcsServiceDisconnect` if the handle was opened before a disconnect.
+    pub fn check(&self, handle: &FakeHandle) -> HcsResult<()> {
+        match handle.connection == self.host.lock().unwrap().connection {
+            true => Ok(()),
+            false => Err(ResultCode::HcsServiceDisconnect),
+        }
+    }
+
+    fn handle(&self, id: String) -> FakeHandle {
+        FakeHandle {
+            id,
+            connection: self.host.lock().unwrap().connection,
+        }
+    }
+
+    /// Records an operation, returning its scripted result if any. Operations on a handle
+    /// opened before a disconnect fail with `HcsServiceDisconnect` instead.
+    fn operation(
+        &self,
+        operation: &'static str,
+        target: &str,
+        handle: Option<&FakeHandle>,
+    ) -> HcsResult<()> {
+        if let Some(handle) = handle {
+            self.check(handle)?;
+        }
+
+        let mut host = self.host.lock().unwrap();
+        host.operations.push(format!("{} {}", operation, target));
+        while host.held.contains(operation) {
+            host = self.released.wait(host).unwrap();
+        }
+
+        match host.script.iter().position(|(name, _)| *name == operation) {
+            Some(index) => host.script.remove(index).unwrap().1,
+            None => Ok(()),
+        }
+    }
+
+    /// Records an operation on an open compute system, and applies its resulting state.
+    fn system_operation(
+        &self,
+        operation: &'static str,
+        system: &FakeHandle,
+        next: Option<State>,
+    ) -> HcsResult<()> {
+        self.operation(operation, &system.id, Some(system))?;
+
+        let mut host = self.host.lock().unwrap();
+        match (host.systems.get_mut(&system.id), next) {
+            (Some(fake_system), Some(next)) => fake_system.state = next,
+            (Some(_), None) => {}
+            (None, _) => return Err(ResultCode::HcsSystemNotFound),
+        }
+        Ok(())
+    }
+
+    fn set_handler(&self, handle: &FakeHandle, handler: EventHandler) -> HcsResult<()> {
+        self.check(handle)?;
+        let mut host = self.host.lock().unwrap();
+        host.handlers.retain(|(id, _)| *id != handle.id);
+        host.handlers.push((handle.id.clone(), handler));
+        Ok(())
+    }
+}
+
+impl HcsComputeBackend for FakeBackend {
+    type System = FakeHandle;
+
+    fn create_system(&self, id: &str, document: &ComputeSystem) -> HcsResult<FakeHandle> {
+        self.operation("create", id, None)?;
+
+        let mut host = self.host.lock().unwrap();
+        if host
+            .systems
+            .get(id)
+            .is_some_and(|system| system.state != State::Stopped)
+        {
+            return Err(ResultCode::HcsSystemAlreadyExists);
+        }
+
+        host.systems.insert(
+            String::from(id),
+            FakeSystem {
+                owner: document.owner.clone(),
+                state: State::Created,
+            },
+        );
+        host.documents.insert(String::from(id), document.clone());
+        drop(host);
+        Ok(self.handle(String::from(id)))
+    }
+
+    fn open_system(&self, id: &str) -> HcsResult<FakeHandle> {
+        self.operation("open", id, None)?;
+        if !self.host.lock().unwrap().systems.contains_key(id) {
+            return Err(ResultCode::HcsSystemNotFound);
+        }
+        Ok(self.handle(String::from(id)))
+    }
+
+    fn start_system(&self, system: &FakeHandle) -> HcsResult<()> {
+        self.system_operation("start", system, Some(State::Running))
+    }
+
+    fn pause_system(&self, system: &FakeHandle, _options: &PauseOptions) -> HcsResult<()> {
+        self.system_operation("pause", system, Some(State::Paused))
+    }
+
+    fn resume_system(&self, system: &FakeHandle) -> HcsResult<()> {
+        self.system_operation("resume", system, Some(State::Running))
+    }
+
+    fn save_system(&self, system: &FakeHandle, options: &SaveOptions) -> HcsResult<()> {
+        let next = match options.save_type {
+            Some(SaveType::AsTemplate) => Some(State::SavedAsTemplate),
+            _ => None,
+        };
+        self.system_operation("save", system, next)
+    }
+
+    fn terminate_system(&self, system: &FakeHandle) -> HcsResult<()> {
+        self.system_operation("terminate", system, Some(State::Stopped))
+    }
+
+    fn system_state(&self, system: &FakeHandle) -> HcsResult<State> {
+        self.system_operation("state", system, None)?;
+        Ok(self.state(&system.id).unwrap())
+    }
+
+    fn create_runtime_state_file(&self, _system_id: &str, path: &str) -> HcsResult<()> {
+        self.operation("file", path, None)
+    }
+}
+
+impl HcsReconnectBackend for FakeBackend {
+    type Process = FakeHandle;
+
+    fn open_process(&self, system: &FakeHandle, process_id: u32) -> HcsResult<FakeHandle> {
+        let id = format!("{}/{}", system.id, process_id);
+        self.operation("open_process", &id, Some(system))?;
+        if !self.host.lock().unwrap().processes.contains(&process_id) {
+            return Err(ResultCode::FileNotFound);
+        }
+        Ok(self.handle(id))
+    }
+
+    fn set_system_callback(&self, system: &mut FakeHandle, handler: EventHandler) -> HcsResult<()> {
+        self.set_handler(system, handler)
+    }
+
+    fn set_process_callback(
+        &self,
+        process: &mut FakeHandle,
+        handler: EventHandler,
+    ) -> HcsResult<()> {
+        self.set_handler(process, handler)
+    }
+}
+
+impl HcsLifecycleBackend for FakeBackend {
+    fn shutdown_system(&self, system: &FakeHandle, _timeout: Duration) -> HcsResult<()> {
+        self.system_operation("shutdown", system, Some(State::Stopped))
+    }
+}
+
+impl HcsFleetBackend for FakeBackend {
+    /// Stopped compute systems are left out, as the service forgets them once they are closed.
+    fn enumerate_systems(&self, query: &SystemQuery) -> HcsResult<Vec<Properties>> {
+        Ok(self
+            .host
+            .lock()
+            .unwrap()
+            .systems
+            .iter()
+            .filter(|(_, system)| system.state != State::Stopped)
+            .filter(|(_, system)| query.owners.contains(&system.owner))
+            .map(|(id, system)| Properties {
+                id: id.clone(),
+                owner: system.owner.clone(),
+                state: system.state.clone(),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn modify_system(&self, system: &FakeHandle, request: &ModifySettingRequest) -> HcsResult<()> {
+        let target = format!("{} {}", system.id, request.resource_path);
+        self.operation("modify", &target, Some(system))
+    }
+}
@@ -12,9 +12,28 @@
 // of the JSON API surface of HCS instead of using straight raw strings.
 
 use crate::compute::defs::*;
+use crate::compute::errorcodes::ResultCode;
+use crate::compute::trace;
 use crate::compute::{HcsSafeHandle, HcsWrappedHandleDropPolicy};
 use crate::computecore;
+use crate::computecore::exec::{exec_process, ExecOptions, ExecOutput, ExecPipes, HcsExecProcess};
+use crate::computecore::lifecycle::HcsLifecycleBackend;
+use crate::computecore::processio::{HcsProcessControl, HcsProcessIo};
+use crate::computecore::reconciler::HcsFleetBackend;
+use crate::computecore::reconnect::{EventHandler, HcsReconnectBackend};
+use crate::computecore::snapshot::HcsComputeBackend;
 use crate::hypervdevicevirtualization::utilities::HdvHost;
+use crate::schema::options::{PauseOptions, SaveOptions};
+use crate::schema::process::{ProcessModifyRequest, ProcessParameters};
+use crate::schema::requests::service::{PropertyQuery, PropertyType};
+use crate::schema::requests::system::{
+    ModifySettingRequest, PropertyQuery as SystemPropertyQuery, PropertyType as SystemPropertyType,
+    SystemQuery,
+};
+use crate::schema::responses::service::{
+    HostCapabilities, ServiceProperties, TypedServiceProperties,
+};
+use crate::schema::responses::system::{ProcessStatus, Properties, State};
 use crate::HcsResult;
 use winutils_rs::windefs::*;
 
@@ -31,6 +50,7 @@ struct HcsEventCallback {
 
 unsafe extern "system" fn hcs_operation_callback(operation: HcsOperationHandle, context: PVoid) {
     let _ = std::panic::catch_unwind(|| {
+        let _entered = trace::enter_operation(operation);
         let mut operation = HcsOperation::wrap_handle(operation);
         operation.set_handle_policy(HcsWrappedHandleDropPolicy::Ignore);
 
@@ -44,6 +64,8 @@ unsafe extern "system" fn hcs_operation_callback(operation: HcsOperationHandle,
 
 unsafe extern "system" fn hcs_event_callback(event: *const HcsEvent, context: PVoid) {
     let _ = std::panic::catch_unwind(|| {
+        trace::callback_event(context, &*event);
+
         if context != std::ptr::null_mut() {
             if let Some(callback) = (*(context as *mut HcsEventCallback)).callback.as_mut() {
                 (callback)(&*event);
@@ -366,6 +388,26 @@ impl HcsSystem {
         computecore::get_compute_system_properties(self.handle, operation.handle, property_query)
     }
 
+    /// Queries for the given property types of a compute system.
+    /// Only the requested sections of the returned properties are populated.
+    pub fn query(&self, property_types: &[SystemPropertyType]) -> HcsResult<Properties> {
+        let property_query = SystemPropertyQuery {
+            property_types: property_types.to_vec(),
+        };
+        let property_query =
+            serde_json::to_string(&property_query).map_err(|_| ResultCode::InvalidArgument)?;
+
+        let operation = HcsOperation::new()?;
+        self.get_properties(&operation, Some(&property_query))?;
+        let (result_document, result) = operation.wait_for_result(INFINITE);
+        result?;
+
+        let mut properties: Properties =
+            serde_json::from_str(&result_document).map_err(|_| ResultCode::Unexpected)?;
+        properties.retain_sections(property_types);
+        Ok(properties)
+    }
+
     /// Modifies a compute system.
     pub fn modify(
         &self,
@@ -444,6 +486,34 @@ impl HcsSystem {
     pub fn initialize_device_host(&self) -> HcsResult<HdvHost> {
         HdvHost::new(self.handle)
     }
+
+    /// Creates a process in the compute system and runs it to completion,
+    /// returning its exit code and captured output.
+    ///
+    /// Std pipes are always created for stdout and stderr, and for stdin
+    /// if the options have data to write to it.
+    pub fn exec(
+        &self,
+        mut process_parameters: ProcessParameters,
+        options: ExecOptions,
+    ) -> HcsResult<ExecOutput> {
+        process_parameters.create_std_in_pipe |= options.stdin.is_some();
+        process_parameters.create_std_out_pipe = true;
+        process_parameters.create_std_err_pipe = !process_parameters.emulate_console;
+
+        let operation = HcsOperation::new()?;
+        let process = self.create_process(
+            &serde_json::to_string(&process_parameters).expect("Failed to serialize parameters"),
+            &operation,
+            None,
+        )?;
+        let process_information = operation.wait_for_result_and_process_info(INFINITE).1?;
+
+        exec_process(
+            HcsExecSystemProcess::new(process, &process_information)?,
+            options,
+        )
+    }
 }
 
 /// Thin wrapper of an HCS Compute System Process that interfaces to all HCS APIs that inherently
@@ -505,3 +575,412 @@ impl HcsProcess {
         )
     }
 }
+
+impl HcsProcessControl for HcsProcess {
+    fn modify_process(&self, request: &ProcessModifyRequest) -> HcsResult<()> {
+        let operation = HcsOperation::new()?;
+        self.modify(
+            &operation,
+            Some(&serde_json::to_string(request).expect("Failed to serialize request")),
+        )?;
+        operation.wait_for_result(INFINITE).1
+    }
+}
+
+/// Safe wrapper of a std pipe handle of a compute system process.
+/// When dropped, the underlying handle is closed.
+pub struct HcsPipe {
+    handle: Handle,
+}
+
+// Pipe handles are not tied to the thread that opened them
+unsafe impl Send for HcsPipe {}
+
+impl std::ops::Drop for HcsPipe {
+    fn drop(&mut self) {
+        unsafe {
+            winapi::um::handleapi::CloseHandle(self.handle);
+        }
+    }
+}
+
+impl HcsPipe {
+    /// Takes ownership of a pipe handle, returning `None` if the handle is null.
+    ///
+    /// # Safety
+    /// The handle must be a valid pipe handle that is not owned by anything else.
+    pub unsafe fn from_raw_handle(handle: Handle) -> Option<HcsPipe> {
+        if handle.is_null() {
+            None
+        } else {
+            Some(HcsPipe { handle })
+        }
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+}
+
+impl std::io::Read for HcsPipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut read: DWord = 0;
+        let length = std::cmp::min(buf.len(), DWord::MAX as usize) as DWord;
+
+        unsafe {
+            if winapi::um::fileapi::ReadFile(
+                self.handle,
+                buf.as_mut_ptr() as PVoid,
+                length,
+                &mut read,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                let error = std::io::Error::last_os_error();
+
+                // The process closed its end of the pipe
+                if error.raw_os_error() == Some(winapi::shared::winerror::ERROR_BROKEN_PIPE as i32)
+                {
+                    return Ok(0);
+                }
+
+                return Err(error);
+            }
+        }
+
+        Ok(read as usize)
+    }
+}
+
+impl std::io::Write for HcsPipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut written: DWord = 0;
+        let length = std::cmp::min(buf.len(), DWord::MAX as usize) as DWord;
+
+        unsafe {
+            if winapi::um::fileapi::WriteFile(
+                self.handle,
+                buf.as_ptr() as PVoid,
+                length,
+                &mut written,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HcsProcessIo<HcsProcess, HcsPipe, HcsPipe> {
+    /// Creates the std IO of a compute system process, taking ownership of the pipe handles
+    /// returned in the process information when the process was created.
+    ///
+    /// # Safety
+    /// The pipe handles of the process information must be valid and not owned by anything else.
+    /// `HcsProcessInformation` is a copy type, so the handles must not be taken from it, or from
+    /// any copy of it, more than once: every owner closes them when dropped.
+    pub unsafe fn from_process_information(
+        process: HcsProcess,
+        process_information: &HcsProcessInformation,
+    ) -> HcsProcessIo<HcsProcess, HcsPipe, HcsPipe> {
+        HcsProcessIo::new(
+            process,
+            HcsPipe::from_raw_handle(process_information.std_input),
+            HcsPipe::from_raw_handle(process_information.std_output),
+            HcsPipe::from_raw_handle(process_information.std_error),
+        )
+    }
+}
+
+/// Interval at which the status of an exec process is polled while waiting for it to exit.
+const EXIT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Compute system process driven by `HcsSystem::exec`.
+struct HcsExecSystemProcess {
+    process: HcsProcess,
+    pipes: ExecPipes<HcsPipe, HcsPipe>,
+    exit_receiver: std::sync::mpsc::Receiver<()>,
+    exited: bool,
+}
+
+impl HcsExecSystemProcess {
+    fn new(
+        mut process: HcsProcess,
+        process_information: &HcsProcessInformation,
+    ) -> HcsResult<HcsExecSystemProcess> {
+        let pipes = unsafe {
+            (
+                HcsPipe::from_raw_handle(process_information.std_input),
+                HcsPipe::from_raw_handle(process_information.std_output),
+                HcsPipe::from_raw_handle(process_information.std_error),
+            )
+        };
+
+        let (exit_sender, exit_receiver) = std::sync::mpsc::channel();
+        process.set_callback(HcsEventOptions::None, move |event| {
+            if event.event_type == HcsEventType::ProcessExited {
+                let _ = exit_sender.send(());
+            }
+        })?;
+
+        Ok(HcsExecSystemProcess {
+            process,
+            pipes,
+            exit_receiver,
+            exited: false,
+        })
+    }
+}
+
+impl HcsExecProcess for HcsExecSystemProcess {
+    type Stdin = HcsPipe;
+    type Output = HcsPipe;
+
+    fn take_pipes(&mut self) -> ExecPipes<HcsPipe, HcsPipe> {
+        (
+            self.pipes.0.take(),
+            self.pipes.1.take(),
+            self.pipes.2.take(),
+        )
+    }
+
+    fn wait_for_exit(&mut self, timeout: Option<std::time::Duration>) -> HcsResult<bool> {
+        let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+
+        // The process might have exited before the callback was set, and the exit
+        // notification is not guaranteed to arrive, so the status is polled as well
+        while !self.exited {
+            self.exited = self.status()?.exited;
+            if self.exited {
+                break;
+            }
+
+            let tick = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                    if remaining == std::time::Duration::from_secs(0) {
+                        break;
+                    }
+                    std::cmp::min(remaining, EXIT_POLL_INTERVAL)
+                }
+                None => EXIT_POLL_INTERVAL,
+            };
+
+            match self.exit_receiver.recv_timeout(tick) {
+                Ok(()) => self.exited = true,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => std::thread::sleep(tick),
+            }
+        }
+
+        Ok(self.exited)
+    }
+
+    fn status(&self) -> HcsResult<ProcessStatus> {
+        let operation = HcsOperation::new()?;
+        self.process.get_properties(&operation, None)?;
+        let (result_document, result) = operation.wait_for_result(INFINITE);
+        result?;
+        serde_json::from_str(&result_document).map_err(|_| ResultCode::Unexpected)
+    }
+
+    fn terminate(&self) -> HcsResult<()> {
+        let operation = HcsOperation::new()?;
+        self.process.terminate(&operation, None)?;
+        operation.wait_for_result(INFINITE).1
+    }
+}
+
+/// Compute backend that drives compute systems through the HCS APIs,
+/// waiting for each operation to complete.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct HcsServiceBackend;
+
+impl HcsServiceBackend {
+    fn run<F>(&self, operation: F) -> HcsResult<String>
+    where
+        F: FnOnce(&HcsOperation) -> HcsResult<()>,
+    {
+        let hcs_operation = HcsOperation::new()?;
+        operation(&hcs_operation)?;
+        let (result_document, result) = hcs_operation.wait_for_result(INFINITE);
+        result.map(|_| result_document)
+    }
+}
+
+impl HcsComputeBackend for HcsServiceBackend {
+    type System = HcsSystem;
+
+    fn create_system(
+        &self,
+        id: &str,
+        document: &crate::schema::ComputeSystem,
+    ) -> HcsResult<HcsSystem> {
+        let configuration =
+            serde_json::to_string(document).map_err(|_| ResultCode::InvalidArgument)?;
+        let operation = HcsOperation::new()?;
+        let system = HcsSystem::create(id, &configuration, &operation, None)?;
+        operation.wait_for_result(INFINITE).1?;
+        Ok(system)
+    }
+
+    fn open_system(&self, id: &str) -> HcsResult<HcsSystem> {
+        HcsSystem::open(id, GENERIC_ALL)
+    }
+
+    fn start_system(&self, system: &HcsSystem) -> HcsResult<()> {
+        self.run(|operation| system.start(operation, None))
+            .map(|_| ())
+    }
+
+    fn pause_system(&self, system: &HcsSystem, options: &PauseOptions) -> HcsResult<()> {
+        let options = serde_json::to_string(options).map_err(|_| ResultCode::InvalidArgument)?;
+        self.run(|operation| system.pause(operation, Some(&options)))
+            .map(|_| ())
+    }
+
+    fn resume_system(&self, system: &HcsSystem) -> HcsResult<()> {
+        self.run(|operation| system.resume(operation, None))
+            .map(|_| ())
+    }
+
+    fn save_system(&self, system: &HcsSystem, options: &SaveOptions) -> HcsResult<()> {
+        let options = serde_json::to_string(options).map_err(|_| ResultCode::InvalidArgument)?;
+        self.run(|operation| system.save(operation, Some(&options)))
+            .map(|_| ())
+    }
+
+    fn terminate_system(&self, system: &HcsSystem) -> HcsResult<()> {
+        self.run(|operation| system.terminate(operation, None))
+            .map(|_| ())
+    }
+
+    fn system_state(&self, system: &HcsSystem) -> HcsResult<State> {
+        let result_document = self.run(|operation| system.get_properties(operation, None))?;
+        let properties: Properties =
+            serde_json::from_str(&result_document).map_err(|_| ResultCode::Unexpected)?;
+        Ok(properties.state)
+    }
+
+    fn create_runtime_state_file(&self, system_id: &str, path: &str) -> HcsResult<()> {
+        computecore::create_empty_runtime_state_file(path)?;
+        computecore::grant_vm_access(system_id, path)
+    }
+}
+
+fn event_data(event: &HcsEvent) -> String {
+    if event.event_data.is_null() {
+        String::new()
+    } else {
+        unsafe { widestring::WideCStr::from_ptr_str(event.event_data).to_string_lossy() }
+    }
+}
+
+impl HcsReconnectBackend for HcsServiceBackend {
+    type Process = HcsProcess;
+
+    fn open_process(&self, system: &HcsSystem, process_id: u32) -> HcsResult<HcsProcess> {
+        system.open_process(process_id, GENERIC_ALL)
+    }
+
+    fn set_system_callback(&self, system: &mut HcsSystem, handler: EventHandler) -> HcsResult<()> {
+        system.set_callback(HcsEventOptions::None, move |event: &HcsEvent| {
+            handler(event.event_type, &event_data(event))
+        })
+    }
+
+    fn set_process_callback(
+        &self,
+        process: &mut HcsProcess,
+        handler: EventHandler,
+    ) -> HcsResult<()> {
+        process.set_callback(HcsEventOptions::None, move |event: &HcsEvent| {
+            handler(event.event_type, &event_data(event))
+        })
+    }
+}
+
+impl HcsLifecycleBackend for HcsServiceBackend {
+    fn shutdown_system(&self, system: &HcsSystem, timeout: std::time::Duration) -> HcsResult<()> {
+        // INFINITE is reserved for waiting without a timeout
+        let timeout_ms = std::cmp::min(timeout.as_millis(), u128::from(INFINITE - 1)) as DWord;
+        let operation = HcsOperation::new()?;
+        system.shutdown(&operation, None)?;
+        operation.wait_for_result(timeout_ms).1
+    }
+}
+
+impl HcsFleetBackend for HcsServiceBackend {
+    fn enumerate_systems(&self, query: &SystemQuery) -> HcsResult<Vec<Properties>> {
+        enumerate_systems(query)
+    }
+
+    fn modify_system(&self, system: &HcsSystem, request: &ModifySettingRequest) -> HcsResult<()> {
+        let configuration =
+            serde_json::to_string(request).map_err(|_| ResultCode::InvalidArgument)?;
+        self.run(|operation| system.modify(operation, &configuration, std::ptr::null_mut()))
+            .map(|_| ())
+    }
+}
+
+/// Enumerates the compute systems visible to the caller that match a query.
+pub fn enumerate_systems(query: &SystemQuery) -> HcsResult<Vec<Properties>> {
+    let query = serde_json::to_string(query).map_err(|_| ResultCode::InvalidArgument)?;
+    let operation = HcsOperation::new()?;
+    computecore::enumerate_compute_systems(operation.handle, Some(&query))?;
+    let (result_document, result) = operation.wait_for_result(INFINITE);
+    result?;
+    serde_json::from_str(&result_document).map_err(|_| ResultCode::Unexpected)
+}
+
+/// Queries properties of the Host Compute Service, decoded into their types.
+pub fn query_service_properties(
+    property_types: &[PropertyType],
+) -> HcsResult<TypedServiceProperties> {
+    let property_query = PropertyQuery {
+        property_types: property_types.to_vec(),
+        filtered_queries: Vec::new(),
+    };
+
+    let result = computecore::get_service_properties(
+        &serde_json::to_string(&property_query).expect("Failed to serialize query"),
+    )?;
+
+    let service_properties: ServiceProperties =
+        serde_json::from_str(&result).map_err(|_| ResultCode::Unexpected)?;
+
+    TypedServiceProperties::decode(property_types, &service_properties)
+        .map_err(|_| ResultCode::Unexpected)
+}
+
+/// Queries the capabilities of the host.
+///
+/// Property types that are not supported by the host are reported as missing capabilities,
+/// instead of failing the whole query.
+pub fn query_host_capabilities() -> HcsResult<HostCapabilities> {
+    let mut properties = query_service_properties(&[PropertyType::Basic])?;
+
+    for property_type in HostCapabilities::property_types() {
+        if property_type == PropertyType::Basic {
+            continue;
+        }
+
+        if let Ok(optional_properties) = query_service_properties(&[property_type]) {
+            properties.qos_capabilities = properties
+                .qos_capabilities
+                .or(optional_properties.qos_capabilities);
+            properties.container_credential_guard = properties
+                .container_credential_guard
+                .or(optional_properties.container_credential_guard);
+        }
+    }
+
+    Ok(HostCapabilities::from_properties(&properties))
+}
@@ -0,0 +1,627 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Pool of pre-booted virtual machines cloned from templates, which hides the boot
+//! latency of utility VMs from the callers acquiring them.
+//!
+//! Each spec of the pool has a template virtual machine, saved with `SaveType::AsTemplate`,
+//! and a number of idle clones of it that are already running. Systems are tracked by ID,
+//! so that the pool can be refilled from a background thread. The background refill
+//! only holds the lock of the pool to plan and commit its work, so that virtual machines
+//! can be acquired while templates and clones are being created.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::computecore::snapshot::{HcsComputeBackend, VmSnapshot};
+use crate::schema::ComputeSystem;
+use crate::HcsResult;
+use std::collections::{BTreeMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Virtual machine configuration kept warm by the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmPoolSpec {
+    /// Name of the spec, used to acquire virtual machines.
+    pub name: String,
+
+    /// Document of the template virtual machine the clones are created from.
+    pub document: ComputeSystem,
+
+    /// Number of idle clones the pool keeps running.
+    pub idle_target: usize,
+}
+
+/// Returns a hash of a compute system document, which changes when the document does.
+pub fn spec_hash(document: &ComputeSystem) -> u64 {
+    // Going through a JSON value sorts the members of maps, making the hash stable
+    let json = serde_json::to_value(document)
+        .map(|value| value.to_string())
+        .unwrap_or_default();
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    json.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Metrics of a spec of the pool.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct WarmPoolSpecMetrics {
+    pub name: String,
+    pub spec_hash: u64,
+    pub template_ready: bool,
+    pub idle: usize,
+    pub idle_target: usize,
+}
+
+/// Metrics of the pool, accumulated since it was created.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct WarmPoolMetrics {
+    pub specs: Vec<WarmPoolSpecMetrics>,
+
+    /// Acquisitions served by an idle clone.
+    pub hits: u64,
+
+    /// Acquisitions that had to clone a virtual machine on demand.
+    pub misses: u64,
+
+    pub templates_created: u64,
+    pub clones_created: u64,
+
+    /// Templates or clones that failed to be created.
+    pub failures: u64,
+
+    /// Templates and idle clones terminated because their spec changed or was removed.
+    pub evictions: u64,
+}
+
+struct SpecPool {
+    spec: WarmPoolSpec,
+    hash: u64,
+    template: Option<VmSnapshot>,
+    idle: VecDeque<String>,
+}
+
+/// Refill of a spec, planned under the lock of the pool and run without it.
+struct SpecRefill {
+    name: String,
+    hash: u64,
+
+    /// Template of the spec when the refill was planned.
+    template: Option<VmSnapshot>,
+
+    /// ID, document and runtime state file path of the template to create, if the spec has none.
+    new_template: Option<(String, ComputeSystem, String)>,
+    created_template: Option<VmSnapshot>,
+
+    /// IDs of the clones to create, and the ones created.
+    clone_ids: Vec<String>,
+    clones: Vec<String>,
+
+    failures: u64,
+    error: Option<ResultCode>,
+}
+
+/// Templates and clones to create for the specs of a pool.
+struct Refill<B> {
+    backend: Arc<B>,
+    specs: Vec<SpecRefill>,
+}
+
+impl<B: HcsComputeBackend> Refill<B> {
+    /// Creates the templates and clones, stopping at the first failure of each spec.
+    fn run(&mut self) {
+        for spec in &mut self.specs {
+            if let Some((id, document, path)) = &spec.new_template {
+                match create_template(&*self.backend, id, document, path) {
+                    Ok(template) => spec.created_template = Some(template),
+                    Err(error) => {
+                        spec.failures += 1;
+                        spec.error = Some(error);
+                        continue;
+                    }
+                }
+            }
+
+            let template = match spec.created_template.as_ref().or(spec.template.as_ref()) {
+                Some(template) => template,
+                None => continue,
+            };
+
+            for id in &spec.clone_ids {
+                match template.clone_systems(&*self.backend, &[id]) {
+                    Ok(_) => spec.clones.push(id.clone()),
+                    Err(error) => {
+                        spec.failures += 1;
+                        spec.error = Some(error);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Boots a virtual machine and saves it as a template, terminating it on failure.
+fn create_template<B: HcsComputeBackend>(
+    backend: &B,
+    id: &str,
+    document: &ComputeSystem,
+    path: &str,
+) -> HcsResult<VmSnapshot> {
+    let template = backend
+        .create_system(id, document)
+        .and_then(|system| backend.start_system(&system))
+        .and_then(|_| VmSnapshot::take_template(backend, id, document, path));
+
+    if template.is_err() {
+        if let Ok(system) = backend.open_system(id) {
+            let _ = backend.terminate_system(&system);
+        }
+    }
+    template
+}
+
+/// Pool of pre-booted virtual machines, cloned from a template per spec.
+pub struct WarmPool<B: HcsComputeBackend> {
+    backend: Arc<B>,
+    template_directory: std::path::PathBuf,
+    id_prefix: String,
+    next_id: u64,
+    pools: BTreeMap<String, SpecPool>,
+    metrics: WarmPoolMetrics,
+}
+
+impl<B: HcsComputeBackend> WarmPool<B> {
+    /// Creates an empty pool. Template runtime state files are saved to `template_directory`,
+    /// and system IDs created by the pool start with `id_prefix`.
+    pub fn new<P: AsRef<std::path::Path>>(
+        backend: B,
+        template_directory: P,
+        id_prefix: &str,
+    ) -> WarmPool<B> {
+        WarmPool {
+            backend: Arc::new(backend),
+            template_directory: template_directory.as_ref().to_path_buf(),
+            id_prefix: String::from(id_prefix),
+            next_id: 0,
+            pools: BTreeMap::new(),
+            metrics: WarmPoolMetrics::default(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    fn new_id(&mut self, spec_name: &str, kind: &str) -> String {
+        self.next_id += 1;
+        format!("{}-{}-{}-{}", self.id_prefix, spec_name, kind, self.next_id)
+    }
+
+    fn template_path(&self, id: &str) -> String {
+        self.template_directory
+            .join(format!("{}.vmrs", id))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn terminate(&mut self, id: &str) {
+        if let Ok(system) = self.backend.open_system(id) {
+            let _ = self.backend.terminate_system(&system);
+        }
+        self.metrics.evictions += 1;
+    }
+
+    fn evict(&mut self, mut pool: SpecPool) {
+        while let Some(id) = pool.idle.pop_front() {
+            self.terminate(&id);
+        }
+
+        if let Some(template) = pool.template.take() {
+            self.terminate(&template.system_id);
+        }
+    }
+
+    /// Adds or updates a spec of the pool. If the document of an existing spec changed,
+    /// its template and idle clones are terminated, to be recreated on the next refill.
+    pub fn set_spec(&mut self, spec: WarmPoolSpec) {
+        let hash = spec_hash(&spec.document);
+
+        if let Some(pool) = self.pools.get_mut(&spec.name) {
+            if pool.hash == hash {
+                pool.spec.idle_target = spec.idle_target;
+                return;
+            }
+        }
+
+        if let Some(stale) = self.pools.remove(&spec.name) {
+            self.evict(stale);
+        }
+
+        self.pools.insert(
+            spec.name.clone(),
+            SpecPool {
+                spec,
+                hash,
+                template: None,
+                idle: VecDeque::new(),
+            },
+        );
+    }
+
+    /// Removes a spec from the pool, terminating its template and idle clones.
+    pub fn remove_spec(&mut self, name: &str) {
+        if let Some(pool) = self.pools.remove(name) {
+            self.evict(pool);
+        }
+    }
+
+    /// Returns the template of a spec, booting and saving it if needed.
+    fn ensure_template(&mut self, name: &str) -> HcsResult<VmSnapshot> {
+        if let Some(template) = self.pools.get(name).and_then(|pool| pool.template.clone()) {
+            return Ok(template);
+        }
+
+        let document = match self.pools.get(name) {
+            Some(pool) => pool.spec.document.clone(),
+            None => return Err(ResultCode::InvalidArgument),
+        };
+
+        let id = self.new_id(name, "template");
+        let path = self.template_path(&id);
+
+        match create_template(&*self.backend, &id, &document, &path) {
+            Ok(template) => {
+                self.metrics.templates_created += 1;
+                if let Some(pool) = self.pools.get_mut(name) {
+                    pool.template = Some(template.clone());
+                }
+                Ok(template)
+            }
+            Err(error) => {
+                self.metrics.failures += 1;
+                Err(error)
+            }
+        }
+    }
+
+    /// Creates and starts a clone of the template of a spec, returning its ID.
+    fn create_clone(&mut self, name: &str) -> HcsResult<String> {
+        let template = self.ensure_template(name)?;
+        let id = self.new_id(name, "clone");
+
+        match template.clone_systems(&*self.backend, &[&id]) {
+            Ok(_) => {
+                self.metrics.clones_created += 1;
+                Ok(id)
+            }
+            Err(error) => {
+                self.metrics.failures += 1;
+                Err(error)
+            }
+        }
+    }
+
+    /// Hands out a running virtual machine of a spec, returning its ID.
+    /// An idle clone is used if available, otherwise one is cloned on demand.
+    /// The caller owns the returned virtual machine.
+    pub fn acquire(&mut self, name: &str) -> HcsResult<String> {
+        let idle = match self.pools.get_mut(name) {
+            Some(pool) => pool.idle.pop_front(),
+            None => return Err(ResultCode::InvalidArgument),
+        };
+
+        match idle {
+            Some(id) => {
+                self.metrics.hits += 1;
+                Ok(id)
+            }
+            None => {
+                self.metrics.misses += 1;
+                self.create_clone(name)
+            }
+        }
+    }
+
+    /// Creates templates and clones until every spec has its target of idle clones.
+    /// Returns the number of clones created, or the first error found once
+    /// every spec has been attempted.
+    pub fn refill(&mut self) -> HcsResult<usize> {
+        let mut refill = self.plan_refill();
+        refill.run();
+        self.commit_refill(refill)
+    }
+
+    /// Plans the templates and clones missing for every spec to have its target of idle clones.
+    fn plan_refill(&mut self) -> Refill<B> {
+        let mut missing = Vec::new();
+        for (name, pool) in &self.pools {
+            if pool.idle.len() < pool.spec.idle_target {
+                missing.push((name.clone(), pool.spec.idle_target - pool.idle.len()));
+            }
+        }
+
+        let mut specs = Vec::new();
+        for (name, count) in missing {
+            let new_template = match self.pools[&name].template {
+                Some(_) => None,
+                None => {
+                    let id = self.new_id(&name, "template");
+                    let path = self.template_path(&id);
+                    Some((id, self.pools[&name].spec.document.clone(), path))
+                }
+            };
+            let clone_ids = (0..count).map(|_| self.new_id(&name, "clone")).collect();
+            let pool = &self.pools[&name];
+
+            specs.push(SpecRefill {
+                name,
+                hash: pool.hash,
+                template: pool.template.clone(),
+                new_template,
+                created_template: None,
+                clone_ids,
+                clones: Vec::new(),
+                failures: 0,
+                error: None,
+            });
+        }
+
+        Refill {
+            backend: self.backend.clone(),
+            specs,
+        }
+    }
+
+    /// Adds the templates and clones created by a refill to the pool. The ones of specs
+    /// that changed or were removed during the refill are terminated.
+    fn commit_refill(&mut self, refill: Refill<B>) -> HcsResult<usize> {
+        let mut created = 0;
+        let mut first_error = None;
+
+        for spec in refill.specs {
+            self.metrics.failures += spec.failures;
+            self.metrics.clones_created += spec.clones.len() as u64;
+            if spec.created_template.is_some() {
+                self.metrics.templates_created += 1;
+            }
+            created += spec.clones.len();
+            first_error = first_error.or(spec.error);
+
+            let mut stale = Vec::new();
+            match self.pools.get_mut(&spec.name) {
+                Some(pool) if pool.hash == spec.hash => {
+                    // An acquisition may have created a template in the meantime
+                    match (&pool.template, spec.created_template) {
+                        (None, template) => pool.template = template,
+                        (Some(_), Some(template)) => stale.push(template.system_id),
+                        (Some(_), None) => {}
+                    }
+                    pool.idle.extend(spec.clones);
+                }
+                _ => {
+                    stale.extend(spec.clones);
+                    stale.extend(spec.created_template.map(|template| template.system_id));
+                }
+            }
+
+            for id in stale {
+                self.terminate(&id);
+            }
+        }
+
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(created),
+        }
+    }
+
+    /// Returns the metrics of the pool.
+    pub fn metrics(&self) -> WarmPoolMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.specs = self
+            .pools
+            .values()
+            .map(|pool| WarmPoolSpecMetrics {
+                name: pool.spec.name.clone(),
+                spec_hash: pool.hash,
+                template_ready: pool.template.is_some(),
+                idle: pool.idle.len(),
+                idle_target: pool.spec.idle_target,
+            })
+            .collect();
+        metrics
+    }
+
+    /// Terminates every template and idle clone of the pool.
+    pub fn shutdown(&mut self) {
+        let names: Vec<String> = self.pools.keys().cloned().collect();
+        for name in names {
+            self.remove_spec(&name);
+        }
+    }
+}
+
+/// Background thread that periodically refills a shared pool.
+/// The thread is stopped when dropped.
+pub struct WarmPoolRefiller {
+    sender: std::sync::mpsc::Sender<bool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl WarmPoolRefiller {
+    /// Spawns a thread that refills the pool every `interval`, or when woken up.
+    /// The pool is only locked to plan each refill and to add the systems it created.
+    pub fn spawn<B>(pool: Arc<Mutex<WarmPool<B>>>, interval: Duration) -> WarmPoolRefiller
+    where
+        B: HcsComputeBackend + Send + Sync + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::channel::<bool>();
+
+        let thread = std::thread::spawn(move || loop {
+            if let Ok(mut refill) = pool.lock().map(|mut pool| pool.plan_refill()) {
+                refill.run();
+                if let Ok(mut pool) = pool.lock() {
+                    // Failures are accounted for in the pool metrics, and retried on the next round
+                    let _ = pool.commit_refill(refill);
+                }
+            }
+
+            match receiver.recv_timeout(interval) {
+                Ok(true) | Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                Ok(false) | Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        });
+
+        WarmPoolRefiller {
+            sender,
+            thread: Some(thread),
+        }
+    }
+
+    /// Wakes up the thread to refill the pool right away, e.g. after acquiring from it.
+    pub fn wake(&self) {
+        let _ = self.sender.send(true);
+    }
+
+    /// Stops the thread, waiting for an ongoing refill to complete.
+    pub fn stop(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        let _ = self.sender.send(false);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl std::ops::Drop for WarmPoolRefiller {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::errorcodes::ResultCode;
+    use crate::computecore::testing::FakeBackend;
+    use crate::schema::responses::system::State;
+    use crate::schema::VirtualMachine;
+
+    fn spec(memory_mb: u64, idle_target: usize) -> WarmPoolSpec {
+        let mut virtual_machine = VirtualMachine::default();
+        virtual_machine.compute_topology.memory.size_in_mb = memory_mb;
+
+        WarmPoolSpec {
+            name: String::from("uvm"),
+            document: ComputeSystem {
+                owner: String::from("pool"),
+                virtual_machine: Some(virtual_machine),
+                ..Default::default()
+            },
+            idle_target,
+        }
+    }
+
+    #[test]
+    fn warm_pool_policy() {
+        let backend = FakeBackend::default();
+        let mut pool = WarmPool::new(backend.clone(), "templates", "pool");
+        pool.set_spec(spec(1024, 2));
+
+        assert_eq!(pool.refill(), Ok(2));
+        assert_eq!(backend.count("template", State::SavedAsTemplate), 1);
+        assert_eq!(backend.count("clone", State::Running), 2);
+
+        let acquired = pool.acquire("uvm").unwrap();
+        assert_eq!(backend.state(&acquired), Some(State::Running));
+        pool.acquire("uvm").unwrap();
+        pool.acquire("uvm").unwrap();
+        assert_eq!(pool.acquire("unknown"), Err(ResultCode::InvalidArgument));
+
+        let metrics = pool.metrics();
+        assert_eq!((metrics.hits, metrics.misses), (2, 1));
+        assert_eq!(metrics.templates_created, 1);
+        assert_eq!(metrics.clones_created, 3);
+        assert_eq!(metrics.specs[0].idle, 0);
+
+        // Same document, only the target changes: the template is kept
+        pool.set_spec(spec(1024, 1));
+        assert_eq!(pool.refill(), Ok(1));
+        assert_eq!(pool.metrics().templates_created, 1);
+
+        // Changed document: the stale template and idle clone are evicted
+        pool.set_spec(spec(2048, 1));
+        let metrics = pool.metrics();
+        assert_eq!(metrics.evictions, 2);
+        assert!(!metrics.specs[0].template_ready);
+        assert_eq!(backend.count("template", State::Stopped), 1);
+
+        assert_eq!(pool.refill(), Ok(1));
+        assert_eq!(pool.metrics().templates_created, 2);
+
+        pool.shutdown();
+        assert!(pool.metrics().specs.is_empty());
+        assert_eq!(backend.count("template", State::SavedAsTemplate), 0);
+    }
+
+    #[test]
+    fn warm_pool_background_refill() {
+        let backend = FakeBackend::default();
+        let pool = Arc::new(Mutex::new(WarmPool::new(
+            backend.clone(),
+            "templates",
+            "pool",
+        )));
+        pool.lock().unwrap().set_spec(spec(1024, 3));
+
+        let refiller = WarmPoolRefiller::spawn(pool.clone(), Duration::from_secs(60));
+        refiller.stop();
+
+        assert_eq!(pool.lock().unwrap().metrics().specs[0].idle, 3);
+    }
+
+    #[test]
+    fn warm_pool_acquire_during_refill() {
+        let backend = FakeBackend::default();
+        let pool = Arc::new(Mutex::new(WarmPool::new(
+            backend.clone(),
+            "templates",
+            "pool",
+        )));
+        pool.lock().unwrap().set_spec(spec(1024, 1));
+        assert_eq!(pool.lock().unwrap().refill(), Ok(1));
+        pool.lock().unwrap().set_spec(spec(1024, 2));
+
+        // The refill waits while creating the missing clone
+        backend.take_operations();
+        backend.hold("create");
+        let refiller = WarmPoolRefiller::spawn(pool.clone(), Duration::from_secs(60));
+        while !backend
+            .take_operations()
+            .iter()
+            .any(|operation| operation.starts_with("create"))
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let acquired = pool.try_lock().ok().map(|mut pool| pool.acquire("uvm"));
+        backend.release("create");
+        refiller.stop();
+
+        let acquired = acquired.expect("The pool is locked during the refill");
+        assert_eq!(backend.state(&acquired.unwrap()), Some(State::Running));
+        let metrics = pool.lock().unwrap().metrics();
+        assert_eq!((metrics.hits, metrics.misses), (1, 0));
+        assert_eq!(metrics.specs[0].idle, 1);
+    }
+}
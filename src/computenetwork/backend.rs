@@ -0,0 +1,83 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Host Compute Network operations behind a trait, so networking code can run
+//! against something other than the HCN service.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::computenetwork::{ErrorResult, HcnResult};
+use crate::netschema::query::{parse_detailed, HostComputeQuery};
+use crate::netschema::{
+    HostComputeEndpoint, HostComputeLoadBalancer, HostComputeNamespace, HostComputeNetwork,
+};
+use crate::schema::utils::GuidSerde;
+use serde::de::DeserializeOwned;
+
+fn parse_enumeration<T: DeserializeOwned>(result: String) -> HcnResult<Vec<T>> {
+    parse_detailed(&result).map_err(|error| ErrorResult {
+        error_record: error.to_string(),
+        result_code: ResultCode::Unexpected,
+    })
+}
+
+/// Host Compute Network operations, on objects referenced by ID instead of handles.
+///
+/// Settings, queries and returned properties are the same JSON documents
+/// the `computenetwork` functions take and return.
+pub trait HcnBackend {
+    fn enumerate_networks(&self, query: &str) -> HcnResult<String>;
+    fn create_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn modify_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn query_network_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String>;
+    fn delete_network(&self, id: &GuidSerde) -> HcnResult<()>;
+
+    fn enumerate_namespaces(&self, query: &str) -> HcnResult<String>;
+    fn create_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn modify_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn query_namespace_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String>;
+    fn delete_namespace(&self, id: &GuidSerde) -> HcnResult<()>;
+
+    fn enumerate_endpoints(&self, query: &str) -> HcnResult<String>;
+
+    /// Creates an endpoint attached to a network.
+    fn create_endpoint(&self, network: &GuidSerde, id: &GuidSerde, settings: &str)
+        -> HcnResult<()>;
+
+    fn modify_endpoint(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn query_endpoint_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String>;
+    fn delete_endpoint(&self, id: &GuidSerde) -> HcnResult<()>;
+
+    fn enumerate_load_balancers(&self, query: &str) -> HcnResult<String>;
+    fn create_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn modify_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()>;
+    fn query_load_balancer_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String>;
+    fn delete_load_balancer(&self, id: &GuidSerde) -> HcnResult<()>;
+
+    /// Enumerates the networks matching a query, as detailed documents.
+    fn query_networks(&self, query: HostComputeQuery) -> HcnResult<Vec<HostComputeNetwork>> {
+        parse_enumeration(self.enumerate_networks(&query.detailed().to_json())?)
+    }
+
+    /// Enumerates the namespaces matching a query, as detailed documents.
+    fn query_namespaces(&self, query: HostComputeQuery) -> HcnResult<Vec<HostComputeNamespace>> {
+        parse_enumeration(self.enumerate_namespaces(&query.detailed().to_json())?)
+    }
+
+    /// Enumerates the endpoints matching a query, as detailed documents.
+    fn query_endpoints(&self, query: HostComputeQuery) -> HcnResult<Vec<HostComputeEndpoint>> {
+        parse_enumeration(self.enumerate_endpoints(&query.detailed().to_json())?)
+    }
+
+    /// Enumerates the load balancers matching a query, as detailed documents.
+    fn query_load_balancers(
+        &self,
+        query: HostComputeQuery,
+    ) -> HcnResult<Vec<HostComputeLoadBalancer>> {
+        parse_enumeration(self.enumerate_load_balancers(&query.detailed().to_json())?)
+    }
+}
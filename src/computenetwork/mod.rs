@@ -14,9 +14,22 @@ pub(crate) mod bindings;
 #[cfg(feature = "bindings")]
 pub mod bindings;
 
+#[cfg(feature = "utilities")]
+pub mod backend;
+
 pub mod defs;
 
+#[cfg(feature = "utilities")]
+pub mod ports;
+
+#[cfg(feature = "utilities")]
+pub mod simulator;
+
+#[cfg(feature = "utilities")]
+pub mod utilities;
+
 use crate::compute::errorcodes::{hresult_to_result_code, ResultCode};
+use crate::compute::trace::ApiCall;
 use crate::computenetwork::bindings::*;
 use crate::computenetwork::defs::*;
 use widestring::WideCString;
@@ -44,7 +57,9 @@ pub type HcnResult<T> = Result<T, ErrorResult>;
 
 /// Return a list of existing Networks.
 pub fn enumerate_networks(query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnEnumerateNetworks").request(query);
+
+    call.finish(unsafe {
         let mut networks = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -56,12 +71,14 @@ pub fn enumerate_networks(query: &str) -> HcnResult<String> {
             0 => Ok(networks.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Create a network.
 pub fn create_network(id: &Guid, settings: &str) -> HcnResult<HcnNetworkHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnCreateNetwork").guid(id).request(settings);
+
+    call.finish(unsafe {
         let mut network_handle: HcnNetworkHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -74,12 +91,14 @@ pub fn create_network(id: &Guid, settings: &str) -> HcnResult<HcnNetworkHandle>
             0 => Ok(network_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Lookup an existing network.
 pub fn open_network(id: &Guid) -> HcnResult<HcnNetworkHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnOpenNetwork").guid(id);
+
+    call.finish(unsafe {
         let mut network_handle: HcnNetworkHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -87,12 +106,16 @@ pub fn open_network(id: &Guid) -> HcnResult<HcnNetworkHandle> {
             0 => Ok(network_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Modify the settings of a Network.
 pub fn modify_network(network: HcnNetworkHandle, settings: &str) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnModifyNetwork")
+        .handle(network)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnModifyNetwork(
@@ -103,12 +126,16 @@ pub fn modify_network(network: HcnNetworkHandle, settings: &str) -> HcnResult<()
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Query network settings.
 pub fn query_network_properties(network: HcnNetworkHandle, query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnQueryNetworkProperties")
+        .handle(network)
+        .request(query);
+
+    call.finish(unsafe {
         let mut properties = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -121,34 +148,40 @@ pub fn query_network_properties(network: HcnNetworkHandle, query: &str) -> HcnRe
             0 => Ok(properties.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Delete a network.
 pub fn delete_network(id: &Guid) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnDeleteNetwork").guid(id);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnDeleteNetwork(id, error_record.ptr_mut()) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Close handle to a Network.
 pub fn close_network(network: HcnNetworkHandle) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnCloseNetwork").close(network);
+
+    call.finish(unsafe {
         match HcnCloseNetwork(network) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(String::from(""), hresult)),
         }
-    }
+    })
 }
 
 /// Return a list of existing Namespaces.
 pub fn enumerate_namespaces(query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnEnumerateNamespaces").request(query);
+
+    call.finish(unsafe {
         let mut namespaces = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -160,12 +193,16 @@ pub fn enumerate_namespaces(query: &str) -> HcnResult<String> {
             0 => Ok(namespaces.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Create a Namespace.
 pub fn create_namespace(id: &Guid, settings: &str) -> HcnResult<HcnNamespaceHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnCreateNamespace")
+        .guid(id)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut namespace_handle: HcnNamespaceHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -178,12 +215,14 @@ pub fn create_namespace(id: &Guid, settings: &str) -> HcnResult<HcnNamespaceHand
             0 => Ok(namespace_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Lookup an existing Namespace.
 pub fn open_namespace(id: &Guid) -> HcnResult<HcnNamespaceHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnOpenNamespace").guid(id);
+
+    call.finish(unsafe {
         let mut namespace_handle: HcnNamespaceHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -191,12 +230,16 @@ pub fn open_namespace(id: &Guid) -> HcnResult<HcnNamespaceHandle> {
             0 => Ok(namespace_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Modify the settings of a Namespace.
 pub fn modify_namespace(namespace: HcnNamespaceHandle, settings: &str) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnModifyNamespace")
+        .handle(namespace)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnModifyNamespace(
@@ -207,12 +250,16 @@ pub fn modify_namespace(namespace: HcnNamespaceHandle, settings: &str) -> HcnRes
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Query Namespace settings.
 pub fn query_namespace_properties(namespace: HcnNamespaceHandle, query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnQueryNamespaceProperties")
+        .handle(namespace)
+        .request(query);
+
+    call.finish(unsafe {
         let mut properties = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -225,34 +272,40 @@ pub fn query_namespace_properties(namespace: HcnNamespaceHandle, query: &str) ->
             0 => Ok(properties.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Delete a Namespace.
 pub fn delete_namespace(id: &Guid) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnDeleteNamespace").guid(id);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnDeleteNamespace(id, error_record.ptr_mut()) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Close a handle to a Namespace.
 pub fn close_namespace(namespace: HcnNamespaceHandle) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnCloseNamespace").close(namespace);
+
+    call.finish(unsafe {
         match HcnCloseNamespace(namespace) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(String::from(""), hresult)),
         }
-    }
+    })
 }
 
 /// Return a list of existing Endpoints.
 pub fn enumerate_endpoints(query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnEnumerateEndpoints").request(query);
+
+    call.finish(unsafe {
         let mut endpoints = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -264,7 +317,7 @@ pub fn enumerate_endpoints(query: &str) -> HcnResult<String> {
             0 => Ok(endpoints.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Create an Endpoint.
@@ -273,7 +326,12 @@ pub fn create_endpoint(
     id: &Guid,
     settings: &str,
 ) -> HcnResult<HcnEndpointHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnCreateEndpoint")
+        .handle(network)
+        .guid(id)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut endpoint_handle: HcnEndpointHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -287,12 +345,14 @@ pub fn create_endpoint(
             0 => Ok(endpoint_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Lookup an existing Endpoint.
 pub fn open_endpoint(id: &Guid) -> HcnResult<HcnEndpointHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnOpenEndpoint").guid(id);
+
+    call.finish(unsafe {
         let mut endpoint_handle: HcnEndpointHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -300,12 +360,16 @@ pub fn open_endpoint(id: &Guid) -> HcnResult<HcnEndpointHandle> {
             0 => Ok(endpoint_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Modify the settings of an Endpoint.
 pub fn modify_endpoint(endpoint: HcnEndpointHandle, settings: &str) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnModifyEndpoint")
+        .handle(endpoint)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnModifyEndpoint(
@@ -316,12 +380,16 @@ pub fn modify_endpoint(endpoint: HcnEndpointHandle, settings: &str) -> HcnResult
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Query Endpoint properties.
 pub fn query_endpoint_properties(endpoint: HcnEndpointHandle, query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnQueryEndpointProperties")
+        .handle(endpoint)
+        .request(query);
+
+    call.finish(unsafe {
         let mut properties = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -334,34 +402,40 @@ pub fn query_endpoint_properties(endpoint: HcnEndpointHandle, query: &str) -> Hc
             0 => Ok(properties.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Delete an Endpoint.
 pub fn delete_endpoint(id: &Guid) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnDeleteEndpoint").guid(id);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnDeleteEndpoint(id, error_record.ptr_mut()) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Close a handle to an Endpoint.
 pub fn close_endpoint(endpoint: HcnEndpointHandle) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnCloseEndpoint").close(endpoint);
+
+    call.finish(unsafe {
         match HcnCloseEndpoint(endpoint) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(String::from(""), hresult)),
         }
-    }
+    })
 }
 
 /// Return a list of existing LoadBalancers.
 pub fn enumerate_load_balancers(query: &str) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnEnumerateLoadBalancers").request(query);
+
+    call.finish(unsafe {
         let mut load_balancers = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -373,12 +447,16 @@ pub fn enumerate_load_balancers(query: &str) -> HcnResult<String> {
             0 => Ok(load_balancers.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Create a LoadBalancer.
 pub fn create_load_balancer(id: &Guid, settings: &str) -> HcnResult<HcnLoadBalancerHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnCreateLoadBalancer")
+        .guid(id)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut load_balancer_handle: HcnLoadBalancerHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -391,12 +469,14 @@ pub fn create_load_balancer(id: &Guid, settings: &str) -> HcnResult<HcnLoadBalan
             0 => Ok(load_balancer_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Lookup an existing LoadBalancer.
 pub fn open_load_balancer(id: &Guid) -> HcnResult<HcnLoadBalancerHandle> {
-    unsafe {
+    let call = ApiCall::new("HcnOpenLoadBalancer").guid(id);
+
+    call.finish(unsafe {
         let mut load_balancer_handle: HcnLoadBalancerHandle = std::ptr::null_mut();
         let mut error_record = CoTaskMemWString::new();
 
@@ -404,12 +484,16 @@ pub fn open_load_balancer(id: &Guid) -> HcnResult<HcnLoadBalancerHandle> {
             0 => Ok(load_balancer_handle),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Modify the settings of a LoadBalancer.
 pub fn modify_load_balancer(load_balancer: HcnLoadBalancerHandle, settings: &str) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnModifyLoadBalancer")
+        .handle(load_balancer)
+        .request(settings);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnModifyLoadBalancer(
@@ -420,7 +504,7 @@ pub fn modify_load_balancer(load_balancer: HcnLoadBalancerHandle, settings: &str
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Query LoadBalancer settings.
@@ -428,7 +512,11 @@ pub fn query_load_balancer_properties(
     load_balancer: HcnLoadBalancerHandle,
     query: &str,
 ) -> HcnResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcnQueryLoadBalancerProperties")
+        .handle(load_balancer)
+        .request(query);
+
+    call.finish(unsafe {
         let mut properties = CoTaskMemWString::new();
         let mut error_record = CoTaskMemWString::new();
 
@@ -441,29 +529,33 @@ pub fn query_load_balancer_properties(
             0 => Ok(properties.to_string()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Delete a LoadBalancer.
 pub fn delete_load_balancer(id: &Guid) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnDeleteLoadBalancer").guid(id);
+
+    call.finish(unsafe {
         let mut error_record = CoTaskMemWString::new();
 
         match HcnDeleteLoadBalancer(id, error_record.ptr_mut()) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(error_record.to_string(), hresult)),
         }
-    }
+    })
 }
 
 /// Close a handle to a LoadBalancer.
 pub fn close_load_balancer(load_balancer: HcnLoadBalancerHandle) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnCloseLoadBalancer").close(load_balancer);
+
+    call.finish(unsafe {
         match HcnCloseLoadBalancer(load_balancer) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(String::from(""), hresult)),
         }
-    }
+    })
 }
 
 /// Registers a callback function to receive notifications of service-wide events.
@@ -472,20 +564,24 @@ pub fn register_service_callback(
     context: *const Void,
     callback_handle: *mut HcnCallback,
 ) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnRegisterServiceCallback");
+
+    call.finish(unsafe {
         match HcnRegisterServiceCallback(callback, context, callback_handle) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(String::from(""), hresult)),
         }
-    }
+    })
 }
 
 /// Unregisters from service-wide notifications.
 pub fn unregister_service_callback(callback_handle: *const HcnCallback) -> HcnResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcnUnregisterServiceCallback").handle(callback_handle);
+
+    call.finish(unsafe {
         match HcnUnregisterServiceCallback(callback_handle) {
             0 => Ok(()),
             hresult => Err(ErrorResult::new(String::from(""), hresult)),
         }
-    }
+    })
 }
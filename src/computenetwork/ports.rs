@@ -0,0 +1,359 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Publishing of endpoint ports on the host, checking the external ports
+//! against the ones already published by every endpoint of the host.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::computenetwork::backend::HcnBackend;
+use crate::computenetwork::{ErrorResult, HcnResult};
+use crate::netschema::policies::{EndpointPolicies, PortMappingPolicySetting};
+use crate::netschema::query::{parse_ids, HostComputeQuery};
+use crate::netschema::HostComputeEndpoint;
+use crate::schema::utils::GuidSerde;
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+
+/// Default range of the ports allocated for mappings without an external port,
+/// the dynamic port range of Windows.
+pub const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+/// Port mapping of an existing endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedPort {
+    pub endpoint: GuidSerde,
+    pub port_mapping: PortMappingPolicySetting,
+}
+
+/// Requested port mapping whose external port is already published.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortConflict {
+    pub requested: PortMappingPolicySetting,
+
+    /// Mapping publishing the port, which is part of the request if its endpoint
+    /// is the one being modified and it isn't published yet.
+    pub existing: PublishedPort,
+}
+
+impl std::fmt::Display for PortConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "External port {} (protocol {}) is already published by endpoint {}",
+            self.requested.external_port, self.requested.protocol, self.existing.endpoint
+        )
+    }
+}
+
+/// Publishes endpoint ports, detecting external port conflicts across all endpoints of the host.
+///
+/// Publishing through a single allocator is atomic, so ports allocated to concurrent
+/// requests never conflict. Ports published by other processes between the check and
+/// the modification are still reported by the HCN service.
+pub struct PortAllocator<B: HcnBackend> {
+    backend: B,
+    ephemeral_ports: RangeInclusive<u16>,
+    lock: Mutex<()>,
+}
+
+impl<B: HcnBackend> PortAllocator<B> {
+    pub fn new(backend: B) -> PortAllocator<B> {
+        PortAllocator {
+            backend,
+            ephemeral_ports: EPHEMERAL_PORTS,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Sets the range of the ports allocated for mappings without an external port.
+    pub fn with_ephemeral_ports(
+        mut self,
+        ephemeral_ports: RangeInclusive<u16>,
+    ) -> PortAllocator<B> {
+        self.ephemeral_ports = ephemeral_ports;
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns the port mappings of all endpoints of the host.
+    pub fn published_ports(&self) -> HcnResult<Vec<PublishedPort>> {
+        let ids = parse_ids(
+            &self
+                .backend
+                .enumerate_endpoints(&HostComputeQuery::new().to_json())?,
+        )
+        .map_err(invalid_document)?;
+        let mut published = Vec::new();
+
+        for id in ids {
+            let endpoint: HostComputeEndpoint = match self
+                .backend
+                .query_endpoint_properties(&id, "")
+            {
+                Ok(properties) => serde_json::from_str(&properties).map_err(invalid_document)?,
+                // The endpoint was deleted after the enumeration
+                Err(error) if error.result_code == ResultCode::HcnEndpointNotFound => continue,
+                Err(error) => return Err(error),
+            };
+
+            for policy in &endpoint.policies {
+                if let Some(port_mapping) =
+                    PortMappingPolicySetting::from_policy(policy).map_err(invalid_document)?
+                {
+                    published.push(PublishedPort {
+                        endpoint: id.clone(),
+                        port_mapping,
+                    });
+                }
+            }
+        }
+
+        Ok(published)
+    }
+
+    /// Returns the conflicts of the port mappings requested for an endpoint, with the ports
+    /// published on the host and among themselves. Mappings without an external port never conflict.
+    pub fn conflicts(
+        &self,
+        endpoint: &GuidSerde,
+        port_mappings: &[PortMappingPolicySetting],
+    ) -> HcnResult<Vec<PortConflict>> {
+        Ok(find_conflicts(
+            endpoint,
+            port_mappings,
+            self.published_ports()?,
+        ))
+    }
+
+    /// Adds port mapping and outbound NAT policies to an endpoint, allocating external
+    /// ports for the mappings without one. Fails with `HcnPortAlreadyExists` without
+    /// modifying the endpoint if an external port is already published.
+    ///
+    /// Returns the policies added to the endpoint.
+    pub fn publish(
+        &self,
+        endpoint: &GuidSerde,
+        mut policies: EndpointPolicies,
+    ) -> HcnResult<EndpointPolicies> {
+        let _lock = self.lock.lock().unwrap();
+        let mut published = self.published_ports()?;
+
+        let conflicts = find_conflicts(endpoint, &policies.port_mappings, published.clone());
+        if !conflicts.is_empty() {
+            let messages: Vec<String> = conflicts.iter().map(ToString::to_string).collect();
+            return Err(port_already_exists(&messages.join(". ")));
+        }
+
+        // Requested external ports are reserved before allocating the rest
+        let (allocated, requested): (Vec<_>, Vec<_>) = policies
+            .port_mappings
+            .iter_mut()
+            .partition(|port_mapping| port_mapping.external_port == 0);
+        published.extend(requested.into_iter().map(|port_mapping| PublishedPort {
+            endpoint: endpoint.clone(),
+            port_mapping: port_mapping.clone(),
+        }));
+
+        for port_mapping in allocated {
+            port_mapping.external_port = self.allocate(port_mapping, &published)?;
+            published.push(PublishedPort {
+                endpoint: endpoint.clone(),
+                port_mapping: port_mapping.clone(),
+            });
+        }
+
+        self.backend
+            .modify_endpoint(endpoint, &policies.add_request().to_json())?;
+        Ok(policies)
+    }
+
+    /// Returns the first ephemeral port not published for the protocol and VIP of a mapping.
+    fn allocate(
+        &self,
+        port_mapping: &PortMappingPolicySetting,
+        published: &[PublishedPort],
+    ) -> HcnResult<u16> {
+        self.ephemeral_ports
+            .clone()
+            .find(|port| {
+                let candidate = PortMappingPolicySetting {
+                    external_port: *port,
+                    ..port_mapping.clone()
+                };
+                !published
+                    .iter()
+                    .any(|existing| candidate.conflicts_with(&existing.port_mapping))
+            })
+            .ok_or_else(|| port_already_exists("No ephemeral ports left to publish"))
+    }
+}
+
+fn invalid_document(error: serde_json::Error) -> ErrorResult {
+    ErrorResult {
+        error_record: error.to_string(),
+        result_code: ResultCode::Unexpected,
+    }
+}
+
+/// Returns an `HcnPortAlreadyExists` error, with an error record like the ones of HCN.
+fn port_already_exists(message: &str) -> ErrorResult {
+    ErrorResult {
+        error_record: serde_json::json!({
+            "Success": false,
+            "Error": message,
+            "ErrorCode": ResultCode::HcnPortAlreadyExists.to_hresult() as u32,
+        })
+        .to_string(),
+        result_code: ResultCode::HcnPortAlreadyExists,
+    }
+}
+
+fn find_conflicts(
+    endpoint: &GuidSerde,
+    port_mappings: &[PortMappingPolicySetting],
+    mut published: Vec<PublishedPort>,
+) -> Vec<PortConflict> {
+    let mut conflicts = Vec::new();
+
+    for requested in port_mappings {
+        if let Some(existing) = published
+            .iter()
+            .find(|existing| requested.conflicts_with(&existing.port_mapping))
+        {
+            conflicts.push(PortConflict {
+                requested: requested.clone(),
+                existing: existing.clone(),
+            });
+        }
+
+        published.push(PublishedPort {
+            endpoint: endpoint.clone(),
+            port_mapping: requested.clone(),
+        });
+    }
+
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computenetwork::simulator::HcnSimulator;
+    use crate::netschema::policies::{OutboundNatPolicySetting, NAT_FLAGS_IPV6};
+
+    fn guid(value: u32) -> GuidSerde {
+        GuidSerde {
+            data1: value,
+            ..GuidSerde::new()
+        }
+    }
+
+    #[test]
+    fn publish_detects_conflicts() {
+        let simulator = HcnSimulator::new();
+        simulator
+            .create_network(&guid(1), r#"{"Type": "NAT"}"#)
+            .unwrap();
+        simulator
+            .create_endpoint(&guid(1), &guid(10), "{}")
+            .unwrap();
+        simulator
+            .create_endpoint(&guid(1), &guid(11), "{}")
+            .unwrap();
+        let allocator = PortAllocator::new(simulator).with_ephemeral_ports(8080..=8082);
+
+        let published = allocator
+            .publish(
+                &guid(10),
+                EndpointPolicies::new()
+                    .port_mapping(PortMappingPolicySetting::tcp(443, 0).vip("10.0.0.1"))
+                    .port_mapping(PortMappingPolicySetting::tcp(80, 8080))
+                    .outbound_nat(OutboundNatPolicySetting::new().exception("10.0.0.0/8")),
+            )
+            .unwrap();
+        assert_eq!(published.port_mappings[0].external_port, 8081);
+        let endpoint: HostComputeEndpoint = serde_json::from_str(
+            &allocator
+                .backend()
+                .query_endpoint_properties(&guid(10), "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(endpoint.policies, published.to_policies());
+
+        // The port is published on all addresses, so it conflicts with any VIP
+        let error = allocator
+            .publish(
+                &guid(11),
+                EndpointPolicies::new()
+                    .port_mapping(PortMappingPolicySetting::udp(53, 8080))
+                    .port_mapping(PortMappingPolicySetting::tcp(80, 8080).vip("10.0.0.2")),
+            )
+            .unwrap_err();
+        assert_eq!(error.result_code, ResultCode::HcnPortAlreadyExists);
+        assert!(allocator
+            .backend()
+            .query_endpoint_properties(&guid(11), "")
+            .unwrap()
+            .find("Policies")
+            .is_none());
+
+        let conflicts = allocator
+            .conflicts(
+                &guid(11),
+                &[
+                    PortMappingPolicySetting::tcp(80, 8081).vip("10.0.0.2"),
+                    PortMappingPolicySetting::tcp(80, 8080).flags(NAT_FLAGS_IPV6),
+                    PortMappingPolicySetting::tcp(81, 8080).flags(NAT_FLAGS_IPV6),
+                ],
+            )
+            .unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].requested.internal_port, 81);
+        assert_eq!(conflicts[0].existing.endpoint, guid(11));
+
+        let published = allocator
+            .publish(
+                &guid(11),
+                EndpointPolicies::new()
+                    .port_mapping(PortMappingPolicySetting::tcp(80, 0))
+                    .outbound_nat_exception("10.0.0.0/8")
+                    .outbound_nat_exception("192.168.0.0/16"),
+            )
+            .unwrap();
+        assert_eq!(published.port_mappings[0].external_port, 8082);
+        assert_eq!(published.outbound_nat.unwrap().exceptions.len(), 2);
+        assert_eq!(
+            allocator
+                .publish(
+                    &guid(11),
+                    EndpointPolicies::new().port_mapping(PortMappingPolicySetting::tcp(81, 0))
+                )
+                .unwrap_err()
+                .result_code,
+            ResultCode::HcnPortAlreadyExists
+        );
+
+        // Port mappings that can't be parsed aren't skipped, as their ports could conflict
+        allocator
+            .backend()
+            .create_endpoint(
+                &guid(1),
+                &guid(12),
+                r#"{"Policies": [{"Type": "PortMapping", "Settings": {"ExternalPort": 8083}}]}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            allocator.published_ports().unwrap_err().result_code,
+            ResultCode::Unexpected
+        );
+    }
+}
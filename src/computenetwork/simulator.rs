@@ -0,0 +1,1072 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! In-memory simulation of the Host Compute Network service, to run networking code off Windows.
+//!
+//! The simulator stores the settings documents of networks, namespaces, endpoints and load balancers
+//! as given, adding the fields the service fills in: `ID`, the network of endpoints, their
+//! IP configurations and MAC address, and the namespace they are attached to.
+//!
+//! Enumeration supports the `Filter` and `Flags` fields of HCN queries. Networks can't be deleted
+//! while they have endpoints, and endpoints can't be deleted while a namespace or load balancer
+//! references them. Failures return the error codes of the HCN service, with error records
+//! in the same JSON format.
+
+use crate::compute::errorcodes::ResultCode;
+use crate::computenetwork::backend::HcnBackend;
+use crate::computenetwork::defs::HcnNotifications;
+use crate::computenetwork::{ErrorResult, HcnResult};
+use crate::netschema::query::HOST_COMPUTE_QUERY_FLAGS_DETAILED;
+use crate::schema::utils::GuidSerde;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use winutils_rs::windefs::HResult;
+
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_NOT_FOUND: HResult = 0x80070490;
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_ALREADY_EXISTS: HResult = 0x800700B7;
+#[allow(overflowing_literals)]
+const HRESULT_ERROR_DEVICE_IN_USE: HResult = 0x80070964;
+
+const NETWORK_TYPES: &[&str] = &[
+    "NAT",
+    "ICS",
+    "Transparent",
+    "L2Bridge",
+    "L2Tunnel",
+    "Overlay",
+    "Private",
+    "Internal",
+    "Mirrored",
+];
+
+/// Callback receiving the service notifications, along with their notification data.
+pub type ServiceCallback = Arc<dyn Fn(HcnNotifications, &str) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ObjectKind {
+    Network,
+    Namespace,
+    Endpoint,
+    LoadBalancer,
+}
+
+impl ObjectKind {
+    fn name(self) -> &'static str {
+        match self {
+            ObjectKind::Network => "Network",
+            ObjectKind::Namespace => "Namespace",
+            ObjectKind::Endpoint => "Endpoint",
+            ObjectKind::LoadBalancer => "LoadBalancer",
+        }
+    }
+
+    fn not_found(self) -> ResultCode {
+        match self {
+            ObjectKind::Network => ResultCode::HcnNetworkNotFound,
+            ObjectKind::Endpoint => ResultCode::HcnEndpointNotFound,
+            _ => ResultCode::from_hresult(HRESULT_ERROR_NOT_FOUND),
+        }
+    }
+
+    fn already_exists(self) -> ResultCode {
+        match self {
+            ObjectKind::Network => ResultCode::HcnNetworkAlreadyExists,
+            _ => ResultCode::from_hresult(HRESULT_ERROR_ALREADY_EXISTS),
+        }
+    }
+}
+
+/// Returns an error with an error record like the ones of the HCN service.
+fn error(result_code: ResultCode, message: String) -> ErrorResult {
+    ErrorResult {
+        error_record: json!({
+            "Success": false,
+            "Error": message,
+            "ErrorCode": result_code.to_hresult() as u32,
+        })
+        .to_string(),
+        result_code,
+    }
+}
+
+fn parse_document(document: &str) -> HcnResult<Map<String, Value>> {
+    match serde_json::from_str(document) {
+        Ok(Value::Object(document)) => Ok(document),
+        _ => Err(error(
+            ResultCode::HcnInvalidJson,
+            String::from("Invalid JSON document string"),
+        )),
+    }
+}
+
+/// Returns the normalized ID of an object referenced in a document.
+fn parse_reference(value: Option<&Value>, field: &str) -> HcnResult<String> {
+    value
+        .and_then(Value::as_str)
+        .and_then(|id| GuidSerde::from_str(id).ok())
+        .map(|id| id.to_string())
+        .ok_or_else(|| {
+            error(
+                ResultCode::HcnInvalidJsonReference,
+                format!("Invalid or missing object reference in {}", field),
+            )
+        })
+}
+
+/// Returns the filter and whether the query is detailed.
+fn parse_query(query: &str) -> HcnResult<(Map<String, Value>, bool)> {
+    if query.trim().is_empty() {
+        return Ok((Map::new(), false));
+    }
+
+    let query = parse_document(query)?;
+    let detailed = query
+        .get("Flags")
+        .and_then(Value::as_u64)
+        .is_some_and(|flags| flags & u64::from(HOST_COMPUTE_QUERY_FLAGS_DETAILED) != 0);
+    let filter = match query.get("Filter") {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::String(filter)) if filter.trim().is_empty() => Map::new(),
+        Some(Value::String(filter)) => parse_document(filter)?,
+        Some(Value::Object(filter)) => filter.clone(),
+        Some(_) => {
+            return Err(error(
+                ResultCode::HcnInvalidJson,
+                String::from("Invalid query filter"),
+            ))
+        }
+    };
+
+    Ok((filter, detailed))
+}
+
+/// Matches a property of a document against a filter value. Strings, like IDs, are compared ignoring case.
+/// Returns the property of an object matched by a query filter. HCN matches the network
+/// of endpoints on `VirtualNetwork`.
+fn filter_property(kind: ObjectKind, key: &str) -> &str {
+    match (kind, key) {
+        (ObjectKind::Endpoint, "VirtualNetwork") => "HostComputeNetwork",
+        _ => key,
+    }
+}
+
+fn filter_matches(property: Option<&Value>, value: &Value) -> bool {
+    match (property, value) {
+        (Some(Value::String(property)), Value::String(value)) => {
+            property.eq_ignore_ascii_case(value)
+        }
+        (Some(property), value) => property == value,
+        (None, value) => value.is_null(),
+    }
+}
+
+/// IPv4 subnet of a network, with the addresses of its gateways.
+struct Subnet {
+    address: u32,
+    prefix_length: u32,
+    gateways: Vec<u32>,
+}
+
+impl Subnet {
+    fn contains(&self, address: u32) -> bool {
+        self.prefix_length == 0
+            || address >> (32 - self.prefix_length) == self.address >> (32 - self.prefix_length)
+    }
+
+    /// Returns the first address of the subnet that isn't used, a gateway,
+    /// or the subnet network and broadcast addresses.
+    fn free_address(&self, used: &BTreeSet<u32>) -> Option<u32> {
+        let size = 1u64 << (32 - self.prefix_length);
+        let first = u64::from(self.address) & !(size - 1);
+        let hosts = if size > 2 {
+            first + 1..first + size - 1
+        } else {
+            first..first + size
+        };
+
+        hosts
+            .map(|address| address as u32)
+            .find(|address| !used.contains(address) && !self.gateways.contains(address))
+    }
+}
+
+fn parse_prefix(prefix: &str) -> Option<(IpAddr, u32)> {
+    let mut parts = prefix.splitn(2, '/');
+    let address: IpAddr = parts.next()?.parse().ok()?;
+    let prefix_length: u32 = parts.next()?.parse().ok()?;
+    let max_length = if address.is_ipv4() { 32 } else { 128 };
+
+    if prefix_length <= max_length {
+        Some((address, prefix_length))
+    } else {
+        None
+    }
+}
+
+fn as_array<'a>(document: &'a Map<String, Value>, field: &str) -> &'a [Value] {
+    document
+        .get(field)
+        .and_then(Value::as_array)
+        .map_or(&[][..], Vec::as_slice)
+}
+
+/// Returns the IPv4 subnets of a network, failing on invalid prefixes.
+fn network_subnets(network: &Map<String, Value>) -> HcnResult<Vec<Subnet>> {
+    let mut subnets = Vec::new();
+
+    for ipam in as_array(network, "Ipams") {
+        for subnet in ipam
+            .get("Subnets")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice)
+        {
+            let prefix = subnet
+                .get("IpAddressPrefix")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let (address, prefix_length) = parse_prefix(prefix).ok_or_else(|| {
+                error(
+                    ResultCode::HcnInvalidIp,
+                    format!("Invalid subnet prefix '{}'", prefix),
+                )
+            })?;
+
+            if let IpAddr::V4(address) = address {
+                let gateways = subnet
+                    .get("Routes")
+                    .and_then(Value::as_array)
+                    .map_or(&[][..], Vec::as_slice)
+                    .iter()
+                    .filter_map(|route| route.get("NextHop")?.as_str()?.parse::<Ipv4Addr>().ok())
+                    .map(u32::from)
+                    .collect();
+
+                subnets.push(Subnet {
+                    address: u32::from(address),
+                    prefix_length,
+                    gateways,
+                });
+            }
+        }
+    }
+
+    Ok(subnets)
+}
+
+fn endpoint_addresses(endpoint: &Map<String, Value>) -> impl Iterator<Item = Ipv4Addr> + '_ {
+    as_array(endpoint, "IpConfigurations")
+        .iter()
+        .filter_map(|configuration| configuration.get("IpAddress")?.as_str()?.parse().ok())
+}
+
+/// Returns the endpoints a namespace document references.
+fn namespace_endpoints(namespace: &Map<String, Value>) -> HcnResult<Vec<String>> {
+    as_array(namespace, "Resources")
+        .iter()
+        .filter(|resource| resource.get("Type").and_then(Value::as_str) == Some("Endpoint"))
+        .map(|resource| {
+            parse_reference(
+                resource.get("Data").and_then(|data| data.get("Id")),
+                "Resources",
+            )
+        })
+        .collect()
+}
+
+/// Returns the endpoints a load balancer document references.
+fn load_balancer_endpoints(load_balancer: &Map<String, Value>) -> HcnResult<Vec<String>> {
+    as_array(load_balancer, "HostComputeEndpoints")
+        .iter()
+        .map(|endpoint| parse_reference(Some(endpoint), "HostComputeEndpoints"))
+        .collect()
+}
+
+/// Applies a policy modify request to the policies of a network or endpoint.
+fn modify_policies(
+    document: &mut Map<String, Value>,
+    request: &Map<String, Value>,
+) -> HcnResult<()> {
+    if request.get("ResourceType").and_then(Value::as_str) != Some("Policy") {
+        return Err(error(
+            ResultCode::HcnRequestUnsupported,
+            String::from("Unsupported modify resource type"),
+        ));
+    }
+
+    let policies = request
+        .get("Settings")
+        .and_then(|settings| settings.get("Policies"))
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(policy) = policies
+        .iter()
+        .find(|policy| policy.get("Type").and_then(Value::as_str).is_none())
+    {
+        return Err(error(
+            ResultCode::HcnInvalidPolicyType,
+            format!("Policy {} has no type", policy),
+        ));
+    }
+
+    let mut current = as_array(document, "Policies").to_vec();
+
+    match request.get("RequestType").and_then(Value::as_str) {
+        Some("Add") => {
+            for policy in policies {
+                if current.contains(&policy) {
+                    return Err(error(
+                        ResultCode::HcnPolicyAlreadyExists,
+                        format!("Policy {} already exists", policy),
+                    ));
+                }
+                current.push(policy);
+            }
+        }
+        Some("Remove") => {
+            for policy in policies {
+                let position = current.iter().position(|existing| *existing == policy);
+                match position {
+                    Some(position) => {
+                        current.remove(position);
+                    }
+                    None => {
+                        return Err(error(
+                            ResultCode::HcnPolicyNotFound,
+                            format!("Policy {} not found", policy),
+                        ))
+                    }
+                }
+            }
+        }
+        Some("Update") | Some("Refresh") => current = policies,
+        _ => {
+            return Err(error(
+                ResultCode::HcnRequestUnsupported,
+                String::from("Unsupported modify request type"),
+            ))
+        }
+    }
+
+    document.insert(String::from("Policies"), Value::Array(current));
+    Ok(())
+}
+
+#[derive(Default)]
+struct SimulatorState {
+    networks: BTreeMap<String, Map<String, Value>>,
+    namespaces: BTreeMap<String, Map<String, Value>>,
+    endpoints: BTreeMap<String, Map<String, Value>>,
+    load_balancers: BTreeMap<String, Map<String, Value>>,
+
+    /// Number of IDs generated, for objects created with GUID_NULL.
+    generated_ids: u32,
+
+    /// Number of MAC addresses assigned.
+    assigned_macs: u32,
+}
+
+impl SimulatorState {
+    fn objects(&self, kind: ObjectKind) -> &BTreeMap<String, Map<String, Value>> {
+        match kind {
+            ObjectKind::Network => &self.networks,
+            ObjectKind::Namespace => &self.namespaces,
+            ObjectKind::Endpoint => &self.endpoints,
+            ObjectKind::LoadBalancer => &self.load_balancers,
+        }
+    }
+
+    fn objects_mut(&mut self, kind: ObjectKind) -> &mut BTreeMap<String, Map<String, Value>> {
+        match kind {
+            ObjectKind::Network => &mut self.networks,
+            ObjectKind::Namespace => &mut self.namespaces,
+            ObjectKind::Endpoint => &mut self.endpoints,
+            ObjectKind::LoadBalancer => &mut self.load_balancers,
+        }
+    }
+
+    fn get(&self, kind: ObjectKind, id: &str) -> HcnResult<&Map<String, Value>> {
+        self.objects(kind).get(id).ok_or_else(|| {
+            error(
+                kind.not_found(),
+                format!("{} {} not found", kind.name(), id),
+            )
+        })
+    }
+
+    fn get_mut(&mut self, kind: ObjectKind, id: &str) -> HcnResult<&mut Map<String, Value>> {
+        self.objects_mut(kind).get_mut(id).ok_or_else(|| {
+            error(
+                kind.not_found(),
+                format!("{} {} not found", kind.name(), id),
+            )
+        })
+    }
+
+    /// Returns the ID of a new object, generating one for GUID_NULL.
+    fn new_id(&mut self, kind: ObjectKind, id: &GuidSerde) -> HcnResult<String> {
+        let id = if *id == GuidSerde::new() {
+            self.generated_ids += 1;
+            GuidSerde {
+                data1: self.generated_ids,
+                data2: 0,
+                data3: 0x4000,
+                data4: [0x80, 0, 0, 0, 0, 0, 0, kind as u8],
+            }
+        } else {
+            id.clone()
+        };
+
+        let id = id.to_string();
+        match self.objects(kind).contains_key(&id) {
+            true => Err(error(
+                kind.already_exists(),
+                format!("{} {} already exists", kind.name(), id),
+            )),
+            false => Ok(id),
+        }
+    }
+
+    fn insert(&mut self, kind: ObjectKind, id: String, mut document: Map<String, Value>) {
+        document.insert(String::from("ID"), Value::from(id.clone()));
+        document
+            .entry("SchemaVersion")
+            .or_insert_with(|| json!({"Major": 2, "Minor": 0}));
+        self.objects_mut(kind).insert(id, document);
+    }
+
+    fn enumerate(&self, kind: ObjectKind, query: &str) -> HcnResult<String> {
+        let (filter, detailed) = parse_query(query)?;
+        let objects = self
+            .objects(kind)
+            .iter()
+            .filter(|(_, document)| {
+                filter.iter().all(|(key, value)| {
+                    filter_matches(document.get(filter_property(kind, key)), value)
+                })
+            })
+            .map(|(id, document)| match detailed {
+                true => Value::Object(document.clone()),
+                false => Value::from(id.clone()),
+            })
+            .collect();
+
+        Ok(Value::Array(objects).to_string())
+    }
+
+    fn query_properties(&self, kind: ObjectKind, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        parse_query(query)?;
+        Ok(Value::Object(self.get(kind, &id.to_string())?.clone()).to_string())
+    }
+
+    fn endpoints_of_network<'a>(&'a self, network: &'a str) -> impl Iterator<Item = &'a str> {
+        self.endpoints
+            .iter()
+            .filter(move |(_, endpoint)| {
+                endpoint.get("HostComputeNetwork").and_then(Value::as_str) == Some(network)
+            })
+            .map(|(id, _)| id.as_str())
+    }
+
+    fn verify_endpoints_exist(&self, endpoints: &[String]) -> HcnResult<()> {
+        endpoints
+            .iter()
+            .try_for_each(|endpoint| self.get(ObjectKind::Endpoint, endpoint).map(|_| ()))
+    }
+
+    /// Verifies that endpoints can be attached to a namespace.
+    fn verify_attachable(&self, namespace: &str, endpoints: &[String]) -> HcnResult<()> {
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            let attached = self
+                .get(ObjectKind::Endpoint, endpoint)?
+                .get("HostComputeNamespace")
+                .and_then(Value::as_str);
+
+            if attached.is_some() || endpoints[..index].contains(endpoint) {
+                return Err(error(
+                    ResultCode::HcnEndpointAlreadyAttached,
+                    format!(
+                        "Endpoint {} is already attached to namespace {}",
+                        endpoint,
+                        attached.unwrap_or(namespace)
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_endpoint_namespace(&mut self, endpoint: &str, namespace: Option<&str>) {
+        if let Some(endpoint) = self.endpoints.get_mut(endpoint) {
+            match namespace {
+                Some(namespace) => {
+                    endpoint.insert(String::from("HostComputeNamespace"), Value::from(namespace));
+                }
+                None => {
+                    endpoint.remove("HostComputeNamespace");
+                }
+            }
+        }
+    }
+
+    /// Validates the IP configurations of a new endpoint, assigning an address if it has none.
+    fn configure_addresses(
+        &self,
+        network: &str,
+        endpoint: &mut Map<String, Value>,
+    ) -> HcnResult<()> {
+        let subnets = network_subnets(self.get(ObjectKind::Network, network)?)?;
+        let used: BTreeSet<u32> = self
+            .endpoints_of_network(network)
+            .flat_map(|id| endpoint_addresses(&self.endpoints[id]))
+            .map(u32::from)
+            .collect();
+
+        let requested: Vec<Ipv4Addr> = endpoint_addresses(endpoint).collect();
+        if requested.is_empty() {
+            if subnets.is_empty() {
+                return Ok(());
+            }
+
+            let (address, prefix_length) = subnets
+                .iter()
+                .find_map(|subnet| {
+                    subnet
+                        .free_address(&used)
+                        .map(|address| (Ipv4Addr::from(address), subnet.prefix_length))
+                })
+                .ok_or_else(|| {
+                    error(
+                        ResultCode::HcnInvalidIp,
+                        format!("No IP addresses left in the subnets of network {}", network),
+                    )
+                })?;
+
+            endpoint.insert(
+                String::from("IpConfigurations"),
+                json!([{"IpAddress": address.to_string(), "PrefixLength": prefix_length}]),
+            );
+            return Ok(());
+        }
+
+        for address in requested {
+            let value = u32::from(address);
+
+            if !subnets.is_empty() && !subnets.iter().any(|subnet| subnet.contains(value)) {
+                return Err(error(
+                    ResultCode::HcnInvalidIp,
+                    format!(
+                        "IP address {} is not in a subnet of network {}",
+                        address, network
+                    ),
+                ));
+            }
+
+            if used.contains(&value) {
+                return Err(error(
+                    ResultCode::HcnInvalidIp,
+                    format!("IP address {} is already in use", address),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `HcnBackend` implementation that simulates the HCN service in memory.
+#[derive(Default)]
+pub struct HcnSimulator {
+    state: Mutex<SimulatorState>,
+    callbacks: Mutex<Vec<ServiceCallback>>,
+}
+
+impl HcnSimulator {
+    pub fn new() -> HcnSimulator {
+        HcnSimulator::default()
+    }
+
+    /// Registers a callback for the service notifications: creation and deletion of networks
+    /// and namespaces, and service disconnects. Notifications are delivered once the
+    /// operation completes, with the ID of the object as notification data.
+    pub fn register_service_callback(&self, callback: ServiceCallback) {
+        self.callbacks.lock().unwrap().push(callback);
+    }
+
+    /// Simulates the HCN service stopping, notifying the service callbacks.
+    pub fn disconnect_service(&self) {
+        self.notify(&[HcnNotifications::ServiceDisconnect], "");
+    }
+
+    fn notify(&self, notifications: &[HcnNotifications], id: &str) {
+        let callbacks = self.callbacks.lock().unwrap().clone();
+        let data = match id {
+            "" => String::new(),
+            id => json!({ "ID": id }).to_string(),
+        };
+
+        for notification in notifications {
+            for callback in &callbacks {
+                callback(*notification, &data);
+            }
+        }
+    }
+}
+
+impl HcnBackend for HcnSimulator {
+    fn enumerate_networks(&self, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .enumerate(ObjectKind::Network, query)
+    }
+
+    fn create_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let settings = parse_document(settings)?;
+        let network_type = settings.get("Type").and_then(Value::as_str);
+
+        if !network_type.is_some_and(|network_type| NETWORK_TYPES.contains(&network_type)) {
+            return Err(error(
+                ResultCode::HcnInvalidNetworkType,
+                format!("Invalid network type {:?}", network_type),
+            ));
+        }
+        network_subnets(&settings)?;
+
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            let id = state.new_id(ObjectKind::Network, id)?;
+            state.insert(ObjectKind::Network, id.clone(), settings);
+            id
+        };
+
+        self.notify(
+            &[
+                HcnNotifications::NetworkPreCreate,
+                HcnNotifications::NetworkCreate,
+            ],
+            &id,
+        );
+        Ok(())
+    }
+
+    fn modify_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let request = parse_document(settings)?;
+        let mut state = self.state.lock().unwrap();
+        modify_policies(
+            state.get_mut(ObjectKind::Network, &id.to_string())?,
+            &request,
+        )
+    }
+
+    fn query_network_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .query_properties(ObjectKind::Network, id, query)
+    }
+
+    fn delete_network(&self, id: &GuidSerde) -> HcnResult<()> {
+        let id = id.to_string();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.get(ObjectKind::Network, &id)?;
+
+            if let Some(endpoint) = state.endpoints_of_network(&id).next() {
+                return Err(error(
+                    ResultCode::from_hresult(HRESULT_ERROR_DEVICE_IN_USE),
+                    format!("Network {} is in use by endpoint {}", id, endpoint),
+                ));
+            }
+
+            state.networks.remove(&id);
+        }
+
+        self.notify(
+            &[
+                HcnNotifications::NetworkPreDelete,
+                HcnNotifications::NetworkDelete,
+            ],
+            &id,
+        );
+        Ok(())
+    }
+
+    fn enumerate_namespaces(&self, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .enumerate(ObjectKind::Namespace, query)
+    }
+
+    fn create_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let settings = parse_document(settings)?;
+        let endpoints = namespace_endpoints(&settings)?;
+
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            let id = state.new_id(ObjectKind::Namespace, id)?;
+            state.verify_attachable(&id, &endpoints)?;
+
+            for endpoint in &endpoints {
+                state.set_endpoint_namespace(endpoint, Some(&id));
+            }
+            state.insert(ObjectKind::Namespace, id.clone(), settings);
+            id
+        };
+
+        self.notify(&[HcnNotifications::NamespaceCreate], &id);
+        Ok(())
+    }
+
+    fn modify_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let request = parse_document(settings)?;
+        let id = id.to_string();
+        let mut state = self.state.lock().unwrap();
+        let attached = namespace_endpoints(state.get(ObjectKind::Namespace, &id)?)?;
+
+        if request.get("ResourceType").and_then(Value::as_str) != Some("Endpoint") {
+            return Err(error(
+                ResultCode::HcnRequestUnsupported,
+                String::from("Unsupported modify resource type"),
+            ));
+        }
+
+        let endpoint = parse_reference(
+            request
+                .get("Settings")
+                .and_then(|settings| settings.get("EndpointId")),
+            "EndpointId",
+        )?;
+
+        match request.get("RequestType").and_then(Value::as_str) {
+            Some("Add") => {
+                state.verify_attachable(&id, std::slice::from_ref(&endpoint))?;
+                state.set_endpoint_namespace(&endpoint, Some(&id));
+
+                let namespace = state.get_mut(ObjectKind::Namespace, &id)?;
+                let mut resources = as_array(namespace, "Resources").to_vec();
+                resources.push(json!({"Type": "Endpoint", "Data": {"Id": endpoint}}));
+                namespace.insert(String::from("Resources"), Value::Array(resources));
+            }
+            Some("Remove") => {
+                if !attached.contains(&endpoint) {
+                    return Err(error(
+                        ResultCode::HcnEndpointNotFound,
+                        format!("Endpoint {} is not attached to namespace {}", endpoint, id),
+                    ));
+                }
+                state.set_endpoint_namespace(&endpoint, None);
+
+                let namespace = state.get_mut(ObjectKind::Namespace, &id)?;
+                let resources = as_array(namespace, "Resources")
+                    .iter()
+                    .filter(|resource| {
+                        let reference = resource.get("Data").and_then(|data| data.get("Id"));
+                        parse_reference(reference, "Resources").ok().as_ref() != Some(&endpoint)
+                    })
+                    .cloned()
+                    .collect();
+                namespace.insert(String::from("Resources"), Value::Array(resources));
+            }
+            _ => {
+                return Err(error(
+                    ResultCode::HcnRequestUnsupported,
+                    String::from("Unsupported modify request type"),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn query_namespace_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .query_properties(ObjectKind::Namespace, id, query)
+    }
+
+    fn delete_namespace(&self, id: &GuidSerde) -> HcnResult<()> {
+        let id = id.to_string();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            let endpoints = namespace_endpoints(state.get(ObjectKind::Namespace, &id)?)?;
+
+            for endpoint in &endpoints {
+                state.set_endpoint_namespace(endpoint, None);
+            }
+            state.namespaces.remove(&id);
+        }
+
+        self.notify(&[HcnNotifications::NamespaceDelete], &id);
+        Ok(())
+    }
+
+    fn enumerate_endpoints(&self, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .enumerate(ObjectKind::Endpoint, query)
+    }
+
+    fn create_endpoint(
+        &self,
+        network: &GuidSerde,
+        id: &GuidSerde,
+        settings: &str,
+    ) -> HcnResult<()> {
+        let mut settings = parse_document(settings)?;
+        let network = network.to_string();
+        let mut state = self.state.lock().unwrap();
+
+        state.get(ObjectKind::Network, &network)?;
+        let id = state.new_id(ObjectKind::Endpoint, id)?;
+        state.configure_addresses(&network, &mut settings)?;
+
+        if !settings.contains_key("MacAddress") {
+            state.assigned_macs += 1;
+            let [_, high, middle, low] = state.assigned_macs.to_be_bytes();
+            settings.insert(
+                String::from("MacAddress"),
+                Value::from(format!("00-15-5D-{:02X}-{:02X}-{:02X}", high, middle, low)),
+            );
+        }
+        settings.remove("HostComputeNamespace");
+        settings.insert(String::from("HostComputeNetwork"), Value::from(network));
+        state.insert(ObjectKind::Endpoint, id, settings);
+        Ok(())
+    }
+
+    fn modify_endpoint(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let request = parse_document(settings)?;
+        let mut state = self.state.lock().unwrap();
+        modify_policies(
+            state.get_mut(ObjectKind::Endpoint, &id.to_string())?,
+            &request,
+        )
+    }
+
+    fn query_endpoint_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .query_properties(ObjectKind::Endpoint, id, query)
+    }
+
+    fn delete_endpoint(&self, id: &GuidSerde) -> HcnResult<()> {
+        let id = id.to_string();
+        let mut state = self.state.lock().unwrap();
+        let endpoint = state.get(ObjectKind::Endpoint, &id)?;
+
+        if let Some(namespace) = endpoint.get("HostComputeNamespace").and_then(Value::as_str) {
+            return Err(error(
+                ResultCode::HcnEndpointAlreadyAttached,
+                format!("Endpoint {} is attached to namespace {}", id, namespace),
+            ));
+        }
+
+        for (load_balancer, document) in &state.load_balancers {
+            if load_balancer_endpoints(document)?.contains(&id) {
+                return Err(error(
+                    ResultCode::from_hresult(HRESULT_ERROR_DEVICE_IN_USE),
+                    format!(
+                        "Endpoint {} is in use by load balancer {}",
+                        id, load_balancer
+                    ),
+                ));
+            }
+        }
+
+        state.endpoints.remove(&id);
+        Ok(())
+    }
+
+    fn enumerate_load_balancers(&self, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .enumerate(ObjectKind::LoadBalancer, query)
+    }
+
+    fn create_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let settings = parse_document(settings)?;
+        let endpoints = load_balancer_endpoints(&settings)?;
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.new_id(ObjectKind::LoadBalancer, id)?;
+        state.verify_endpoints_exist(&endpoints)?;
+        state.insert(ObjectKind::LoadBalancer, id, settings);
+        Ok(())
+    }
+
+    /// Replaces the settings of a load balancer.
+    fn modify_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        let settings = parse_document(settings)?;
+        let endpoints = load_balancer_endpoints(&settings)?;
+        let id = id.to_string();
+        let mut state = self.state.lock().unwrap();
+
+        state.get(ObjectKind::LoadBalancer, &id)?;
+        state.verify_endpoints_exist(&endpoints)?;
+        state.insert(ObjectKind::LoadBalancer, id, settings);
+        Ok(())
+    }
+
+    fn query_load_balancer_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .query_properties(ObjectKind::LoadBalancer, id, query)
+    }
+
+    fn delete_load_balancer(&self, id: &GuidSerde) -> HcnResult<()> {
+        let id = id.to_string();
+        let mut state = self.state.lock().unwrap();
+
+        state.get(ObjectKind::LoadBalancer, &id)?;
+        state.load_balancers.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netschema::query::HostComputeQuery;
+
+    fn guid(value: u32) -> GuidSerde {
+        GuidSerde {
+            data1: value,
+            ..GuidSerde::new()
+        }
+    }
+
+    fn properties(document: HcnResult<String>) -> Value {
+        serde_json::from_str(&document.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn networks_endpoints_and_namespaces() {
+        let simulator = HcnSimulator::new();
+        let notifications = Arc::new(Mutex::new(Vec::new()));
+        let received = notifications.clone();
+        simulator.register_service_callback(Arc::new(move |notification, _| {
+            received.lock().unwrap().push(notification);
+        }));
+
+        let network = guid(1);
+        simulator
+            .create_network(
+                &network,
+                r#"{"Name": "nat", "Type": "NAT", "Ipams": [{"Type": "Static", "Subnets": [{
+                    "IpAddressPrefix": "172.16.0.0/30",
+                    "Routes": [{"NextHop": "172.16.0.1", "DestinationPrefix": "0.0.0.0/0"}]
+                }]}]}"#,
+            )
+            .unwrap();
+        let error = simulator
+            .create_network(&guid(2), r#"{"Type": "Bridge"}"#)
+            .unwrap_err();
+        assert_eq!(error.result_code, ResultCode::HcnInvalidNetworkType);
+        assert_eq!(
+            properties(Ok(error.error_record))["ErrorCode"],
+            ResultCode::HcnInvalidNetworkType.to_hresult() as u32
+        );
+
+        // The only address left in the subnet is assigned, and the next endpoint gets none
+        simulator
+            .create_endpoint(&network, &guid(10), r#"{"Name": "first"}"#)
+            .unwrap();
+        let endpoint = properties(simulator.query_endpoint_properties(&guid(10), ""));
+        assert_eq!(endpoint["IpConfigurations"][0]["IpAddress"], "172.16.0.2");
+        assert_eq!(endpoint["HostComputeNetwork"], network.to_string());
+        assert_eq!(
+            simulator
+                .create_endpoint(&network, &guid(11), "{}")
+                .unwrap_err()
+                .result_code,
+            ResultCode::HcnInvalidIp
+        );
+        assert_eq!(
+            simulator
+                .create_endpoint(&guid(3), &guid(11), "{}")
+                .unwrap_err()
+                .result_code,
+            ResultCode::HcnNetworkNotFound
+        );
+
+        let query = json!({
+            "Flags": HOST_COMPUTE_QUERY_FLAGS_DETAILED,
+            "Filter": json!({"HostComputeNetwork": network.to_string().to_uppercase()}).to_string(),
+        });
+        let endpoints = properties(simulator.enumerate_endpoints(&query.to_string()));
+        assert_eq!(endpoints.as_array().unwrap().len(), 1);
+        assert_eq!(endpoints[0]["Name"], "first");
+        let query = HostComputeQuery::new()
+            .network(&network)
+            .detailed()
+            .to_json();
+        let endpoints = properties(simulator.enumerate_endpoints(&query));
+        assert_eq!(endpoints.as_array().unwrap().len(), 1);
+        assert_eq!(endpoints[0]["Name"], "first");
+        assert_eq!(
+            properties(simulator.enumerate_networks(r#"{"Filter": "{\"Name\": \"other\"}"}"#)),
+            json!([])
+        );
+
+        // Objects can't be deleted while referenced
+        simulator
+            .create_namespace(
+                &guid(20),
+                &json!({"Resources": [{"Type": "Endpoint", "Data": {"Id": guid(10).to_string()}}]})
+                    .to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            simulator
+                .delete_endpoint(&guid(10))
+                .unwrap_err()
+                .result_code,
+            ResultCode::HcnEndpointAlreadyAttached
+        );
+        assert_eq!(
+            simulator.delete_network(&network).unwrap_err().result_code,
+            ResultCode::from_hresult(HRESULT_ERROR_DEVICE_IN_USE)
+        );
+
+        let detach = json!({
+            "ResourceType": "Endpoint",
+            "RequestType": "Remove",
+            "Settings": {"EndpointId": guid(10).to_string()},
+        });
+        simulator
+            .modify_namespace(&guid(20), &detach.to_string())
+            .unwrap();
+        simulator.delete_endpoint(&guid(10)).unwrap();
+        simulator.delete_network(&network).unwrap();
+
+        assert_eq!(
+            *notifications.lock().unwrap(),
+            vec![
+                HcnNotifications::NetworkPreCreate,
+                HcnNotifications::NetworkCreate,
+                HcnNotifications::NamespaceCreate,
+                HcnNotifications::NetworkPreDelete,
+                HcnNotifications::NetworkDelete,
+            ]
+        );
+    }
+}
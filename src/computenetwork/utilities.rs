@@ -0,0 +1,162 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Rust types that provide convenient functionality built on top of the computenetwork APIs.
+
+use crate::computenetwork;
+use crate::computenetwork::backend::HcnBackend;
+use crate::computenetwork::HcnResult;
+use crate::schema::utils::GuidSerde;
+
+/// Runs a function on an open handle, closing it afterwards.
+fn with_handle<H, T, F>(
+    handle: HcnResult<H>,
+    close: fn(H) -> HcnResult<()>,
+    function: F,
+) -> HcnResult<T>
+where
+    H: Copy,
+    F: FnOnce(H) -> HcnResult<T>,
+{
+    let handle = handle?;
+    let result = function(handle);
+    let closed = close(handle);
+    result.and_then(|value| closed.map(|_| value))
+}
+
+/// `HcnBackend` implementation on top of the Host Compute Network service.
+pub struct HcnServiceBackend;
+
+impl HcnBackend for HcnServiceBackend {
+    fn enumerate_networks(&self, query: &str) -> HcnResult<String> {
+        computenetwork::enumerate_networks(query)
+    }
+
+    fn create_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        computenetwork::create_network(&id.to_win_guid(), settings)
+            .and_then(computenetwork::close_network)
+    }
+
+    fn modify_network(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        with_handle(
+            computenetwork::open_network(&id.to_win_guid()),
+            computenetwork::close_network,
+            |network| computenetwork::modify_network(network, settings),
+        )
+    }
+
+    fn query_network_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        with_handle(
+            computenetwork::open_network(&id.to_win_guid()),
+            computenetwork::close_network,
+            |network| computenetwork::query_network_properties(network, query),
+        )
+    }
+
+    fn delete_network(&self, id: &GuidSerde) -> HcnResult<()> {
+        computenetwork::delete_network(&id.to_win_guid())
+    }
+
+    fn enumerate_namespaces(&self, query: &str) -> HcnResult<String> {
+        computenetwork::enumerate_namespaces(query)
+    }
+
+    fn create_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        computenetwork::create_namespace(&id.to_win_guid(), settings)
+            .and_then(computenetwork::close_namespace)
+    }
+
+    fn modify_namespace(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        with_handle(
+            computenetwork::open_namespace(&id.to_win_guid()),
+            computenetwork::close_namespace,
+            |namespace| computenetwork::modify_namespace(namespace, settings),
+        )
+    }
+
+    fn query_namespace_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        with_handle(
+            computenetwork::open_namespace(&id.to_win_guid()),
+            computenetwork::close_namespace,
+            |namespace| computenetwork::query_namespace_properties(namespace, query),
+        )
+    }
+
+    fn delete_namespace(&self, id: &GuidSerde) -> HcnResult<()> {
+        computenetwork::delete_namespace(&id.to_win_guid())
+    }
+
+    fn enumerate_endpoints(&self, query: &str) -> HcnResult<String> {
+        computenetwork::enumerate_endpoints(query)
+    }
+
+    fn create_endpoint(
+        &self,
+        network: &GuidSerde,
+        id: &GuidSerde,
+        settings: &str,
+    ) -> HcnResult<()> {
+        with_handle(
+            computenetwork::open_network(&network.to_win_guid()),
+            computenetwork::close_network,
+            |network| {
+                computenetwork::create_endpoint(network, &id.to_win_guid(), settings)
+                    .and_then(computenetwork::close_endpoint)
+            },
+        )
+    }
+
+    fn modify_endpoint(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        with_handle(
+            computenetwork::open_endpoint(&id.to_win_guid()),
+            computenetwork::close_endpoint,
+            |endpoint| computenetwork::modify_endpoint(endpoint, settings),
+        )
+    }
+
+    fn query_endpoint_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        with_handle(
+            computenetwork::open_endpoint(&id.to_win_guid()),
+            computenetwork::close_endpoint,
+            |endpoint| computenetwork::query_endpoint_properties(endpoint, query),
+        )
+    }
+
+    fn delete_endpoint(&self, id: &GuidSerde) -> HcnResult<()> {
+        computenetwork::delete_endpoint(&id.to_win_guid())
+    }
+
+    fn enumerate_load_balancers(&self, query: &str) -> HcnResult<String> {
+        computenetwork::enumerate_load_balancers(query)
+    }
+
+    fn create_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        computenetwork::create_load_balancer(&id.to_win_guid(), settings)
+            .and_then(computenetwork::close_load_balancer)
+    }
+
+    fn modify_load_balancer(&self, id: &GuidSerde, settings: &str) -> HcnResult<()> {
+        with_handle(
+            computenetwork::open_load_balancer(&id.to_win_guid()),
+            computenetwork::close_load_balancer,
+            |load_balancer| computenetwork::modify_load_balancer(load_balancer, settings),
+        )
+    }
+
+    fn query_load_balancer_properties(&self, id: &GuidSerde, query: &str) -> HcnResult<String> {
+        with_handle(
+            computenetwork::open_load_balancer(&id.to_win_guid()),
+            computenetwork::close_load_balancer,
+            |load_balancer| computenetwork::query_load_balancer_properties(load_balancer, query),
+        )
+    }
+
+    fn delete_load_balancer(&self, id: &GuidSerde) -> HcnResult<()> {
+        computenetwork::delete_load_balancer(&id.to_win_guid())
+    }
+}
@@ -15,6 +15,7 @@ pub(crate) mod bindings;
 pub mod bindings;
 
 use crate::compute::errorcodes::hresult_to_result_code;
+use crate::compute::trace::ApiCall;
 use crate::computestorage::bindings::*;
 use crate::HcsResult;
 use widestring::WideCString;
@@ -23,7 +24,11 @@ use winutils_rs::windefs::*;
 
 /// Imports a container layer.
 pub fn import_layer(path: &str, source_folder_path: &str, layer_data: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsImportLayer")
+        .target(path)
+        .request(layer_data);
+
+    call.finish(unsafe {
         match HcsImportLayer(
             WideCString::from_str(path).unwrap().as_ptr(),
             WideCString::from_str(source_folder_path).unwrap().as_ptr(),
@@ -32,7 +37,7 @@ pub fn import_layer(path: &str, source_folder_path: &str, layer_data: &str) -> H
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Exports a container layer.
@@ -42,7 +47,12 @@ pub fn export_layer(
     layer_data: &str,
     options: &str,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsExportLayer")
+        .target(path)
+        .request(layer_data)
+        .request(options);
+
+    call.finish(unsafe {
         match HcsExportLayer(
             WideCString::from_str(path).unwrap().as_ptr(),
             WideCString::from_str(export_folder_path).unwrap().as_ptr(),
@@ -52,7 +62,7 @@ pub fn export_layer(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Exports a legacy container writable layer.
@@ -62,7 +72,11 @@ pub fn export_legacy_writable_layer(
     export_folder_path: &str,
     layer_data: &str,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsExportLegacyWritableLayer")
+        .target(mount_path)
+        .request(layer_data);
+
+    call.finish(unsafe {
         match HcsExportLegacyWritableLayer(
             WideCString::from_str(mount_path).unwrap().as_ptr(),
             WideCString::from_str(folder_path).unwrap().as_ptr(),
@@ -72,7 +86,7 @@ pub fn export_legacy_writable_layer(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Deletes a container layer.
@@ -82,17 +96,24 @@ pub fn export_legacy_writable_layer(
 /// to delete a directory. Misuse of this API could lead to potential deletion
 /// of important files, not revertible.
 pub fn destroy_layer(layer_path: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsDestroyLayer").target(layer_path);
+
+    call.finish(unsafe {
         match HcsDestroyLayer(WideCString::from_str(layer_path).unwrap().as_ptr()) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Sets up a layer that contains a base OS for a container.
 pub fn setup_base_os_layer(layer_path: &str, vhd_handle: Handle, options: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsSetupBaseOSLayer")
+        .target(layer_path)
+        .handle(vhd_handle)
+        .request(options);
+
+    call.finish(unsafe {
         match HcsSetupBaseOSLayer(
             WideCString::from_str(layer_path).unwrap().as_ptr(),
             vhd_handle,
@@ -101,7 +122,7 @@ pub fn setup_base_os_layer(layer_path: &str, vhd_handle: Handle, options: &str)
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Initializes a writable layer for a container.
@@ -110,7 +131,12 @@ pub fn initialize_writable_layer(
     layer_data: &str,
     options: &str,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsInitializeWritableLayer")
+        .target(layer_path)
+        .request(layer_data)
+        .request(options);
+
+    call.finish(unsafe {
         match HcsInitializeWritableLayer(
             WideCString::from_str(layer_path).unwrap().as_ptr(),
             WideCString::from_str(layer_data).unwrap().as_ptr(),
@@ -119,7 +145,7 @@ pub fn initialize_writable_layer(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Initializes a writable layer for a container using the legacy hive folder format.
@@ -129,7 +155,12 @@ pub fn initialize_legacy_writable_layer(
     layer_data: &str,
     options: &str,
 ) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsInitializeLegacyWritableLayer")
+        .target(mount_path)
+        .request(layer_data)
+        .request(options);
+
+    call.finish(unsafe {
         match HcsInitializeLegacyWritableLayer(
             WideCString::from_str(mount_path).unwrap().as_ptr(),
             WideCString::from_str(folder_path).unwrap().as_ptr(),
@@ -139,12 +170,16 @@ pub fn initialize_legacy_writable_layer(
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Sets up the layer storage filter on a writable container layer.
 pub fn attach_layer_storage_filter(layer_path: &str, layer_data: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsAttachLayerStorageFilter")
+        .target(layer_path)
+        .request(layer_data);
+
+    call.finish(unsafe {
         match HcsAttachLayerStorageFilter(
             WideCString::from_str(layer_path).unwrap().as_ptr(),
             WideCString::from_str(layer_data).unwrap().as_ptr(),
@@ -152,37 +187,43 @@ pub fn attach_layer_storage_filter(layer_path: &str, layer_data: &str) -> HcsRes
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Detaches the layer storage filter from a writable container layer.
 pub fn detach_layer_storage_filter(layer_path: &str) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsDetachLayerStorageFilter").target(layer_path);
+
+    call.finish(unsafe {
         match HcsDetachLayerStorageFilter(WideCString::from_str(layer_path).unwrap().as_ptr()) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Formats a virtual disk for the use as a writable container layer.
 pub fn format_writable_layer_vhd(vhd_handle: Handle) -> HcsResult<()> {
-    unsafe {
+    let call = ApiCall::new("HcsFormatWritableLayerVhd").handle(vhd_handle);
+
+    call.finish(unsafe {
         match HcsFormatWritableLayerVhd(vhd_handle) {
             0 => Ok(()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
 
 /// Returns the volume path for a virtual disk of a writable container layer.
 pub fn get_layer_vhd_mount_path(vhd_handle: Handle) -> HcsResult<String> {
-    unsafe {
+    let call = ApiCall::new("HcsGetLayerVhdMountPath").handle(vhd_handle);
+
+    call.finish(unsafe {
         let mut mount_path = CoTaskMemWString::new();
 
         match HcsGetLayerVhdMountPath(vhd_handle, mount_path.ptr_mut()) {
             0 => Ok(mount_path.to_string()),
             hresult => Err(hresult_to_result_code(&hresult)),
         }
-    }
+    })
 }
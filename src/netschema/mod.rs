@@ -7,3 +7,329 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 //! Contains all the JSON schema definitions used by the HCN APIs
+
+pub mod policies;
+pub mod query;
+
+use crate::schema::utils::{is_default, GuidSerde};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Version of the HCN schema, which is versioned independently of the HCS schema.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SchemaVersion {
+    #[serde(rename = "Major")]
+    pub major: u32,
+    #[serde(rename = "Minor")]
+    pub minor: u32,
+}
+
+impl std::default::Default for SchemaVersion {
+    fn default() -> Self {
+        SchemaVersion { major: 2, minor: 0 }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkType {
+    #[default]
+    NAT,
+    ICS,
+    Transparent,
+    L2Bridge,
+    L2Tunnel,
+    Overlay,
+    Private,
+    Internal,
+    Mirrored,
+}
+
+/// Policy of a network, endpoint or subnet. The settings depend on the policy type.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Policy {
+    #[serde(rename = "Type")]
+    pub policy_type: String,
+
+    #[serde(default, rename = "Settings", skip_serializing_if = "is_default")]
+    pub settings: Value,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Route {
+    #[serde(default, rename = "NextHop", skip_serializing_if = "is_default")]
+    pub next_hop: String,
+
+    #[serde(
+        default,
+        rename = "DestinationPrefix",
+        skip_serializing_if = "is_default"
+    )]
+    pub destination_prefix: String,
+
+    #[serde(default, rename = "Metric", skip_serializing_if = "is_default")]
+    pub metric: u16,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Subnet {
+    #[serde(
+        default,
+        rename = "IpAddressPrefix",
+        skip_serializing_if = "is_default"
+    )]
+    pub ip_address_prefix: String,
+
+    #[serde(default, rename = "Policies", skip_serializing_if = "is_default")]
+    pub policies: Vec<Policy>,
+
+    #[serde(default, rename = "Routes", skip_serializing_if = "is_default")]
+    pub routes: Vec<Route>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Ipam {
+    /// Either "Static" or "DHCP".
+    #[serde(default, rename = "Type", skip_serializing_if = "is_default")]
+    pub ipam_type: String,
+
+    #[serde(default, rename = "Subnets", skip_serializing_if = "is_default")]
+    pub subnets: Vec<Subnet>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MacRange {
+    #[serde(
+        default,
+        rename = "StartMacAddress",
+        skip_serializing_if = "is_default"
+    )]
+    pub start_mac_address: String,
+
+    #[serde(default, rename = "EndMacAddress", skip_serializing_if = "is_default")]
+    pub end_mac_address: String,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MacPool {
+    #[serde(default, rename = "Ranges", skip_serializing_if = "is_default")]
+    pub ranges: Vec<MacRange>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Dns {
+    #[serde(default, rename = "Domain", skip_serializing_if = "is_default")]
+    pub domain: String,
+
+    #[serde(default, rename = "Search", skip_serializing_if = "is_default")]
+    pub search: Vec<String>,
+
+    #[serde(default, rename = "ServerList", skip_serializing_if = "is_default")]
+    pub server_list: Vec<String>,
+
+    #[serde(default, rename = "Options", skip_serializing_if = "is_default")]
+    pub options: Vec<String>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HostComputeNetwork {
+    #[serde(default, rename = "ID", skip_serializing_if = "is_default")]
+    pub id: GuidSerde,
+
+    #[serde(default, rename = "Name", skip_serializing_if = "is_default")]
+    pub name: String,
+
+    #[serde(default, rename = "Type")]
+    pub network_type: NetworkType,
+
+    #[serde(default, rename = "Policies", skip_serializing_if = "is_default")]
+    pub policies: Vec<Policy>,
+
+    #[serde(default, rename = "MacPool", skip_serializing_if = "is_default")]
+    pub mac_pool: MacPool,
+
+    #[serde(default, rename = "Dns", skip_serializing_if = "is_default")]
+    pub dns: Dns,
+
+    #[serde(default, rename = "Ipams", skip_serializing_if = "is_default")]
+    pub ipams: Vec<Ipam>,
+
+    #[serde(default, rename = "Flags", skip_serializing_if = "is_default")]
+    pub flags: u32,
+
+    #[serde(default, rename = "SchemaVersion")]
+    pub schema_version: SchemaVersion,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct IpConfig {
+    #[serde(default, rename = "IpAddress", skip_serializing_if = "is_default")]
+    pub ip_address: String,
+
+    #[serde(default, rename = "PrefixLength", skip_serializing_if = "is_default")]
+    pub prefix_length: u8,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HostComputeEndpoint {
+    #[serde(default, rename = "ID", skip_serializing_if = "is_default")]
+    pub id: GuidSerde,
+
+    #[serde(default, rename = "Name", skip_serializing_if = "is_default")]
+    pub name: String,
+
+    #[serde(
+        default,
+        rename = "HostComputeNetwork",
+        skip_serializing_if = "is_default"
+    )]
+    pub host_compute_network: GuidSerde,
+
+    #[serde(
+        default,
+        rename = "HostComputeNamespace",
+        skip_serializing_if = "is_default"
+    )]
+    pub host_compute_namespace: GuidSerde,
+
+    #[serde(default, rename = "Policies", skip_serializing_if = "is_default")]
+    pub policies: Vec<Policy>,
+
+    #[serde(
+        default,
+        rename = "IpConfigurations",
+        skip_serializing_if = "is_default"
+    )]
+    pub ip_configurations: Vec<IpConfig>,
+
+    #[serde(default, rename = "Dns", skip_serializing_if = "is_default")]
+    pub dns: Dns,
+
+    #[serde(default, rename = "Routes", skip_serializing_if = "is_default")]
+    pub routes: Vec<Route>,
+
+    #[serde(default, rename = "MacAddress", skip_serializing_if = "is_default")]
+    pub mac_address: String,
+
+    #[serde(default, rename = "Flags", skip_serializing_if = "is_default")]
+    pub flags: u32,
+
+    #[serde(default, rename = "SchemaVersion")]
+    pub schema_version: SchemaVersion,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamespaceType {
+    #[default]
+    Host,
+    HostDefault,
+    Guest,
+    GuestDefault,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamespaceResourceType {
+    Container,
+    #[default]
+    Endpoint,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct NamespaceResource {
+    #[serde(default, rename = "Type")]
+    pub resource_type: NamespaceResourceType,
+
+    /// Holds the ID of the endpoint or container, as `{"Id": "..."}`.
+    #[serde(default, rename = "Data", skip_serializing_if = "is_default")]
+    pub data: Value,
+}
+
+impl NamespaceResource {
+    pub fn endpoint(id: &GuidSerde) -> NamespaceResource {
+        NamespaceResource {
+            resource_type: NamespaceResourceType::Endpoint,
+            data: serde_json::json!({ "Id": id }),
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HostComputeNamespace {
+    #[serde(default, rename = "ID", skip_serializing_if = "is_default")]
+    pub id: GuidSerde,
+
+    #[serde(default, rename = "NamespaceId", skip_serializing_if = "is_default")]
+    pub namespace_id: u32,
+
+    #[serde(default, rename = "Type")]
+    pub namespace_type: NamespaceType,
+
+    #[serde(default, rename = "Resources", skip_serializing_if = "is_default")]
+    pub resources: Vec<NamespaceResource>,
+
+    #[serde(default, rename = "SchemaVersion")]
+    pub schema_version: SchemaVersion,
+}
+
+impl HostComputeNamespace {
+    /// Returns the IDs of the endpoints attached to the namespace.
+    pub fn endpoints(&self) -> Vec<GuidSerde> {
+        self.resources
+            .iter()
+            .filter(|resource| resource.resource_type == NamespaceResourceType::Endpoint)
+            .filter_map(|resource| resource.data.get("Id")?.as_str())
+            .filter_map(|id| GuidSerde::from_str(id).ok())
+            .collect()
+    }
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct LoadBalancerPortMapping {
+    /// IANA protocol number, 6 for TCP and 17 for UDP.
+    #[serde(default, rename = "Protocol", skip_serializing_if = "is_default")]
+    pub protocol: u32,
+
+    #[serde(default, rename = "InternalPort", skip_serializing_if = "is_default")]
+    pub internal_port: u16,
+
+    #[serde(default, rename = "ExternalPort", skip_serializing_if = "is_default")]
+    pub external_port: u16,
+
+    #[serde(
+        default,
+        rename = "DistributionType",
+        skip_serializing_if = "is_default"
+    )]
+    pub distribution_type: u32,
+
+    #[serde(default, rename = "Flags", skip_serializing_if = "is_default")]
+    pub flags: u32,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HostComputeLoadBalancer {
+    #[serde(default, rename = "ID", skip_serializing_if = "is_default")]
+    pub id: GuidSerde,
+
+    #[serde(
+        default,
+        rename = "HostComputeEndpoints",
+        skip_serializing_if = "is_default"
+    )]
+    pub host_compute_endpoints: Vec<GuidSerde>,
+
+    #[serde(default, rename = "SourceVIP", skip_serializing_if = "is_default")]
+    pub source_vip: String,
+
+    #[serde(default, rename = "FrontendVIPs", skip_serializing_if = "is_default")]
+    pub frontend_vips: Vec<String>,
+
+    #[serde(default, rename = "PortMappings", skip_serializing_if = "is_default")]
+    pub port_mappings: Vec<LoadBalancerPortMapping>,
+
+    #[serde(default, rename = "Flags", skip_serializing_if = "is_default")]
+    pub flags: u32,
+
+    #[serde(default, rename = "SchemaVersion")]
+    pub schema_version: SchemaVersion,
+}
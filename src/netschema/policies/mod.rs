@@ -0,0 +1,255 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Typed settings of endpoint policies, and the requests that modify them.
+
+use crate::netschema::Policy;
+use crate::schema::utils::is_default;
+use serde::{Deserialize, Serialize};
+
+pub const PROTOCOL_TCP: u32 = 6;
+pub const PROTOCOL_UDP: u32 = 17;
+
+/// The VIP is routed to the host locally.
+pub const NAT_FLAGS_LOCAL_ROUTED_VIP: u32 = 0x1;
+/// The mapping or NAT applies to IPv6 traffic.
+pub const NAT_FLAGS_IPV6: u32 = 0x2;
+
+pub const POLICY_TYPE_PORT_MAPPING: &str = "PortMapping";
+pub const POLICY_TYPE_OUTBOUND_NAT: &str = "OutBoundNAT";
+
+/// Publishes a port of an endpoint on the host.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PortMappingPolicySetting {
+    /// IANA protocol number, `PROTOCOL_TCP` or `PROTOCOL_UDP`.
+    #[serde(rename = "Protocol")]
+    pub protocol: u32,
+
+    #[serde(rename = "InternalPort")]
+    pub internal_port: u16,
+
+    /// Port on the host, 0 to have one allocated.
+    #[serde(default, rename = "ExternalPort", skip_serializing_if = "is_default")]
+    pub external_port: u16,
+
+    /// Host address the port is published on, all of them if empty.
+    #[serde(default, rename = "VIP", skip_serializing_if = "is_default")]
+    pub vip: String,
+
+    #[serde(default, rename = "Flags", skip_serializing_if = "is_default")]
+    pub flags: u32,
+}
+
+impl PortMappingPolicySetting {
+    pub fn tcp(internal_port: u16, external_port: u16) -> PortMappingPolicySetting {
+        PortMappingPolicySetting {
+            protocol: PROTOCOL_TCP,
+            internal_port,
+            external_port,
+            ..Default::default()
+        }
+    }
+
+    pub fn udp(internal_port: u16, external_port: u16) -> PortMappingPolicySetting {
+        PortMappingPolicySetting {
+            protocol: PROTOCOL_UDP,
+            internal_port,
+            external_port,
+            ..Default::default()
+        }
+    }
+
+    pub fn vip(mut self, vip: &str) -> PortMappingPolicySetting {
+        self.vip = String::from(vip);
+        self
+    }
+
+    pub fn flags(mut self, flags: u32) -> PortMappingPolicySetting {
+        self.flags |= flags;
+        self
+    }
+
+    /// Returns whether both mappings publish the same external port of the host.
+    /// Mappings on all host addresses conflict with mappings on any VIP.
+    pub fn conflicts_with(&self, other: &PortMappingPolicySetting) -> bool {
+        self.external_port != 0
+            && self.external_port == other.external_port
+            && self.protocol == other.protocol
+            && self.flags & NAT_FLAGS_IPV6 == other.flags & NAT_FLAGS_IPV6
+            && (self.vip.is_empty() || other.vip.is_empty() || self.vip == other.vip)
+    }
+
+    /// Returns the settings of a port mapping policy, or `None` for other policies.
+    /// Fails if the settings of a port mapping policy are invalid.
+    pub fn from_policy(
+        policy: &Policy,
+    ) -> Result<Option<PortMappingPolicySetting>, serde_json::Error> {
+        match policy.policy_type == POLICY_TYPE_PORT_MAPPING {
+            true => serde_json::from_value(policy.settings.clone()).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    pub fn to_policy(&self) -> Policy {
+        Policy {
+            policy_type: String::from(POLICY_TYPE_PORT_MAPPING),
+            settings: serde_json::to_value(self).unwrap(),
+        }
+    }
+}
+
+/// NATs outbound traffic of an endpoint behind a virtual IP, except for the exception
+/// address prefixes, which are reached with the endpoint address.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutboundNatPolicySetting {
+    #[serde(default, rename = "VirtualIP", skip_serializing_if = "is_default")]
+    pub virtual_ip: String,
+
+    #[serde(default, rename = "Exceptions", skip_serializing_if = "is_default")]
+    pub exceptions: Vec<String>,
+
+    #[serde(default, rename = "Destinations", skip_serializing_if = "is_default")]
+    pub destinations: Vec<String>,
+
+    #[serde(default, rename = "Flags", skip_serializing_if = "is_default")]
+    pub flags: u32,
+}
+
+impl OutboundNatPolicySetting {
+    pub fn new() -> OutboundNatPolicySetting {
+        OutboundNatPolicySetting::default()
+    }
+
+    pub fn virtual_ip(mut self, virtual_ip: &str) -> OutboundNatPolicySetting {
+        self.virtual_ip = String::from(virtual_ip);
+        self
+    }
+
+    /// Adds an address prefix, like "10.0.0.0/8", that is not NATed.
+    pub fn exception(mut self, prefix: &str) -> OutboundNatPolicySetting {
+        self.exceptions.push(String::from(prefix));
+        self
+    }
+
+    /// Restricts the NAT to an address prefix.
+    pub fn destination(mut self, prefix: &str) -> OutboundNatPolicySetting {
+        self.destinations.push(String::from(prefix));
+        self
+    }
+
+    pub fn flags(mut self, flags: u32) -> OutboundNatPolicySetting {
+        self.flags |= flags;
+        self
+    }
+
+    pub fn to_policy(&self) -> Policy {
+        Policy {
+            policy_type: String::from(POLICY_TYPE_OUTBOUND_NAT),
+            settings: serde_json::to_value(self).unwrap(),
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointResourceType {
+    Port,
+    #[default]
+    Policy,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestType {
+    #[default]
+    Add,
+    Remove,
+    Update,
+    Refresh,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PolicyEndpointRequest {
+    #[serde(rename = "Policies")]
+    pub policies: Vec<Policy>,
+}
+
+/// Request of `modify_endpoint`.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ModifyEndpointSettingRequest {
+    #[serde(rename = "ResourceType")]
+    pub resource_type: EndpointResourceType,
+
+    #[serde(rename = "RequestType")]
+    pub request_type: RequestType,
+
+    #[serde(rename = "Settings")]
+    pub settings: PolicyEndpointRequest,
+}
+
+impl ModifyEndpointSettingRequest {
+    pub fn policies(
+        request_type: RequestType,
+        policies: Vec<Policy>,
+    ) -> ModifyEndpointSettingRequest {
+        ModifyEndpointSettingRequest {
+            resource_type: EndpointResourceType::Policy,
+            request_type,
+            settings: PolicyEndpointRequest { policies },
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Failed to serialize ModifyEndpointSettingRequest")
+    }
+}
+
+/// Builder of the port mapping and outbound NAT policies of an endpoint.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct EndpointPolicies {
+    pub port_mappings: Vec<PortMappingPolicySetting>,
+    pub outbound_nat: Option<OutboundNatPolicySetting>,
+}
+
+impl EndpointPolicies {
+    pub fn new() -> EndpointPolicies {
+        EndpointPolicies::default()
+    }
+
+    pub fn port_mapping(mut self, port_mapping: PortMappingPolicySetting) -> EndpointPolicies {
+        self.port_mappings.push(port_mapping);
+        self
+    }
+
+    pub fn outbound_nat(mut self, outbound_nat: OutboundNatPolicySetting) -> EndpointPolicies {
+        self.outbound_nat = Some(outbound_nat);
+        self
+    }
+
+    /// Adds an outbound NAT exception, creating the outbound NAT policy if needed.
+    pub fn outbound_nat_exception(mut self, prefix: &str) -> EndpointPolicies {
+        let outbound_nat = self.outbound_nat.take().unwrap_or_default();
+        self.outbound_nat = Some(outbound_nat.exception(prefix));
+        self
+    }
+
+    pub fn to_policies(&self) -> Vec<Policy> {
+        self.port_mappings
+            .iter()
+            .map(PortMappingPolicySetting::to_policy)
+            .chain(
+                self.outbound_nat
+                    .iter()
+                    .map(OutboundNatPolicySetting::to_policy),
+            )
+            .collect()
+    }
+
+    /// Returns the request adding the policies to an endpoint.
+    pub fn add_request(&self) -> ModifyEndpointSettingRequest {
+        ModifyEndpointSettingRequest::policies(RequestType::Add, self.to_policies())
+    }
+}
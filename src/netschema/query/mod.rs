@@ -0,0 +1,169 @@
+// Copyright (c) 2019-2020 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Query documents taken by the HCN enumeration functions, and parsing of their results.
+
+use crate::netschema::SchemaVersion;
+use crate::schema::utils::{is_default, GuidSerde};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Returns full documents instead of IDs from enumerations.
+pub const HOST_COMPUTE_QUERY_FLAGS_DETAILED: u32 = 0x1;
+
+/// Query of the HCN enumeration functions.
+///
+/// The filter is a JSON object of property values the enumerated objects must have,
+/// embedded in the query as a string.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HostComputeQuery {
+    #[serde(default, rename = "SchemaVersion")]
+    pub schema_version: SchemaVersion,
+
+    #[serde(default, rename = "Flags")]
+    pub flags: u32,
+
+    #[serde(default, rename = "Filter", skip_serializing_if = "is_default")]
+    pub filter: String,
+}
+
+impl HostComputeQuery {
+    /// Creates a query matching all objects.
+    pub fn new() -> HostComputeQuery {
+        HostComputeQuery::default()
+    }
+
+    /// Matches the object with an ID.
+    pub fn id(self, id: &GuidSerde) -> HostComputeQuery {
+        self.property("ID", id.to_string())
+    }
+
+    /// Matches the objects with a name.
+    pub fn name(self, name: &str) -> HostComputeQuery {
+        self.property("Name", name)
+    }
+
+    /// Matches the endpoints of a network. HCN filters them on `VirtualNetwork`,
+    /// not on the `HostComputeNetwork` property of their documents.
+    pub fn network(self, network: &GuidSerde) -> HostComputeQuery {
+        self.property("VirtualNetwork", network.to_string())
+    }
+
+    /// Matches the endpoints attached to a namespace.
+    pub fn namespace(self, namespace: &GuidSerde) -> HostComputeQuery {
+        self.property("HostComputeNamespace", namespace.to_string())
+    }
+
+    /// Requests full documents instead of IDs, to parse with `parse_detailed`.
+    pub fn detailed(mut self) -> HostComputeQuery {
+        self.flags |= HOST_COMPUTE_QUERY_FLAGS_DETAILED;
+        self
+    }
+
+    pub fn is_detailed(&self) -> bool {
+        self.flags & HOST_COMPUTE_QUERY_FLAGS_DETAILED != 0
+    }
+
+    /// Matches the objects with a property value, replacing any previous filter on it.
+    pub fn property<V: Into<Value>>(mut self, name: &str, value: V) -> HostComputeQuery {
+        let mut filter = self.filter_properties();
+        filter.insert(String::from(name), value.into());
+        self.filter = Value::Object(filter).to_string();
+        self
+    }
+
+    /// Returns the property values of the filter.
+    pub fn filter_properties(&self) -> Map<String, Value> {
+        match serde_json::from_str(&self.filter) {
+            Ok(Value::Object(filter)) => filter,
+            _ => Map::new(),
+        }
+    }
+
+    /// Returns the query as the JSON document taken by the enumeration functions.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Failed to serialize HostComputeQuery")
+    }
+}
+
+/// Parses the result of an enumeration that is not detailed.
+pub fn parse_ids(result: &str) -> serde_json::Result<Vec<GuidSerde>> {
+    let ids: Vec<String> = parse_detailed(result)?;
+    ids.iter()
+        .map(|id| {
+            GuidSerde::from_str(id).map_err(|_| {
+                serde::de::Error::custom(format!("Invalid object ID {} in enumeration", id))
+            })
+        })
+        .collect()
+}
+
+/// Parses the result of a detailed enumeration into networks, endpoints, namespaces or load balancers.
+/// Empty results parse to no objects.
+pub fn parse_detailed<T: DeserializeOwned>(result: &str) -> serde_json::Result<Vec<T>> {
+    match result.trim() {
+        "" => Ok(Vec::new()),
+        result => serde_json::from_str(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netschema::HostComputeEndpoint;
+
+    #[test]
+    fn query_filters_and_results() {
+        let network = GuidSerde {
+            data1: 1,
+            ..GuidSerde::new()
+        };
+        let query = HostComputeQuery::new()
+            .network(&network)
+            .name("first")
+            .name("second");
+
+        assert!(!query.is_detailed());
+        assert_eq!(
+            Value::Object(query.filter_properties()),
+            serde_json::json!({
+                "VirtualNetwork": "00000001-0000-0000-0000-000000000000",
+                "Name": "second",
+            })
+        );
+        let document: Value = serde_json::from_str(&query.detailed().to_json()).unwrap();
+        assert_eq!(document["Flags"], HOST_COMPUTE_QUERY_FLAGS_DETAILED);
+        assert!(document["Filter"].is_string());
+        assert_eq!(
+            HostComputeQuery::new().to_json(),
+            r#"{"SchemaVersion":{"Major":2,"Minor":0},"Flags":0}"#
+        );
+
+        assert_eq!(
+            parse_ids(r#"["00000001-0000-0000-0000-000000000000"]"#).unwrap(),
+            vec![network.clone()]
+        );
+        assert!(parse_ids(r#"["nat"]"#).is_err());
+
+        let endpoints: Vec<HostComputeEndpoint> = parse_detailed(
+            r#"[{
+                "ID": "00000002-0000-0000-0000-000000000000",
+                "HostComputeNetwork": "00000001-0000-0000-0000-000000000000",
+                "IpConfigurations": [{"IpAddress": "172.16.0.2", "PrefixLength": 24}],
+                "SchemaVersion": {"Major": 2, "Minor": 0}
+            }]"#,
+        )
+        .unwrap();
+        assert_eq!(endpoints[0].host_compute_network, network);
+        assert_eq!(endpoints[0].ip_configurations[0].ip_address, "172.16.0.2");
+        assert!(parse_detailed::<HostComputeEndpoint>("")
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -7,16 +7,17 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 use crate::schema;
+use crate::schema::utils::is_default;
+use crate::schema::versioning::{SchemaVersioned, VersionedField};
+use crate::schema::Version;
 use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "19h1")]
 impl std::default::Default for DeviceType {
     fn default() -> Self {
         DeviceType::ClassGuid
     }
 }
 
-#[cfg(feature = "19h1")]
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum DeviceType {
     ClassGuid,
@@ -28,8 +29,8 @@ pub enum DeviceType {
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Device {
     /// The type of device to assign to the container.
-    #[cfg(feature = "19h1")]
-    #[serde(rename = "Type")]
+    /// Requires schema version 2.2.
+    #[serde(default, rename = "Type", skip_serializing_if = "is_default")]
     pub device_type: DeviceType,
 
     /// The interface class guid of the device interfaces to assign to the container.
@@ -39,7 +40,16 @@ pub struct Device {
 
     /// The location path of the device to assign to the container.
     /// Only used when Type is DeviceInstance.
-    #[cfg(feature = "19h1")]
-    #[serde(rename = "LocationPath")]
+    /// Requires schema version 2.2.
+    #[serde(default, rename = "LocationPath", skip_serializing_if = "is_default")]
     pub location_path: String,
 }
+
+impl SchemaVersioned for Device {
+    fn versioned_fields() -> Vec<VersionedField> {
+        vec![
+            VersionedField::new("Type", Version::schema_version_19h1()),
+            VersionedField::new("LocationPath", Version::schema_version_19h1()),
+        ]
+    }
+}
@@ -6,8 +6,11 @@
 // except according to those terms.
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
+pub mod sddl;
+
 use crate::schema::utils::is_default;
 use crate::schema::utils::GuidSerde;
+use sddl::{SddlResult, SecurityDescriptor};
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
@@ -69,6 +72,265 @@ pub struct HvSocketSystemConfig {
     pub service_table: std::collections::HashMap<GuidSerde, HvSocketServiceConfig>,
 }
 
+/// HvSocket service ID, used as the key of `HvSocketSystemConfig::service_table`.
+///
+/// Linux guests address services through AF_VSOCK ports, which map onto the
+/// HvSocket service ID `xxxxxxxx-facb-11e6-bd58-64006a7986d3` where `xxxxxxxx`
+/// is the port number.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct HvSocketServiceId(pub GuidSerde);
+
+impl HvSocketServiceId {
+    /// Template for service IDs that map to AF_VSOCK ports, with the port set to zero.
+    pub const VSOCK_TEMPLATE: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0x00000000,
+        data2: 0xfacb,
+        data3: 0x11e6,
+        data4: [0xbd, 0x58, 0x64, 0x00, 0x6a, 0x79, 0x86, 0xd3],
+    });
+
+    /// `HV_GUID_WILDCARD`: listens on all partitions, or matches any service.
+    pub const WILDCARD: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0x00000000,
+        data2: 0x0000,
+        data3: 0x0000,
+        data4: [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    });
+
+    /// `HV_GUID_BROADCAST`
+    pub const BROADCAST: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0xffffffff,
+        data2: 0xffff,
+        data3: 0xffff,
+        data4: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+    });
+
+    /// `HV_GUID_CHILDREN`: listens on all child partitions.
+    pub const CHILDREN: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0x90db8b89,
+        data2: 0x0d35,
+        data3: 0x4f79,
+        data4: [0x8c, 0xe9, 0x49, 0xea, 0x0a, 0xc8, 0xb7, 0xcd],
+    });
+
+    /// `HV_GUID_LOOPBACK`: connects to the same partition.
+    pub const LOOPBACK: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0xe0e16197,
+        data2: 0xdd56,
+        data3: 0x4a10,
+        data4: [0x91, 0x95, 0x5e, 0xe7, 0xa1, 0x55, 0xa8, 0x38],
+    });
+
+    /// `HV_GUID_PARENT`: connects to the parent partition, from a VM or container.
+    pub const PARENT: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0xa42e7cda,
+        data2: 0xd03f,
+        data3: 0x480c,
+        data4: [0x9c, 0xc2, 0xa4, 0xde, 0x20, 0xab, 0xb8, 0x78],
+    });
+
+    /// `HV_GUID_SILOHOST`: connects to the host of a silo, from a container.
+    pub const SILO_HOST: HvSocketServiceId = HvSocketServiceId(GuidSerde {
+        data1: 0x36bd0c5c,
+        data2: 0x7276,
+        data3: 0x4223,
+        data4: [0x88, 0xba, 0x7d, 0x03, 0xb6, 0x54, 0xc5, 0x68],
+    });
+
+    /// Returns the service ID that maps to the given AF_VSOCK port.
+    pub fn from_vsock_port(port: u32) -> HvSocketServiceId {
+        let mut service_id = HvSocketServiceId::VSOCK_TEMPLATE;
+        service_id.0.data1 = port;
+        service_id
+    }
+
+    /// Returns the AF_VSOCK port the service ID maps to, or `None` if the
+    /// service ID is not derived from the VSOCK template.
+    pub fn to_vsock_port(&self) -> Option<u32> {
+        let template = &HvSocketServiceId::VSOCK_TEMPLATE.0;
+
+        if self.0.data2 == template.data2
+            && self.0.data3 == template.data3
+            && self.0.data4 == template.data4
+        {
+            Some(self.0.data1)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the service ID maps to an AF_VSOCK port.
+    pub fn is_vsock(&self) -> bool {
+        self.to_vsock_port().is_some()
+    }
+
+    /// Returns the underlying GUID of the service ID.
+    pub fn guid(&self) -> &GuidSerde {
+        &self.0
+    }
+}
+
+impl std::convert::From<GuidSerde> for HvSocketServiceId {
+    fn from(guid: GuidSerde) -> HvSocketServiceId {
+        HvSocketServiceId(guid)
+    }
+}
+
+impl std::convert::From<HvSocketServiceId> for GuidSerde {
+    fn from(service_id: HvSocketServiceId) -> GuidSerde {
+        service_id.0
+    }
+}
+
+impl std::fmt::Display for HvSocketServiceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses an optional SDDL string, prefixing errors with the name of the field it came from.
+fn parse_security_descriptor(
+    field: &str,
+    sddl: &Option<String>,
+) -> SddlResult<Option<SecurityDescriptor>> {
+    match sddl {
+        Some(sddl) => SecurityDescriptor::parse(sddl)
+            .map(Some)
+            .map_err(|mut error| {
+                error.message = format!("{}: {}", field, error.message);
+                error
+            }),
+        None => Ok(None),
+    }
+}
+
+impl HvSocketServiceConfig {
+    /// Creates a service configuration from typed bind and connect security descriptors.
+    pub fn new(
+        bind_security_descriptor: Option<&SecurityDescriptor>,
+        connect_security_descriptor: Option<&SecurityDescriptor>,
+        allow_wildcard_binds: bool,
+    ) -> HvSocketServiceConfig {
+        HvSocketServiceConfig {
+            bind_security_descriptor: bind_security_descriptor.map(ToString::to_string),
+            connect_security_descriptor: connect_security_descriptor.map(ToString::to_string),
+            allow_wildcard_binds,
+        }
+    }
+
+    /// Creates a service configuration that only allows the local system account
+    /// and the supplied SID to bind to and connect to the service.
+    pub fn allow_system_and(sid: sddl::Sid) -> HvSocketServiceConfig {
+        let security_descriptor = SecurityDescriptor::allow_system_and(sid);
+        HvSocketServiceConfig::new(
+            Some(&security_descriptor),
+            Some(&security_descriptor),
+            false,
+        )
+    }
+
+    /// Parses the bind security descriptor SDDL string, if any.
+    pub fn parse_bind_security_descriptor(&self) -> SddlResult<Option<SecurityDescriptor>> {
+        parse_security_descriptor("BindSecurityDescriptor", &self.bind_security_descriptor)
+    }
+
+    /// Parses the connect security descriptor SDDL string, if any.
+    pub fn parse_connect_security_descriptor(&self) -> SddlResult<Option<SecurityDescriptor>> {
+        parse_security_descriptor(
+            "ConnectSecurityDescriptor",
+            &self.connect_security_descriptor,
+        )
+    }
+
+    /// Validates that all SDDL strings of the configuration are well formed.
+    pub fn validate(&self) -> SddlResult<()> {
+        self.parse_bind_security_descriptor()?;
+        self.parse_connect_security_descriptor()?;
+        Ok(())
+    }
+}
+
+impl HvSocketSystemConfig {
+    /// Adds or replaces the configuration of a service in the service table,
+    /// returning the previous configuration, if any.
+    pub fn register_service(
+        &mut self,
+        service_id: HvSocketServiceId,
+        service_config: HvSocketServiceConfig,
+    ) -> Option<HvSocketServiceConfig> {
+        self.service_table.insert(service_id.0, service_config)
+    }
+
+    /// Adds or replaces the configuration of the service that maps to
+    /// the given AF_VSOCK port, returning the previous configuration, if any.
+    pub fn register_vsock_port(
+        &mut self,
+        port: u32,
+        service_config: HvSocketServiceConfig,
+    ) -> Option<HvSocketServiceConfig> {
+        self.register_service(HvSocketServiceId::from_vsock_port(port), service_config)
+    }
+
+    /// Removes a service from the service table, returning its configuration, if any.
+    pub fn unregister_service(
+        &mut self,
+        service_id: &HvSocketServiceId,
+    ) -> Option<HvSocketServiceConfig> {
+        self.service_table.remove(&service_id.0)
+    }
+
+    /// Returns the configuration of a service in the service table, if any.
+    pub fn service_config(&self, service_id: &HvSocketServiceId) -> Option<&HvSocketServiceConfig> {
+        self.service_table.get(&service_id.0)
+    }
+
+    /// Returns the sorted list of AF_VSOCK ports registered in the service table.
+    pub fn vsock_ports(&self) -> Vec<u32> {
+        let mut ports: Vec<u32> = self
+            .service_table
+            .keys()
+            .filter_map(|guid| HvSocketServiceId(guid.clone()).to_vsock_port())
+            .collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    /// Parses the default bind security descriptor SDDL string, if any.
+    pub fn parse_default_bind_security_descriptor(&self) -> SddlResult<Option<SecurityDescriptor>> {
+        parse_security_descriptor(
+            "DefaultBindSecurityDescriptor",
+            &self.default_bind_security_descriptor,
+        )
+    }
+
+    /// Parses the default connect security descriptor SDDL string, if any.
+    pub fn parse_default_connect_security_descriptor(
+        &self,
+    ) -> SddlResult<Option<SecurityDescriptor>> {
+        parse_security_descriptor(
+            "DefaultConnectSecurityDescriptor",
+            &self.default_connect_security_descriptor,
+        )
+    }
+
+    /// Validates that all SDDL strings of the configuration, including the ones
+    /// in each service table entry, are well formed.
+    pub fn validate(&self) -> SddlResult<()> {
+        self.parse_default_bind_security_descriptor()?;
+        self.parse_default_connect_security_descriptor()?;
+
+        for (service_id, service_config) in &self.service_table {
+            service_config.validate().map_err(|mut error| {
+                error.message = format!("ServiceTable[{}].{}", service_id, error.message);
+                error
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -145,4 +407,80 @@ mod tests {
         assert!(&hvsocket_system_config_string == r#"{"DefaultBindSecurityDescriptor":"SomeBindSecurityDescriptor","DefaultConnectSecurityDescriptor":"SomeConnectSecurityDescriptor","ServiceTable":{"db20fa3e-c476-447f-94a5-51b8322c4c4f":{"BindSecurityDescriptor":"SomeBindSecurityDescriptor","ConnectSecurityDescriptor":"SomeConnectSecurityDescriptor","AllowWildcardBinds":true},"00000000-0000-0000-0000-000000000000":{"AllowWildcardBinds":true}}}"#
         || &hvsocket_system_config_string == r#"{"DefaultBindSecurityDescriptor":"SomeBindSecurityDescriptor","DefaultConnectSecurityDescriptor":"SomeConnectSecurityDescriptor","ServiceTable":{"00000000-0000-0000-0000-000000000000":{"AllowWildcardBinds":true},"db20fa3e-c476-447f-94a5-51b8322c4c4f":{"BindSecurityDescriptor":"SomeBindSecurityDescriptor","ConnectSecurityDescriptor":"SomeConnectSecurityDescriptor","AllowWildcardBinds":true}}}"#);
     }
+
+    #[test]
+    fn hvsocket_service_config_security_descriptors() {
+        let sid: sddl::Sid = "S-1-5-21-1-2-3-1001".parse().unwrap();
+        let service_config = HvSocketServiceConfig::allow_system_and(sid);
+        assert_eq!(
+            &serde_json::to_string(&service_config).unwrap(),
+            r#"{"BindSecurityDescriptor":"D:P(A;;FA;;;SY)(A;;FA;;;S-1-5-21-1-2-3-1001)","ConnectSecurityDescriptor":"D:P(A;;FA;;;SY)(A;;FA;;;S-1-5-21-1-2-3-1001)"}"#
+        );
+        assert!(service_config.validate().is_ok());
+
+        let mut system_config = HvSocketSystemConfig::default();
+        system_config.service_table.insert(
+            GUID_SERDE_TEST,
+            HvSocketServiceConfig {
+                bind_security_descriptor: None,
+                connect_security_descriptor: Some(String::from("D:P(A;;FA;;;SYSTEM)")),
+                allow_wildcard_binds: false,
+            },
+        );
+        let error = system_config.validate().unwrap_err();
+        assert!(error.message.starts_with(
+            "ServiceTable[db20fa3e-c476-447f-94a5-51b8322c4c4f].ConnectSecurityDescriptor"
+        ));
+    }
+
+    #[test]
+    fn hvsocket_service_id_vsock() {
+        let service_id = HvSocketServiceId::from_vsock_port(0x40000000);
+        assert_eq!(
+            service_id.to_string(),
+            "40000000-facb-11e6-bd58-64006a7986d3"
+        );
+        assert_eq!(service_id.to_vsock_port(), Some(0x40000000));
+        assert_eq!(
+            &serde_json::to_string(&service_id).unwrap(),
+            r#""40000000-facb-11e6-bd58-64006a7986d3""#
+        );
+        assert_eq!(HvSocketServiceId::PARENT.to_vsock_port(), None);
+        assert_eq!(
+            HvSocketServiceId::PARENT.to_string(),
+            "a42e7cda-d03f-480c-9cc2-a4de20abb878"
+        );
+    }
+
+    #[test]
+    fn hvsocket_system_config_register_vsock_port() {
+        let mut system_config = HvSocketSystemConfig::default();
+        system_config.register_vsock_port(
+            5000,
+            HvSocketServiceConfig {
+                bind_security_descriptor: None,
+                connect_security_descriptor: None,
+                allow_wildcard_binds: true,
+            },
+        );
+        system_config.register_service(
+            HvSocketServiceId::from(GUID_SERDE_TEST),
+            HvSocketServiceConfig::default(),
+        );
+        system_config.register_vsock_port(80, HvSocketServiceConfig::default());
+
+        assert_eq!(system_config.vsock_ports(), vec![80, 5000]);
+        assert!(
+            system_config
+                .service_config(&HvSocketServiceId::from_vsock_port(5000))
+                .unwrap()
+                .allow_wildcard_binds
+        );
+        let service_config =
+            &system_config.service_table[HvSocketServiceId::from_vsock_port(5000).guid()];
+        assert_eq!(
+            &serde_json::to_string(service_config).unwrap(),
+            r#"{"AllowWildcardBinds":true}"#
+        );
+    }
 }
@@ -0,0 +1,1115 @@
+// Copyright  rafawo (rafawo1@hotmail.com). All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Pure Rust model of the Security Descriptor Definition Language (SDDL),
+//! used by HvSocket to secure binds and connects to services.
+//!
+//! Security descriptors can be parsed from and serialized back to SDDL strings.
+//! Serialization is canonical: components are emitted in `O:`, `G:`, `D:`, `S:` order,
+//! SIDs that have a well-known alias are emitted as the alias, and access masks
+//! are emitted using the rights tokens whenever they can be represented exactly.
+//!
+//! Refer to https://docs.microsoft.com/en-us/windows/win32/secauthz/security-descriptor-string-format
+
+use crate::schema::utils::GuidSerde;
+use std::convert::TryFrom;
+
+/// Error returned when an SDDL string fails to parse or validate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SddlError {
+    /// Byte offset in the SDDL string where the error was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl SddlError {
+    fn new(position: usize, message: String) -> SddlError {
+        SddlError { position, message }
+    }
+}
+
+impl std::fmt::Display for SddlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid SDDL at offset {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for SddlError {}
+
+pub type SddlResult<T> = Result<T, SddlError>;
+
+/// SIDs that have a two letter alias in SDDL and do not depend on a domain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum WellKnownSid {
+    /// `AN`: S-1-5-7
+    Anonymous,
+    /// `AU`: S-1-5-11
+    AuthenticatedUsers,
+    /// `BA`: S-1-5-32-544
+    BuiltinAdministrators,
+    /// `BG`: S-1-5-32-546
+    BuiltinGuests,
+    /// `BO`: S-1-5-32-551
+    BackupOperators,
+    /// `BU`: S-1-5-32-545
+    BuiltinUsers,
+    /// `CG`: S-1-3-1
+    CreatorGroup,
+    /// `CO`: S-1-3-0
+    CreatorOwner,
+    /// `IU`: S-1-5-4
+    Interactive,
+    /// `LS`: S-1-5-19
+    LocalService,
+    /// `NS`: S-1-5-20
+    NetworkService,
+    /// `NU`: S-1-5-2
+    Network,
+    /// `OW`: S-1-3-4
+    OwnerRights,
+    /// `PS`: S-1-5-10
+    PrincipalSelf,
+    /// `PU`: S-1-5-32-547
+    PowerUsers,
+    /// `RD`: S-1-5-32-555
+    RemoteDesktopUsers,
+    /// `SO`: S-1-5-32-549
+    ServerOperators,
+    /// `SU`: S-1-5-6
+    Service,
+    /// `SY`: S-1-5-18
+    LocalSystem,
+    /// `WD`: S-1-1-0
+    Everyone,
+    /// `AC`: S-1-15-2-1
+    AllAppPackages,
+    /// `HA`: S-1-5-32-578
+    HyperVAdministrators,
+    /// `LW`: S-1-16-4096
+    LowIntegrity,
+    /// `ME`: S-1-16-8192
+    MediumIntegrity,
+    /// `HI`: S-1-16-12288
+    HighIntegrity,
+    /// `SI`: S-1-16-16384
+    SystemIntegrity,
+}
+
+const WELL_KNOWN_SIDS: &[(WellKnownSid, &str, u64, &[u32])] = &[
+    (WellKnownSid::Anonymous, "AN", 5, &[7]),
+    (WellKnownSid::AuthenticatedUsers, "AU", 5, &[11]),
+    (WellKnownSid::BuiltinAdministrators, "BA", 5, &[32, 544]),
+    (WellKnownSid::BuiltinGuests, "BG", 5, &[32, 546]),
+    (WellKnownSid::BackupOperators, "BO", 5, &[32, 551]),
+    (WellKnownSid::BuiltinUsers, "BU", 5, &[32, 545]),
+    (WellKnownSid::CreatorGroup, "CG", 3, &[1]),
+    (WellKnownSid::CreatorOwner, "CO", 3, &[0]),
+    (WellKnownSid::Interactive, "IU", 5, &[4]),
+    (WellKnownSid::LocalService, "LS", 5, &[19]),
+    (WellKnownSid::NetworkService, "NS", 5, &[20]),
+    (WellKnownSid::Network, "NU", 5, &[2]),
+    (WellKnownSid::OwnerRights, "OW", 3, &[4]),
+    (WellKnownSid::PrincipalSelf, "PS", 5, &[10]),
+    (WellKnownSid::PowerUsers, "PU", 5, &[32, 547]),
+    (WellKnownSid::RemoteDesktopUsers, "RD", 5, &[32, 555]),
+    (WellKnownSid::ServerOperators, "SO", 5, &[32, 549]),
+    (WellKnownSid::Service, "SU", 5, &[6]),
+    (WellKnownSid::LocalSystem, "SY", 5, &[18]),
+    (WellKnownSid::Everyone, "WD", 1, &[0]),
+    (WellKnownSid::AllAppPackages, "AC", 15, &[2, 1]),
+    (WellKnownSid::HyperVAdministrators, "HA", 5, &[32, 578]),
+    (WellKnownSid::LowIntegrity, "LW", 16, &[4096]),
+    (WellKnownSid::MediumIntegrity, "ME", 16, &[8192]),
+    (WellKnownSid::HighIntegrity, "HI", 16, &[12288]),
+    (WellKnownSid::SystemIntegrity, "SI", 16, &[16384]),
+];
+
+impl WellKnownSid {
+    /// Returns the SDDL alias of the well-known SID.
+    pub fn alias(self) -> &'static str {
+        WELL_KNOWN_SIDS
+            .iter()
+            .find(|entry| entry.0 == self)
+            .map(|entry| entry.1)
+            .unwrap()
+    }
+
+    /// Returns the well-known SID that corresponds to an SDDL alias, case insensitive.
+    pub fn from_alias(alias: &str) -> Option<WellKnownSid> {
+        WELL_KNOWN_SIDS
+            .iter()
+            .find(|entry| entry.1.eq_ignore_ascii_case(alias))
+            .map(|entry| entry.0)
+    }
+
+    /// Returns the full SID of the well-known SID.
+    pub fn sid(self) -> Sid {
+        let entry = WELL_KNOWN_SIDS
+            .iter()
+            .find(|entry| entry.0 == self)
+            .unwrap();
+        Sid {
+            revision: 1,
+            identifier_authority: entry.2,
+            sub_authorities: entry.3.to_vec(),
+        }
+    }
+}
+
+/// Maximum number of sub authorities a SID can have.
+pub const SID_MAX_SUB_AUTHORITIES: usize = 15;
+
+/// Security identifier, as in `S-1-5-21-1004336348-1177238915-682003330-512`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sid {
+    pub revision: u8,
+
+    /// 48 bit identifier authority.
+    pub identifier_authority: u64,
+
+    pub sub_authorities: Vec<u32>,
+}
+
+impl Sid {
+    /// Returns the SID for the local system account (`SY`).
+    pub fn local_system() -> Sid {
+        WellKnownSid::LocalSystem.sid()
+    }
+
+    /// Returns the SID for the builtin administrators group (`BA`).
+    pub fn builtin_administrators() -> Sid {
+        WellKnownSid::BuiltinAdministrators.sid()
+    }
+
+    /// Returns the well-known SID that matches this SID, if any.
+    pub fn well_known(&self) -> Option<WellKnownSid> {
+        if self.revision != 1 {
+            return None;
+        }
+
+        WELL_KNOWN_SIDS
+            .iter()
+            .find(|entry| {
+                entry.2 == self.identifier_authority && entry.3 == &self.sub_authorities[..]
+            })
+            .map(|entry| entry.0)
+    }
+
+    fn validate(&self, position: usize) -> SddlResult<()> {
+        if self.revision != 1 {
+            return Err(SddlError::new(
+                position,
+                format!("unsupported SID revision {}", self.revision),
+            ));
+        }
+
+        if self.identifier_authority >= 1 << 48 {
+            return Err(SddlError::new(
+                position,
+                String::from("SID identifier authority does not fit in 48 bits"),
+            ));
+        }
+
+        if self.sub_authorities.is_empty() || self.sub_authorities.len() > SID_MAX_SUB_AUTHORITIES {
+            return Err(SddlError::new(
+                position,
+                format!(
+                    "SID must have between 1 and {} sub authorities",
+                    SID_MAX_SUB_AUTHORITIES
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl std::convert::From<WellKnownSid> for Sid {
+    fn from(well_known: WellKnownSid) -> Sid {
+        well_known.sid()
+    }
+}
+
+impl std::fmt::Display for Sid {
+    /// Formats the SID using its alias if it's well-known, or the `S-R-I-S...` form otherwise.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(well_known) = self.well_known() {
+            return write!(f, "{}", well_known.alias());
+        }
+
+        write!(f, "S-{}-", self.revision)?;
+
+        if self.identifier_authority >= 1 << 32 {
+            write!(f, "0x{:012X}", self.identifier_authority)?;
+        } else {
+            write!(f, "{}", self.identifier_authority)?;
+        }
+
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{}", sub_authority)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Sid {
+    type Err = SddlError;
+
+    fn from_str(sid: &str) -> SddlResult<Sid> {
+        let mut parser = Parser::new(sid);
+        let result = parser.sid()?;
+        parser.end()?;
+        Ok(result)
+    }
+}
+
+/// Access rights mask of an ACE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct AccessMask(pub u32);
+
+impl AccessMask {
+    pub const GENERIC_ALL: AccessMask = AccessMask(0x1000_0000);
+    pub const GENERIC_READ: AccessMask = AccessMask(0x8000_0000);
+    pub const GENERIC_WRITE: AccessMask = AccessMask(0x4000_0000);
+    pub const GENERIC_EXECUTE: AccessMask = AccessMask(0x2000_0000);
+    pub const READ_CONTROL: AccessMask = AccessMask(0x0002_0000);
+    pub const DELETE: AccessMask = AccessMask(0x0001_0000);
+    pub const WRITE_DAC: AccessMask = AccessMask(0x0004_0000);
+    pub const WRITE_OWNER: AccessMask = AccessMask(0x0008_0000);
+    pub const FILE_ALL_ACCESS: AccessMask = AccessMask(0x001F_01FF);
+    pub const FILE_GENERIC_READ: AccessMask = AccessMask(0x0012_0089);
+    pub const FILE_GENERIC_WRITE: AccessMask = AccessMask(0x0012_0116);
+    pub const FILE_GENERIC_EXECUTE: AccessMask = AccessMask(0x0012_00A0);
+    pub const KEY_ALL_ACCESS: AccessMask = AccessMask(0x000F_003F);
+    pub const KEY_READ: AccessMask = AccessMask(0x0002_0019);
+    pub const KEY_WRITE: AccessMask = AccessMask(0x0002_0006);
+
+    /// Returns true if all the bits in `other` are set in this mask.
+    pub fn contains(self, other: AccessMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for AccessMask {
+    type Output = AccessMask;
+
+    fn bitor(self, other: AccessMask) -> AccessMask {
+        AccessMask(self.0 | other.0)
+    }
+}
+
+/// Access rights tokens that map to a combination of bits, only used when they match exactly.
+const COMPOSITE_RIGHTS: &[(&str, u32)] = &[
+    ("FA", 0x001F_01FF),
+    ("FR", 0x0012_0089),
+    ("FW", 0x0012_0116),
+    ("FX", 0x0012_00A0),
+    ("KA", 0x000F_003F),
+    ("KR", 0x0002_0019),
+    ("KW", 0x0002_0006),
+    ("KX", 0x0002_0019),
+];
+
+/// Access rights tokens that map to a single bit, in canonical order.
+const BIT_RIGHTS: &[(&str, u32)] = &[
+    ("GA", 0x1000_0000),
+    ("GR", 0x8000_0000),
+    ("GW", 0x4000_0000),
+    ("GX", 0x2000_0000),
+    ("RC", 0x0002_0000),
+    ("SD", 0x0001_0000),
+    ("WD", 0x0004_0000),
+    ("WO", 0x0008_0000),
+    ("RP", 0x0000_0010),
+    ("WP", 0x0000_0020),
+    ("CC", 0x0000_0001),
+    ("DC", 0x0000_0002),
+    ("LC", 0x0000_0004),
+    ("SW", 0x0000_0008),
+    ("LO", 0x0000_0080),
+    ("DT", 0x0000_0040),
+    ("CR", 0x0000_0100),
+];
+
+/// Access rights tokens only valid in mandatory label ACEs.
+const LABEL_RIGHTS: &[(&str, u32)] = &[("NW", 0x1), ("NR", 0x2), ("NX", 0x4)];
+
+impl AccessMask {
+    fn write_sddl(self, ace_type: AceType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let bit_rights = match ace_type {
+            AceType::MandatoryLabel => LABEL_RIGHTS,
+            _ => {
+                if let Some(composite) = COMPOSITE_RIGHTS.iter().find(|entry| entry.1 == self.0) {
+                    return write!(f, "{}", composite.0);
+                }
+                BIT_RIGHTS
+            }
+        };
+
+        let representable = bit_rights.iter().fold(0, |mask, entry| mask | entry.1);
+
+        if self.0 & !representable != 0 {
+            return write!(f, "0x{:x}", self.0);
+        }
+
+        for entry in bit_rights {
+            if self.0 & entry.1 != 0 {
+                write!(f, "{}", entry.0)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AceType {
+    /// `A`
+    AccessAllowed,
+    /// `D`
+    AccessDenied,
+    /// `OA`
+    ObjectAccessAllowed,
+    /// `OD`
+    ObjectAccessDenied,
+    /// `AU`
+    SystemAudit,
+    /// `AL`
+    SystemAlarm,
+    /// `OU`
+    ObjectSystemAudit,
+    /// `OL`
+    ObjectSystemAlarm,
+    /// `ML`
+    MandatoryLabel,
+    /// `SP`
+    ScopedPolicyId,
+}
+
+const ACE_TYPES: &[(AceType, &str)] = &[
+    (AceType::AccessAllowed, "A"),
+    (AceType::AccessDenied, "D"),
+    (AceType::ObjectAccessAllowed, "OA"),
+    (AceType::ObjectAccessDenied, "OD"),
+    (AceType::SystemAudit, "AU"),
+    (AceType::SystemAlarm, "AL"),
+    (AceType::ObjectSystemAudit, "OU"),
+    (AceType::ObjectSystemAlarm, "OL"),
+    (AceType::MandatoryLabel, "ML"),
+    (AceType::ScopedPolicyId, "SP"),
+];
+
+impl AceType {
+    /// Returns the SDDL token of the ACE type.
+    pub fn token(self) -> &'static str {
+        ACE_TYPES.iter().find(|entry| entry.0 == self).unwrap().1
+    }
+
+    /// Returns true if the ACE type carries object type GUIDs.
+    pub fn is_object_ace(self) -> bool {
+        matches!(
+            self,
+            AceType::ObjectAccessAllowed
+                | AceType::ObjectAccessDenied
+                | AceType::ObjectSystemAudit
+                | AceType::ObjectSystemAlarm
+        )
+    }
+
+    /// Returns true if the ACE type can only be present in a SACL.
+    pub fn is_system_ace(self) -> bool {
+        !matches!(
+            self,
+            AceType::AccessAllowed
+                | AceType::AccessDenied
+                | AceType::ObjectAccessAllowed
+                | AceType::ObjectAccessDenied
+        )
+    }
+}
+
+/// Inheritance and auditing flags of an ACE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct AceFlags(pub u8);
+
+impl AceFlags {
+    pub const OBJECT_INHERIT: AceFlags = AceFlags(0x01);
+    pub const CONTAINER_INHERIT: AceFlags = AceFlags(0x02);
+    pub const NO_PROPAGATE_INHERIT: AceFlags = AceFlags(0x04);
+    pub const INHERIT_ONLY: AceFlags = AceFlags(0x08);
+    pub const INHERITED: AceFlags = AceFlags(0x10);
+    pub const SUCCESSFUL_ACCESS: AceFlags = AceFlags(0x40);
+    pub const FAILED_ACCESS: AceFlags = AceFlags(0x80);
+
+    /// Returns true if all the bits in `other` are set.
+    pub fn contains(self, other: AceFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for AceFlags {
+    type Output = AceFlags;
+
+    fn bitor(self, other: AceFlags) -> AceFlags {
+        AceFlags(self.0 | other.0)
+    }
+}
+
+const ACE_FLAGS: &[(&str, u8)] = &[
+    ("OI", 0x01),
+    ("CI", 0x02),
+    ("NP", 0x04),
+    ("IO", 0x08),
+    ("ID", 0x10),
+    ("SA", 0x40),
+    ("FA", 0x80),
+];
+
+impl std::fmt::Display for AceFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for entry in ACE_FLAGS {
+            if self.0 & entry.1 != 0 {
+                write!(f, "{}", entry.0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Access control entry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ace {
+    pub ace_type: AceType,
+    pub flags: AceFlags,
+    pub rights: AccessMask,
+
+    /// Only valid for object ACE types.
+    pub object_type: Option<GuidSerde>,
+
+    /// Only valid for object ACE types.
+    pub inherited_object_type: Option<GuidSerde>,
+
+    pub sid: Sid,
+}
+
+impl Ace {
+    /// Returns an access allowed ACE without flags.
+    pub fn allow(sid: Sid, rights: AccessMask) -> Ace {
+        Ace {
+            ace_type: AceType::AccessAllowed,
+            flags: AceFlags::default(),
+            rights,
+            object_type: None,
+            inherited_object_type: None,
+            sid,
+        }
+    }
+
+    /// Returns an access denied ACE without flags.
+    pub fn deny(sid: Sid, rights: AccessMask) -> Ace {
+        Ace {
+            ace_type: AceType::AccessDenied,
+            ..Ace::allow(sid, rights)
+        }
+    }
+}
+
+impl std::fmt::Display for Ace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "({};{};", self.ace_type.token(), self.flags)?;
+        self.rights.write_sddl(self.ace_type, f)?;
+        write!(f, ";")?;
+        if let Some(object_type) = &self.object_type {
+            write!(f, "{}", object_type)?;
+        }
+        write!(f, ";")?;
+        if let Some(inherited_object_type) = &self.inherited_object_type {
+            write!(f, "{}", inherited_object_type)?;
+        }
+        write!(f, ";{})", self.sid)
+    }
+}
+
+/// Control flags of a DACL or SACL.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct AclFlags(pub u8);
+
+impl AclFlags {
+    /// `P`: inheritable ACEs from the parent are ignored.
+    pub const PROTECTED: AclFlags = AclFlags(0x01);
+    /// `AR`: ACL inheritance is required.
+    pub const AUTO_INHERIT_REQUIRED: AclFlags = AclFlags(0x02);
+    /// `AI`: ACL was inherited.
+    pub const AUTO_INHERITED: AclFlags = AclFlags(0x04);
+    /// `NO_ACCESS_CONTROL`: the ACL is null, granting everyone full access.
+    pub const NO_ACCESS_CONTROL: AclFlags = AclFlags(0x08);
+
+    /// Returns true if all the bits in `other` are set.
+    pub fn contains(self, other: AclFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for AclFlags {
+    type Output = AclFlags;
+
+    fn bitor(self, other: AclFlags) -> AclFlags {
+        AclFlags(self.0 | other.0)
+    }
+}
+
+const ACL_FLAGS: &[(&str, u8)] = &[
+    ("P", 0x01),
+    ("AR", 0x02),
+    ("AI", 0x04),
+    ("NO_ACCESS_CONTROL", 0x08),
+];
+
+impl std::fmt::Display for AclFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for entry in ACL_FLAGS {
+            if self.0 & entry.1 != 0 {
+                write!(f, "{}", entry.0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Access control list, either discretionary or system.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Acl {
+    pub flags: AclFlags,
+    pub aces: Vec<Ace>,
+}
+
+impl std::fmt::Display for Acl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.flags)?;
+        for ace in &self.aces {
+            write!(f, "{}", ace)?;
+        }
+        Ok(())
+    }
+}
+
+/// Security descriptor, as described by an SDDL string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SecurityDescriptor {
+    pub owner: Option<Sid>,
+    pub group: Option<Sid>,
+    pub dacl: Option<Acl>,
+    pub sacl: Option<Acl>,
+}
+
+impl SecurityDescriptor {
+    /// Returns a security descriptor with a protected DACL that grants `rights`
+    /// to each of the supplied SIDs.
+    pub fn allow(sids: &[Sid], rights: AccessMask) -> SecurityDescriptor {
+        SecurityDescriptor {
+            owner: None,
+            group: None,
+            dacl: Some(Acl {
+                flags: AclFlags::PROTECTED,
+                aces: sids
+                    .iter()
+                    .map(|sid| Ace::allow(sid.clone(), rights))
+                    .collect(),
+            }),
+            sacl: None,
+        }
+    }
+
+    /// Returns a security descriptor that grants full access to the local system account
+    /// and the supplied SID, which is what HvSocket expects to allow a host process
+    /// running as that identity to bind or connect to a service.
+    ///
+    /// The resulting SDDL looks like `D:P(A;;FA;;;SY)(A;;FA;;;S-1-5-...)`.
+    pub fn allow_system_and(sid: Sid) -> SecurityDescriptor {
+        SecurityDescriptor::allow(&[Sid::local_system(), sid], AccessMask::FILE_ALL_ACCESS)
+    }
+
+    /// Parses and validates an SDDL string.
+    pub fn parse(sddl: &str) -> SddlResult<SecurityDescriptor> {
+        let mut parser = Parser::new(sddl);
+        let security_descriptor = parser.security_descriptor()?;
+        security_descriptor.validate()?;
+        Ok(security_descriptor)
+    }
+
+    /// Validates semantic rules that the SDDL grammar can't express, like
+    /// audit ACEs in a DACL or object type GUIDs in non-object ACEs.
+    pub fn validate(&self) -> SddlResult<()> {
+        for (sid, name) in [(&self.owner, "owner"), (&self.group, "group")].iter() {
+            if let Some(sid) = sid {
+                sid.validate(0)
+                    .map_err(|error| SddlError::new(0, format!("{} {}", name, error.message)))?;
+            }
+        }
+
+        if let Some(dacl) = &self.dacl {
+            Self::validate_acl(dacl, "D")?;
+        }
+
+        if let Some(sacl) = &self.sacl {
+            Self::validate_acl(sacl, "S")?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_acl(acl: &Acl, name: &str) -> SddlResult<()> {
+        if acl.flags.contains(AclFlags::NO_ACCESS_CONTROL) && !acl.aces.is_empty() {
+            return Err(SddlError::new(
+                0,
+                format!("{}: ACL marked NO_ACCESS_CONTROL can't contain ACEs", name),
+            ));
+        }
+
+        for (index, ace) in acl.aces.iter().enumerate() {
+            let error = |message: &str| {
+                Err(SddlError::new(
+                    0,
+                    format!("{}: ACE {} ({}) {}", name, index, ace, message),
+                ))
+            };
+
+            if name == "D" && ace.ace_type.is_system_ace() {
+                return error("is only valid in a SACL");
+            }
+
+            if name == "S" && !ace.ace_type.is_system_ace() {
+                return error("is only valid in a DACL");
+            }
+
+            if !ace.ace_type.is_object_ace()
+                && (ace.object_type.is_some() || ace.inherited_object_type.is_some())
+            {
+                return error("has object GUIDs but is not an object ACE");
+            }
+
+            if ace.rights.0 == 0 && ace.ace_type != AceType::MandatoryLabel {
+                return error("grants no access rights");
+            }
+
+            if let Err(sid_error) = ace.sid.validate(0) {
+                return error(&sid_error.message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for SecurityDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(owner) = &self.owner {
+            write!(f, "O:{}", owner)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, "G:{}", group)?;
+        }
+        if let Some(dacl) = &self.dacl {
+            write!(f, "D:{}", dacl)?;
+        }
+        if let Some(sacl) = &self.sacl {
+            write!(f, "S:{}", sacl)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for SecurityDescriptor {
+    type Err = SddlError;
+
+    fn from_str(sddl: &str) -> SddlResult<SecurityDescriptor> {
+        SecurityDescriptor::parse(sddl)
+    }
+}
+
+/// Recursive descent parser over an SDDL string.
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Parser<'a> {
+        Parser { input, position: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn error<T>(&self, message: String) -> SddlResult<T> {
+        Err(SddlError::new(self.position, message))
+    }
+
+    fn end(&self) -> SddlResult<()> {
+        match self.rest() {
+            "" => Ok(()),
+            rest => self.error(format!("unexpected trailing characters '{}'", rest)),
+        }
+    }
+
+    /// Consumes `token` if the input continues with it, case insensitive.
+    fn eat(&mut self, token: &str) -> bool {
+        let rest = self.rest();
+        if rest.len() >= token.len()
+            && rest.is_char_boundary(token.len())
+            && rest[..token.len()].eq_ignore_ascii_case(token)
+        {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> SddlResult<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", token))
+        }
+    }
+
+    /// Consumes characters while `predicate` holds, returning them.
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> &'a str {
+        let rest = self.rest();
+        let length = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
+        self.position += length;
+        &rest[..length]
+    }
+
+    fn is_component_start(&self) -> bool {
+        let rest = self.rest().as_bytes();
+        rest.len() >= 2 && rest[1] == b':' && b"OGDS".contains(&rest[0].to_ascii_uppercase())
+    }
+
+    fn security_descriptor(&mut self) -> SddlResult<SecurityDescriptor> {
+        let mut security_descriptor = SecurityDescriptor::default();
+        self.take_while(char::is_whitespace);
+
+        while !self.rest().is_empty() {
+            if !self.is_component_start() {
+                return self.error(String::from("expected one of 'O:', 'G:', 'D:' or 'S:'"));
+            }
+
+            let component_position = self.position;
+            let component = self.rest().as_bytes()[0].to_ascii_uppercase();
+            self.position += 2;
+
+            let duplicated = match component {
+                b'O' => security_descriptor.owner.replace(self.sid()?).is_some(),
+                b'G' => security_descriptor.group.replace(self.sid()?).is_some(),
+                b'D' => security_descriptor.dacl.replace(self.acl()?).is_some(),
+                _ => security_descriptor.sacl.replace(self.acl()?).is_some(),
+            };
+
+            if duplicated {
+                return Err(SddlError::new(
+                    component_position,
+                    format!("duplicated '{}:' component", component as char),
+                ));
+            }
+
+            self.take_while(char::is_whitespace);
+        }
+
+        Ok(security_descriptor)
+    }
+
+    fn sid(&mut self) -> SddlResult<Sid> {
+        let start = self.position;
+
+        if !self.eat("S-") {
+            let alias = self.rest().get(..2).unwrap_or("");
+            return match WellKnownSid::from_alias(alias) {
+                Some(well_known) => {
+                    self.position += 2;
+                    Ok(well_known.sid())
+                }
+                None => self.error(format!("unknown or unsupported SID alias '{}'", alias)),
+            };
+        }
+
+        let revision = self.number(10)?;
+        self.expect("-")?;
+        let identifier_authority = if self.eat("0x") {
+            self.number(16)?
+        } else {
+            self.number(10)?
+        };
+
+        let mut sub_authorities = Vec::new();
+        while self.eat("-") {
+            let sub_authority = self.number(10)?;
+            if sub_authority > u64::from(u32::MAX) {
+                return self.error(String::from("SID sub authority does not fit in 32 bits"));
+            }
+            sub_authorities.push(sub_authority as u32);
+        }
+
+        if revision > u64::from(u8::MAX) {
+            return Err(SddlError::new(start, String::from("invalid SID revision")));
+        }
+
+        let sid = Sid {
+            revision: revision as u8,
+            identifier_authority,
+            sub_authorities,
+        };
+        sid.validate(start)?;
+        Ok(sid)
+    }
+
+    fn number(&mut self, radix: u32) -> SddlResult<u64> {
+        let start = self.position;
+        let digits = self.take_while(|c| c.is_digit(radix));
+        u64::from_str_radix(digits, radix)
+            .map_err(|_| SddlError::new(start, String::from("expected a number")))
+    }
+
+    fn acl(&mut self) -> SddlResult<Acl> {
+        let mut acl = Acl::default();
+
+        while !self.rest().starts_with('(') && !self.rest().is_empty() && !self.is_component_start()
+        {
+            match ACL_FLAGS.iter().find(|entry| self.eat(entry.0)) {
+                Some(entry) => acl.flags.0 |= entry.1,
+                None => {
+                    let flags = self.rest().split('(').next().unwrap_or("");
+                    return self.error(format!("unknown ACL flag in '{}'", flags));
+                }
+            }
+        }
+
+        while self.eat("(") {
+            acl.aces.push(self.ace()?);
+            self.expect(")")?;
+        }
+
+        Ok(acl)
+    }
+
+    fn ace(&mut self) -> SddlResult<Ace> {
+        let ace_type_token = self.take_while(|c| c.is_ascii_alphabetic());
+        let ace_type = match ACE_TYPES
+            .iter()
+            .find(|entry| entry.1.eq_ignore_ascii_case(ace_type_token))
+        {
+            Some(entry) => entry.0,
+            None => {
+                self.position -= ace_type_token.len();
+                return self.error(format!(
+                    "unknown or unsupported ACE type '{}'",
+                    ace_type_token
+                ));
+            }
+        };
+        self.expect(";")?;
+
+        let mut flags = AceFlags::default();
+        while !self.rest().starts_with(';') {
+            match ACE_FLAGS.iter().find(|entry| self.eat(entry.0)) {
+                Some(entry) => flags.0 |= entry.1,
+                None => return self.error(String::from("unknown ACE flag")),
+            }
+        }
+        self.expect(";")?;
+
+        let rights = self.rights(ace_type)?;
+        self.expect(";")?;
+        let object_type = self.guid()?;
+        self.expect(";")?;
+        let inherited_object_type = self.guid()?;
+        self.expect(";")?;
+
+        if !ace_type.is_object_ace() && (object_type.is_some() || inherited_object_type.is_some()) {
+            return self.error(format!(
+                "ACE type '{}' can't have object GUIDs",
+                ace_type.token()
+            ));
+        }
+
+        let sid_start = self.position;
+        let sid_token = self.take_while(|c| c != ';' && c != ')');
+        let sid = sid_token
+            .parse::<Sid>()
+            .map_err(|error| SddlError::new(sid_start + error.position, error.message))?;
+
+        if self.rest().starts_with(';') {
+            return self.error(String::from(
+                "conditional and resource attribute ACEs are not supported",
+            ));
+        }
+
+        Ok(Ace {
+            ace_type,
+            flags,
+            rights,
+            object_type,
+            inherited_object_type,
+            sid,
+        })
+    }
+
+    fn rights(&mut self, ace_type: AceType) -> SddlResult<AccessMask> {
+        if self.eat("0x") {
+            let start = self.position;
+            let mask = self.number(16)?;
+            return u32::try_from(mask).map(AccessMask).map_err(|_| {
+                SddlError::new(start, String::from("access mask does not fit in 32 bits"))
+            });
+        }
+
+        let bit_rights = match ace_type {
+            AceType::MandatoryLabel => LABEL_RIGHTS,
+            _ => BIT_RIGHTS,
+        };
+
+        let mut mask = 0;
+        while !self.rest().starts_with(';') {
+            let token = self.rest().get(..2).unwrap_or("");
+            match COMPOSITE_RIGHTS
+                .iter()
+                .chain(bit_rights.iter())
+                .find(|entry| entry.0.eq_ignore_ascii_case(token))
+            {
+                Some(entry) => {
+                    mask |= entry.1;
+                    self.position += 2;
+                }
+                None => return self.error(format!("unknown access right '{}'", token)),
+            }
+        }
+
+        Ok(AccessMask(mask))
+    }
+
+    fn guid(&mut self) -> SddlResult<Option<GuidSerde>> {
+        let start = self.position;
+        let guid = self.take_while(|c| c.is_ascii_hexdigit() || c == '-');
+        if guid.is_empty() {
+            return Ok(None);
+        }
+
+        match GuidSerde::from_str(guid) {
+            Ok(guid) => Ok(Some(guid)),
+            Err(_) => Err(SddlError::new(start, format!("invalid GUID '{}'", guid))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_serialize_canonical() {
+        let sd =
+            SecurityDescriptor::parse("d:p(a;;fa;;;S-1-5-18)(A;OICI;GRGX;;;BA)O:SYG:BA").unwrap();
+        assert_eq!(sd.owner, Some(Sid::local_system()));
+        assert_eq!(sd.group, Some(Sid::builtin_administrators()));
+        assert_eq!(sd.to_string(), "O:SYG:BAD:P(A;;FA;;;SY)(A;OICI;GRGX;;;BA)");
+        assert_eq!(SecurityDescriptor::parse(&sd.to_string()).unwrap(), sd);
+    }
+
+    #[test]
+    fn access_masks() {
+        let sd = SecurityDescriptor::parse("D:(A;;0x1f01ff;;;WD)(A;;0x100001;;;AU)(A;;RCSD;;;BU)")
+            .unwrap();
+        assert_eq!(
+            sd.to_string(),
+            "D:(A;;FA;;;WD)(A;;0x100001;;;AU)(A;;RCSD;;;BU)"
+        );
+    }
+
+    #[test]
+    fn literal_sids() {
+        let sid: Sid = "S-1-5-21-1004336348-1177238915-682003330-512"
+            .parse()
+            .unwrap();
+        assert_eq!(sid.identifier_authority, 5);
+        assert_eq!(sid.sub_authorities.len(), 5);
+        assert_eq!(sid.well_known(), None);
+        assert_eq!(
+            sid.to_string(),
+            "S-1-5-21-1004336348-1177238915-682003330-512"
+        );
+
+        let sid: Sid = "S-1-0x100000000000-3".parse().unwrap();
+        assert_eq!(sid.to_string(), "S-1-0x100000000000-3");
+        let sid: Sid = "S-1-0x000000000100-3".parse().unwrap();
+        assert_eq!(sid.to_string(), "S-1-256-3");
+
+        assert!("S-1-5".parse::<Sid>().is_err());
+        assert!("S-2-5-18".parse::<Sid>().is_err());
+    }
+
+    #[test]
+    fn object_aces() {
+        let sd =
+            SecurityDescriptor::parse("D:(OA;CI;RPWP;db20fa3e-c476-447f-94a5-51b8322c4c4f;;SY)")
+                .unwrap();
+        assert_eq!(
+            sd.to_string(),
+            "D:(OA;CI;RPWP;db20fa3e-c476-447f-94a5-51b8322c4c4f;;SY)"
+        );
+        assert!(
+            SecurityDescriptor::parse("D:(A;;RP;db20fa3e-c476-447f-94a5-51b8322c4c4f;;SY)")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = SecurityDescriptor::parse("D:P(A;;FA;;;SYS)").unwrap_err();
+        assert_eq!(error.position, 14);
+
+        let error = SecurityDescriptor::parse("D:P(A;;FB;;;SY)").unwrap_err();
+        assert_eq!(error.position, 7);
+
+        assert!(SecurityDescriptor::parse("D:P(X;;FA;;;SY)").is_err());
+        assert!(SecurityDescriptor::parse("D:P(A;;FA;;;SY").is_err());
+        assert!(SecurityDescriptor::parse("D:P(A;;;;;SY)").is_err());
+        assert!(SecurityDescriptor::parse("D:P(AU;SA;FA;;;SY)").is_err());
+        assert!(SecurityDescriptor::parse("O:SYO:BA").is_err());
+        assert!(SecurityDescriptor::parse("X:SY").is_err());
+
+        let error = SecurityDescriptor::parse("D:(A;;0x100000000;;;WD)").unwrap_err();
+        assert_eq!(error.position, 8);
+    }
+
+    #[test]
+    fn sacl_and_labels() {
+        let sd = SecurityDescriptor::parse("D:NO_ACCESS_CONTROLS:(ML;;NWNR;;;LW)(AU;SAFA;FA;;;WD)")
+            .unwrap();
+        assert_eq!(sd.dacl.as_ref().unwrap().flags, AclFlags::NO_ACCESS_CONTROL);
+        assert_eq!(
+            sd.to_string(),
+            "D:NO_ACCESS_CONTROLS:(ML;;NWNR;;;LW)(AU;SAFA;FA;;;WD)"
+        );
+    }
+
+    #[test]
+    fn allow_system_and() {
+        let sid: Sid = "S-1-5-21-1-2-3-1001".parse().unwrap();
+        assert_eq!(
+            SecurityDescriptor::allow_system_and(sid).to_string(),
+            "D:P(A;;FA;;;SY)(A;;FA;;;S-1-5-21-1-2-3-1001)"
+        );
+    }
+}
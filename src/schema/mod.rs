@@ -8,25 +8,26 @@
 
 //! Contains all the JSON schema definitions used by the HCS APIs
 
-// TODO:rafawo Add conditional compilation to portions of the schema depending the release.
-
 pub mod common;
 pub mod containers;
 pub mod device_assignment;
 pub mod hvsocket;
 pub mod layer_management;
 pub mod options;
+pub mod preserved;
 pub mod process;
 pub mod registry;
 pub mod requests;
 pub mod responses;
 pub mod utils;
+pub mod versioning;
 pub mod virtual_machines;
 
 use crate::schema::utils::is_default;
+use crate::schema::versioning::{SchemaVersioned, VersionedField};
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Version {
     #[serde(rename = "Major")]
     pub major: u32,
@@ -46,11 +47,15 @@ impl Version {
         Self { major: 2, minor: 1 }
     }
 
-    #[cfg(any(feature = "19h1"))]
     /// Returns a `Version` object constructured to properly reflect 19H1.
     pub fn schema_version_19h1() -> Self {
         Self { major: 2, minor: 2 }
     }
+
+    /// Returns the latest schema version known by this crate.
+    pub fn latest() -> Self {
+        Version::schema_version_19h1()
+    }
 }
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
@@ -193,3 +198,37 @@ pub struct HostedSystem {
     #[serde(rename = "Container")]
     pub container: Container,
 }
+
+impl SchemaVersioned for Container {
+    fn versioned_fields() -> Vec<VersionedField> {
+        VersionedField::nested::<device_assignment::Device>("AssignedDevices")
+    }
+}
+
+impl SchemaVersioned for VirtualMachine {
+    fn versioned_fields() -> Vec<VersionedField> {
+        let mut fields = VersionedField::nested::<virtual_machines::resources::Chipset>("Chipset");
+        fields.extend(VersionedField::nested::<
+            virtual_machines::resources::compute::Topology,
+        >("ComputeTopology"));
+        fields.extend(VersionedField::nested::<virtual_machines::Devices>(
+            "Devices",
+        ));
+        fields
+    }
+}
+
+impl SchemaVersioned for ComputeSystem {
+    fn versioned_fields() -> Vec<VersionedField> {
+        let mut fields = VersionedField::nested::<Container>("HostedSystem.Container");
+        fields.extend(VersionedField::nested::<Container>("Container"));
+        fields.extend(VersionedField::nested::<VirtualMachine>("VirtualMachine"));
+        fields
+    }
+}
+
+impl SchemaVersioned for HostedSystem {
+    fn versioned_fields() -> Vec<VersionedField> {
+        VersionedField::nested::<Container>("Container")
+    }
+}
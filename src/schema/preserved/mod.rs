@@ -0,0 +1,405 @@
+// Copyright  rafawo (rafawo1@hotmail.com). All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Preservation of JSON members that are not modeled by the schema.
+//!
+//! Newer HCS builds can return members that the schema types don't know about,
+//! which are dropped when deserializing. Wrapping a schema document in `Preserved`
+//! keeps those members around, so that they survive a deserialize, mutate and
+//! serialize round trip.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// JSON member not modeled by a schema type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownField {
+    /// Path of the member in the document, separated by dots.
+    /// Map keys and array indexes are included as path segments.
+    pub path: String,
+    pub value: serde_json::Value,
+}
+
+/// Members of a JSON document not modeled by a schema type,
+/// laid out following the structure of the document.
+#[derive(Debug, Clone, PartialEq)]
+enum UnknownTree {
+    /// Member not modeled by the schema type, with its whole value.
+    Member(serde_json::Value),
+    /// Modeled object with unknown members somewhere below it.
+    Object(std::collections::BTreeMap<String, UnknownTree>),
+    /// Modeled array with unknown members somewhere below some of its elements.
+    Array(Vec<UnknownElement>),
+}
+
+/// Element of a modeled array with unknown members somewhere below it.
+#[derive(Debug, Clone, PartialEq)]
+struct UnknownElement {
+    /// Index of the element in the original array.
+    index: usize,
+    /// Modeled value of the element, used to find it once the array is modified.
+    modeled: serde_json::Value,
+    unknown: UnknownTree,
+}
+
+/// Schema document that keeps the JSON members not modeled by `T`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Preserved<T> {
+    pub document: T,
+
+    /// Members of the original JSON that were dropped when deserializing `T`.
+    unknown: Option<UnknownTree>,
+}
+
+impl<T> Preserved<T> {
+    /// Wraps a document that has no unknown members.
+    pub fn new(document: T) -> Preserved<T> {
+        Preserved {
+            document,
+            unknown: None,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.document
+    }
+
+    /// Returns true if the original JSON had members not modeled by `T`.
+    pub fn has_unknown_fields(&self) -> bool {
+        self.unknown.is_some()
+    }
+
+    /// Returns the members of the original JSON not modeled by `T`.
+    /// Modeled members explicitly set to a value that is skipped when serialized,
+    /// such as `false` or an empty string, are reported as well.
+    pub fn unknown_fields(&self) -> Vec<UnknownField> {
+        let mut fields = Vec::new();
+        if let Some(unknown) = &self.unknown {
+            collect_unknown(unknown, String::new(), &mut fields);
+        }
+        fields
+    }
+
+    /// Drops the preserved unknown members, so that they are not serialized.
+    pub fn clear_unknown_fields(&mut self) {
+        self.unknown = None;
+    }
+}
+
+impl<T> std::ops::Deref for Preserved<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.document
+    }
+}
+
+impl<T> std::ops::DerefMut for Preserved<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.document
+    }
+}
+
+impl<T> std::convert::From<T> for Preserved<T> {
+    fn from(document: T) -> Self {
+        Preserved::new(document)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Preserved<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let original = serde_json::Value::deserialize(deserializer)?;
+        let document: T = serde_json::from_value(original.clone()).map_err(D::Error::custom)?;
+        let modeled = serde_json::to_value(&document).map_err(D::Error::custom)?;
+
+        Ok(Preserved {
+            document,
+            unknown: diff_unknown(&original, &modeled),
+        })
+    }
+}
+
+impl<T: Serialize> Serialize for Preserved<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::Error;
+
+        let mut value = serde_json::to_value(&self.document).map_err(S::Error::custom)?;
+        if let Some(unknown) = &self.unknown {
+            merge_unknown(&mut value, unknown);
+        }
+        value.serialize(serializer)
+    }
+}
+
+/// Returns the members of a JSON document not modeled by `T`.
+/// Useful to diagnose drift between the schema and the JSON returned by a host.
+pub fn unknown_fields<T>(json: &str) -> serde_json::Result<Vec<UnknownField>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let preserved: Preserved<T> = serde_json::from_str(json)?;
+    Ok(preserved.unknown_fields())
+}
+
+/// Returns the members present in `original` but missing from `modeled`, if any.
+/// Members that are only missing because they hold a default value are
+/// preserved as well, which is harmless since serializing never overwrites.
+fn diff_unknown(original: &serde_json::Value, modeled: &serde_json::Value) -> Option<UnknownTree> {
+    match (original, modeled) {
+        (serde_json::Value::Object(original), serde_json::Value::Object(modeled)) => {
+            let mut unknown = std::collections::BTreeMap::new();
+
+            for (key, original_member) in original {
+                let member = match modeled.get(key) {
+                    Some(modeled_member) => diff_unknown(original_member, modeled_member),
+                    None => Some(UnknownTree::Member(original_member.clone())),
+                };
+
+                if let Some(member) = member {
+                    unknown.insert(key.clone(), member);
+                }
+            }
+
+            if unknown.is_empty() {
+                None
+            } else {
+                Some(UnknownTree::Object(unknown))
+            }
+        }
+        (serde_json::Value::Array(original), serde_json::Value::Array(modeled))
+            if original.len() == modeled.len() =>
+        {
+            let elements: Vec<UnknownElement> = original
+                .iter()
+                .zip(modeled.iter())
+                .enumerate()
+                .filter_map(|(index, (original, modeled))| {
+                    Some(UnknownElement {
+                        index,
+                        modeled: modeled.clone(),
+                        unknown: diff_unknown(original, modeled)?,
+                    })
+                })
+                .collect();
+
+            if elements.is_empty() {
+                None
+            } else {
+                Some(UnknownTree::Array(elements))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Adds the unknown members to `value`, without overwriting members already present.
+fn merge_unknown(value: &mut serde_json::Value, unknown: &UnknownTree) {
+    match (value, unknown) {
+        (serde_json::Value::Object(members), UnknownTree::Object(unknown)) => {
+            for (key, unknown_member) in unknown {
+                match (members.get_mut(key), unknown_member) {
+                    (Some(member), _) => merge_unknown(member, unknown_member),
+                    (None, UnknownTree::Member(original)) => {
+                        members.insert(key.clone(), original.clone());
+                    }
+                    // The modeled member holding unknown members is gone, so are they
+                    (None, _) => {}
+                }
+            }
+        }
+        (serde_json::Value::Array(elements), UnknownTree::Array(unknown)) => {
+            // Elements are matched by their modeled value rather than their index, so the
+            // unknown members follow elements that are moved, and are dropped along with
+            // elements that are modified or removed
+            let mut matched = vec![false; elements.len()];
+            for unknown_element in unknown {
+                let index = (0..elements.len())
+                    .find(|index| !matched[*index] && elements[*index] == unknown_element.modeled);
+
+                if let Some(index) = index {
+                    matched[index] = true;
+                    merge_unknown(&mut elements[index], &unknown_element.unknown);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect_unknown(unknown: &UnknownTree, path: String, fields: &mut Vec<UnknownField>) {
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+
+    match unknown {
+        UnknownTree::Member(value) => fields.push(UnknownField {
+            path: path.clone(),
+            value: value.clone(),
+        }),
+        UnknownTree::Object(members) => {
+            for (key, member) in members {
+                collect_unknown(member, child_path(key), fields);
+            }
+        }
+        UnknownTree::Array(elements) => {
+            for element in elements {
+                collect_unknown(
+                    &element.unknown,
+                    child_path(&element.index.to_string()),
+                    fields,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ComputeSystem;
+
+    #[test]
+    fn unknown_fields_round_trip() {
+        let json = r#"{
+            "Owner": "owner",
+            "SchemaVersion": {"Major": 2, "Minor": 1},
+            "FutureTopLevel": {"Enabled": true},
+            "VirtualMachine": {
+                "Chipset": {},
+                "ComputeTopology": {
+                    "Memory": {"SizeInMB": 1024, "FutureMemoryKnob": 7},
+                    "Processor": {"Count": 2}
+                },
+                "Devices": {
+                    "Plan9": {
+                        "Shares": [
+                            {"Name": "a", "Path": "C:/a", "Port": 564},
+                            {"Name": "b", "Path": "C:/b", "Port": 564, "FutureFlags": 1}
+                        ]
+                    }
+                }
+            }
+        }"#;
+
+        let mut preserved: Preserved<ComputeSystem> = serde_json::from_str(json).unwrap();
+        assert!(preserved.has_unknown_fields());
+        assert_eq!(
+            preserved.unknown_fields(),
+            vec![
+                UnknownField {
+                    path: String::from("FutureTopLevel"),
+                    value: serde_json::json!({"Enabled": true}),
+                },
+                UnknownField {
+                    path: String::from("VirtualMachine.ComputeTopology.Memory.FutureMemoryKnob"),
+                    value: serde_json::json!(7),
+                },
+                UnknownField {
+                    path: String::from("VirtualMachine.Devices.Plan9.Shares.1.FutureFlags"),
+                    value: serde_json::json!(1),
+                },
+            ]
+        );
+
+        preserved
+            .virtual_machine
+            .as_mut()
+            .unwrap()
+            .compute_topology
+            .memory
+            .size_in_mb = 2048;
+
+        let value = serde_json::to_value(&preserved).unwrap();
+        let memory = &value["VirtualMachine"]["ComputeTopology"]["Memory"];
+        assert_eq!(
+            memory,
+            &serde_json::json!({"SizeInMB": 2048, "FutureMemoryKnob": 7})
+        );
+        assert_eq!(
+            value["FutureTopLevel"],
+            serde_json::json!({"Enabled": true})
+        );
+        assert_eq!(
+            value["VirtualMachine"]["Devices"]["Plan9"]["Shares"][1]["FutureFlags"],
+            serde_json::json!(1)
+        );
+
+        preserved.clear_unknown_fields();
+        assert!(serde_json::to_value(&preserved)
+            .unwrap()
+            .get("FutureTopLevel")
+            .is_none());
+        assert_eq!(unknown_fields::<ComputeSystem>(json).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn unknown_fields_follow_array_elements() {
+        let json = r#"{
+            "Owner": "owner",
+            "SchemaVersion": {"Major": 2, "Minor": 1},
+            "VirtualMachine": {
+                "Chipset": {},
+                "ComputeTopology": {"Memory": {"SizeInMB": 1024}, "Processor": {"Count": 2}},
+                "Devices": {
+                    "Plan9": {
+                        "Shares": [
+                            {"Name": "a", "Path": "C:/a", "Port": 564, "FutureFlags": 1},
+                            {"Name": "b", "Path": "C:/b", "Port": 564, "FutureFlags": 2}
+                        ]
+                    }
+                }
+            }
+        }"#;
+        let shares = |value: &serde_json::Value| {
+            value["VirtualMachine"]["Devices"]["Plan9"]["Shares"]
+                .as_array()
+                .unwrap()
+                .clone()
+        };
+
+        let mut preserved: Preserved<ComputeSystem> = serde_json::from_str(json).unwrap();
+        {
+            let shares = &mut preserved
+                .virtual_machine
+                .as_mut()
+                .unwrap()
+                .devices
+                .plan9
+                .as_mut()
+                .unwrap()
+                .shares;
+            let mut inserted = shares[0].clone();
+            inserted.name = String::from("c");
+            shares.insert(0, inserted);
+            shares[1].port = 565;
+        }
+
+        let shares = shares(&serde_json::to_value(&preserved).unwrap());
+        assert_eq!(shares.len(), 3);
+        assert_eq!(shares[0]["Name"], "c");
+        assert!(shares[0].get("FutureFlags").is_none());
+        assert!(shares[1].get("FutureFlags").is_none());
+        assert_eq!(shares[2]["Name"], "b");
+        assert_eq!(shares[2]["FutureFlags"], 2);
+    }
+}
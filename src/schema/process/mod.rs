@@ -6,6 +6,7 @@
 // except according to those terms.
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
+use crate::schema::responses::system::OsType;
 use crate::schema::utils::is_default;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -156,3 +157,528 @@ pub struct ProcessModifyRequest {
     #[serde(default, rename = "CloseHandle", skip_serializing_if = "is_default")]
     pub close_handle: Option<CloseHandle>,
 }
+
+/// Errors produced while composing a process command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandLineError {
+    /// The argument vector is empty, so there is no program to run.
+    EmptyArgv,
+    /// The program name contains a character that can't be represented
+    /// in a Windows command line, such as a double quote.
+    InvalidProgramName(String),
+}
+
+impl std::fmt::Display for CommandLineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CommandLineError::EmptyArgv => write!(f, "the argument vector is empty"),
+            CommandLineError::InvalidProgramName(name) => {
+                write!(f, "invalid program name for a command line: {}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandLineError {}
+
+fn is_windows_argument_separator(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn push_backslashes(string: &mut String, count: usize) {
+    for _ in 0..count {
+        string.push('\\');
+    }
+}
+
+/// Appends an argument to a Windows command line, quoting and escaping it
+/// so that `CommandLineToArgvW` parses it back unchanged.
+pub fn append_windows_argument(command_line: &mut String, argument: &str) {
+    let needs_quotes = argument.is_empty()
+        || argument
+            .chars()
+            .any(|c| is_windows_argument_separator(c) || c == '\n' || c == '\x0b' || c == '"');
+
+    if !needs_quotes {
+        command_line.push_str(argument);
+        return;
+    }
+
+    command_line.push('"');
+
+    let mut backslashes = 0;
+
+    for c in argument.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes preceding a quote are escaped, as is the quote itself
+                push_backslashes(command_line, backslashes * 2 + 1);
+                command_line.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                push_backslashes(command_line, backslashes);
+                command_line.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+
+    // Backslashes preceding the closing quote need to be escaped
+    push_backslashes(command_line, backslashes * 2);
+    command_line.push('"');
+}
+
+/// Composes a Windows command line out of an argument vector,
+/// following the rules used by `CommandLineToArgvW` to split it back.
+///
+/// The program name is parsed without escape sequences, so it can't contain double quotes.
+pub fn compose_windows_command_line<S: AsRef<str>>(argv: &[S]) -> Result<String, CommandLineError> {
+    let (program, arguments) = match argv.split_first() {
+        Some(split) => split,
+        None => return Err(CommandLineError::EmptyArgv),
+    };
+
+    let program = program.as_ref();
+
+    if program.contains('"') {
+        return Err(CommandLineError::InvalidProgramName(program.to_string()));
+    }
+
+    let mut command_line = String::new();
+
+    if program.is_empty() || program.chars().any(is_windows_argument_separator) {
+        command_line.push('"');
+        command_line.push_str(program);
+        command_line.push('"');
+    } else {
+        command_line.push_str(program);
+    }
+
+    for argument in arguments {
+        command_line.push(' ');
+        append_windows_argument(&mut command_line, argument.as_ref());
+    }
+
+    Ok(command_line)
+}
+
+/// Splits a Windows command line into an argument vector,
+/// following the rules used by `CommandLineToArgvW`.
+pub fn parse_windows_command_line(command_line: &str) -> Vec<String> {
+    let mut argv = Vec::new();
+    let mut chars = command_line.chars().peekable();
+
+    if chars.peek().is_none() {
+        return argv;
+    }
+
+    // The program name ends at the next whitespace, or at the closing quote
+    // if it starts with one. Backslashes have no special meaning in it.
+    let mut program = String::new();
+
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        for c in chars.by_ref() {
+            if c == '"' {
+                break;
+            }
+            program.push(c);
+        }
+    } else {
+        while let Some(&c) = chars.peek() {
+            if is_windows_argument_separator(c) {
+                break;
+            }
+            program.push(c);
+            chars.next();
+        }
+    }
+
+    argv.push(program);
+
+    loop {
+        while let Some(&c) = chars.peek() {
+            if !is_windows_argument_separator(c) {
+                break;
+            }
+            chars.next();
+        }
+
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut argument = String::new();
+        let mut in_quotes = false;
+        let mut backslashes = 0;
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                '\\' => {
+                    backslashes += 1;
+                    chars.next();
+                    continue;
+                }
+                '"' => {
+                    push_backslashes(&mut argument, backslashes / 2);
+                    chars.next();
+
+                    if backslashes % 2 == 1 {
+                        argument.push('"');
+                    } else if in_quotes && chars.peek() == Some(&'"') {
+                        // Two consecutive quotes inside a quoted block produce a literal quote
+                        argument.push('"');
+                        chars.next();
+                    } else {
+                        in_quotes = !in_quotes;
+                    }
+                }
+                _ => {
+                    push_backslashes(&mut argument, backslashes);
+
+                    if !in_quotes && is_windows_argument_separator(c) {
+                        backslashes = 0;
+                        break;
+                    }
+
+                    argument.push(c);
+                    chars.next();
+                }
+            }
+
+            backslashes = 0;
+        }
+
+        push_backslashes(&mut argument, backslashes);
+        argv.push(argument);
+    }
+
+    argv
+}
+
+/// Merges environment variables on top of a set of defaults, such as the ones
+/// coming from a container image. Later values win over earlier ones.
+///
+/// Windows environment variable names are case insensitive, so a variable
+/// overriding a default with a different casing replaces it, keeping the casing
+/// of the override. Linux environment variable names are case sensitive.
+pub fn merge_environment<I, K, V>(
+    defaults: &HashMap<String, String>,
+    overrides: I,
+    os_type: &OsType,
+) -> HashMap<String, String>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    let mut environment = defaults.clone();
+
+    for (name, value) in overrides {
+        let name = name.into();
+
+        if *os_type == OsType::Windows {
+            let existing: Vec<String> = environment
+                .keys()
+                .filter(|existing| existing.eq_ignore_ascii_case(&name))
+                .cloned()
+                .collect();
+
+            for existing in existing {
+                environment.remove(&existing);
+            }
+        }
+
+        environment.insert(name, value.into());
+    }
+
+    environment
+}
+
+impl ProcessParameters {
+    /// Returns the argument vector of the process, either directly from `command_args`
+    /// or by splitting `command_line` with Windows rules.
+    pub fn argv(&self) -> Vec<String> {
+        if !self.command_args.is_empty() {
+            self.command_args.clone()
+        } else {
+            parse_windows_command_line(&self.command_line)
+        }
+    }
+}
+
+/// Builder for `ProcessParameters` targeting a guest OS.
+///
+/// Windows guests only honour `CommandLine`, so the argument vector gets
+/// quoted into a single command line. Linux guests get `CommandArgs` instead.
+#[derive(Debug, Clone)]
+pub struct ProcessParametersBuilder {
+    os_type: OsType,
+    argv: Vec<String>,
+    image_environment: HashMap<String, String>,
+    environment: Vec<(String, String)>,
+    parameters: ProcessParameters,
+}
+
+impl ProcessParametersBuilder {
+    pub fn new(os_type: OsType) -> ProcessParametersBuilder {
+        ProcessParametersBuilder {
+            os_type,
+            argv: Vec::new(),
+            image_environment: HashMap::new(),
+            environment: Vec::new(),
+            parameters: ProcessParameters::default(),
+        }
+    }
+
+    /// Appends an argument to the argument vector. The first one is the program to run.
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> ProcessParametersBuilder {
+        self.argv.push(arg.into());
+        self
+    }
+
+    /// Appends multiple arguments to the argument vector.
+    pub fn args<I, S>(mut self, args: I) -> ProcessParametersBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.argv.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the default environment, usually the one defined by the image.
+    pub fn image_environment(
+        mut self,
+        environment: HashMap<String, String>,
+    ) -> ProcessParametersBuilder {
+        self.image_environment = environment;
+        self
+    }
+
+    /// Sets an environment variable on top of the image environment.
+    pub fn env<K: Into<String>, V: Into<String>>(
+        mut self,
+        name: K,
+        value: V,
+    ) -> ProcessParametersBuilder {
+        self.environment.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets multiple environment variables on top of the image environment.
+    pub fn envs<I, K, V>(mut self, environment: I) -> ProcessParametersBuilder
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.environment.extend(
+            environment
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into())),
+        );
+        self
+    }
+
+    pub fn application_name<S: Into<String>>(mut self, name: S) -> ProcessParametersBuilder {
+        self.parameters.application_name = name.into();
+        self
+    }
+
+    pub fn user<S: Into<String>>(mut self, user: S) -> ProcessParametersBuilder {
+        self.parameters.user = user.into();
+        self
+    }
+
+    pub fn working_directory<S: Into<String>>(
+        mut self,
+        working_directory: S,
+    ) -> ProcessParametersBuilder {
+        self.parameters.working_directory = working_directory.into();
+        self
+    }
+
+    /// Selects which std handles get a pipe created for them.
+    pub fn std_pipes(
+        mut self,
+        std_in: bool,
+        std_out: bool,
+        std_err: bool,
+    ) -> ProcessParametersBuilder {
+        self.parameters.create_std_in_pipe = std_in;
+        self.parameters.create_std_out_pipe = std_out;
+        self.parameters.create_std_err_pipe = std_err;
+        self
+    }
+
+    /// Emulates a console of the given size, in which case StdErr is merged into StdOut.
+    pub fn console(mut self, console_size: ConsoleSize) -> ProcessParametersBuilder {
+        self.parameters.emulate_console = true;
+        self.parameters.console_size = [console_size.height, console_size.width];
+        self
+    }
+
+    pub fn restricted_token(mut self, restricted_token: bool) -> ProcessParametersBuilder {
+        self.parameters.restricted_token = restricted_token;
+        self
+    }
+
+    pub fn build(self) -> Result<ProcessParameters, CommandLineError> {
+        let mut parameters = self.parameters;
+
+        if self.argv.is_empty() {
+            return Err(CommandLineError::EmptyArgv);
+        }
+
+        match self.os_type {
+            OsType::Windows => {
+                parameters.command_line = compose_windows_command_line(&self.argv)?;
+            }
+            OsType::Linux => {
+                parameters.command_args = self.argv;
+            }
+        }
+
+        parameters.environment =
+            merge_environment(&self.image_environment, self.environment, &self.os_type);
+
+        Ok(parameters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(argv: &[&str]) {
+        let command_line = compose_windows_command_line(argv).unwrap();
+        assert_eq!(
+            parse_windows_command_line(&command_line),
+            argv,
+            "{}",
+            command_line
+        );
+    }
+
+    #[test]
+    fn windows_command_line_composition() {
+        assert_eq!(
+            compose_windows_command_line(&["cmd.exe", "/c", "echo hello"]).unwrap(),
+            r#"cmd.exe /c "echo hello""#
+        );
+        assert_eq!(
+            compose_windows_command_line(&[r"C:\Program Files\app.exe", "", r#"a"b"#, r"c:\dir\"])
+                .unwrap(),
+            r#""C:\Program Files\app.exe" "" "a\"b" c:\dir\"#
+        );
+        assert_eq!(
+            compose_windows_command_line(&["app.exe", r"c:\my dir\", r#"x\"y"#]).unwrap(),
+            r#"app.exe "c:\my dir\\" "x\\\"y""#
+        );
+        assert_eq!(
+            compose_windows_command_line::<&str>(&[]),
+            Err(CommandLineError::EmptyArgv)
+        );
+        assert!(compose_windows_command_line(&[r#"a"b.exe"#]).is_err());
+
+        round_trip(&[
+            "app.exe",
+            "",
+            " ",
+            "\t",
+            r"\",
+            r"\\",
+            r#"\""#,
+            r#"""#,
+            r#"a\\"b c"#,
+        ]);
+        round_trip(&[
+            r"C:\Program Files\app.exe",
+            r"C:\Program Files\",
+            "--flag=\"quoted value\"",
+        ]);
+    }
+
+    #[test]
+    fn windows_command_line_parsing() {
+        assert_eq!(
+            parse_windows_command_line(r#""C:\a b\app.exe"  one   "two three" four"five six""#),
+            vec![r"C:\a b\app.exe", "one", "two three", "fourfive six"]
+        );
+        assert_eq!(
+            parse_windows_command_line(r#"app.exe a\\\b d"e f"g h"#),
+            vec!["app.exe", r"a\\\b", "de fg", "h"]
+        );
+        assert_eq!(
+            parse_windows_command_line(r#"app.exe a\\\"b c d"#),
+            vec!["app.exe", r#"a\"b"#, "c", "d"]
+        );
+        assert_eq!(
+            parse_windows_command_line(r#"app.exe a\\\\"b c" d e"#),
+            vec!["app.exe", r"a\\b c", "d", "e"]
+        );
+        assert_eq!(
+            parse_windows_command_line(r#"app.exe "a""b" """#),
+            vec!["app.exe", r#"a"b"#, ""]
+        );
+        assert!(parse_windows_command_line("").is_empty());
+    }
+
+    #[test]
+    fn environment_merge() {
+        let mut image = HashMap::new();
+        image.insert(String::from("Path"), String::from(r"C:\Windows"));
+        image.insert(String::from("TEMP"), String::from(r"C:\Temp"));
+
+        let windows = merge_environment(
+            &image,
+            vec![("PATH", r"C:\bin"), ("Foo", "1")],
+            &OsType::Windows,
+        );
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows["PATH"], r"C:\bin");
+        assert!(!windows.contains_key("Path"));
+
+        let linux = merge_environment(&image, vec![("PATH", "/bin")], &OsType::Linux);
+        assert_eq!(linux.len(), 3);
+        assert_eq!(linux["Path"], r"C:\Windows");
+        assert_eq!(linux["PATH"], "/bin");
+    }
+
+    #[test]
+    fn process_parameters_builder() {
+        let windows = ProcessParametersBuilder::new(OsType::Windows)
+            .args(vec!["cmd.exe", "/c", "echo hello"])
+            .env("Foo", "Bar")
+            .env("FOO", "Baz")
+            .std_pipes(true, true, false)
+            .build()
+            .unwrap();
+        assert_eq!(
+            &serde_json::to_string(&windows).unwrap(),
+            r#"{"CommandLine":"cmd.exe /c \"echo hello\"","Environment":{"FOO":"Baz"},"CreateStdInPipe":true,"CreateStdOutPipe":true}"#
+        );
+        assert_eq!(windows.argv(), vec!["cmd.exe", "/c", "echo hello"]);
+
+        let linux = ProcessParametersBuilder::new(OsType::Linux)
+            .arg("/bin/sh")
+            .arg("-c")
+            .arg("echo hello")
+            .working_directory("/")
+            .build()
+            .unwrap();
+        assert_eq!(
+            &serde_json::to_string(&linux).unwrap(),
+            r#"{"CommandArgs":["/bin/sh","-c","echo hello"],"WorkingDirectory":"/"}"#
+        );
+        assert_eq!(linux.argv(), vec!["/bin/sh", "-c", "echo hello"]);
+
+        assert_eq!(
+            ProcessParametersBuilder::new(OsType::Linux).build(),
+            Err(CommandLineError::EmptyArgv)
+        );
+    }
+}
@@ -41,6 +41,76 @@ pub struct GuestModifySettingRequest {
     pub settings: serde_json::Value,
 }
 
+/// Typed settings of a guest modify request, serialized as its
+/// `ResourceType` and `Settings` pair.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "ResourceType", content = "Settings")]
+pub enum GuestRequest {
+    Memory(schema::containers::resources::Memory),
+    MappedDirectory(schema::containers::resources::MappedDirectory),
+    MappedPipe(schema::containers::resources::MappedPipe),
+    MappedVirtualDisk(schema::virtual_machines::resources::storage::MappedVirtualDisk),
+    CombinedLayers(schema::containers::resources::CombinedLayers),
+    /// The HCN namespace document, which is not modeled by the schema.
+    NetworkNamespace(serde_json::Value),
+}
+
+impl GuestRequest {
+    pub fn resource_type(&self) -> ModifyResourceType {
+        match self {
+            GuestRequest::Memory(_) => ModifyResourceType::Memory,
+            GuestRequest::MappedDirectory(_) => ModifyResourceType::MappedDirectory,
+            GuestRequest::MappedPipe(_) => ModifyResourceType::MappedPipe,
+            GuestRequest::MappedVirtualDisk(_) => ModifyResourceType::MappedVirtualDisk,
+            GuestRequest::CombinedLayers(_) => ModifyResourceType::CombinedLayers,
+            GuestRequest::NetworkNamespace(_) => ModifyResourceType::NetworkNamespace,
+        }
+    }
+
+    /// Returns a guest modify request of the given type with these settings.
+    pub fn into_modify_request(
+        self,
+        request_type: schema::requests::ModifyRequestType,
+    ) -> serde_json::Result<GuestModifySettingRequest> {
+        Ok(GuestModifySettingRequest {
+            resource_type: self.resource_type(),
+            request_type,
+            settings: match self {
+                GuestRequest::Memory(settings) => serde_json::to_value(settings)?,
+                GuestRequest::MappedDirectory(settings) => serde_json::to_value(settings)?,
+                GuestRequest::MappedPipe(settings) => serde_json::to_value(settings)?,
+                GuestRequest::MappedVirtualDisk(settings) => serde_json::to_value(settings)?,
+                GuestRequest::CombinedLayers(settings) => serde_json::to_value(settings)?,
+                GuestRequest::NetworkNamespace(settings) => settings,
+            },
+        })
+    }
+}
+
+impl GuestModifySettingRequest {
+    /// Decodes the settings of the request into the type expected by its resource type.
+    pub fn typed_settings(&self) -> serde_json::Result<GuestRequest> {
+        let settings = self.settings.clone();
+
+        Ok(match self.resource_type {
+            ModifyResourceType::Memory => GuestRequest::Memory(serde_json::from_value(settings)?),
+            ModifyResourceType::MappedDirectory => {
+                GuestRequest::MappedDirectory(serde_json::from_value(settings)?)
+            }
+            ModifyResourceType::MappedPipe => {
+                GuestRequest::MappedPipe(serde_json::from_value(settings)?)
+            }
+            ModifyResourceType::MappedVirtualDisk => {
+                GuestRequest::MappedVirtualDisk(serde_json::from_value(settings)?)
+            }
+            ModifyResourceType::CombinedLayers => {
+                GuestRequest::CombinedLayers(serde_json::from_value(settings)?)
+            }
+            ModifyResourceType::NetworkNamespace => GuestRequest::NetworkNamespace(settings),
+        })
+    }
+}
+
 impl std::default::Default for NetworkModifyRequestType {
     fn default() -> Self {
         NetworkModifyRequestType::PreAdd
@@ -82,3 +152,52 @@ pub struct NetworkModifySettingRequest {
     )]
     pub settings: serde_json::Value,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::requests::system::ModifySettingRequest;
+    use crate::schema::requests::ModifyRequestType;
+
+    #[test]
+    fn guest_requests() {
+        let guest_request = GuestRequest::MappedVirtualDisk(
+            schema::virtual_machines::resources::storage::MappedVirtualDisk {
+                container_path: String::from("/mnt/disk"),
+                lun: 2,
+            },
+        );
+
+        assert_eq!(
+            serde_json::to_string(&guest_request).unwrap(),
+            r#"{"ResourceType":"MappedVirtualDisk","Settings":{"ContainerPath":"/mnt/disk","Lun":2}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<GuestRequest>(
+                r#"{"ResourceType":"MappedVirtualDisk","Settings":{"ContainerPath":"/mnt/disk","Lun":2}}"#
+            )
+            .unwrap(),
+            guest_request
+        );
+
+        let request = ModifySettingRequest {
+            resource_path: String::from("VirtualMachine/Devices/Scsi/0/Attachments/2"),
+            ..Default::default()
+        }
+        .with_guest_request(ModifyRequestType::Add, guest_request.clone())
+        .unwrap();
+
+        assert_eq!(
+            request.guest_request,
+            serde_json::json!({
+                "ResourceType": "MappedVirtualDisk",
+                "RequestType": "Add",
+                "Settings": {"ContainerPath": "/mnt/disk", "Lun": 2},
+            })
+        );
+
+        let embedded = request.typed_guest_request().unwrap().unwrap();
+        assert_eq!(embedded.request_type, ModifyRequestType::Add);
+        assert_eq!(embedded.typed_settings().unwrap(), guest_request);
+    }
+}
@@ -7,6 +7,7 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 pub mod guest;
+pub mod modifications;
 pub mod service;
 pub mod system;
 
@@ -0,0 +1,465 @@
+// Copyright  rafawo (rafawo1@hotmail.com). All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Planning of the modify requests that reconcile a running compute system
+//! with a new desired document.
+
+use crate::schema;
+use crate::schema::requests::system::{ModifySettingRequest, ResourcePath};
+use crate::schema::requests::ModifyRequestType;
+use crate::schema::virtual_machines::resources::compute::{
+    ProcessorLimits, PROCESSOR_LIMIT_MAX, PROCESSOR_WEIGHT_DEFAULT,
+};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// Modify requests that reconcile a running compute system with a desired document.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct ModificationPlan {
+    /// Requests to send to the compute system, in order.
+    pub requests: Vec<ModifySettingRequest>,
+
+    /// Paths of the document, separated by slashes, that changed in ways
+    /// that can't be applied to a running compute system.
+    pub restart_required: Vec<String>,
+}
+
+impl ModificationPlan {
+    /// Returns true if the compute system needs to be restarted to reach the desired document.
+    pub fn requires_restart(&self) -> bool {
+        !self.restart_required.is_empty()
+    }
+
+    /// Returns true if the compute system already matches the desired document.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.restart_required.is_empty()
+    }
+}
+
+fn modify_request<T: Serialize>(
+    resource_path: String,
+    request_type: ModifyRequestType,
+    settings: Option<&T>,
+) -> ModifySettingRequest {
+    ModifySettingRequest {
+        resource_path,
+        request_type,
+        settings: match settings {
+            Some(settings) => serde_json::to_value(settings).unwrap_or(serde_json::Value::Null),
+            None => serde_json::Value::Null,
+        },
+        guest_request: serde_json::Value::Null,
+    }
+}
+
+/// Returns the keys of both maps whose values differ, in order.
+fn changed_keys<K, V>(current: &HashMap<K, V>, desired: &HashMap<K, V>) -> Vec<K>
+where
+    K: Ord + Clone + std::hash::Hash,
+    V: PartialEq,
+{
+    current
+        .keys()
+        .chain(desired.keys())
+        .cloned()
+        .collect::<BTreeSet<K>>()
+        .into_iter()
+        .filter(|key| current.get(key) != desired.get(key))
+        .collect()
+}
+
+/// Plans the requests for a keyed collection of devices, where a changed device
+/// is removed and added back.
+fn plan_keyed_devices<K, V, F>(
+    current: &HashMap<K, V>,
+    desired: &HashMap<K, V>,
+    resource_path: F,
+    requests: &mut Vec<ModifySettingRequest>,
+) where
+    K: Ord + Clone + std::hash::Hash,
+    V: PartialEq + Serialize,
+    F: Fn(&K) -> String,
+{
+    for key in changed_keys(current, desired) {
+        if current.contains_key(&key) {
+            requests.push(modify_request::<V>(
+                resource_path(&key),
+                ModifyRequestType::Remove,
+                None,
+            ));
+        }
+
+        if let Some(device) = desired.get(&key) {
+            requests.push(modify_request(
+                resource_path(&key),
+                ModifyRequestType::Add,
+                Some(device),
+            ));
+        }
+    }
+}
+
+/// Plans the requests for a collection of shares or directories added and removed
+/// through a single resource path, where a changed item is removed and added back.
+fn plan_collection<T: PartialEq + Serialize>(
+    current: &[T],
+    desired: &[T],
+    resource_path: &str,
+    requests: &mut Vec<ModifySettingRequest>,
+) {
+    for item in current.iter().filter(|item| !desired.contains(item)) {
+        requests.push(modify_request(
+            String::from(resource_path),
+            ModifyRequestType::Remove,
+            Some(item),
+        ));
+    }
+
+    for item in desired.iter().filter(|item| !current.contains(item)) {
+        requests.push(modify_request(
+            String::from(resource_path),
+            ModifyRequestType::Add,
+            Some(item),
+        ));
+    }
+}
+
+/// Plans the requests for the parts of a virtual machine that can be modified while running,
+/// updating `current` to reflect them.
+fn plan_virtual_machine(
+    current: &mut schema::VirtualMachine,
+    desired: &schema::VirtualMachine,
+    requests: &mut Vec<ModifySettingRequest>,
+) {
+    let devices = &mut current.devices;
+
+    // Attachments can only be changed on SCSI controllers that already exist
+    let mut controllers: Vec<String> = devices.scsi.keys().cloned().collect();
+    controllers.sort();
+    for controller in controllers {
+        if let (Some(scsi), Some(desired_scsi)) = (
+            devices.scsi.get_mut(&controller),
+            desired.devices.scsi.get(&controller),
+        ) {
+            plan_keyed_devices(
+                &scsi.attachments,
+                &desired_scsi.attachments,
+                |lun| {
+                    ResourcePath::ScsiAttachment {
+                        controller: controller.clone(),
+                        lun: *lun,
+                    }
+                    .to_string()
+                },
+                requests,
+            );
+            scsi.attachments = desired_scsi.attachments.clone();
+        }
+    }
+
+    for id in changed_keys(&devices.network_adapters, &desired.devices.network_adapters) {
+        let resource_path = ResourcePath::NetworkAdapter { id: id.clone() }.to_string();
+        let request = match (
+            devices.network_adapters.get(&id),
+            desired.devices.network_adapters.get(&id),
+        ) {
+            (Some(_), Some(adapter)) => {
+                modify_request(resource_path, ModifyRequestType::Update, Some(adapter))
+            }
+            (None, Some(adapter)) => {
+                modify_request(resource_path, ModifyRequestType::Add, Some(adapter))
+            }
+            (adapter, None) => modify_request(resource_path, ModifyRequestType::Remove, adapter),
+        };
+        requests.push(request);
+    }
+    devices.network_adapters = desired.devices.network_adapters.clone();
+
+    if let (Some(plan9), Some(desired_plan9)) = (devices.plan9.as_mut(), &desired.devices.plan9) {
+        plan_collection(
+            &plan9.shares,
+            &desired_plan9.shares,
+            &ResourcePath::Plan9Shares.to_string(),
+            requests,
+        );
+        plan9.shares = desired_plan9.shares.clone();
+    }
+
+    if let (Some(virtual_smb), Some(desired_virtual_smb)) =
+        (devices.virtual_smb.as_mut(), &desired.devices.virtual_smb)
+    {
+        plan_collection(
+            &virtual_smb.shares,
+            &desired_virtual_smb.shares,
+            &ResourcePath::VirtualSmbShares.to_string(),
+            requests,
+        );
+        virtual_smb.shares = desired_virtual_smb.shares.clone();
+    }
+
+    if let (Some(virtual_pmem), Some(desired_virtual_pmem)) =
+        (devices.virtual_pmem.as_mut(), &desired.devices.virtual_pmem)
+    {
+        plan_keyed_devices(
+            &virtual_pmem.devices,
+            &desired_virtual_pmem.devices,
+            |index| ResourcePath::VirtualPMemDevice { index: *index }.to_string(),
+            requests,
+        );
+        virtual_pmem.devices = desired_virtual_pmem.devices.clone();
+    }
+
+    let memory = &mut current.compute_topology.memory;
+    let desired_memory = &desired.compute_topology.memory;
+    if memory.size_in_mb != desired_memory.size_in_mb {
+        requests.push(modify_request(
+            ResourcePath::MemorySize.to_string(),
+            ModifyRequestType::Update,
+            Some(&desired_memory.size_in_mb),
+        ));
+        memory.size_in_mb = desired_memory.size_in_mb;
+    }
+
+    let processor = &mut current.compute_topology.processor;
+    let desired_processor = &desired.compute_topology.processor;
+    if processor.limit != desired_processor.limit || processor.weight != desired_processor.weight {
+        // Unset limits are reset explicitly, since zero values are left out of the request
+        requests.push(modify_request(
+            ResourcePath::CpuLimits.to_string(),
+            ModifyRequestType::Update,
+            Some(&ProcessorLimits {
+                limit: desired_processor.limit.unwrap_or(PROCESSOR_LIMIT_MAX),
+                weight: desired_processor.weight.unwrap_or(PROCESSOR_WEIGHT_DEFAULT),
+                ..Default::default()
+            }),
+        ));
+        processor.limit = desired_processor.limit;
+        processor.weight = desired_processor.weight;
+    }
+}
+
+/// Collects the paths of the leaves that differ between two JSON values.
+fn collect_differences(
+    current: &serde_json::Value,
+    desired: &serde_json::Value,
+    path: &str,
+    differences: &mut Vec<String>,
+) {
+    match (current, desired) {
+        (serde_json::Value::Object(current), serde_json::Value::Object(desired)) => {
+            let keys: BTreeSet<&String> = current.keys().chain(desired.keys()).collect();
+            for key in keys {
+                let child_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}/{}", path, key)
+                };
+
+                match (current.get(key), desired.get(key)) {
+                    (Some(current), Some(desired)) => {
+                        collect_differences(current, desired, &child_path, differences)
+                    }
+                    _ => differences.push(child_path),
+                }
+            }
+        }
+        (current, desired) if current != desired => differences.push(String::from(path)),
+        _ => {}
+    }
+}
+
+/// Plans the modify requests that bring a running compute system described by `current`
+/// to the `desired` document.
+///
+/// SCSI attachments, network adapters, mapped directories, Plan9 and VirtualSmb shares,
+/// vPMem devices, memory size and processor limits are modified while running.
+/// Any other difference between the documents is reported as requiring a restart.
+pub fn plan_modifications(
+    current: &schema::ComputeSystem,
+    desired: &schema::ComputeSystem,
+) -> ModificationPlan {
+    let mut requests = Vec::new();
+    let mut planned = current.clone();
+
+    if let (Some(virtual_machine), Some(desired_virtual_machine)) =
+        (planned.virtual_machine.as_mut(), &desired.virtual_machine)
+    {
+        plan_virtual_machine(virtual_machine, desired_virtual_machine, &mut requests);
+    }
+
+    if let (Some(container), Some(desired_container)) =
+        (planned.container.as_mut(), &desired.container)
+    {
+        plan_collection(
+            &container.mapped_directories,
+            &desired_container.mapped_directories,
+            &ResourcePath::MappedDirectories.to_string(),
+            &mut requests,
+        );
+        container.mapped_directories = desired_container.mapped_directories.clone();
+    }
+
+    let mut restart_required = Vec::new();
+    if let (Ok(planned), Ok(desired)) = (
+        serde_json::to_value(&planned),
+        serde_json::to_value(desired),
+    ) {
+        collect_differences(&planned, &desired, "", &mut restart_required);
+    }
+
+    ModificationPlan {
+        requests,
+        restart_required,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::virtual_machines::resources::network::NetworkAdapter;
+    use crate::schema::virtual_machines::resources::storage::{
+        Attachment, Plan9, Plan9Share, Scsi,
+    };
+
+    fn compute_system() -> schema::ComputeSystem {
+        let mut virtual_machine = schema::VirtualMachine::default();
+        virtual_machine.compute_topology.memory.size_in_mb = 1024;
+        virtual_machine.compute_topology.processor.count = 2;
+
+        let mut scsi = Scsi::default();
+        scsi.attachments.insert(
+            0,
+            Attachment {
+                path: String::from("C:\\os.vhdx"),
+                ..Default::default()
+            },
+        );
+        virtual_machine
+            .devices
+            .scsi
+            .insert(String::from("primary"), scsi);
+        virtual_machine.devices.network_adapters.insert(
+            String::from("nic0"),
+            NetworkAdapter {
+                mac_address: Some(String::from("00-15-5D-00-00-01")),
+                ..Default::default()
+            },
+        );
+        virtual_machine.devices.plan9 = Some(Plan9 {
+            shares: vec![Plan9Share {
+                name: String::from("share"),
+                path: String::from("C:\\share"),
+                port: 564,
+                ..Default::default()
+            }],
+        });
+
+        schema::ComputeSystem {
+            owner: String::from("owner"),
+            virtual_machine: Some(virtual_machine),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plan_hot_modifications() {
+        let current = compute_system();
+        assert!(plan_modifications(&current, &current).is_empty());
+
+        let mut desired = current.clone();
+        let virtual_machine = desired.virtual_machine.as_mut().unwrap();
+        virtual_machine.compute_topology.memory.size_in_mb = 2048;
+        virtual_machine.compute_topology.processor.limit = Some(5000);
+        virtual_machine
+            .devices
+            .scsi
+            .get_mut("primary")
+            .unwrap()
+            .attachments
+            .insert(
+                1,
+                Attachment {
+                    path: String::from("C:\\data.vhdx"),
+                    ..Default::default()
+                },
+            );
+        virtual_machine.devices.network_adapters.clear();
+        virtual_machine.devices.plan9.as_mut().unwrap().shares[0].port = 565;
+
+        let plan = plan_modifications(&current, &desired);
+        assert!(!plan.requires_restart());
+
+        let summary: Vec<(String, ModifyRequestType)> = plan
+            .requests
+            .iter()
+            .map(|request| (request.resource_path.clone(), request.request_type.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    String::from("VirtualMachine/Devices/Scsi/primary/Attachments/1"),
+                    ModifyRequestType::Add
+                ),
+                (
+                    String::from("VirtualMachine/Devices/NetworkAdapters/nic0"),
+                    ModifyRequestType::Remove
+                ),
+                (
+                    String::from("VirtualMachine/Devices/Plan9/Shares"),
+                    ModifyRequestType::Remove
+                ),
+                (
+                    String::from("VirtualMachine/Devices/Plan9/Shares"),
+                    ModifyRequestType::Add
+                ),
+                (
+                    ResourcePath::MemorySize.to_string(),
+                    ModifyRequestType::Update
+                ),
+                (
+                    ResourcePath::CpuLimits.to_string(),
+                    ModifyRequestType::Update
+                ),
+            ]
+        );
+        assert_eq!(plan.requests[4].settings, serde_json::json!(2048));
+        assert_eq!(
+            plan.requests[5].settings,
+            serde_json::json!({"Limit": 5000, "Weight": PROCESSOR_WEIGHT_DEFAULT})
+        );
+
+        let plan = plan_modifications(&desired, &current);
+        assert_eq!(
+            plan.requests.last().unwrap().settings,
+            serde_json::json!({"Limit": PROCESSOR_LIMIT_MAX, "Weight": PROCESSOR_WEIGHT_DEFAULT})
+        );
+    }
+
+    #[test]
+    fn plan_restart_required() {
+        let current = compute_system();
+        let mut desired = current.clone();
+        let virtual_machine = desired.virtual_machine.as_mut().unwrap();
+        virtual_machine.compute_topology.processor.count = 4;
+        virtual_machine
+            .devices
+            .scsi
+            .insert(String::from("secondary"), Scsi::default());
+
+        let plan = plan_modifications(&current, &desired);
+        assert!(plan.requests.is_empty());
+        assert_eq!(
+            plan.restart_required,
+            vec![
+                String::from("VirtualMachine/ComputeTopology/Processor/Count"),
+                String::from("VirtualMachine/Devices/Scsi/secondary"),
+            ]
+        );
+    }
+}
@@ -33,6 +33,401 @@ pub struct ModifySettingRequest {
     pub guest_request: serde_json::Value,
 }
 
+/// Known resource paths of a compute system that can be modified through a `ModifySettingRequest`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ResourcePath {
+    /// SCSI attachment at a LUN of a virtual machine SCSI controller.
+    ScsiAttachment { controller: String, lun: u32 },
+
+    /// Virtual machine network adapter, by its identifier.
+    NetworkAdapter { id: String },
+
+    /// Virtual machine VirtualSmb shares, added and removed by name.
+    VirtualSmbShares,
+
+    /// Virtual machine Plan9 shares, added and removed by name.
+    Plan9Shares,
+
+    /// Device of the virtual machine vPMem controller, by its index.
+    VirtualPMemDevice { index: u8 },
+
+    /// Virtual machine memory size in MB.
+    MemorySize,
+
+    /// CPU group the virtual machine is assigned to.
+    CpuGroup,
+
+    /// Processor limits of the virtual machine.
+    CpuLimits,
+
+    /// Entry of the virtual machine hvsocket service table.
+    HvSocketService {
+        service_id: schema::hvsocket::HvSocketServiceId,
+    },
+
+    /// Container mapped directories.
+    MappedDirectories,
+
+    /// Container mapped pipes. Virtual machines map each pipe on its own path,
+    /// see `VirtualMachineMappedPipe`.
+    MappedPipes,
+
+    /// Pipe of the host mapped into a virtual machine, by its name.
+    /// Its settings are the host path of the pipe.
+    VirtualMachineMappedPipe { name: String },
+
+    /// Container credential guard state.
+    ContainerCredentialGuard,
+}
+
+/// Errors on resource paths and their typed settings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourcePathError {
+    /// The resource path is not one of the known resource paths.
+    UnknownPath(String),
+
+    /// The settings don't have the type expected by the resource path.
+    SettingsMismatch(ResourcePath),
+
+    /// The settings can't be decoded into the type expected by the resource path.
+    InvalidSettings(ResourcePath, String),
+}
+
+impl std::fmt::Display for ResourcePathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResourcePathError::UnknownPath(path) => write!(f, "unknown resource path {}", path),
+            ResourcePathError::SettingsMismatch(path) => {
+                write!(f, "settings type doesn't match resource path {}", path)
+            }
+            ResourcePathError::InvalidSettings(path, error) => {
+                write!(f, "invalid settings for resource path {}: {}", path, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResourcePathError {}
+
+impl std::fmt::Display for ResourcePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResourcePath::ScsiAttachment { controller, lun } => write!(
+                f,
+                "VirtualMachine/Devices/Scsi/{}/Attachments/{}",
+                controller, lun
+            ),
+            ResourcePath::NetworkAdapter { id } => {
+                write!(f, "VirtualMachine/Devices/NetworkAdapters/{}", id)
+            }
+            ResourcePath::VirtualSmbShares => write!(f, "VirtualMachine/Devices/VirtualSmb/Shares"),
+            ResourcePath::Plan9Shares => write!(f, "VirtualMachine/Devices/Plan9/Shares"),
+            ResourcePath::VirtualPMemDevice { index } => {
+                write!(f, "VirtualMachine/Devices/VirtualPMem/Devices/{}", index)
+            }
+            ResourcePath::MemorySize => write!(f, "VirtualMachine/ComputeTopology/Memory/SizeInMB"),
+            ResourcePath::CpuGroup => {
+                write!(f, "VirtualMachine/ComputeTopology/Processor/CpuGroup")
+            }
+            ResourcePath::CpuLimits => {
+                write!(f, "VirtualMachine/ComputeTopology/Processor/Limits")
+            }
+            ResourcePath::HvSocketService { service_id } => write!(
+                f,
+                "VirtualMachine/Devices/HvSocket/HvSocketConfig/ServiceTable/{}",
+                service_id
+            ),
+            ResourcePath::MappedDirectories => write!(f, "Container/MappedDirectories"),
+            ResourcePath::MappedPipes => write!(f, "Container/MappedPipes"),
+            ResourcePath::VirtualMachineMappedPipe { name } => {
+                write!(f, "VirtualMachine/Devices/MappedPipes/{}", name)
+            }
+            ResourcePath::ContainerCredentialGuard => {
+                write!(f, "Container/ContainerCredentialGuard")
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ResourcePath {
+    type Err = ResourcePathError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let unknown = || ResourcePathError::UnknownPath(String::from(path));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match segments.as_slice() {
+            ["VirtualMachine", "Devices", "Scsi", controller, "Attachments", lun] => {
+                Ok(ResourcePath::ScsiAttachment {
+                    controller: controller.to_string(),
+                    lun: lun.parse().map_err(|_| unknown())?,
+                })
+            }
+            ["VirtualMachine", "Devices", "NetworkAdapters", id] => {
+                Ok(ResourcePath::NetworkAdapter { id: id.to_string() })
+            }
+            ["VirtualMachine", "Devices", "VirtualSmb", "Shares"] => {
+                Ok(ResourcePath::VirtualSmbShares)
+            }
+            ["VirtualMachine", "Devices", "Plan9", "Shares"] => Ok(ResourcePath::Plan9Shares),
+            ["VirtualMachine", "Devices", "VirtualPMem", "Devices", index] => {
+                Ok(ResourcePath::VirtualPMemDevice {
+                    index: index.parse().map_err(|_| unknown())?,
+                })
+            }
+            ["VirtualMachine", "ComputeTopology", "Memory", "SizeInMB"] => {
+                Ok(ResourcePath::MemorySize)
+            }
+            ["VirtualMachine", "ComputeTopology", "Processor", "CpuGroup"] => {
+                Ok(ResourcePath::CpuGroup)
+            }
+            ["VirtualMachine", "ComputeTopology", "Processor", "Limits"] => {
+                Ok(ResourcePath::CpuLimits)
+            }
+            ["VirtualMachine", "Devices", "HvSocket", "HvSocketConfig", "ServiceTable", service_id] => {
+                Ok(ResourcePath::HvSocketService {
+                    service_id: schema::hvsocket::HvSocketServiceId(
+                        schema::utils::GuidSerde::from_str(service_id).map_err(|_| unknown())?,
+                    ),
+                })
+            }
+            ["Container", "MappedDirectories"] => Ok(ResourcePath::MappedDirectories),
+            ["Container", "MappedPipes"] => Ok(ResourcePath::MappedPipes),
+            ["VirtualMachine", "Devices", "MappedPipes", name] => {
+                Ok(ResourcePath::VirtualMachineMappedPipe {
+                    name: name.to_string(),
+                })
+            }
+            ["Container", "ContainerCredentialGuard"] => Ok(ResourcePath::ContainerCredentialGuard),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+/// Typed settings of a `ModifySettingRequest`, one variant per kind of resource path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceSettings {
+    ScsiAttachment(schema::virtual_machines::resources::storage::Attachment),
+    NetworkAdapter(schema::virtual_machines::resources::network::NetworkAdapter),
+    VirtualSmbShare(schema::virtual_machines::resources::storage::VirtualSmbShare),
+    Plan9Share(schema::virtual_machines::resources::storage::Plan9Share),
+    VirtualPMemDevice(schema::virtual_machines::resources::storage::VirtualPMemDevice),
+    MemorySize(u64),
+    CpuGroup(schema::virtual_machines::resources::compute::CpuGroup),
+    CpuLimits(schema::virtual_machines::resources::compute::ProcessorLimits),
+    HvSocketService(schema::hvsocket::HvSocketServiceConfig),
+    MappedDirectory(schema::containers::resources::MappedDirectory),
+    MappedPipe(schema::containers::resources::MappedPipe),
+    VirtualMachineMappedPipe(String),
+    ContainerCredentialGuard(schema::containers::credential_guard::CcgState),
+}
+
+impl ResourceSettings {
+    /// Returns true if the settings have the type expected by the resource path.
+    pub fn matches(&self, resource_path: &ResourcePath) -> bool {
+        matches!(
+            (self, resource_path),
+            (
+                ResourceSettings::ScsiAttachment(_),
+                ResourcePath::ScsiAttachment { .. }
+            ) | (
+                ResourceSettings::NetworkAdapter(_),
+                ResourcePath::NetworkAdapter { .. }
+            ) | (
+                ResourceSettings::VirtualSmbShare(_),
+                ResourcePath::VirtualSmbShares
+            ) | (ResourceSettings::Plan9Share(_), ResourcePath::Plan9Shares)
+                | (
+                    ResourceSettings::VirtualPMemDevice(_),
+                    ResourcePath::VirtualPMemDevice { .. }
+                )
+                | (ResourceSettings::MemorySize(_), ResourcePath::MemorySize)
+                | (ResourceSettings::CpuGroup(_), ResourcePath::CpuGroup)
+                | (ResourceSettings::CpuLimits(_), ResourcePath::CpuLimits)
+                | (
+                    ResourceSettings::HvSocketService(_),
+                    ResourcePath::HvSocketService { .. }
+                )
+                | (
+                    ResourceSettings::MappedDirectory(_),
+                    ResourcePath::MappedDirectories
+                )
+                | (ResourceSettings::MappedPipe(_), ResourcePath::MappedPipes)
+                | (
+                    ResourceSettings::VirtualMachineMappedPipe(_),
+                    ResourcePath::VirtualMachineMappedPipe { .. }
+                )
+                | (
+                    ResourceSettings::ContainerCredentialGuard(_),
+                    ResourcePath::ContainerCredentialGuard
+                )
+        )
+    }
+
+    /// Returns the settings as a JSON value.
+    pub fn to_value(&self) -> serde_json::Result<serde_json::Value> {
+        match self {
+            ResourceSettings::ScsiAttachment(settings) => serde_json::to_value(settings),
+            ResourceSettings::NetworkAdapter(settings) => serde_json::to_value(settings),
+            ResourceSettings::VirtualSmbShare(settings) => serde_json::to_value(settings),
+            ResourceSettings::Plan9Share(settings) => serde_json::to_value(settings),
+            ResourceSettings::VirtualPMemDevice(settings) => serde_json::to_value(settings),
+            ResourceSettings::MemorySize(settings) => serde_json::to_value(settings),
+            ResourceSettings::CpuGroup(settings) => serde_json::to_value(settings),
+            ResourceSettings::CpuLimits(settings) => serde_json::to_value(settings),
+            ResourceSettings::HvSocketService(settings) => serde_json::to_value(settings),
+            ResourceSettings::MappedDirectory(settings) => serde_json::to_value(settings),
+            ResourceSettings::MappedPipe(settings) => serde_json::to_value(settings),
+            ResourceSettings::VirtualMachineMappedPipe(settings) => serde_json::to_value(settings),
+            ResourceSettings::ContainerCredentialGuard(settings) => serde_json::to_value(settings),
+        }
+    }
+
+    /// Decodes settings from a JSON value, into the type expected by the resource path.
+    pub fn from_value(
+        resource_path: &ResourcePath,
+        value: serde_json::Value,
+    ) -> serde_json::Result<ResourceSettings> {
+        Ok(match resource_path {
+            ResourcePath::ScsiAttachment { .. } => {
+                ResourceSettings::ScsiAttachment(serde_json::from_value(value)?)
+            }
+            ResourcePath::NetworkAdapter { .. } => {
+                ResourceSettings::NetworkAdapter(serde_json::from_value(value)?)
+            }
+            ResourcePath::VirtualSmbShares => {
+                ResourceSettings::VirtualSmbShare(serde_json::from_value(value)?)
+            }
+            ResourcePath::Plan9Shares => {
+                ResourceSettings::Plan9Share(serde_json::from_value(value)?)
+            }
+            ResourcePath::VirtualPMemDevice { .. } => {
+                ResourceSettings::VirtualPMemDevice(serde_json::from_value(value)?)
+            }
+            ResourcePath::MemorySize => {
+                ResourceSettings::MemorySize(serde_json::from_value(value)?)
+            }
+            ResourcePath::CpuGroup => ResourceSettings::CpuGroup(serde_json::from_value(value)?),
+            ResourcePath::CpuLimits => ResourceSettings::CpuLimits(serde_json::from_value(value)?),
+            ResourcePath::HvSocketService { .. } => {
+                ResourceSettings::HvSocketService(serde_json::from_value(value)?)
+            }
+            ResourcePath::MappedDirectories => {
+                ResourceSettings::MappedDirectory(serde_json::from_value(value)?)
+            }
+            ResourcePath::MappedPipes => {
+                ResourceSettings::MappedPipe(serde_json::from_value(value)?)
+            }
+            ResourcePath::VirtualMachineMappedPipe { .. } => {
+                ResourceSettings::VirtualMachineMappedPipe(serde_json::from_value(value)?)
+            }
+            ResourcePath::ContainerCredentialGuard => {
+                ResourceSettings::ContainerCredentialGuard(serde_json::from_value(value)?)
+            }
+        })
+    }
+}
+
+impl ModifySettingRequest {
+    /// Creates a request on a known resource path, checking that the settings
+    /// have the type expected by it.
+    pub fn with_resource(
+        resource_path: ResourcePath,
+        request_type: schema::requests::ModifyRequestType,
+        settings: Option<ResourceSettings>,
+    ) -> Result<ModifySettingRequest, ResourcePathError> {
+        let settings = match settings {
+            Some(settings) if !settings.matches(&resource_path) => {
+                return Err(ResourcePathError::SettingsMismatch(resource_path));
+            }
+            Some(settings) => settings.to_value().map_err(|error| {
+                ResourcePathError::InvalidSettings(resource_path.clone(), error.to_string())
+            })?,
+            None => serde_json::Value::Null,
+        };
+
+        Ok(ModifySettingRequest {
+            resource_path: resource_path.to_string(),
+            request_type,
+            settings,
+            guest_request: serde_json::Value::Null,
+        })
+    }
+
+    pub fn add(
+        resource_path: ResourcePath,
+        settings: ResourceSettings,
+    ) -> Result<ModifySettingRequest, ResourcePathError> {
+        ModifySettingRequest::with_resource(
+            resource_path,
+            schema::requests::ModifyRequestType::Add,
+            Some(settings),
+        )
+    }
+
+    pub fn update(
+        resource_path: ResourcePath,
+        settings: ResourceSettings,
+    ) -> Result<ModifySettingRequest, ResourcePathError> {
+        ModifySettingRequest::with_resource(
+            resource_path,
+            schema::requests::ModifyRequestType::Update,
+            Some(settings),
+        )
+    }
+
+    pub fn remove(
+        resource_path: ResourcePath,
+        settings: Option<ResourceSettings>,
+    ) -> Result<ModifySettingRequest, ResourcePathError> {
+        ModifySettingRequest::with_resource(
+            resource_path,
+            schema::requests::ModifyRequestType::Remove,
+            settings,
+        )
+    }
+
+    /// Parses the resource path of the request.
+    pub fn parse_resource_path(&self) -> Result<ResourcePath, ResourcePathError> {
+        self.resource_path.parse()
+    }
+
+    /// Decodes the settings of the request into the type expected by its resource path.
+    pub fn typed_settings(&self) -> Result<Option<ResourceSettings>, ResourcePathError> {
+        let resource_path = self.parse_resource_path()?;
+
+        if self.settings.is_null() {
+            return Ok(None);
+        }
+
+        ResourceSettings::from_value(&resource_path, self.settings.clone())
+            .map(Some)
+            .map_err(|error| ResourcePathError::InvalidSettings(resource_path, error.to_string()))
+    }
+
+    /// Embeds a guest modify request into this host request.
+    pub fn with_guest_request(
+        mut self,
+        request_type: schema::requests::ModifyRequestType,
+        guest_request: schema::requests::guest::GuestRequest,
+    ) -> serde_json::Result<Self> {
+        self.guest_request =
+            serde_json::to_value(guest_request.into_modify_request(request_type)?)?;
+        Ok(self)
+    }
+
+    /// Decodes the guest modify request embedded into this host request, if any.
+    pub fn typed_guest_request(
+        &self,
+    ) -> serde_json::Result<Option<schema::requests::guest::GuestModifySettingRequest>> {
+        if self.guest_request.is_null() {
+            return Ok(None);
+        }
+
+        serde_json::from_value(self.guest_request.clone()).map(Some)
+    }
+}
+
 impl std::default::Default for PropertyType {
     fn default() -> Self {
         PropertyType::Memory
@@ -74,3 +469,92 @@ pub struct SystemQuery {
     #[serde(default, rename = "Owners", skip_serializing_if = "Vec::is_empty")]
     pub owners: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::virtual_machines::resources::storage::Attachment;
+
+    #[test]
+    fn resource_paths() {
+        let paths = vec![
+            ResourcePath::ScsiAttachment {
+                controller: String::from("0"),
+                lun: 1,
+            },
+            ResourcePath::NetworkAdapter {
+                id: String::from("nic"),
+            },
+            ResourcePath::VirtualSmbShares,
+            ResourcePath::Plan9Shares,
+            ResourcePath::VirtualPMemDevice { index: 3 },
+            ResourcePath::MemorySize,
+            ResourcePath::CpuGroup,
+            ResourcePath::CpuLimits,
+            ResourcePath::HvSocketService {
+                service_id: schema::hvsocket::HvSocketServiceId::from_vsock_port(80),
+            },
+            ResourcePath::MappedDirectories,
+            ResourcePath::MappedPipes,
+            ResourcePath::VirtualMachineMappedPipe {
+                name: String::from("docker_engine"),
+            },
+            ResourcePath::ContainerCredentialGuard,
+        ];
+
+        for path in paths {
+            assert_eq!(path.to_string().parse::<ResourcePath>(), Ok(path));
+        }
+
+        assert_eq!(
+            "VirtualMachine/Devices/Scsi/0/Attachments/1"
+                .parse::<ResourcePath>()
+                .unwrap(),
+            ResourcePath::ScsiAttachment {
+                controller: String::from("0"),
+                lun: 1,
+            }
+        );
+        assert_eq!(
+            ResourcePath::CpuLimits.to_string(),
+            "VirtualMachine/ComputeTopology/Processor/Limits"
+        );
+        assert_eq!(
+            "VirtualMachine/Devices/Scsi/0/Attachment/1".parse::<ResourcePath>(),
+            Err(ResourcePathError::UnknownPath(String::from(
+                "VirtualMachine/Devices/Scsi/0/Attachment/1"
+            )))
+        );
+    }
+
+    #[test]
+    fn typed_modify_setting_requests() {
+        let path = ResourcePath::ScsiAttachment {
+            controller: String::from("0"),
+            lun: 1,
+        };
+        let attachment = Attachment {
+            path: String::from("C:\\disk.vhdx"),
+            ..Default::default()
+        };
+
+        let request = ModifySettingRequest::add(
+            path.clone(),
+            ResourceSettings::ScsiAttachment(attachment.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            request.resource_path,
+            "VirtualMachine/Devices/Scsi/0/Attachments/1"
+        );
+        assert_eq!(
+            request.typed_settings(),
+            Ok(Some(ResourceSettings::ScsiAttachment(attachment)))
+        );
+
+        assert_eq!(
+            ModifySettingRequest::update(path.clone(), ResourceSettings::MemorySize(1024)),
+            Err(ResourcePathError::SettingsMismatch(path))
+        );
+    }
+}
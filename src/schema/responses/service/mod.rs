@@ -7,6 +7,7 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 use crate::schema;
+use crate::schema::requests::service::PropertyType;
 use crate::schema::utils::is_default;
 use serde::{Deserialize, Serialize};
 
@@ -26,6 +27,14 @@ pub struct BasicInformation {
     pub supported_schema_versions: Vec<schema::Version>,
 }
 
+impl BasicInformation {
+    /// Returns the schema version documents sent to the host should target,
+    /// which is the highest supported by both the host and this crate.
+    pub fn negotiate_schema_version(&self) -> Option<schema::Version> {
+        schema::versioning::negotiate_schema_version(&self.supported_schema_versions)
+    }
+}
+
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct QoSCapabilities {
     #[serde(
@@ -36,6 +45,190 @@ pub struct QoSCapabilities {
     pub processor_qo_s_supported: bool,
 }
 
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct LogicalProcessor {
+    #[serde(default, rename = "LpIndex", skip_serializing_if = "is_default")]
+    pub lp_index: u32,
+
+    #[serde(default, rename = "NodeNumber", skip_serializing_if = "is_default")]
+    pub node_number: u8,
+
+    #[serde(default, rename = "PackageId", skip_serializing_if = "is_default")]
+    pub package_id: u32,
+
+    #[serde(default, rename = "CoreId", skip_serializing_if = "is_default")]
+    pub core_id: u32,
+
+    #[serde(default, rename = "RootVpIndex", skip_serializing_if = "is_default")]
+    pub root_vp_index: i32,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ProcessorTopology {
+    #[serde(
+        default,
+        rename = "LogicalProcessorCount",
+        skip_serializing_if = "is_default"
+    )]
+    pub logical_processor_count: u32,
+
+    #[serde(
+        default,
+        rename = "LogicalProcessors",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub logical_processors: Vec<LogicalProcessor>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CpuGroupAffinity {
+    #[serde(rename = "LogicalProcessorCount")]
+    pub logical_processor_count: i32,
+
+    #[serde(rename = "LogicalProcessors")]
+    pub logical_processors: Vec<u32>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CpuGroupProperty {
+    #[serde(rename = "PropertyCode")]
+    pub property_code: u32,
+
+    #[serde(rename = "PropertyValue")]
+    pub property_value: u32,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CpuGroupConfig {
+    #[serde(default, rename = "GroupId", skip_serializing_if = "is_default")]
+    pub group_id: schema::utils::GuidSerde,
+
+    #[serde(default, rename = "Affinity", skip_serializing_if = "is_default")]
+    pub affinity: Option<CpuGroupAffinity>,
+
+    #[serde(
+        default,
+        rename = "GroupProperties",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub group_properties: Vec<CpuGroupProperty>,
+
+    #[serde(
+        default,
+        rename = "HypervisorGroupId",
+        skip_serializing_if = "is_default"
+    )]
+    pub hypervisor_group_id: u64,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CpuGroupConfigurations {
+    #[serde(default, rename = "CpuGroups", skip_serializing_if = "Vec::is_empty")]
+    pub cpu_groups: Vec<CpuGroupConfig>,
+}
+
+/// Service properties decoded into their types, according to the property types
+/// that were queried. Property types without a typed representation are kept
+/// as raw JSON values in `other`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TypedServiceProperties {
+    pub basic: Option<BasicInformation>,
+    pub processor_topology: Option<ProcessorTopology>,
+    pub cpu_groups: Option<CpuGroupConfigurations>,
+    pub container_credential_guard: Option<schema::containers::credential_guard::CcgSystemInfo>,
+    pub qos_capabilities: Option<QoSCapabilities>,
+    pub other: Vec<(PropertyType, serde_json::Value)>,
+}
+
+impl TypedServiceProperties {
+    /// Decodes the properties returned by a query of the given property types.
+    /// The service returns one entry per queried property type, in the same order.
+    pub fn decode(
+        property_types: &[PropertyType],
+        service_properties: &ServiceProperties,
+    ) -> serde_json::Result<TypedServiceProperties> {
+        let mut properties = TypedServiceProperties::default();
+
+        for (property_type, value) in property_types
+            .iter()
+            .zip(service_properties.properties.iter())
+        {
+            let value = value.clone();
+
+            match property_type {
+                PropertyType::Basic => properties.basic = Some(serde_json::from_value(value)?),
+                PropertyType::ProcessorTopology => {
+                    properties.processor_topology = Some(serde_json::from_value(value)?)
+                }
+                PropertyType::CpuGroup => {
+                    properties.cpu_groups = Some(serde_json::from_value(value)?)
+                }
+                PropertyType::ContainerCredentialGuard => {
+                    properties.container_credential_guard = Some(serde_json::from_value(value)?)
+                }
+                PropertyType::QoSCapabilities => {
+                    properties.qos_capabilities = Some(serde_json::from_value(value)?)
+                }
+                other => properties.other.push((other.clone(), value)),
+            }
+        }
+
+        Ok(properties)
+    }
+}
+
+/// Summary of the capabilities of the host, out of its service properties.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct HostCapabilities {
+    pub supported_schema_versions: Vec<schema::Version>,
+    pub max_schema_version: Option<schema::Version>,
+    pub processor_qos_supported: bool,
+    pub container_credential_guard_supported: bool,
+}
+
+impl HostCapabilities {
+    /// Property types that need to be queried to build the host capabilities.
+    pub fn property_types() -> Vec<PropertyType> {
+        vec![
+            PropertyType::Basic,
+            PropertyType::QoSCapabilities,
+            PropertyType::ContainerCredentialGuard,
+        ]
+    }
+
+    pub fn from_properties(properties: &TypedServiceProperties) -> HostCapabilities {
+        let supported_schema_versions = match &properties.basic {
+            Some(basic) => basic.supported_schema_versions.clone(),
+            None => Vec::new(),
+        };
+
+        let max_schema_version = supported_schema_versions.iter().max().cloned();
+
+        HostCapabilities {
+            supported_schema_versions,
+            max_schema_version,
+            processor_qos_supported: matches!(
+                properties.qos_capabilities,
+                Some(QoSCapabilities {
+                    processor_qo_s_supported: true
+                })
+            ),
+            container_credential_guard_supported: properties.container_credential_guard.is_some(),
+        }
+    }
+
+    /// Returns true if the host supports the given schema version.
+    pub fn supports_schema_version(&self, version: &schema::Version) -> bool {
+        self.supported_schema_versions.contains(version)
+    }
+
+    /// Returns the schema version documents sent to the host should target,
+    /// which is the highest supported by both the host and this crate.
+    pub fn target_schema_version(&self) -> Option<schema::Version> {
+        schema::versioning::negotiate_schema_version(&self.supported_schema_versions)
+    }
+}
+
 impl std::default::Default for EventDataType {
     fn default() -> Self {
         EventDataType::Empty
@@ -110,3 +303,68 @@ pub struct ResultError {
     #[serde(default, rename = "ErrorEvents", skip_serializing_if = "Vec::is_empty")]
     pub error_events: Vec<ErrorEvent>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_service_properties() {
+        let property_types = HostCapabilities::property_types();
+        let service_properties: ServiceProperties = serde_json::from_str(
+            r#"{"Properties":[{"SupportedSchemaVersions":[{"Major":2,"Minor":1},{"Major":2,"Minor":2}]},{"ProcessorQoSSupported":true},{"Instances":[]}]}"#,
+        )
+        .unwrap();
+
+        let properties =
+            TypedServiceProperties::decode(&property_types, &service_properties).unwrap();
+        assert_eq!(
+            properties
+                .container_credential_guard
+                .as_ref()
+                .unwrap()
+                .instances
+                .len(),
+            0
+        );
+
+        let capabilities = HostCapabilities::from_properties(&properties);
+        assert_eq!(
+            capabilities.max_schema_version,
+            Some(schema::Version { major: 2, minor: 2 })
+        );
+        assert!(capabilities.supports_schema_version(&schema::Version::schema_version_rs5()));
+        assert!(capabilities.processor_qos_supported);
+        assert!(capabilities.container_credential_guard_supported);
+
+        let service_properties: ServiceProperties = serde_json::from_str(
+            r#"{"Properties":[{"LogicalProcessorCount":2,"LogicalProcessors":[{"CoreId":1},{"LpIndex":1,"CoreId":1}]},{"Foo":1}]}"#,
+        )
+        .unwrap();
+        let properties = TypedServiceProperties::decode(
+            &[PropertyType::ProcessorTopology, PropertyType::Memory],
+            &service_properties,
+        )
+        .unwrap();
+        assert_eq!(
+            properties
+                .processor_topology
+                .as_ref()
+                .unwrap()
+                .logical_processors[1],
+            LogicalProcessor {
+                lp_index: 1,
+                core_id: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            properties.other,
+            vec![(PropertyType::Memory, serde_json::json!({"Foo": 1}))]
+        );
+        assert_eq!(
+            HostCapabilities::from_properties(&properties),
+            HostCapabilities::default()
+        );
+    }
+}
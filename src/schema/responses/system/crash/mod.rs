@@ -0,0 +1,617 @@
+// Copyright  rafawo (rafawo1@hotmail.com). All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Analysis of `CrashReport` notifications: symbolic bugcheck codes,
+//! the header of the kernel dump file and a human readable report.
+
+use crate::schema::responses::system::{CrashReport, WindowsCrashReport};
+
+/// Known bugcheck code, with the meaning of its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BugCheckInfo {
+    pub code: u32,
+    pub name: &'static str,
+    pub parameters: [&'static str; 4],
+}
+
+const BUGCHECKS: &[BugCheckInfo] = &[
+    BugCheckInfo {
+        code: 0x0A,
+        name: "IRQL_NOT_LESS_OR_EQUAL",
+        parameters: [
+            "Memory referenced",
+            "IRQL at the time of the reference",
+            "Access type (0 read, 1 write, 8 execute)",
+            "Address that referenced memory",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x1A,
+        name: "MEMORY_MANAGEMENT",
+        parameters: [
+            "Memory management violation subtype",
+            "Subtype specific",
+            "Subtype specific",
+            "Subtype specific",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x1E,
+        name: "KMODE_EXCEPTION_NOT_HANDLED",
+        parameters: [
+            "Exception code",
+            "Address where the exception occurred",
+            "First parameter of the exception",
+            "Second parameter of the exception",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x3B,
+        name: "SYSTEM_SERVICE_EXCEPTION",
+        parameters: [
+            "Exception code",
+            "Address of the faulting instruction",
+            "Address of the context record",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x50,
+        name: "PAGE_FAULT_IN_NONPAGED_AREA",
+        parameters: [
+            "Memory referenced",
+            "Access type (0 read, 1 write, 2 or 10 execute)",
+            "Address that referenced memory",
+            "Type of page fault",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x7B,
+        name: "INACCESSIBLE_BOOT_DEVICE",
+        parameters: [
+            "Address of the device object or ARC name",
+            "Status code",
+            "Reserved",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x7E,
+        name: "SYSTEM_THREAD_EXCEPTION_NOT_HANDLED",
+        parameters: [
+            "Exception code",
+            "Address where the exception occurred",
+            "Address of the exception record",
+            "Address of the context record",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x7F,
+        name: "UNEXPECTED_KERNEL_MODE_TRAP",
+        parameters: ["Trap number", "Reserved", "Reserved", "Reserved"],
+    },
+    BugCheckInfo {
+        code: 0x9F,
+        name: "DRIVER_POWER_STATE_FAILURE",
+        parameters: [
+            "Violation subtype",
+            "Subtype specific",
+            "Subtype specific",
+            "Subtype specific",
+        ],
+    },
+    BugCheckInfo {
+        code: 0xC2,
+        name: "BAD_POOL_CALLER",
+        parameters: [
+            "Violation subtype",
+            "Subtype specific",
+            "Subtype specific",
+            "Subtype specific",
+        ],
+    },
+    BugCheckInfo {
+        code: 0xD1,
+        name: "DRIVER_IRQL_NOT_LESS_OR_EQUAL",
+        parameters: [
+            "Memory referenced",
+            "IRQL at the time of the reference",
+            "Access type (0 read, 1 write, 8 execute)",
+            "Address that referenced memory",
+        ],
+    },
+    BugCheckInfo {
+        code: 0xE2,
+        name: "MANUALLY_INITIATED_CRASH",
+        parameters: ["Reserved", "Reserved", "Reserved", "Reserved"],
+    },
+    BugCheckInfo {
+        code: 0xEF,
+        name: "CRITICAL_PROCESS_DIED",
+        parameters: [
+            "Process object",
+            "Termination kind (0 process, 1 thread)",
+            "Reserved",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0xF4,
+        name: "CRITICAL_OBJECT_TERMINATION",
+        parameters: [
+            "Object type (3 process, 6 thread)",
+            "Terminating object",
+            "Process image file name",
+            "Explanatory message",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x101,
+        name: "CLOCK_WATCHDOG_TIMEOUT",
+        parameters: [
+            "Clock interrupt time-out interval, in ticks",
+            "Reserved",
+            "Address of the PRCB of the unresponsive processor",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x124,
+        name: "WHEA_UNCORRECTABLE_ERROR",
+        parameters: [
+            "Error source type",
+            "Address of the WHEA_ERROR_RECORD",
+            "High 32 bits of the MCi_STATUS MSR",
+            "Low 32 bits of the MCi_STATUS MSR",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x133,
+        name: "DPC_WATCHDOG_VIOLATION",
+        parameters: [
+            "Violation kind (0 single DPC, 1 cumulative)",
+            "DPC time count, in ticks",
+            "DPC time allotment, in ticks",
+            "Address of the DPC watchdog triage block",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x139,
+        name: "KERNEL_SECURITY_CHECK_FAILURE",
+        parameters: [
+            "Type of corruption",
+            "Address of the trap frame",
+            "Address of the exception record",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x13A,
+        name: "KERNEL_MODE_HEAP_CORRUPTION",
+        parameters: [
+            "Type of corruption",
+            "Address of the heap",
+            "Address of the corrupted entry",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0x20001,
+        name: "HYPERVISOR_ERROR",
+        parameters: ["Reserved", "Reserved", "Reserved", "Reserved"],
+    },
+    BugCheckInfo {
+        code: 0xC000021A,
+        name: "WINLOGON_FATAL_ERROR",
+        parameters: [
+            "Address of the status message",
+            "Error code",
+            "Reserved",
+            "Reserved",
+        ],
+    },
+    BugCheckInfo {
+        code: 0xDEADDEAD,
+        name: "MANUALLY_INITIATED_CRASH1",
+        parameters: ["Reserved", "Reserved", "Reserved", "Reserved"],
+    },
+];
+
+/// Returns the symbolic name and parameter meanings of a bugcheck code, if known.
+pub fn bugcheck_info(code: u32) -> Option<&'static BugCheckInfo> {
+    BUGCHECKS.iter().find(|info| info.code == code)
+}
+
+/// Bugcheck parameter, with its meaning if the bugcheck code is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BugCheckParameter {
+    pub value: u64,
+    pub meaning: Option<&'static str>,
+}
+
+/// Bugcheck decoded from the crash parameters of a report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BugCheck {
+    pub code: u64,
+    pub name: Option<&'static str>,
+    pub parameters: Vec<BugCheckParameter>,
+}
+
+impl BugCheck {
+    /// Decodes crash parameters, where the first one is the bugcheck code
+    /// and the following ones its parameters.
+    pub fn from_crash_parameters(crash_parameters: &[u64]) -> Option<BugCheck> {
+        let (code, parameters) = crash_parameters.split_first()?;
+
+        let info = std::convert::TryFrom::try_from(*code)
+            .ok()
+            .and_then(bugcheck_info);
+
+        Some(BugCheck {
+            code: *code,
+            name: info.map(|info| info.name),
+            parameters: parameters
+                .iter()
+                .enumerate()
+                .map(|(index, value)| BugCheckParameter {
+                    value: *value,
+                    meaning: info.and_then(|info| info.parameters.get(index).cloned()),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Size of the header of a 64-bit kernel dump file.
+pub const DUMP_HEADER64_SIZE: usize = 0x2000;
+
+const DUMP_SIGNATURE: u32 = 0x4547_4150; // "PAGE"
+const DUMP_VALID_DUMP64: u32 = 0x3436_5544; // "DU64"
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpType {
+    Full,
+    Summary,
+    Header,
+    Triage,
+    BitmapFull,
+    BitmapKernel,
+    Automatic,
+    Unknown(u32),
+}
+
+impl std::convert::From<u32> for DumpType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => DumpType::Full,
+            2 => DumpType::Summary,
+            3 => DumpType::Header,
+            4 => DumpType::Triage,
+            5 => DumpType::BitmapFull,
+            6 => DumpType::BitmapKernel,
+            7 => DumpType::Automatic,
+            other => DumpType::Unknown(other),
+        }
+    }
+}
+
+/// Fields of the DUMP_HEADER64 structure at the start of a 64-bit kernel dump file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpHeader64 {
+    pub major_version: u32,
+    /// Build number of the crashed OS.
+    pub minor_version: u32,
+    pub directory_table_base: u64,
+    pub pfn_database: u64,
+    pub ps_loaded_module_list: u64,
+    pub ps_active_process_head: u64,
+    pub machine_image_type: u32,
+    pub number_processors: u32,
+    pub bugcheck_code: u32,
+    pub bugcheck_parameters: [u64; 4],
+    /// Kernel version string written by the crashed OS, if any.
+    pub version_user: String,
+    pub kd_debugger_data_block: u64,
+    pub dump_type: DumpType,
+    pub required_dump_space: u64,
+    /// Crash time, as a FILETIME.
+    pub system_time: u64,
+    pub comment: String,
+    /// Uptime of the crashed OS, in 100ns units.
+    pub system_up_time: u64,
+    pub product_type: u32,
+    pub suite_mask: u32,
+}
+
+#[derive(Debug)]
+pub enum DumpHeaderError {
+    Io(std::io::Error),
+    /// The buffer is smaller than a DUMP_HEADER64.
+    TooShort(usize),
+    /// The buffer doesn't start with the signature of a 64-bit kernel dump.
+    InvalidSignature,
+}
+
+impl std::fmt::Display for DumpHeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DumpHeaderError::Io(error) => write!(f, "{}", error),
+            DumpHeaderError::TooShort(size) => write!(
+                f,
+                "dump header is {} bytes, expected {}",
+                size, DUMP_HEADER64_SIZE
+            ),
+            DumpHeaderError::InvalidSignature => write!(f, "not a 64-bit kernel dump file"),
+        }
+    }
+}
+
+impl std::error::Error for DumpHeaderError {}
+
+impl std::convert::From<std::io::Error> for DumpHeaderError {
+    fn from(error: std::io::Error) -> Self {
+        DumpHeaderError::Io(error)
+    }
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buffer[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_string(buffer: &[u8], offset: usize, size: usize) -> String {
+    let bytes = &buffer[offset..offset + size];
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(size);
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+impl DumpHeader64 {
+    /// Parses the header out of the first `DUMP_HEADER64_SIZE` bytes of a kernel dump file.
+    pub fn parse(buffer: &[u8]) -> Result<DumpHeader64, DumpHeaderError> {
+        if buffer.len() < DUMP_HEADER64_SIZE {
+            return Err(DumpHeaderError::TooShort(buffer.len()));
+        }
+
+        if read_u32(buffer, 0x0) != DUMP_SIGNATURE || read_u32(buffer, 0x4) != DUMP_VALID_DUMP64 {
+            return Err(DumpHeaderError::InvalidSignature);
+        }
+
+        Ok(DumpHeader64 {
+            major_version: read_u32(buffer, 0x8),
+            minor_version: read_u32(buffer, 0xC),
+            directory_table_base: read_u64(buffer, 0x10),
+            pfn_database: read_u64(buffer, 0x18),
+            ps_loaded_module_list: read_u64(buffer, 0x20),
+            ps_active_process_head: read_u64(buffer, 0x28),
+            machine_image_type: read_u32(buffer, 0x30),
+            number_processors: read_u32(buffer, 0x34),
+            bugcheck_code: read_u32(buffer, 0x38),
+            bugcheck_parameters: [
+                read_u64(buffer, 0x40),
+                read_u64(buffer, 0x48),
+                read_u64(buffer, 0x50),
+                read_u64(buffer, 0x58),
+            ],
+            version_user: read_string(buffer, 0x60, 32),
+            kd_debugger_data_block: read_u64(buffer, 0x80),
+            dump_type: DumpType::from(read_u32(buffer, 0xF98)),
+            required_dump_space: read_u64(buffer, 0xFA0),
+            system_time: read_u64(buffer, 0xFA8),
+            comment: read_string(buffer, 0xFB0, 128),
+            system_up_time: read_u64(buffer, 0x1030),
+            product_type: read_u32(buffer, 0x1040),
+            suite_mask: read_u32(buffer, 0x1044),
+        })
+    }
+
+    /// Reads the header of a kernel dump file.
+    pub fn read<P: AsRef<std::path::Path>>(path: P) -> Result<DumpHeader64, DumpHeaderError> {
+        use std::io::Read;
+
+        let mut buffer = Vec::with_capacity(DUMP_HEADER64_SIZE);
+        std::fs::File::open(path)?
+            .take(DUMP_HEADER64_SIZE as u64)
+            .read_to_end(&mut buffer)?;
+        DumpHeader64::parse(&buffer)
+    }
+}
+
+/// Analysis of a crash report, which displays as a human readable report.
+#[derive(Debug)]
+pub struct CrashAnalysis {
+    pub system_id: String,
+    pub bugcheck: Option<BugCheck>,
+    pub windows_crash_info: Option<WindowsCrashReport>,
+    pub dump_header: Option<Result<DumpHeader64, DumpHeaderError>>,
+}
+
+impl CrashAnalysis {
+    /// Analyzes a crash report, without reading its dump file.
+    pub fn new(report: &CrashReport) -> CrashAnalysis {
+        CrashAnalysis {
+            system_id: report.system_id.clone(),
+            bugcheck: BugCheck::from_crash_parameters(&report.crash_parameters),
+            windows_crash_info: report.windows_crash_info.clone(),
+            dump_header: None,
+        }
+    }
+
+    /// Analyzes a crash report, reading the header of its dump file if it has one.
+    /// The dump file must be accessible from the calling process.
+    pub fn with_dump_file(report: &CrashReport) -> CrashAnalysis {
+        let mut analysis = CrashAnalysis::new(report);
+        analysis.dump_header = report
+            .windows_crash_info
+            .as_ref()
+            .filter(|info| !info.dump_file.is_empty())
+            .map(|info| DumpHeader64::read(&info.dump_file));
+        analysis
+    }
+}
+
+impl std::fmt::Display for CrashAnalysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Crash report for compute system {}", self.system_id)?;
+
+        match &self.bugcheck {
+            Some(bugcheck) => {
+                writeln!(
+                    f,
+                    "Bugcheck 0x{:08X} {}",
+                    bugcheck.code,
+                    bugcheck.name.unwrap_or("(unknown)")
+                )?;
+                for (index, parameter) in bugcheck.parameters.iter().enumerate() {
+                    write!(f, "  Parameter {}: 0x{:016X}", index + 1, parameter.value)?;
+                    match parameter.meaning {
+                        Some(meaning) => writeln!(f, " ({})", meaning)?,
+                        None => writeln!(f)?,
+                    }
+                }
+            }
+            None => writeln!(f, "No bugcheck parameters")?,
+        }
+
+        if let Some(info) = &self.windows_crash_info {
+            writeln!(
+                f,
+                "Guest OS {}.{}.{}, service pack {}.{}, product type {}, suite mask 0x{:X}",
+                info.os_major_version,
+                info.os_minor_version,
+                info.os_build_number,
+                info.os_service_pack_major_version,
+                info.os_service_pack_minor_version,
+                info.os_product_type,
+                info.os_suite_mask
+            )?;
+            writeln!(
+                f,
+                "Crash dump phase {:?}, status 0x{:08X}",
+                info.final_phase, info.status as u32
+            )?;
+            if !info.dump_file.is_empty() {
+                writeln!(f, "Dump file {}", info.dump_file)?;
+            }
+        }
+
+        match &self.dump_header {
+            Some(Ok(header)) => {
+                writeln!(
+                    f,
+                    "Dump header: {:?} dump, build {}, {} processors, machine 0x{:X}",
+                    header.dump_type,
+                    header.minor_version,
+                    header.number_processors,
+                    header.machine_image_type
+                )?;
+                writeln!(
+                    f,
+                    "  Bugcheck 0x{:08X} {}",
+                    header.bugcheck_code,
+                    bugcheck_info(header.bugcheck_code)
+                        .map(|info| info.name)
+                        .unwrap_or("(unknown)")
+                )?;
+                if !header.comment.is_empty() {
+                    writeln!(f, "  Comment: {}", header.comment)?;
+                }
+            }
+            Some(Err(error)) => writeln!(f, "Dump header unavailable: {}", error)?,
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl CrashReport {
+    /// Analyzes the report, without reading its dump file.
+    pub fn analyze(&self) -> CrashAnalysis {
+        CrashAnalysis::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::responses::system::WindowsCrashPhase;
+
+    #[test]
+    fn crash_report_analysis() {
+        let report = CrashReport {
+            system_id: String::from("uvm"),
+            crash_parameters: vec![0xD1, 0x10, 0x2, 0x0, 0xFFFF_F800_0000_1000],
+            windows_crash_info: Some(WindowsCrashReport {
+                os_major_version: 10,
+                os_build_number: 17763,
+                final_phase: WindowsCrashPhase::Complete,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let analysis = report.analyze();
+        let bugcheck = analysis.bugcheck.as_ref().unwrap();
+        assert_eq!(bugcheck.name, Some("DRIVER_IRQL_NOT_LESS_OR_EQUAL"));
+        assert_eq!(bugcheck.parameters[0].meaning, Some("Memory referenced"));
+
+        let rendered = analysis.to_string();
+        assert!(rendered.contains("Bugcheck 0x000000D1 DRIVER_IRQL_NOT_LESS_OR_EQUAL"));
+        assert!(
+            rendered.contains("Parameter 4: 0xFFFFF80000001000 (Address that referenced memory)")
+        );
+        assert!(rendered.contains("Guest OS 10.0.17763"));
+        assert!(rendered.contains("Crash dump phase Complete"));
+
+        let unknown = BugCheck::from_crash_parameters(&[0x1234, 1]).unwrap();
+        assert_eq!(unknown.name, None);
+        assert_eq!(unknown.parameters[0].meaning, None);
+        assert_eq!(BugCheck::from_crash_parameters(&[]), None);
+    }
+
+    #[test]
+    fn dump_header64() {
+        let mut buffer = vec![0u8; DUMP_HEADER64_SIZE];
+        buffer[0x0..0x4].copy_from_slice(b"PAGE");
+        buffer[0x4..0x8].copy_from_slice(b"DU64");
+        buffer[0xC..0x10].copy_from_slice(&17763u32.to_le_bytes());
+        buffer[0x30..0x34].copy_from_slice(&0x8664u32.to_le_bytes());
+        buffer[0x34..0x38].copy_from_slice(&2u32.to_le_bytes());
+        buffer[0x38..0x3C].copy_from_slice(&0xE2u32.to_le_bytes());
+        buffer[0x58..0x60].copy_from_slice(&7u64.to_le_bytes());
+        buffer[0xF98..0xF9C].copy_from_slice(&5u32.to_le_bytes());
+        buffer[0xFB0..0xFB7].copy_from_slice(b"comment");
+
+        let header = DumpHeader64::parse(&buffer).unwrap();
+        assert_eq!(header.minor_version, 17763);
+        assert_eq!(header.machine_image_type, 0x8664);
+        assert_eq!(header.number_processors, 2);
+        assert_eq!(header.bugcheck_code, 0xE2);
+        assert_eq!(header.bugcheck_parameters, [0, 0, 0, 7]);
+        assert_eq!(header.dump_type, DumpType::BitmapFull);
+        assert_eq!(header.comment, "comment");
+
+        match DumpHeader64::parse(&buffer[..0x100]) {
+            Err(DumpHeaderError::TooShort(0x100)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        buffer[0x4..0x8].copy_from_slice(b"DUMP");
+        match DumpHeader64::parse(&buffer) {
+            Err(DumpHeaderError::InvalidSignature) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -6,7 +6,11 @@
 // except according to those terms.
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
+pub mod crash;
+pub mod statistics;
+
 use crate::schema;
+use crate::schema::requests::system::PropertyType;
 use crate::schema::utils::is_default;
 use serde::{Deserialize, Serialize};
 
@@ -337,12 +341,92 @@ pub struct Properties {
     pub guest_connection_info: Option<GuestConnectionInfo>,
 }
 
+impl Properties {
+    /// Clears the sections of the properties that were not requested by a query
+    /// of the given property types, so only the requested ones are populated.
+    /// Memory information is returned for both `Memory` and `GuestMemory` queries.
+    pub fn retain_sections(&mut self, property_types: &[PropertyType]) {
+        let requested = |property_type: PropertyType| property_types.contains(&property_type);
+
+        if !requested(PropertyType::Memory) && !requested(PropertyType::GuestMemory) {
+            self.memory = MemoryInformationForVm::default();
+        }
+
+        if !requested(PropertyType::Statistics) {
+            self.statistics = Statistics::default();
+        }
+
+        if !requested(PropertyType::ProcessList) {
+            self.process_list.clear();
+        }
+
+        if !requested(PropertyType::TerminateOnLastHandleClosed) {
+            self.terminate_on_last_handle_closed = false;
+        }
+
+        if !requested(PropertyType::SharedMemoryRegion) {
+            self.shared_memory_region_info.clear();
+        }
+
+        if !requested(PropertyType::GuestConnection) {
+            self.guest_connection_info = None;
+        }
+    }
+}
+
+/// Filters compute system properties locally, such as the ones returned by an enumeration.
+/// Empty criteria match any compute system.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SystemFilter {
+    pub system_types: Vec<SystemType>,
+    pub states: Vec<State>,
+    pub os_types: Vec<OsType>,
+}
+
+impl SystemFilter {
+    pub fn by_system_type(system_type: SystemType) -> SystemFilter {
+        SystemFilter {
+            system_types: vec![system_type],
+            ..Default::default()
+        }
+    }
+
+    pub fn by_state(state: State) -> SystemFilter {
+        SystemFilter {
+            states: vec![state],
+            ..Default::default()
+        }
+    }
+
+    pub fn by_os_type(os_type: OsType) -> SystemFilter {
+        SystemFilter {
+            os_types: vec![os_type],
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if the compute system matches all the criteria of the filter.
+    pub fn matches(&self, properties: &Properties) -> bool {
+        (self.system_types.is_empty() || self.system_types.contains(&properties.system_type))
+            && (self.states.is_empty() || self.states.contains(&properties.state))
+            && (self.os_types.is_empty() || self.os_types.contains(&properties.runtime_os_type))
+    }
+
+    /// Returns the compute systems that match the filter.
+    pub fn apply(&self, systems: Vec<Properties>) -> Vec<Properties> {
+        systems
+            .into_iter()
+            .filter(|properties| self.matches(properties))
+            .collect()
+    }
+}
+
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct SystemExitStatus {
     #[serde(rename = "Status")]
     pub status: i32,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(default, rename = "ExitType", skip_serializing_if = "is_default")]
     pub exit_type: NotificationType,
 }
@@ -446,3 +530,67 @@ pub struct CrashReport {
     #[serde(default, rename = "CrashLog", skip_serializing_if = "is_default")]
     pub crash_log: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain_sections_and_filter() {
+        let mut properties = Properties {
+            id: String::from("vm"),
+            system_type: SystemType::VirtualMachine,
+            runtime_os_type: OsType::Linux,
+            state: State::Running,
+            process_list: vec![ProcessDetails::default()],
+            guest_connection_info: Some(GuestConnectionInfo {
+                protocol_version: 4,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        properties.memory.virtual_node_count = 1;
+        let container = Properties {
+            id: String::from("container"),
+            state: State::Stopped,
+            ..Default::default()
+        };
+
+        properties.retain_sections(&[PropertyType::GuestMemory, PropertyType::GuestConnection]);
+        assert_ne!(properties.memory, MemoryInformationForVm::default());
+        assert!(properties.process_list.is_empty());
+        assert_eq!(
+            properties
+                .guest_connection_info
+                .as_ref()
+                .unwrap()
+                .protocol_version,
+            4
+        );
+
+        properties.retain_sections(&[]);
+        assert_eq!(properties.memory, MemoryInformationForVm::default());
+        assert_eq!(properties.guest_connection_info, None);
+
+        let systems = vec![properties, container];
+        let ids = |systems: Vec<Properties>| -> Vec<String> {
+            systems
+                .into_iter()
+                .map(|properties| properties.id)
+                .collect()
+        };
+        assert_eq!(
+            ids(SystemFilter::by_system_type(SystemType::VirtualMachine).apply(systems.clone())),
+            vec!["vm"]
+        );
+        assert_eq!(
+            ids(SystemFilter::by_state(State::Stopped).apply(systems.clone())),
+            vec!["container"]
+        );
+        assert_eq!(
+            ids(SystemFilter::by_os_type(OsType::Windows).apply(systems.clone())),
+            vec!["container"]
+        );
+        assert_eq!(ids(SystemFilter::default().apply(systems)).len(), 2);
+    }
+}
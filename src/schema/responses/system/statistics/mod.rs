@@ -0,0 +1,443 @@
+// Copyright  rafawo (rafawo1@hotmail.com). All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Rates computed out of successive `Statistics` snapshots of a compute system,
+//! and their exposition in the Prometheus text format.
+
+use crate::schema::responses::system::Statistics;
+use crate::schema::virtual_machines::resources::compute::Processor;
+
+const HUNDRED_NS_PER_SECOND: f64 = 10_000_000.0;
+
+/// Rates computed between two `Statistics` snapshots of a compute system.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct StatisticsRates {
+    /// Elapsed time between the two snapshots, in seconds.
+    pub interval_seconds: f64,
+
+    /// True if the counters were reset between the two snapshots, usually due to
+    /// a restart of the compute system. In that case, rates are computed since the reset.
+    pub counters_reset: bool,
+
+    /// Processor usage, normalized by the processor count so that 100 means all processors are busy.
+    pub cpu_percent: f64,
+    pub cpu_user_percent: f64,
+    pub cpu_kernel_percent: f64,
+
+    pub read_bytes_per_second: f64,
+    pub write_bytes_per_second: f64,
+    pub read_operations_per_second: f64,
+    pub write_operations_per_second: f64,
+
+    pub memory_commit_bytes: u64,
+    pub memory_commit_peak_bytes: u64,
+
+    /// Current memory commit as a fraction of the peak commit.
+    pub memory_commit_to_peak_ratio: f64,
+}
+
+/// Takes successive `Statistics` snapshots of a compute system and computes rates out of them.
+#[derive(Debug, Clone)]
+pub struct StatisticsSampler {
+    system_id: String,
+    processor_count: u32,
+    previous: Option<Statistics>,
+    rates: Option<StatisticsRates>,
+}
+
+fn counters_reset(previous: &Statistics, current: &Statistics) -> bool {
+    current.container_start_time != previous.container_start_time
+        || current.uptime100ns < previous.uptime100ns
+        || current.processor.total_runtime100ns < previous.processor.total_runtime100ns
+        || current.storage.read_size_bytes < previous.storage.read_size_bytes
+        || current.storage.write_size_bytes < previous.storage.write_size_bytes
+        || current.storage.read_count_normalized < previous.storage.read_count_normalized
+        || current.storage.write_count_normalized < previous.storage.write_count_normalized
+}
+
+fn interval_seconds(previous: &Statistics, current: &Statistics) -> f64 {
+    match (previous.timestamp, current.timestamp) {
+        (Some(previous), Some(current)) if current > previous => {
+            let elapsed = current - previous;
+            match elapsed.num_microseconds() {
+                Some(microseconds) => microseconds as f64 / 1_000_000.0,
+                None => elapsed.num_milliseconds() as f64 / 1_000.0,
+            }
+        }
+        _ => {
+            current.uptime100ns.saturating_sub(previous.uptime100ns) as f64 / HUNDRED_NS_PER_SECOND
+        }
+    }
+}
+
+impl StatisticsSampler {
+    pub fn new<S: Into<String>>(system_id: S, processor_count: u32) -> StatisticsSampler {
+        StatisticsSampler {
+            system_id: system_id.into(),
+            processor_count,
+            previous: None,
+            rates: None,
+        }
+    }
+
+    /// Creates a sampler for a compute system configured with the given processor settings.
+    pub fn from_processor<S: Into<String>>(
+        system_id: S,
+        processor: &Processor,
+    ) -> StatisticsSampler {
+        StatisticsSampler::new(system_id, processor.count)
+    }
+
+    pub fn system_id(&self) -> &str {
+        &self.system_id
+    }
+
+    /// Returns the last snapshot taken, if any.
+    pub fn last_statistics(&self) -> Option<&Statistics> {
+        self.previous.as_ref()
+    }
+
+    /// Returns the rates computed out of the last two snapshots, if any.
+    pub fn last_rates(&self) -> Option<&StatisticsRates> {
+        self.rates.as_ref()
+    }
+
+    /// Takes a new snapshot, returning the latest rates.
+    ///
+    /// The first snapshot yields no rates, and snapshots that are not later
+    /// than the previous one keep the last rates.
+    pub fn sample(&mut self, statistics: Statistics) -> Option<&StatisticsRates> {
+        let rates = match self.previous.as_ref() {
+            Some(previous) => self.compute_rates(previous, &statistics),
+            None => None,
+        };
+
+        if rates.is_some() {
+            self.rates = rates;
+        }
+
+        self.previous = Some(statistics);
+        self.rates.as_ref()
+    }
+
+    fn compute_rates(
+        &self,
+        previous: &Statistics,
+        current: &Statistics,
+    ) -> Option<StatisticsRates> {
+        let counters_reset = counters_reset(previous, current);
+
+        // After a reset counters start from zero, so rates are computed since then
+        let baseline = if counters_reset {
+            Statistics::default()
+        } else {
+            previous.clone()
+        };
+
+        let interval_seconds = if counters_reset {
+            current.uptime100ns as f64 / HUNDRED_NS_PER_SECOND
+        } else {
+            interval_seconds(previous, current)
+        };
+
+        if interval_seconds <= 0.0 {
+            return None;
+        }
+
+        let per_second = |current: u64, previous: u64| {
+            current.saturating_sub(previous) as f64 / interval_seconds
+        };
+
+        let cpu_percent = |current: u64, previous: u64| {
+            let processor_seconds =
+                interval_seconds * f64::from(std::cmp::max(self.processor_count, 1));
+            current.saturating_sub(previous) as f64 / HUNDRED_NS_PER_SECOND / processor_seconds
+                * 100.0
+        };
+
+        let memory = &current.memory;
+
+        Some(StatisticsRates {
+            interval_seconds,
+            counters_reset,
+            cpu_percent: cpu_percent(
+                current.processor.total_runtime100ns,
+                baseline.processor.total_runtime100ns,
+            ),
+            cpu_user_percent: cpu_percent(
+                current.processor.runtime_user100ns,
+                baseline.processor.runtime_user100ns,
+            ),
+            cpu_kernel_percent: cpu_percent(
+                current.processor.runtime_kernel100ns,
+                baseline.processor.runtime_kernel100ns,
+            ),
+            read_bytes_per_second: per_second(
+                current.storage.read_size_bytes,
+                baseline.storage.read_size_bytes,
+            ),
+            write_bytes_per_second: per_second(
+                current.storage.write_size_bytes,
+                baseline.storage.write_size_bytes,
+            ),
+            read_operations_per_second: per_second(
+                current.storage.read_count_normalized,
+                baseline.storage.read_count_normalized,
+            ),
+            write_operations_per_second: per_second(
+                current.storage.write_count_normalized,
+                baseline.storage.write_count_normalized,
+            ),
+            memory_commit_bytes: memory.memory_usage_commit_bytes,
+            memory_commit_peak_bytes: memory.memory_usage_commit_peak_bytes,
+            memory_commit_to_peak_ratio: if memory.memory_usage_commit_peak_bytes == 0 {
+                0.0
+            } else {
+                memory.memory_usage_commit_bytes as f64
+                    / memory.memory_usage_commit_peak_bytes as f64
+            },
+        })
+    }
+
+    /// Renders the last snapshot and rates of this sampler in the Prometheus text format.
+    pub fn render_prometheus(&self) -> String {
+        render_prometheus(&[self])
+    }
+}
+
+type MetricValue = fn(&Statistics, Option<&StatisticsRates>) -> Option<f64>;
+
+/// Name, type, help text and value of each exposed metric.
+const METRICS: &[(&str, &str, &str, MetricValue)] = &[
+    (
+        "hcs_uptime_seconds",
+        "gauge",
+        "Time since the compute system started.",
+        |statistics, _| Some(statistics.uptime100ns as f64 / HUNDRED_NS_PER_SECOND),
+    ),
+    (
+        "hcs_processor_runtime_seconds_total",
+        "counter",
+        "Total processor time consumed by the compute system.",
+        |statistics, _| {
+            Some(statistics.processor.total_runtime100ns as f64 / HUNDRED_NS_PER_SECOND)
+        },
+    ),
+    (
+        "hcs_processor_usage_percent",
+        "gauge",
+        "Processor usage normalized by the processor count.",
+        |_, rates| rates.map(|rates| rates.cpu_percent),
+    ),
+    (
+        "hcs_memory_commit_bytes",
+        "gauge",
+        "Committed memory.",
+        |statistics, _| Some(statistics.memory.memory_usage_commit_bytes as f64),
+    ),
+    (
+        "hcs_memory_commit_peak_bytes",
+        "gauge",
+        "Peak committed memory.",
+        |statistics, _| Some(statistics.memory.memory_usage_commit_peak_bytes as f64),
+    ),
+    (
+        "hcs_memory_private_working_set_bytes",
+        "gauge",
+        "Private working set.",
+        |statistics, _| Some(statistics.memory.memory_usage_private_working_set_bytes as f64),
+    ),
+    (
+        "hcs_storage_read_bytes_total",
+        "counter",
+        "Total bytes read from storage.",
+        |statistics, _| Some(statistics.storage.read_size_bytes as f64),
+    ),
+    (
+        "hcs_storage_write_bytes_total",
+        "counter",
+        "Total bytes written to storage.",
+        |statistics, _| Some(statistics.storage.write_size_bytes as f64),
+    ),
+    (
+        "hcs_storage_read_operations_total",
+        "counter",
+        "Total normalized storage read operations.",
+        |statistics, _| Some(statistics.storage.read_count_normalized as f64),
+    ),
+    (
+        "hcs_storage_write_operations_total",
+        "counter",
+        "Total normalized storage write operations.",
+        |statistics, _| Some(statistics.storage.write_count_normalized as f64),
+    ),
+    (
+        "hcs_storage_read_bytes_per_second",
+        "gauge",
+        "Storage read throughput.",
+        |_, rates| rates.map(|rates| rates.read_bytes_per_second),
+    ),
+    (
+        "hcs_storage_write_bytes_per_second",
+        "gauge",
+        "Storage write throughput.",
+        |_, rates| rates.map(|rates| rates.write_bytes_per_second),
+    ),
+    (
+        "hcs_storage_read_operations_per_second",
+        "gauge",
+        "Normalized storage read operations per second.",
+        |_, rates| rates.map(|rates| rates.read_operations_per_second),
+    ),
+    (
+        "hcs_storage_write_operations_per_second",
+        "gauge",
+        "Normalized storage write operations per second.",
+        |_, rates| rates.map(|rates| rates.write_operations_per_second),
+    ),
+];
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders the last snapshot and rates of multiple samplers in the Prometheus text format,
+/// with one sample per compute system labeled by its ID.
+/// Samplers without snapshots are skipped.
+pub fn render_prometheus(samplers: &[&StatisticsSampler]) -> String {
+    let mut exposition = String::new();
+
+    for (name, metric_type, help, value) in METRICS {
+        let samples: Vec<(&str, f64)> = samplers
+            .iter()
+            .filter_map(|sampler| {
+                let statistics = sampler.last_statistics()?;
+                value(statistics, sampler.last_rates()).map(|value| (sampler.system_id(), value))
+            })
+            .collect();
+
+        if samples.is_empty() {
+            continue;
+        }
+
+        exposition.push_str(&format!("# HELP {} {}\n", name, help));
+        exposition.push_str(&format!("# TYPE {} {}\n", name, metric_type));
+
+        for (system_id, value) in samples {
+            exposition.push_str(&format!(
+                "{}{{id=\"{}\"}} {}\n",
+                name,
+                escape_label_value(system_id),
+                value
+            ));
+        }
+    }
+
+    exposition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::responses::system::{MemoryStats, ProcessorStats, StorageStats};
+
+    fn statistics(seconds: u64, runtime_seconds: u64, read_bytes: u64) -> Statistics {
+        Statistics {
+            timestamp: Some(
+                chrono::DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z")
+                    .unwrap()
+                    .with_timezone(&chrono::Utc)
+                    + chrono::Duration::seconds(seconds as i64),
+            ),
+            container_start_time: None,
+            uptime100ns: seconds * 10_000_000,
+            processor: ProcessorStats {
+                total_runtime100ns: runtime_seconds * 10_000_000,
+                runtime_user100ns: runtime_seconds * 10_000_000,
+                runtime_kernel100ns: 0,
+            },
+            memory: MemoryStats {
+                memory_usage_commit_bytes: 512,
+                memory_usage_commit_peak_bytes: 1024,
+                memory_usage_private_working_set_bytes: 256,
+            },
+            storage: StorageStats {
+                read_count_normalized: read_bytes / 4096,
+                read_size_bytes: read_bytes,
+                write_count_normalized: 0,
+                write_size_bytes: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn sampler_rates() {
+        let mut sampler = StatisticsSampler::new("vm", 4);
+        assert!(sampler.sample(statistics(10, 4, 0)).is_none());
+
+        let rates = sampler.sample(statistics(20, 24, 40960)).unwrap().clone();
+        assert_eq!(rates.interval_seconds, 10.0);
+        assert!(!rates.counters_reset);
+        assert_eq!(rates.cpu_percent, 50.0);
+        assert_eq!(rates.cpu_user_percent, 50.0);
+        assert_eq!(rates.read_bytes_per_second, 4096.0);
+        assert_eq!(rates.read_operations_per_second, 1.0);
+        assert_eq!(rates.memory_commit_to_peak_ratio, 0.5);
+
+        // A snapshot at the same time yields no new rates, but keeps the last ones
+        assert_eq!(sampler.sample(statistics(20, 24, 40960)), Some(&rates));
+    }
+
+    #[test]
+    fn sampler_counter_reset() {
+        let mut sampler = StatisticsSampler::new("vm", 1);
+        sampler.sample(statistics(100, 50, 1_000_000));
+
+        let rates = sampler.sample(statistics(2, 1, 8192)).unwrap();
+        assert!(rates.counters_reset);
+        assert_eq!(rates.interval_seconds, 2.0);
+        assert_eq!(rates.cpu_percent, 50.0);
+        assert_eq!(rates.read_bytes_per_second, 4096.0);
+    }
+
+    #[test]
+    fn prometheus_exposition() {
+        let mut first = StatisticsSampler::new("vm\"1", 1);
+        first.sample(statistics(1, 0, 0));
+        first.sample(statistics(2, 1, 4096));
+
+        let mut second = StatisticsSampler::new("vm2", 1);
+        second.sample(statistics(1, 0, 0));
+
+        let exposition = render_prometheus(&[&first, &second]);
+
+        assert!(exposition.contains(
+            "# HELP hcs_processor_usage_percent Processor usage normalized by the processor count.\n\
+             # TYPE hcs_processor_usage_percent gauge\n\
+             hcs_processor_usage_percent{id=\"vm\\\"1\"} 100\n\
+             # HELP"
+        ));
+        assert!(exposition.contains(
+            "# TYPE hcs_storage_read_bytes_total counter\n\
+             hcs_storage_read_bytes_total{id=\"vm\\\"1\"} 4096\n\
+             hcs_storage_read_bytes_total{id=\"vm2\"} 0\n"
+        ));
+        assert_eq!(
+            exposition
+                .matches("# TYPE hcs_uptime_seconds gauge")
+                .count(),
+            1
+        );
+        assert!(StatisticsSampler::new("empty", 1)
+            .render_prometheus()
+            .is_empty());
+    }
+}
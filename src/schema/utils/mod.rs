@@ -111,12 +111,10 @@ impl GuidSerde {
     }
 }
 
-impl serde::Serialize for GuidSerde {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::ser::Serializer,
-    {
-        let guid_string = format!(
+impl std::fmt::Display for GuidSerde {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
             "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
             self.data1,
             self.data2,
@@ -129,8 +127,16 @@ impl serde::Serialize for GuidSerde {
             self.data4[5],
             self.data4[6],
             self.data4[7],
-        );
-        serializer.serialize_str(&guid_string)
+        )
+    }
+}
+
+impl serde::Serialize for GuidSerde {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
     }
 }
 
@@ -0,0 +1,355 @@
+// Copyright  rafawo (rafawo1@hotmail.com). All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Runtime targeting of schema versions.
+//!
+//! All schema fields are always available, regardless of the schema version they were
+//! introduced in. Types annotate the fields that need a schema version newer than RS5
+//! through `SchemaVersioned`, so that documents can be serialized for the schema version
+//! supported by the host they are sent to.
+
+use crate::schema::Version;
+use serde::Serialize;
+
+/// Field of a schema document that requires a minimum schema version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionedField {
+    /// Path of JSON member names from the root of the document, separated by dots.
+    /// A `*` segment matches every value of a map or every element of an array.
+    pub path: String,
+
+    /// Minimum schema version that supports the field.
+    pub min_version: Version,
+}
+
+impl VersionedField {
+    pub fn new(path: &str, min_version: Version) -> VersionedField {
+        VersionedField {
+            path: path.to_string(),
+            min_version,
+        }
+    }
+
+    /// Returns the versioned fields of a nested type, with their paths prefixed by `prefix`.
+    pub fn nested<T: SchemaVersioned>(prefix: &str) -> Vec<VersionedField> {
+        T::versioned_fields()
+            .into_iter()
+            .map(|field| VersionedField {
+                path: format!("{}.{}", prefix, field.path),
+                min_version: field.min_version,
+            })
+            .collect()
+    }
+}
+
+/// Schema types that have fields requiring a schema version newer than RS5.
+pub trait SchemaVersioned {
+    /// Returns the fields of the type, and of its nested types, that require
+    /// a schema version newer than RS5.
+    fn versioned_fields() -> Vec<VersionedField>;
+}
+
+/// What to do with fields that are not supported by the targeted schema version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedFieldPolicy {
+    /// Fail the serialization.
+    Error,
+    /// Remove the fields from the serialized document.
+    Strip,
+}
+
+/// Field set in a document that is not supported by the targeted schema version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedField {
+    /// Path of the field in the document, with map keys and array indexes resolved.
+    pub path: String,
+    pub min_version: Version,
+}
+
+#[derive(Debug)]
+pub enum SchemaVersionError {
+    /// The document sets fields that are not supported by the targeted schema version.
+    UnsupportedFields {
+        target: Version,
+        fields: Vec<UnsupportedField>,
+    },
+    Serialization(serde_json::Error),
+}
+
+impl std::fmt::Display for SchemaVersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SchemaVersionError::UnsupportedFields { target, fields } => {
+                write!(
+                    f,
+                    "fields not supported by schema version {}.{}:",
+                    target.major, target.minor
+                )?;
+                for field in fields {
+                    write!(
+                        f,
+                        " {} (requires {}.{})",
+                        field.path, field.min_version.major, field.min_version.minor
+                    )?;
+                }
+                Ok(())
+            }
+            SchemaVersionError::Serialization(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SchemaVersionError {}
+
+impl std::convert::From<serde_json::Error> for SchemaVersionError {
+    fn from(error: serde_json::Error) -> Self {
+        SchemaVersionError::Serialization(error)
+    }
+}
+
+/// Removes the members matching `segments` from `value`, collecting their resolved paths.
+fn remove_members(
+    value: &mut serde_json::Value,
+    segments: &[&str],
+    path: &str,
+    remove: bool,
+    found: &mut Vec<String>,
+) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+
+    match value {
+        serde_json::Value::Object(members) if *segment == "*" => {
+            for (key, member) in members.iter_mut() {
+                remove_members(member, rest, &child_path(key), remove, found);
+            }
+        }
+        serde_json::Value::Array(elements) if *segment == "*" => {
+            for (index, element) in elements.iter_mut().enumerate() {
+                remove_members(
+                    element,
+                    rest,
+                    &child_path(&index.to_string()),
+                    remove,
+                    found,
+                );
+            }
+        }
+        serde_json::Value::Object(members) => {
+            if rest.is_empty() {
+                if members.contains_key(*segment) {
+                    found.push(child_path(segment));
+                    if remove {
+                        members.remove(*segment);
+                    }
+                }
+            } else if let Some(member) = members.get_mut(*segment) {
+                remove_members(member, rest, &child_path(segment), remove, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Serializes a schema document into a JSON value targeting the given schema version,
+/// handling the fields that are not supported by it according to `policy`.
+pub fn to_value_for<T>(
+    document: &T,
+    target: &Version,
+    policy: UnsupportedFieldPolicy,
+) -> Result<serde_json::Value, SchemaVersionError>
+where
+    T: Serialize + SchemaVersioned,
+{
+    let mut value = serde_json::to_value(document)?;
+    let mut unsupported = Vec::new();
+
+    for field in T::versioned_fields() {
+        if field.min_version <= *target {
+            continue;
+        }
+
+        let segments: Vec<&str> = field.path.split('.').collect();
+        let mut found = Vec::new();
+        remove_members(
+            &mut value,
+            &segments,
+            "",
+            policy == UnsupportedFieldPolicy::Strip,
+            &mut found,
+        );
+
+        unsupported.extend(found.into_iter().map(|path| UnsupportedField {
+            path,
+            min_version: field.min_version.clone(),
+        }));
+    }
+
+    if policy == UnsupportedFieldPolicy::Error && !unsupported.is_empty() {
+        return Err(SchemaVersionError::UnsupportedFields {
+            target: target.clone(),
+            fields: unsupported,
+        });
+    }
+
+    Ok(value)
+}
+
+/// Serializes a schema document into a JSON string targeting the given schema version,
+/// failing if the document sets fields that are not supported by it.
+pub fn to_json_for<T>(document: &T, target: &Version) -> Result<String, SchemaVersionError>
+where
+    T: Serialize + SchemaVersioned,
+{
+    to_json_for_with_policy(document, target, UnsupportedFieldPolicy::Error)
+}
+
+/// Serializes a schema document into a JSON string targeting the given schema version,
+/// handling the fields that are not supported by it according to `policy`.
+pub fn to_json_for_with_policy<T>(
+    document: &T,
+    target: &Version,
+    policy: UnsupportedFieldPolicy,
+) -> Result<String, SchemaVersionError>
+where
+    T: Serialize + SchemaVersioned,
+{
+    Ok(serde_json::to_string(&to_value_for(
+        document, target, policy,
+    )?)?)
+}
+
+/// Returns the schema version to target out of the versions supported by a host,
+/// which is the highest of them that is also known by this crate.
+pub fn negotiate_schema_version(supported_schema_versions: &[Version]) -> Option<Version> {
+    let latest = Version::latest();
+
+    supported_schema_versions
+        .iter()
+        .filter(|version| version.major == latest.major && **version <= latest)
+        .max()
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::virtual_machines::resources::compute::{
+        Memory, MemoryBackingPageSize, Processor,
+    };
+    use crate::schema::virtual_machines::resources::storage::{Plan9, Plan9Share};
+    use crate::schema::{ComputeSystem, VirtualMachine};
+
+    fn compute_system() -> ComputeSystem {
+        let mut virtual_machine = VirtualMachine::default();
+        virtual_machine.compute_topology.memory = Memory {
+            size_in_mb: 1024,
+            backing_page_size: Some(MemoryBackingPageSize::Large),
+            ..Default::default()
+        };
+        virtual_machine.compute_topology.processor = Processor {
+            count: 2,
+            ..Default::default()
+        };
+        virtual_machine.devices.plan9 = Some(Plan9 {
+            shares: vec![
+                Plan9Share {
+                    name: String::from("share"),
+                    ..Default::default()
+                },
+                Plan9Share {
+                    name: String::from("files"),
+                    allowed_files: vec![String::from("file.txt")],
+                    ..Default::default()
+                },
+            ],
+        });
+
+        ComputeSystem {
+            owner: String::from("owner"),
+            virtual_machine: Some(virtual_machine),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unsupported_fields() {
+        let document = compute_system();
+
+        match to_json_for(&document, &Version::schema_version_rs5()) {
+            Err(SchemaVersionError::UnsupportedFields { target, fields }) => {
+                assert_eq!(target, Version::schema_version_rs5());
+                assert_eq!(
+                    fields,
+                    vec![
+                        UnsupportedField {
+                            path: String::from(
+                                "VirtualMachine.ComputeTopology.Memory.BackingPageSize"
+                            ),
+                            min_version: Version::schema_version_19h1(),
+                        },
+                        UnsupportedField {
+                            path: String::from(
+                                "VirtualMachine.Devices.Plan9.Shares.1.AllowedFiles"
+                            ),
+                            min_version: Version::schema_version_19h1(),
+                        },
+                    ]
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let stripped = to_value_for(
+            &document,
+            &Version::schema_version_rs5(),
+            UnsupportedFieldPolicy::Strip,
+        )
+        .unwrap();
+        let memory = &stripped["VirtualMachine"]["ComputeTopology"]["Memory"];
+        assert_eq!(memory, &serde_json::json!({"SizeInMB": 1024}));
+        assert!(stripped["VirtualMachine"]["Devices"]["Plan9"]["Shares"][1]
+            .get("AllowedFiles")
+            .is_none());
+
+        let full = to_value_for(
+            &document,
+            &Version::schema_version_19h1(),
+            UnsupportedFieldPolicy::Error,
+        )
+        .unwrap();
+        assert_eq!(full, serde_json::to_value(&document).unwrap());
+    }
+
+    #[test]
+    fn schema_version_negotiation() {
+        let rs5 = Version::schema_version_rs5();
+        let v19h1 = Version::schema_version_19h1();
+        let future = Version { major: 2, minor: 9 };
+
+        assert_eq!(
+            negotiate_schema_version(&[rs5.clone(), v19h1.clone(), future]),
+            Some(v19h1)
+        );
+        assert_eq!(
+            negotiate_schema_version(&[Version { major: 1, minor: 0 }, rs5.clone()]),
+            Some(rs5)
+        );
+        assert_eq!(negotiate_schema_version(&[]), None);
+    }
+}
@@ -10,6 +10,8 @@ pub mod resources;
 
 use crate::schema;
 use crate::schema::utils::is_default;
+use crate::schema::versioning::{SchemaVersioned, VersionedField};
+use crate::schema::Version;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
@@ -17,7 +19,7 @@ pub struct Devices {
     #[serde(default, rename = "ComPorts", skip_serializing_if = "is_default")]
     pub com_ports: std::collections::HashMap<u32, schema::virtual_machines::resources::ComPort>,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(default, rename = "VirtioSerial", skip_serializing_if = "is_default")]
     pub virtio_serial: Option<schema::virtual_machines::resources::VirtioSerial>,
 
@@ -221,3 +223,19 @@ pub struct GuestConnection {
     )]
     pub use_connected_suspend: bool,
 }
+
+impl SchemaVersioned for Devices {
+    fn versioned_fields() -> Vec<VersionedField> {
+        let mut fields = vec![VersionedField::new(
+            "VirtioSerial",
+            Version::schema_version_19h1(),
+        )];
+        fields.extend(VersionedField::nested::<
+            schema::virtual_machines::resources::storage::VirtualPMemController,
+        >("VirtualPMem"));
+        fields.extend(VersionedField::nested::<
+            schema::virtual_machines::resources::storage::Plan9,
+        >("Plan9"));
+        fields
+    }
+}
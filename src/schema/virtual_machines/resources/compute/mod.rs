@@ -7,16 +7,16 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 use crate::schema::utils::is_default;
+use crate::schema::versioning::{SchemaVersioned, VersionedField};
+use crate::schema::Version;
 use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "19h1")]
 impl std::default::Default for MemoryBackingPageSize {
     fn default() -> Self {
         MemoryBackingPageSize::Small
     }
 }
 
-#[cfg(feature = "19h1")]
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum MemoryBackingPageSize {
     /// Small (4KB) page size unit
@@ -40,7 +40,7 @@ pub struct Memory {
     pub allow_overcommit: bool,
 
     /// The preferred page size unit (chunk size) used when allocating backing pages for the VM.
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "BackingPageSize",
@@ -49,15 +49,23 @@ pub struct Memory {
     pub backing_page_size: Option<MemoryBackingPageSize>,
 
     /// If enabled, then each backing page is physically pinned on first access.
-    #[cfg(feature = "19h1")]
-    #[serde(rename = "PinBackingPages")]
+    /// Requires schema version 2.2.
+    #[serde(
+        default,
+        rename = "PinBackingPages",
+        skip_serializing_if = "is_default"
+    )]
     pub pin_backing_pages: bool,
 
     /// If enabled, then backing page chunks smaller than the backing page size are never used unless
     /// the system is under extreme memory pressure. If the backing page size is Small, then it is
     /// forced to Large when this option is enabled.
-    #[cfg(feature = "19h1")]
-    #[serde(rename = "ForbidSmallBackingPages")]
+    /// Requires schema version 2.2.
+    #[serde(
+        default,
+        rename = "ForbidSmallBackingPages",
+        skip_serializing_if = "is_default"
+    )]
     pub forbid_small_backing_pages: bool,
 
     /// If enabled, then the memory hot hint feature is exposed to the VM, allowing it to prefetch
@@ -72,7 +80,7 @@ pub struct Memory {
 
     /// If enabled, then the memory cold discard hint feature is exposed to the VM, allowing it to trim
     /// non-zeroed pages from the working set (if supported by the guest operating system).
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "EnableColdDiscardHint",
@@ -107,7 +115,7 @@ pub struct Processor {
     )]
     pub expose_virtualization_extensions: bool,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "EnablePerfmonPmu",
@@ -115,7 +123,7 @@ pub struct Processor {
     )]
     pub enable_perfmon_pmu: bool,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "EnablePerfmonPebs",
@@ -123,7 +131,7 @@ pub struct Processor {
     )]
     pub enable_perfmon_pebs: bool,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "EnablePerfmonLbr",
@@ -131,7 +139,7 @@ pub struct Processor {
     )]
     pub enable_perfmon_lbr: bool,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "EnablePerfmonIpt",
@@ -140,6 +148,39 @@ pub struct Processor {
     pub enable_perfmon_ipt: bool,
 }
 
+/// CPU group a virtual machine is assigned to.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CpuGroup {
+    #[serde(default, rename = "Id", skip_serializing_if = "is_default")]
+    pub id: String,
+}
+
+/// Limit of the processors of a virtual machine that is not capped, as a percentage times 1000.
+pub const PROCESSOR_LIMIT_MAX: u64 = 100_000;
+
+/// Weight of the processors of a virtual machine that wasn't assigned one.
+pub const PROCESSOR_WEIGHT_DEFAULT: u64 = 100;
+
+/// Processor limits of a virtual machine, which can be updated while it's running.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ProcessorLimits {
+    #[serde(default, rename = "Limit", skip_serializing_if = "is_default")]
+    pub limit: u64,
+
+    #[serde(default, rename = "Weight", skip_serializing_if = "is_default")]
+    pub weight: u64,
+
+    #[serde(default, rename = "Reservation", skip_serializing_if = "is_default")]
+    pub reservation: u64,
+
+    #[serde(
+        default,
+        rename = "MaximumFrequencyMHz",
+        skip_serializing_if = "is_default"
+    )]
+    pub maximum_frequency_mhz: u32,
+}
+
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Topology {
     #[serde(rename = "Memory")]
@@ -148,3 +189,33 @@ pub struct Topology {
     #[serde(rename = "Processor")]
     pub processor: Processor,
 }
+
+impl SchemaVersioned for Memory {
+    fn versioned_fields() -> Vec<VersionedField> {
+        vec![
+            VersionedField::new("BackingPageSize", Version::schema_version_19h1()),
+            VersionedField::new("PinBackingPages", Version::schema_version_19h1()),
+            VersionedField::new("ForbidSmallBackingPages", Version::schema_version_19h1()),
+            VersionedField::new("EnableColdDiscardHint", Version::schema_version_19h1()),
+        ]
+    }
+}
+
+impl SchemaVersioned for Processor {
+    fn versioned_fields() -> Vec<VersionedField> {
+        vec![
+            VersionedField::new("EnablePerfmonPmu", Version::schema_version_19h1()),
+            VersionedField::new("EnablePerfmonPebs", Version::schema_version_19h1()),
+            VersionedField::new("EnablePerfmonLbr", Version::schema_version_19h1()),
+            VersionedField::new("EnablePerfmonIpt", Version::schema_version_19h1()),
+        ]
+    }
+}
+
+impl SchemaVersioned for Topology {
+    fn versioned_fields() -> Vec<VersionedField> {
+        let mut fields = VersionedField::nested::<Memory>("Memory");
+        fields.extend(VersionedField::nested::<Processor>("Processor"));
+        fields
+    }
+}
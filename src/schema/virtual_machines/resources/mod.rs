@@ -14,6 +14,8 @@ pub mod vpci;
 
 use crate::schema::utils::is_default;
 use crate::schema::utils::*;
+use crate::schema::versioning::{SchemaVersioned, VersionedField};
+use crate::schema::Version;
 use serde::{Deserialize, Serialize};
 
 impl std::default::Default for UefiBootDevice {
@@ -80,7 +82,7 @@ pub struct Uefi {
     #[serde(default, rename = "Console", skip_serializing_if = "is_default")]
     pub console: SerialConsole,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "StopOnBootFailure",
@@ -89,7 +91,6 @@ pub struct Uefi {
     pub stop_on_boot_failure: bool,
 }
 
-#[cfg(feature = "19h1")]
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct LinuxKernelDirect {
     #[serde(default, rename = "KernelFilePath", skip_serializing_if = "is_default")]
@@ -134,7 +135,7 @@ pub struct Chipset {
     #[serde(default, rename = "UseUtc", skip_serializing_if = "is_default")]
     pub use_utc: bool,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(
         default,
         rename = "LinuxKernelDirect",
@@ -158,7 +159,6 @@ pub struct ComPort {
     pub optimize_for_debugger: bool,
 }
 
-#[cfg(feature = "19h1")]
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct VirtioSerialPort {
     #[serde(default, rename = "NamedPipe", skip_serializing_if = "is_default")]
@@ -168,7 +168,6 @@ pub struct VirtioSerialPort {
     pub name: String,
 }
 
-#[cfg(feature = "19h1")]
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct VirtioSerial {
     #[serde(default, rename = "Ports", skip_serializing_if = "is_default")]
@@ -364,3 +363,23 @@ pub struct SharedMemoryConfiguration {
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct KernelIntegration {}
+
+impl SchemaVersioned for Uefi {
+    fn versioned_fields() -> Vec<VersionedField> {
+        vec![VersionedField::new(
+            "StopOnBootFailure",
+            Version::schema_version_19h1(),
+        )]
+    }
+}
+
+impl SchemaVersioned for Chipset {
+    fn versioned_fields() -> Vec<VersionedField> {
+        let mut fields = VersionedField::nested::<Uefi>("Uefi");
+        fields.push(VersionedField::new(
+            "LinuxKernelDirect",
+            Version::schema_version_19h1(),
+        ));
+        fields
+    }
+}
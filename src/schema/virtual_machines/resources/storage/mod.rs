@@ -7,6 +7,8 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 use crate::schema::utils::is_default;
+use crate::schema::versioning::{SchemaVersioned, VersionedField};
+use crate::schema::Version;
 use serde::{Deserialize, Serialize};
 
 impl std::default::Default for AttachmentType {
@@ -203,7 +205,7 @@ pub struct Plan9Share {
     #[serde(rename = "Port")]
     pub port: u32,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(default, rename = "AllowedFiles", skip_serializing_if = "is_default")]
     pub allowed_files: Vec<String>,
 }
@@ -235,7 +237,6 @@ pub enum VirtualPMemImageFormat {
     Vhd1,
 }
 
-#[cfg(feature = "19h1")]
 #[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct VirtualPMemMapping {
     #[serde(rename = "HostPath")]
@@ -256,11 +257,11 @@ pub struct VirtualPMemDevice {
     #[serde(default, rename = "ImageFormat", skip_serializing_if = "is_default")]
     pub image_format: VirtualPMemImageFormat,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(default, rename = "SizeBytes", skip_serializing_if = "is_default")]
     pub size_bytes: u64,
 
-    #[cfg(feature = "19h1")]
+    /// Requires schema version 2.2.
     #[serde(default, rename = "Mappings", skip_serializing_if = "is_default")]
     pub mappings: std::collections::HashMap<u64, VirtualPMemMapping>,
 }
@@ -300,3 +301,33 @@ pub struct VirtualPMemController {
     #[serde(default, rename = "Backing", skip_serializing_if = "is_default")]
     pub backing: VirtualPMemBackingType,
 }
+
+impl SchemaVersioned for Plan9Share {
+    fn versioned_fields() -> Vec<VersionedField> {
+        vec![VersionedField::new(
+            "AllowedFiles",
+            Version::schema_version_19h1(),
+        )]
+    }
+}
+
+impl SchemaVersioned for Plan9 {
+    fn versioned_fields() -> Vec<VersionedField> {
+        VersionedField::nested::<Plan9Share>("Shares.*")
+    }
+}
+
+impl SchemaVersioned for VirtualPMemDevice {
+    fn versioned_fields() -> Vec<VersionedField> {
+        vec![
+            VersionedField::new("SizeBytes", Version::schema_version_19h1()),
+            VersionedField::new("Mappings", Version::schema_version_19h1()),
+        ]
+    }
+}
+
+impl SchemaVersioned for VirtualPMemController {
+    fn versioned_fields() -> Vec<VersionedField> {
+        VersionedField::nested::<VirtualPMemDevice>("Devices.*")
+    }
+}